@@ -0,0 +1,468 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::{self, Debug, Display},
+    hash::{BuildHasher, BuildHasherDefault},
+    num::NonZeroUsize,
+};
+
+use string_interner::{backend::Backend, symbol::SymbolUsize, DefaultBackend, StringInterner};
+
+#[derive(Debug)]
+#[non_exhaustive]
+enum Expr<B: Backend> {
+    Symbol(B::Symbol),
+    Number(i64),
+    List(Vec<Expr<B>>),
+    Bool(bool),
+    Str(String),
+}
+
+impl<B: Backend> Clone for Expr<B> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Symbol(arg0) => Self::Symbol(*arg0),
+            Self::Number(arg0) => Self::Number(*arg0),
+            Self::List(arg0) => Self::List(arg0.clone()),
+            Self::Bool(b) => Self::Bool(*b),
+            Self::Str(s) => Self::Str(s.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Token<B: Backend> {
+    Symbol(B::Symbol),
+    Str(String),
+}
+
+#[derive(Debug)]
+pub enum Err {
+    UnmatchedCloser,
+    UnmatchedOpeners { depth: NonZeroUsize },
+    UnterminatedString,
+}
+
+impl Display for Err {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Err::UnmatchedCloser => write!(f, "Unmatched closing delimiter"),
+            Err::UnmatchedOpeners { depth } => write!(f, "{} unmatched opening delimiter", depth),
+            Err::UnterminatedString => write!(f, "Unterminated string"),
+        }
+    }
+}
+
+// #[derive(Debug, Clone)]
+// struct Env {
+//     data: HashMap<SymbolUsize, Expr>,
+// }
+
+#[derive(Debug)]
+struct InternTable<B: Backend, H: BuildHasher> {
+    intern_table: StringInterner<B, H>,
+    open_paren: B::Symbol,
+    close_paren: B::Symbol,
+    add_symbol: B::Symbol,
+    sub_symbol: B::Symbol,
+    mul_symbol: B::Symbol,
+    div_symbol: B::Symbol,
+    true_symbol: B::Symbol,
+    false_symbol: B::Symbol,
+    if_symbol: B::Symbol,
+    list_symbol: B::Symbol,
+    progn_symbol: B::Symbol,
+}
+
+//A value on its way in or out of the interpreter, without any interned
+//symbols so the host can hold on to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Number(i64),
+    Bool(bool),
+    Str(String),
+    Symbol(String),
+    List(Vec<Value>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Bool(true) => write!(f, "#t"),
+            Value::Bool(false) => write!(f, "#f"),
+            Value::Str(s) => write!(f, "{s:?}"),
+            Value::Symbol(s) => write!(f, "{s}"),
+            Value::List(list) => {
+                write!(f, "(")?;
+                for (i, value) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+//Whatever embeds the interpreter. Calls to functions the interpreter doesn't
+//know about go here.
+pub trait Host {
+    //None if the host has no function called `name` either
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Option<Result<Value, String>>;
+}
+
+//A host with no functions, for running bunlang on its own
+#[derive(Debug, Default)]
+pub struct NoHost;
+
+impl Host for NoHost {
+    fn call(&mut self, _name: &str, _args: Vec<Value>) -> Option<Result<Value, String>> {
+        None
+    }
+}
+
+type DefaultTable = InternTable<DefaultBackend<SymbolUsize>, BuildHasherDefault<DefaultHasher>>;
+
+//Parsed source, its symbols interned by the interpreter that parsed it
+#[derive(Debug, Clone)]
+pub struct Program {
+    exprs: Vec<Expr<DefaultBackend<SymbolUsize>>>,
+}
+
+#[derive(Debug)]
+pub struct Interpreter {
+    intern_table: DefaultTable,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut interner: StringInterner<
+            DefaultBackend<SymbolUsize>,
+            BuildHasherDefault<DefaultHasher>,
+        > = StringInterner::new();
+
+        let intern_table = InternTable {
+            open_paren: interner.get_or_intern("("),
+            close_paren: interner.get_or_intern(")"),
+            add_symbol: interner.get_or_intern("+"),
+            sub_symbol: interner.get_or_intern("-"),
+            mul_symbol: interner.get_or_intern("*"),
+            div_symbol: interner.get_or_intern("/"),
+            true_symbol: interner.get_or_intern("#t"),
+            false_symbol: interner.get_or_intern("#f"),
+            if_symbol: interner.get_or_intern("if"),
+            list_symbol: interner.get_or_intern("list"),
+            progn_symbol: interner.get_or_intern("progn"),
+            intern_table: interner,
+        };
+        Interpreter { intern_table }
+    }
+
+    //Evaluates every expression in `source`, returning one result each.
+    //Parse errors stop anything from running.
+    pub fn eval_str(
+        &mut self,
+        source: &str,
+        host: &mut dyn Host,
+    ) -> Result<Vec<Result<Value, String>>, Vec<Err>> {
+        let program = self.parse(source)?;
+        Ok(self.eval_program(&program, host))
+    }
+
+    //Parses `source` once for running again and again with eval_program
+    pub fn parse(&mut self, source: &str) -> Result<Program, Vec<Err>> {
+        let tokens = tokenize(source, &mut self.intern_table)?;
+        let exprs = parse(tokens, &mut self.intern_table)?;
+        Ok(Program { exprs })
+    }
+
+    //Evaluates every expression in `program`, returning one result each.
+    //`program` has to come from this interpreter's parse.
+    pub fn eval_program(
+        &mut self,
+        program: &Program,
+        host: &mut dyn Host,
+    ) -> Vec<Result<Value, String>> {
+        program
+            .exprs
+            .iter()
+            .map(|expr| {
+                eval(expr, &mut self.intern_table, host)
+                    .map(|value| to_value(&value, &self.intern_table))
+            })
+            .collect()
+    }
+}
+
+fn to_value<B: Backend, H: BuildHasher>(expr: &Expr<B>, intern_table: &InternTable<B, H>) -> Value {
+    match expr {
+        Expr::Symbol(s) => Value::Symbol(intern_table.intern_table.resolve(*s).unwrap().to_owned()),
+        Expr::Number(n) => Value::Number(*n),
+        Expr::List(l) => Value::List(l.iter().map(|e| to_value(e, intern_table)).collect()),
+        Expr::Bool(b) => Value::Bool(*b),
+        Expr::Str(s) => Value::Str(s.clone()),
+    }
+}
+
+fn from_value<B: Backend, H: BuildHasher>(
+    value: Value,
+    intern_table: &mut InternTable<B, H>,
+) -> Expr<B> {
+    match value {
+        Value::Number(n) => Expr::Number(n),
+        Value::Bool(b) => Expr::Bool(b),
+        Value::Str(s) => Expr::Str(s),
+        Value::Symbol(s) => Expr::Symbol(intern_table.intern_table.get_or_intern(s)),
+        Value::List(l) => Expr::List(l.into_iter().map(|v| from_value(v, intern_table)).collect()),
+    }
+}
+
+fn tokenize<B: Backend, H: BuildHasher>(
+    expr: &str,
+    intern_table: &mut InternTable<B, H>,
+) -> Result<Vec<Token<B>>, Vec<Err>> {
+    let mut tokens = vec![];
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Symbol(intern_table.open_paren)),
+            ')' => tokens.push(Token::Symbol(intern_table.close_paren)),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(c),
+                            None => return Err(vec![Err::UnterminatedString]),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err(vec![Err::UnterminatedString]),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            ';' => {
+                //Comment to the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut atom = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';') {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Symbol(intern_table.intern_table.get_or_intern(atom)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse<B: Backend, H: BuildHasher>(
+    token_stream: Vec<Token<B>>,
+    intern_table: &mut InternTable<B, H>,
+) -> Result<Vec<Expr<B>>, Vec<Err>> {
+    let mut stack = vec![];
+    let mut curr = Vec::with_capacity(token_stream.len());
+
+    let mut errs = vec![];
+
+    for token in token_stream {
+        let symbol = match token {
+            Token::Str(s) => {
+                curr.push(Expr::Str(s));
+                continue;
+            }
+            Token::Symbol(symbol) => symbol,
+        };
+        if symbol == intern_table.open_paren {
+            stack.push(curr);
+            curr = vec![];
+        } else if symbol == intern_table.close_paren {
+            if let Some(mut old) = stack.pop() {
+                old.push(Expr::List(curr));
+                curr = old;
+            } else {
+                errs.push(Err::UnmatchedCloser)
+            }
+        } else if symbol == intern_table.true_symbol {
+            curr.push(Expr::Bool(true))
+        } else if symbol == intern_table.false_symbol {
+            curr.push(Expr::Bool(false))
+        } else {
+            let str = intern_table.intern_table.resolve(symbol).unwrap();
+            match str.parse() {
+                Ok(num) => curr.push(Expr::Number(num)),
+                Result::Err(_) => curr.push(Expr::Symbol(symbol)),
+            }
+        }
+    }
+
+    if let Some(depth) = NonZeroUsize::new(stack.len()) {
+        errs.push(Err::UnmatchedOpeners { depth })
+    }
+
+    if !errs.is_empty() {
+        Err(errs)
+    } else {
+        Ok(curr)
+    }
+}
+
+fn eval<B: Backend, H: BuildHasher>(
+    expr: &Expr<B>,
+    intern_table: &mut InternTable<B, H>,
+    host: &mut dyn Host,
+) -> Result<Expr<B>, String>
+where
+    B::Symbol: Clone,
+{
+    match expr {
+        Expr::List(list) => call_fn(list, intern_table, host),
+        _ => Ok(expr.clone()),
+    }
+}
+
+fn call_fn<B: Backend, H: BuildHasher>(
+    list: &[Expr<B>],
+    intern_table: &mut InternTable<B, H>,
+    host: &mut dyn Host,
+) -> Result<Expr<B>, String> {
+    //if only evaluates the branch it takes, everything else gets all its
+    //arguments evaluated up front
+    if let [Expr::Symbol(sym), args @ ..] = list {
+        if *sym == intern_table.if_symbol {
+            return if let [cond, then, otherwise] = args {
+                if let Expr::Bool(b) = eval(cond, intern_table, host)? {
+                    eval(if b { then } else { otherwise }, intern_table, host)
+                } else {
+                    Err("Non boolean condition to if statement".to_owned())
+                }
+            } else {
+                Err(format!("Expected 3 args found {} args", args.len()).to_string())
+            };
+        }
+    }
+    let mut iter = list
+        .iter()
+        .map(|e| eval(e, intern_table, host))
+        .collect::<Vec<_>>()
+        .into_iter();
+    if let Expr::Symbol(sym) = iter.next().unwrap_or(Err("called empty list".to_owned()))? {
+        if sym == intern_table.add_symbol {
+            let mut sum = 0;
+            for elem in iter {
+                let elem = elem?;
+                if let Expr::Number(num) = elem {
+                    sum += num;
+                } else if let Expr::List(_) = elem {
+                    if let Expr::Number(num) = eval(&elem, intern_table, host)? {
+                        sum += num;
+                    } else {
+                        return Err("Non number elem in math call".to_string());
+                    }
+                } else {
+                    return Err("Non number elem in math function".to_string());
+                }
+            }
+            Ok(Expr::Number(sum))
+        } else if sym == intern_table.sub_symbol {
+            match iter.len() {
+                1 => {
+                    let elem = iter
+                        .next()
+                        .expect("iter.len is incoherent with actual length?");
+                    if let Expr::Number(n) = elem? {
+                        Ok(Expr::Number(-n))
+                    } else {
+                        Err("Cannot negate a non-number".to_owned())
+                    }
+                }
+                _ => {
+                    let elem = iter
+                        .next()
+                        .expect("iter.len is incoherent with actual length?");
+                    if let Expr::Number(mut res) = elem? {
+                        for elem in iter {
+                            if let Expr::Number(n) = elem? {
+                                res -= n;
+                            } else {
+                                Err("Non number elem in math call")?
+                            }
+                        }
+                        Ok(Expr::Number(res))
+                    } else {
+                        Err("Non number elem in math call".to_owned())
+                    }
+                }
+            }
+        } else if sym == intern_table.div_symbol {
+            let elem = iter
+                .next()
+                .unwrap_or(Err("Called div on an empty list".to_owned()));
+            if let Expr::Number(mut res) = elem? {
+                for elem in iter {
+                    if let Expr::Number(n) = elem? {
+                        if n == 0 {
+                            return Err("Divide by zero!".to_owned());
+                        } else {
+                            res /= n;
+                        }
+                    } else {
+                        return Err("Non number in math function".to_owned());
+                    }
+                }
+                Ok(Expr::Number(res))
+            } else {
+                Err("Non number in math function".to_owned())
+            }
+        } else if sym == intern_table.mul_symbol {
+            let mut res = 1;
+
+            for elem in iter {
+                if let Expr::Number(n) = elem? {
+                    res *= n;
+                } else {
+                    return Err("Non number in math function".to_owned());
+                }
+            }
+            Ok(Expr::Number(res))
+        } else if sym == intern_table.list_symbol {
+            Ok(Expr::List(iter.collect::<Result<_, _>>()?))
+        } else if sym == intern_table.progn_symbol {
+            iter.last().unwrap_or(Ok(Expr::List(vec![])))
+        } else {
+            let args = iter
+                .map(|elem| elem.map(|e| to_value(&e, intern_table)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let name = intern_table.intern_table.resolve(sym).unwrap().to_owned();
+            match host.call(&name, args) {
+                Some(result) => result.map(|value| from_value(value, intern_table)),
+                None => Err(format!("Unknown op {name}")),
+            }
+        }
+    } else if !list.is_empty() {
+        Err("Nonsymbol in head positon: Cannot call".to_owned())
+    } else {
+        Err("calling empty list".to_owned())
+    }
+}
@@ -0,0 +1,85 @@
+//The tree-sitter grammar for bunlang, see grammar.js
+
+use tree_sitter_language::LanguageFn;
+
+extern "C" {
+    fn tree_sitter_bunlang() -> *const ();
+}
+
+pub const LANGUAGE: LanguageFn = unsafe { LanguageFn::from_raw(tree_sitter_bunlang) };
+
+pub const HIGHLIGHTS_QUERY: &str = include_str!("../../queries/highlights.scm");
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+    use tree_sitter::{Language, Parser, Query};
+
+    fn language() -> Language {
+        super::LANGUAGE.into()
+    }
+
+    fn is_rule(line: &str, c: char) -> bool {
+        line.len() >= 3 && line.chars().all(|l| l == c)
+    }
+
+    //The examples in test/corpus as (name, source, tree), in the format
+    //`tree-sitter test` reads: the name between lines of =, the source, a
+    //line of - and the tree it parses to
+    fn corpus() -> Vec<(String, String, String)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/corpus");
+        let mut examples = vec![];
+        for entry in fs::read_dir(dir).unwrap() {
+            let contents = fs::read_to_string(entry.unwrap().path()).unwrap();
+            let mut sections = vec![String::new()];
+            for line in contents.lines() {
+                if is_rule(line, '=') {
+                    sections.push(String::new());
+                } else {
+                    let section = sections.last_mut().unwrap();
+                    section.push_str(line);
+                    section.push('\n');
+                }
+            }
+            for example in sections[1..].chunks(2) {
+                let [name, body] = example else {
+                    panic!("{name:?} has no body", name = example[0]);
+                };
+                let lines: Vec<&str> = body.lines().collect();
+                let split = lines.iter().position(|line| is_rule(line, '-')).unwrap();
+                examples.push((
+                    name.trim().to_owned(),
+                    lines[..split].join("\n").trim().to_owned(),
+                    lines[split + 1..].join(" "),
+                ));
+            }
+        }
+        examples
+    }
+
+    //Trees written out over several lines, the way to_sexp has them
+    fn normalize(tree: &str) -> String {
+        tree.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace("( ", "(")
+            .replace(" )", ")")
+    }
+
+    #[test]
+    fn parses_the_corpus() {
+        let mut parser = Parser::new();
+        parser.set_language(&language()).unwrap();
+        let examples = corpus();
+        assert!(!examples.is_empty());
+        for (name, source, expected) in examples {
+            let tree = parser.parse(&source, None).unwrap();
+            assert_eq!(tree.root_node().to_sexp(), normalize(&expected), "{name}");
+        }
+    }
+
+    #[test]
+    fn highlights_query_compiles() {
+        Query::new(&language(), super::HIGHLIGHTS_QUERY).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+//The same syntax bunlang's own tokenizer reads. src/parser.c is written
+//by hand from this, keep the two in sync or regenerate it with
+//`tree-sitter generate`. test/corpus has the trees it should come up with,
+//which both `cargo test` and `tree-sitter test` check.
+
+module.exports = grammar({
+  name: 'bunlang',
+
+  extras: $ => [/\s/, $.comment],
+
+  rules: {
+    source_file: $ => repeat($._form),
+
+    _form: $ => choice($.list, $.string, $.number, $.boolean, $.symbol),
+
+    list: $ => seq('(', repeat($._form), ')'),
+
+    string: _ => token(seq('"', repeat(choice(/[^"\\]/, /\\(.|\n)/)), '"')),
+
+    //Atoms that parse as an i64, #t and #f, everything else is a symbol
+    number: _ => /[+-]?[0-9]+/,
+
+    boolean: _ => /#[tf]/,
+
+    symbol: _ => /[^\s()";]+/,
+
+    comment: _ => token(seq(';', /.*/)),
+  },
+});
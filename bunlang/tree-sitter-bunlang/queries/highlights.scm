@@ -0,0 +1,11 @@
+(comment) @comment
+(string) @string
+(number) @number
+(boolean) @constant
+
+;The head of a list is what gets called
+(list
+  .
+  (symbol) @function)
+
+["(" ")"] @punctuation.bracket
@@ -0,0 +1,447 @@
+/*
+ * Written by hand from grammar.js in the layout `tree-sitter generate`
+ * produces, keep the two in sync. Regenerating replaces this file.
+ *
+ * Repeats are expanded the way the generator does it,
+ * source_file_repeat1 -> (_form | repeat) (_form | repeat), shared by
+ * source_file and list.
+ */
+
+#include "tree_sitter/parser.h"
+
+#if defined(__GNUC__) || defined(__clang__)
+#pragma GCC diagnostic ignored "-Wmissing-field-initializers"
+#endif
+
+#define LANGUAGE_VERSION 14
+#define STATE_COUNT 11
+#define LARGE_STATE_COUNT 7
+#define SYMBOL_COUNT 12
+#define ALIAS_COUNT 0
+#define TOKEN_COUNT 8
+#define EXTERNAL_TOKEN_COUNT 0
+#define FIELD_COUNT 0
+#define MAX_ALIAS_SEQUENCE_LENGTH 3
+#define PRODUCTION_ID_COUNT 1
+
+enum ts_symbol_identifiers {
+  anon_sym_LPAREN = 1,
+  anon_sym_RPAREN = 2,
+  sym_string = 3,
+  sym_number = 4,
+  sym_boolean = 5,
+  sym_symbol = 6,
+  sym_comment = 7,
+  sym_source_file = 8,
+  sym__form = 9,
+  sym_list = 10,
+  aux_sym_source_file_repeat1 = 11,
+};
+
+static const char * const ts_symbol_names[] = {
+  [ts_builtin_sym_end] = "end",
+  [anon_sym_LPAREN] = "(",
+  [anon_sym_RPAREN] = ")",
+  [sym_string] = "string",
+  [sym_number] = "number",
+  [sym_boolean] = "boolean",
+  [sym_symbol] = "symbol",
+  [sym_comment] = "comment",
+  [sym_source_file] = "source_file",
+  [sym__form] = "_form",
+  [sym_list] = "list",
+  [aux_sym_source_file_repeat1] = "source_file_repeat1",
+};
+
+static const TSSymbol ts_symbol_map[] = {
+  [ts_builtin_sym_end] = ts_builtin_sym_end,
+  [anon_sym_LPAREN] = anon_sym_LPAREN,
+  [anon_sym_RPAREN] = anon_sym_RPAREN,
+  [sym_string] = sym_string,
+  [sym_number] = sym_number,
+  [sym_boolean] = sym_boolean,
+  [sym_symbol] = sym_symbol,
+  [sym_comment] = sym_comment,
+  [sym_source_file] = sym_source_file,
+  [sym__form] = sym__form,
+  [sym_list] = sym_list,
+  [aux_sym_source_file_repeat1] = aux_sym_source_file_repeat1,
+};
+
+static const TSSymbolMetadata ts_symbol_metadata[] = {
+  [ts_builtin_sym_end] = {
+    .visible = false,
+    .named = true,
+  },
+  [anon_sym_LPAREN] = {
+    .visible = true,
+    .named = false,
+  },
+  [anon_sym_RPAREN] = {
+    .visible = true,
+    .named = false,
+  },
+  [sym_string] = {
+    .visible = true,
+    .named = true,
+  },
+  [sym_number] = {
+    .visible = true,
+    .named = true,
+  },
+  [sym_boolean] = {
+    .visible = true,
+    .named = true,
+  },
+  [sym_symbol] = {
+    .visible = true,
+    .named = true,
+  },
+  [sym_comment] = {
+    .visible = true,
+    .named = true,
+  },
+  [sym_source_file] = {
+    .visible = true,
+    .named = true,
+  },
+  [sym__form] = {
+    .visible = false,
+    .named = true,
+  },
+  [sym_list] = {
+    .visible = true,
+    .named = true,
+  },
+  [aux_sym_source_file_repeat1] = {
+    .visible = false,
+    .named = false,
+  },
+};
+
+static const TSSymbol ts_alias_sequences[PRODUCTION_ID_COUNT][MAX_ALIAS_SEQUENCE_LENGTH] = {
+  [0] = {0},
+};
+
+static const uint16_t ts_non_terminal_alias_map[] = {
+  0,
+};
+
+static const TSStateId ts_primary_state_ids[STATE_COUNT] = {
+  [0] = 0,
+  [1] = 1,
+  [2] = 2,
+  [3] = 3,
+  [4] = 4,
+  [5] = 5,
+  [6] = 6,
+  [7] = 7,
+  [8] = 8,
+  [9] = 9,
+  [10] = 10,
+};
+
+/* The same characters as Rust's char::is_whitespace */
+static inline bool is_space(int32_t c) {
+  return ('\t' <= c && c <= '\r') ||
+         c == ' ' ||
+         c == 0x85 ||
+         c == 0xa0 ||
+         c == 0x1680 ||
+         (0x2000 <= c && c <= 0x200a) ||
+         c == 0x2028 ||
+         c == 0x2029 ||
+         c == 0x202f ||
+         c == 0x205f ||
+         c == 0x3000;
+}
+
+static inline bool is_atom(bool eof, int32_t c) {
+  return !eof &&
+         !is_space(c) &&
+         c != '(' &&
+         c != ')' &&
+         c != '"' &&
+         c != ';';
+}
+
+static bool ts_lex(TSLexer *lexer, TSStateId state) {
+  START_LEXER();
+  eof = lexer->eof(lexer);
+  switch (state) {
+    case 0:
+      if (eof) ADVANCE(1);
+      ADVANCE_MAP(
+        '"', 4,
+        '#', 10,
+        '(', 2,
+        ')', 3,
+        '+', 8,
+        '-', 8,
+        ';', 7,
+      );
+      if (is_space(lookahead)) SKIP(0);
+      if (('0' <= lookahead && lookahead <= '9')) ADVANCE(9);
+      if (is_atom(eof, lookahead)) ADVANCE(12);
+      END_STATE();
+    case 1:
+      ACCEPT_TOKEN(ts_builtin_sym_end);
+      END_STATE();
+    case 2:
+      ACCEPT_TOKEN(anon_sym_LPAREN);
+      END_STATE();
+    case 3:
+      ACCEPT_TOKEN(anon_sym_RPAREN);
+      END_STATE();
+    case 4:
+      if (eof) END_STATE();
+      if (lookahead == '"') ADVANCE(6);
+      if (lookahead == '\\') ADVANCE(5);
+      ADVANCE(4);
+    case 5:
+      if (!eof) ADVANCE(4);
+      END_STATE();
+    case 6:
+      ACCEPT_TOKEN(sym_string);
+      END_STATE();
+    case 7:
+      ACCEPT_TOKEN(sym_comment);
+      if (!eof && lookahead != '\n') ADVANCE(7);
+      END_STATE();
+    case 8:
+      ACCEPT_TOKEN(sym_symbol);
+      if (('0' <= lookahead && lookahead <= '9')) ADVANCE(9);
+      if (is_atom(eof, lookahead)) ADVANCE(12);
+      END_STATE();
+    case 9:
+      ACCEPT_TOKEN(sym_number);
+      if (('0' <= lookahead && lookahead <= '9')) ADVANCE(9);
+      if (is_atom(eof, lookahead)) ADVANCE(12);
+      END_STATE();
+    case 10:
+      ACCEPT_TOKEN(sym_symbol);
+      if (lookahead == 'f' ||
+          lookahead == 't') ADVANCE(11);
+      if (is_atom(eof, lookahead)) ADVANCE(12);
+      END_STATE();
+    case 11:
+      ACCEPT_TOKEN(sym_boolean);
+      if (is_atom(eof, lookahead)) ADVANCE(12);
+      END_STATE();
+    case 12:
+      ACCEPT_TOKEN(sym_symbol);
+      if (is_atom(eof, lookahead)) ADVANCE(12);
+      END_STATE();
+    default:
+      return false;
+  }
+}
+
+static const TSLexMode ts_lex_modes[STATE_COUNT] = {
+  [0] = {.lex_state = 0},
+  [1] = {.lex_state = 0},
+  [2] = {.lex_state = 0},
+  [3] = {.lex_state = 0},
+  [4] = {.lex_state = 0},
+  [5] = {.lex_state = 0},
+  [6] = {.lex_state = 0},
+  [7] = {.lex_state = 0},
+  [8] = {.lex_state = 0},
+  [9] = {.lex_state = 0},
+  [10] = {.lex_state = 0},
+};
+
+static const uint16_t ts_parse_table[LARGE_STATE_COUNT][SYMBOL_COUNT] = {
+  [0] = {
+    [ts_builtin_sym_end] = ACTIONS(1),
+    [anon_sym_LPAREN] = ACTIONS(1),
+    [anon_sym_RPAREN] = ACTIONS(1),
+    [sym_string] = ACTIONS(1),
+    [sym_number] = ACTIONS(1),
+    [sym_boolean] = ACTIONS(1),
+    [sym_symbol] = ACTIONS(1),
+    [sym_comment] = ACTIONS(3),
+  },
+  [1] = {
+    [sym_source_file] = STATE(10),
+    [sym__form] = STATE(2),
+    [sym_list] = STATE(7),
+    [aux_sym_source_file_repeat1] = STATE(2),
+    [ts_builtin_sym_end] = ACTIONS(5),
+    [anon_sym_LPAREN] = ACTIONS(7),
+    [sym_string] = ACTIONS(9),
+    [sym_number] = ACTIONS(9),
+    [sym_boolean] = ACTIONS(9),
+    [sym_symbol] = ACTIONS(9),
+    [sym_comment] = ACTIONS(3),
+  },
+  [2] = {
+    [sym__form] = STATE(3),
+    [sym_list] = STATE(7),
+    [aux_sym_source_file_repeat1] = STATE(3),
+    [ts_builtin_sym_end] = ACTIONS(11),
+    [anon_sym_LPAREN] = ACTIONS(7),
+    [sym_string] = ACTIONS(9),
+    [sym_number] = ACTIONS(9),
+    [sym_boolean] = ACTIONS(9),
+    [sym_symbol] = ACTIONS(9),
+    [sym_comment] = ACTIONS(3),
+  },
+  [3] = {
+    [sym__form] = STATE(3),
+    [sym_list] = STATE(7),
+    [aux_sym_source_file_repeat1] = STATE(3),
+    [ts_builtin_sym_end] = ACTIONS(13),
+    [anon_sym_LPAREN] = ACTIONS(15),
+    [sym_string] = ACTIONS(18),
+    [sym_number] = ACTIONS(18),
+    [sym_boolean] = ACTIONS(18),
+    [sym_symbol] = ACTIONS(18),
+    [sym_comment] = ACTIONS(3),
+  },
+  [4] = {
+    [sym__form] = STATE(5),
+    [sym_list] = STATE(7),
+    [aux_sym_source_file_repeat1] = STATE(5),
+    [anon_sym_LPAREN] = ACTIONS(7),
+    [anon_sym_RPAREN] = ACTIONS(21),
+    [sym_string] = ACTIONS(9),
+    [sym_number] = ACTIONS(9),
+    [sym_boolean] = ACTIONS(9),
+    [sym_symbol] = ACTIONS(9),
+    [sym_comment] = ACTIONS(3),
+  },
+  [5] = {
+    [sym__form] = STATE(6),
+    [sym_list] = STATE(7),
+    [aux_sym_source_file_repeat1] = STATE(6),
+    [anon_sym_LPAREN] = ACTIONS(7),
+    [anon_sym_RPAREN] = ACTIONS(23),
+    [sym_string] = ACTIONS(9),
+    [sym_number] = ACTIONS(9),
+    [sym_boolean] = ACTIONS(9),
+    [sym_symbol] = ACTIONS(9),
+    [sym_comment] = ACTIONS(3),
+  },
+  [6] = {
+    [sym__form] = STATE(6),
+    [sym_list] = STATE(7),
+    [aux_sym_source_file_repeat1] = STATE(6),
+    [anon_sym_LPAREN] = ACTIONS(15),
+    [anon_sym_RPAREN] = ACTIONS(13),
+    [sym_string] = ACTIONS(18),
+    [sym_number] = ACTIONS(18),
+    [sym_boolean] = ACTIONS(18),
+    [sym_symbol] = ACTIONS(18),
+    [sym_comment] = ACTIONS(3),
+  },
+};
+
+static const uint16_t ts_small_parse_table[] = {
+  [0] = 2,
+    ACTIONS(3), 1,
+      sym_comment,
+    ACTIONS(25), 7,
+      ts_builtin_sym_end,
+      anon_sym_LPAREN,
+      anon_sym_RPAREN,
+      sym_string,
+      sym_number,
+      sym_boolean,
+      sym_symbol,
+  [13] = 2,
+    ACTIONS(3), 1,
+      sym_comment,
+    ACTIONS(27), 7,
+      ts_builtin_sym_end,
+      anon_sym_LPAREN,
+      anon_sym_RPAREN,
+      sym_string,
+      sym_number,
+      sym_boolean,
+      sym_symbol,
+  [26] = 2,
+    ACTIONS(3), 1,
+      sym_comment,
+    ACTIONS(29), 7,
+      ts_builtin_sym_end,
+      anon_sym_LPAREN,
+      anon_sym_RPAREN,
+      sym_string,
+      sym_number,
+      sym_boolean,
+      sym_symbol,
+  [39] = 2,
+    ACTIONS(3), 1,
+      sym_comment,
+    ACTIONS(31), 1,
+      ts_builtin_sym_end,
+};
+
+static const uint32_t ts_small_parse_table_map[] = {
+  [SMALL_STATE(7)] = 0,
+  [SMALL_STATE(8)] = 13,
+  [SMALL_STATE(9)] = 26,
+  [SMALL_STATE(10)] = 39,
+};
+
+static const TSParseActionEntry ts_parse_actions[] = {
+  [0] = {.entry = {.count = 0, .reusable = false}},
+  [1] = {.entry = {.count = 1, .reusable = false}}, RECOVER(),
+  [3] = {.entry = {.count = 1, .reusable = true}}, SHIFT_EXTRA(),
+  [5] = {.entry = {.count = 1, .reusable = true}}, REDUCE(sym_source_file, 0, 0, 0),
+  [7] = {.entry = {.count = 1, .reusable = true}}, SHIFT(4),
+  [9] = {.entry = {.count = 1, .reusable = true}}, SHIFT(7),
+  [11] = {.entry = {.count = 1, .reusable = true}}, REDUCE(sym_source_file, 1, 0, 0),
+  [13] = {.entry = {.count = 1, .reusable = true}}, REDUCE(aux_sym_source_file_repeat1, 2, 0, 0),
+  [15] = {.entry = {.count = 2, .reusable = true}}, REDUCE(aux_sym_source_file_repeat1, 2, 0, 0), SHIFT_REPEAT(4),
+  [18] = {.entry = {.count = 2, .reusable = true}}, REDUCE(aux_sym_source_file_repeat1, 2, 0, 0), SHIFT_REPEAT(7),
+  [21] = {.entry = {.count = 1, .reusable = true}}, SHIFT(8),
+  [23] = {.entry = {.count = 1, .reusable = true}}, SHIFT(9),
+  [25] = {.entry = {.count = 1, .reusable = true}}, REDUCE(sym__form, 1, 0, 0),
+  [27] = {.entry = {.count = 1, .reusable = true}}, REDUCE(sym_list, 2, 0, 0),
+  [29] = {.entry = {.count = 1, .reusable = true}}, REDUCE(sym_list, 3, 0, 0),
+  [31] = {.entry = {.count = 1, .reusable = true}},  ACCEPT_INPUT(),
+};
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+#ifdef TREE_SITTER_HIDE_SYMBOLS
+#define TS_PUBLIC
+#elif defined(_WIN32)
+#define TS_PUBLIC __declspec(dllexport)
+#else
+#define TS_PUBLIC __attribute__((visibility("default")))
+#endif
+
+TS_PUBLIC const TSLanguage *tree_sitter_bunlang(void) {
+  static const TSLanguage language = {
+    .version = LANGUAGE_VERSION,
+    .symbol_count = SYMBOL_COUNT,
+    .alias_count = ALIAS_COUNT,
+    .token_count = TOKEN_COUNT,
+    .external_token_count = EXTERNAL_TOKEN_COUNT,
+    .state_count = STATE_COUNT,
+    .large_state_count = LARGE_STATE_COUNT,
+    .production_id_count = PRODUCTION_ID_COUNT,
+    .field_count = FIELD_COUNT,
+    .max_alias_sequence_length = MAX_ALIAS_SEQUENCE_LENGTH,
+    .parse_table = &ts_parse_table[0][0],
+    .small_parse_table = ts_small_parse_table,
+    .small_parse_table_map = ts_small_parse_table_map,
+    .parse_actions = ts_parse_actions,
+    .symbol_names = ts_symbol_names,
+    .symbol_metadata = ts_symbol_metadata,
+    .public_symbol_map = ts_symbol_map,
+    .alias_map = ts_non_terminal_alias_map,
+    .alias_sequences = &ts_alias_sequences[0][0],
+    .lex_modes = ts_lex_modes,
+    .lex_fn = ts_lex,
+    .primary_state_ids = ts_primary_state_ids,
+  };
+  return &language;
+}
+#ifdef __cplusplus
+}
+#endif
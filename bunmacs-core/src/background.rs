@@ -0,0 +1,46 @@
+//Work that runs off the editor thread, like project-search. It goes on the
+//async runtime the frontend starts and sends its results back over
+//channels the editor drains in Editor::process_background_events.
+
+use std::{
+    fmt::{self, Debug},
+    sync::Arc,
+};
+
+use tokio::runtime::Handle;
+
+//Called from other threads when there's something for the editor to pick
+//up, so the frontend can wake its event loop
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Background {
+    runtime: Handle,
+    waker: Option<Waker>,
+}
+
+impl Debug for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Background")
+            .field("waker", &self.waker.is_some())
+            .finish()
+    }
+}
+
+impl Background {
+    pub fn new(runtime: Handle, waker: Option<Waker>) -> Self {
+        Background { runtime, waker }
+    }
+
+    pub fn runtime(&self) -> &Handle {
+        &self.runtime
+    }
+
+    //Frontends without a waker poll while Editor::has_background_work says
+    //something is still going
+    pub fn wake(&self) {
+        if let Some(waker) = &self.waker {
+            waker();
+        }
+    }
+}
@@ -0,0 +1,676 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use bunlang::Value;
+use ropey::Rope;
+use tree_sitter::{InputEdit, Point};
+
+use crate::{
+    fileio::{self, Encoding, FileFormat},
+    syntax::{self, Syntax, SyntaxLanguage},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BufferId(pub(crate) u64);
+
+//A position that moves with edits, like an Emacs marker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarkerId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OverlayId(pub(crate) usize);
+
+//A face laid over a stretch of the buffer without being part of the text.
+//Text inserted at the start ends up inside, at the end outside, same as
+//Emacs' default overlays.
+#[derive(Debug)]
+struct Overlay {
+    start: MarkerId,
+    end: MarkerId,
+    face: String,
+    //Higher priorities win where overlays overlap
+    priority: i64,
+    //Shown in the left fringe beside the overlay's first line, like an
+    //Emacs left-fringe display spec: the symbol and its face
+    fringe: Option<(char, String)>,
+}
+
+#[derive(Debug)]
+pub struct VisitedFile {
+    pub path: PathBuf,
+    pub format: FileFormat,
+    //Emacs style: only the first save of a session makes a backup so the
+    //backup holds what the file looked like before we touched it
+    backed_up: bool,
+}
+
+//Where a char is as a line and the column within it, counted every way a
+//language server might want it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextPosition {
+    pub line: usize,
+    pub chars: usize,
+    pub bytes: usize,
+    pub utf16: usize,
+}
+
+pub fn text_position(text: &Rope, pos: usize) -> TextPosition {
+    let pos = pos.min(text.len_chars());
+    let line = text.char_to_line(pos);
+    let line_start = text.line_to_char(line);
+    TextPosition {
+        line,
+        chars: pos - line_start,
+        bytes: text.char_to_byte(pos) - text.char_to_byte(line_start),
+        utf16: text.char_to_utf16_cu(pos) - text.char_to_utf16_cu(line_start),
+    }
+}
+
+//An edit with the positions it had in the text before it, the way
+//incremental document sync describes them
+#[derive(Debug, Clone)]
+pub struct TextChange {
+    pub start: TextPosition,
+    pub end: TextPosition,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct Buffer {
+    id: BufferId,
+    name: String,
+    text: Rope,
+    file: Option<VisitedFile>,
+    //Bumped on every edit. The buffer is modified when this disagrees with
+    //the tick of the last save/revert.
+    change_tick: u64,
+    save_tick: u64,
+    //Char index
+    point: usize,
+    mark: Option<usize>,
+    //Transient mark mode, the region only counts while the mark is active
+    mark_active: bool,
+    //Freed slots are None and get reused
+    markers: Vec<Option<usize>>,
+    //The `face` text property, sorted and non-overlapping. Properties stick
+    //to their chars, text inserted in the middle gets none.
+    face_properties: Vec<(Range<usize>, String)>,
+    overlays: Vec<Option<Overlay>>,
+    //Only for buffers in a language there's a grammar for
+    syntax: Option<Syntax>,
+    major_mode: String,
+    //Buffer-local minor modes that are on, in the order they were turned on
+    minor_modes: Vec<String>,
+    //Values from setq-local, all cleared when the major mode changes
+    local_variables: HashMap<String, Value>,
+    //Edits since the last take_changes, while something wants to hear
+    //about them
+    changes: Option<Vec<TextChange>>,
+}
+
+//Where a position ends up after `len` chars are inserted at `at`. Positions
+//at the insertion point only move if `advance` is set, like Emacs markers.
+fn adjust_for_insert(pos: usize, at: usize, len: usize, advance: bool) -> usize {
+    if pos > at || (pos == at && advance) {
+        pos + len
+    } else {
+        pos
+    }
+}
+
+fn adjust_for_delete(pos: usize, start: usize, end: usize) -> usize {
+    if pos >= end {
+        pos - (end - start)
+    } else if pos > start {
+        start
+    } else {
+        pos
+    }
+}
+
+impl Buffer {
+    pub(crate) fn new(id: BufferId, name: String) -> Self {
+        Buffer {
+            id,
+            name,
+            text: Rope::new(),
+            file: None,
+            change_tick: 0,
+            save_tick: 0,
+            point: 0,
+            mark: None,
+            mark_active: false,
+            markers: vec![],
+            face_properties: vec![],
+            overlays: vec![],
+            syntax: None,
+            major_mode: crate::mode::FUNDAMENTAL_MODE.to_owned(),
+            minor_modes: vec![],
+            local_variables: HashMap::new(),
+            changes: None,
+        }
+    }
+
+    pub(crate) fn visit(id: BufferId, name: String, path: PathBuf) -> io::Result<Self> {
+        let mut buffer = Buffer::new(id, name);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let (text, format) = fileio::decode(&bytes);
+                buffer.text = Rope::from(text);
+                buffer.file = Some(VisitedFile {
+                    path,
+                    format,
+                    backed_up: false,
+                });
+            }
+            //Visiting a file that doesn't exist yet is how you create one
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                buffer.file = Some(VisitedFile {
+                    path,
+                    format: FileFormat::default(),
+                    backed_up: true,
+                });
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(buffer)
+    }
+
+    pub fn id(&self) -> BufferId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    pub fn file(&self) -> Option<&VisitedFile> {
+        self.file.as_ref()
+    }
+
+    pub fn file_path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|f| f.path.as_path())
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.change_tick != self.save_tick
+    }
+
+    pub fn change_tick(&self) -> u64 {
+        self.change_tick
+    }
+
+    pub fn point(&self) -> usize {
+        self.point
+    }
+
+    pub fn set_point(&mut self, point: usize) {
+        self.point = point.min(self.text.len_chars());
+    }
+
+    pub fn mark(&self) -> Option<usize> {
+        self.mark
+    }
+
+    pub fn set_mark(&mut self, mark: usize) {
+        self.mark = Some(mark.min(self.text.len_chars()));
+        self.mark_active = true;
+    }
+
+    pub fn deactivate_mark(&mut self) {
+        self.mark_active = false;
+    }
+
+    //The active region as a sorted char range
+    pub fn region(&self) -> Option<(usize, usize)> {
+        match self.mark {
+            Some(mark) if self.mark_active => Some((mark.min(self.point), mark.max(self.point))),
+            _ => None,
+        }
+    }
+
+    pub fn make_marker(&mut self, pos: usize) -> MarkerId {
+        let pos = Some(pos.min(self.text.len_chars()));
+        match self.markers.iter().position(Option::is_none) {
+            Some(free) => {
+                self.markers[free] = pos;
+                MarkerId(free)
+            }
+            None => {
+                self.markers.push(pos);
+                MarkerId(self.markers.len() - 1)
+            }
+        }
+    }
+
+    pub fn marker(&self, id: MarkerId) -> Option<usize> {
+        self.markers.get(id.0).copied().flatten()
+    }
+
+    pub fn set_marker(&mut self, id: MarkerId, pos: usize) {
+        let pos = pos.min(self.text.len_chars());
+        if let Some(slot @ Some(_)) = self.markers.get_mut(id.0) {
+            *slot = Some(pos);
+        }
+    }
+
+    pub fn delete_marker(&mut self, id: MarkerId) {
+        if let Some(slot) = self.markers.get_mut(id.0) {
+            *slot = None;
+        }
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.text.len_chars()
+    }
+
+    pub fn syntax(&self) -> Option<&Syntax> {
+        self.syntax.as_ref()
+    }
+
+    pub fn major_mode(&self) -> &str {
+        &self.major_mode
+    }
+
+    //Switching modes throws away the local variables of the old one and
+    //parses the text again in `language`. Returns the names of the
+    //variables it threw away so their watchers can run.
+    pub(crate) fn set_major_mode(
+        &mut self,
+        mode: &str,
+        language: Option<&'static SyntaxLanguage>,
+    ) -> Vec<String> {
+        self.major_mode = mode.to_owned();
+        self.syntax = language.map(|language| Syntax::new(language, &self.text));
+        self.local_variables.drain().map(|(name, _)| name).collect()
+    }
+
+    pub fn minor_modes(&self) -> &[String] {
+        &self.minor_modes
+    }
+
+    pub(crate) fn minor_modes_mut(&mut self) -> &mut Vec<String> {
+        &mut self.minor_modes
+    }
+
+    pub fn local_variable(&self, name: &str) -> Option<&Value> {
+        self.local_variables.get(name)
+    }
+
+    //Bypasses type checks and watchers, Editor::set_local goes through them
+    pub(crate) fn set_local_variable(&mut self, name: &str, value: Value) {
+        self.local_variables.insert(name.to_owned(), value);
+    }
+
+    pub(crate) fn kill_local_variable(&mut self, name: &str) -> Option<Value> {
+        self.local_variables.remove(name)
+    }
+
+    //Starts or stops keeping the edits for take_changes
+    pub fn track_changes(&mut self, on: bool) {
+        self.changes = on.then(Vec::new);
+    }
+
+    pub fn take_changes(&mut self) -> Vec<TextChange> {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    //Chars `start` to `end` are about to be replaced by `text`
+    fn record_change(&mut self, start: usize, end: usize, text: &str) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(TextChange {
+                start: text_position(&self.text, start),
+                end: text_position(&self.text, end),
+                text: text.to_owned(),
+            });
+        }
+    }
+
+    //Where byte `byte` is, for telling the syntax tree about edits
+    fn byte_point(&self, byte: usize) -> (usize, Point) {
+        (byte, syntax::point(&self.text, byte))
+    }
+
+    //The bytes from `start` to `old_end` were replaced by ones up to
+    //`new_end`, positions from before the edit except `new_end`
+    fn reparse(&mut self, start: (usize, Point), old_end: (usize, Point), new_end: usize) {
+        if let Some(syntax) = &mut self.syntax {
+            let edit = InputEdit {
+                start_byte: start.0,
+                old_end_byte: old_end.0,
+                new_end_byte: new_end,
+                start_position: start.1,
+                old_end_position: old_end.1,
+                new_end_position: syntax::point(&self.text, new_end),
+            };
+            syntax.reparse(&self.text, Some(&edit));
+        }
+    }
+
+    pub fn insert(&mut self, at: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let at = at.min(self.text.len_chars());
+        self.record_change(at, at, text);
+        let start = self.byte_point(self.text.char_to_byte(at));
+        self.text.insert(at, text);
+        self.reparse(start, start, start.0 + text.len());
+        let len = text.chars().count();
+        self.point = adjust_for_insert(self.point, at, len, true);
+        self.mark = self.mark.map(|m| adjust_for_insert(m, at, len, false));
+        for marker in self.markers.iter_mut().flatten() {
+            *marker = adjust_for_insert(*marker, at, len, false);
+        }
+        let mut properties = Vec::with_capacity(self.face_properties.len() + 1);
+        for (range, face) in self.face_properties.drain(..) {
+            if range.start < at && at < range.end {
+                properties.push((range.start..at, face.clone()));
+                properties.push((at + len..range.end + len, face));
+            } else {
+                let start = adjust_for_insert(range.start, at, len, true);
+                let end = adjust_for_insert(range.end, at, len, false);
+                properties.push((start..end, face));
+            }
+        }
+        self.face_properties = properties;
+        self.change_tick += 1;
+    }
+
+    pub fn delete(&mut self, start: usize, end: usize) {
+        let end = end.min(self.text.len_chars());
+        if start >= end {
+            return;
+        }
+        self.record_change(start, end, "");
+        let old_end = self.byte_point(self.text.char_to_byte(end));
+        let start_point = self.byte_point(self.text.char_to_byte(start));
+        self.text.remove(start..end);
+        self.reparse(start_point, old_end, start_point.0);
+        self.point = adjust_for_delete(self.point, start, end);
+        self.mark = self.mark.map(|m| adjust_for_delete(m, start, end));
+        for marker in self.markers.iter_mut().flatten() {
+            *marker = adjust_for_delete(*marker, start, end);
+        }
+        for (range, _) in &mut self.face_properties {
+            *range = adjust_for_delete(range.start, start, end)
+                ..adjust_for_delete(range.end, start, end);
+        }
+        self.face_properties.retain(|(range, _)| !range.is_empty());
+        self.change_tick += 1;
+    }
+
+    //Like Emacs' `put-text-property` with `face`. Doesn't count as an edit.
+    pub fn put_face(&mut self, start: usize, end: usize, face: &str) {
+        self.remove_faces(start, end);
+        let end = end.min(self.text.len_chars());
+        if start >= end {
+            return;
+        }
+        let i = self
+            .face_properties
+            .partition_point(|(range, _)| range.start < start);
+        self.face_properties
+            .insert(i, (start..end, face.to_owned()));
+    }
+
+    //Takes the `face` property off [start, end), splitting runs that stick out
+    pub fn remove_faces(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let mut properties = Vec::with_capacity(self.face_properties.len() + 1);
+        for (range, face) in self.face_properties.drain(..) {
+            if range.end <= start || range.start >= end {
+                properties.push((range, face));
+                continue;
+            }
+            if range.start < start {
+                properties.push((range.start..start, face.clone()));
+            }
+            if range.end > end {
+                properties.push((end..range.end, face));
+            }
+        }
+        self.face_properties = properties;
+    }
+
+    pub fn make_overlay(
+        &mut self,
+        start: usize,
+        end: usize,
+        face: &str,
+        priority: i64,
+    ) -> OverlayId {
+        let overlay = Overlay {
+            start: self.make_marker(start.min(end)),
+            end: self.make_marker(start.max(end)),
+            face: face.to_owned(),
+            priority,
+            fringe: None,
+        };
+        match self.overlays.iter().position(Option::is_none) {
+            Some(free) => {
+                self.overlays[free] = Some(overlay);
+                OverlayId(free)
+            }
+            None => {
+                self.overlays.push(Some(overlay));
+                OverlayId(self.overlays.len() - 1)
+            }
+        }
+    }
+
+    //Where overlay `id` currently is, None once it's deleted
+    pub fn overlay_range(&self, id: OverlayId) -> Option<Range<usize>> {
+        let overlay = self.overlays.get(id.0)?.as_ref()?;
+        Some(self.marker(overlay.start)?..self.marker(overlay.end)?)
+    }
+
+    pub fn move_overlay(&mut self, id: OverlayId, start: usize, end: usize) {
+        if let Some(Some(overlay)) = self.overlays.get(id.0) {
+            let (start_marker, end_marker) = (overlay.start, overlay.end);
+            self.set_marker(start_marker, start.min(end));
+            self.set_marker(end_marker, start.max(end));
+        }
+    }
+
+    pub fn set_overlay_fringe(&mut self, id: OverlayId, symbol: char, face: &str) {
+        if let Some(Some(overlay)) = self.overlays.get_mut(id.0) {
+            overlay.fringe = Some((symbol, face.to_owned()));
+        }
+    }
+
+    //What the fringe shows beside each of `lines` that has anything, by
+    //line. Where overlays share a line the strongest one wins, the same
+    //as with faces.
+    pub fn fringe_marks(&self, lines: Range<usize>) -> HashMap<usize, (char, &str)> {
+        let mut marks: Vec<(i64, usize, usize, char, &str)> = self
+            .overlays
+            .iter()
+            .enumerate()
+            .filter_map(|(i, overlay)| {
+                let overlay = overlay.as_ref()?;
+                let (symbol, face) = overlay.fringe.as_ref()?;
+                let start = self.marker(overlay.start)?;
+                let line = self.text.char_to_line(start.min(self.text.len_chars()));
+                lines
+                    .contains(&line)
+                    .then_some((overlay.priority, i, line, *symbol, face.as_str()))
+            })
+            .collect();
+        marks.sort_by_key(|(priority, i, ..)| (*priority, *i));
+        marks
+            .into_iter()
+            .map(|(_, _, line, symbol, face)| (line, (symbol, face)))
+            .collect()
+    }
+
+    pub fn delete_overlay(&mut self, id: OverlayId) {
+        if let Some(overlay) = self.overlays.get_mut(id.0).and_then(Option::take) {
+            self.delete_marker(overlay.start);
+            self.delete_marker(overlay.end);
+        }
+    }
+
+    //Faces at `pos`, strongest first: overlays by priority, newer ones
+    //winning ties, then the text property
+    pub fn faces_at(&self, pos: usize) -> Vec<&str> {
+        let mut overlays: Vec<(i64, usize, &str)> = self
+            .overlays
+            .iter()
+            .enumerate()
+            .filter_map(|(i, overlay)| {
+                let overlay = overlay.as_ref()?;
+                let range = self.marker(overlay.start)?..self.marker(overlay.end)?;
+                range
+                    .contains(&pos)
+                    .then_some((overlay.priority, i, overlay.face.as_str()))
+            })
+            .collect();
+        overlays.sort_by_key(|(priority, i, _)| std::cmp::Reverse((*priority, *i)));
+        let i = self
+            .face_properties
+            .partition_point(|(range, _)| range.end <= pos);
+        let property = self
+            .face_properties
+            .get(i)
+            .filter(|(range, _)| range.contains(&pos))
+            .map(|(_, face)| face.as_str());
+        overlays
+            .into_iter()
+            .map(|(_, _, face)| face)
+            .chain(property)
+            .collect()
+    }
+
+    //Splits `range` wherever the faces change, with the faces of each piece.
+    //Pieces without any face are left out. `transient` faces aren't part of
+    //the buffer, like search matches, and beat everything in it. Syntax
+    //highlighting comes after the faces_at ones, it's the weakest.
+    pub fn face_runs<'a>(
+        &'a self,
+        range: Range<usize>,
+        transient: &[(Range<usize>, &'a str)],
+    ) -> Vec<(Range<usize>, Vec<&'a str>)> {
+        let highlights = self
+            .syntax
+            .as_ref()
+            .map(|syntax| syntax.highlights(&self.text, range.clone()))
+            .unwrap_or_default();
+        let mut bounds: Vec<usize> = self
+            .face_properties
+            .iter()
+            .flat_map(|(r, _)| [r.start, r.end])
+            .chain(self.overlays.iter().flatten().flat_map(|overlay| {
+                [self.marker(overlay.start), self.marker(overlay.end)]
+                    .into_iter()
+                    .flatten()
+            }))
+            .chain(highlights.iter().flat_map(|(r, _)| [r.start, r.end]))
+            .chain(transient.iter().flat_map(|(r, _)| [r.start, r.end]))
+            .filter(|pos| range.contains(pos))
+            .chain([range.start, range.end])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        bounds
+            .windows(2)
+            .map(|w| {
+                let mut faces: Vec<&str> = transient
+                    .iter()
+                    .filter(|(r, _)| r.contains(&w[0]))
+                    .map(|(_, face)| *face)
+                    .collect();
+                faces.extend(self.faces_at(w[0]));
+                let i = highlights.partition_point(|(r, _)| r.end <= w[0]);
+                if let Some((r, face)) = highlights.get(i) {
+                    if r.contains(&w[0]) {
+                        faces.push(face);
+                    }
+                }
+                (w[0]..w[1], faces)
+            })
+            .filter(|(_, faces)| !faces.is_empty())
+            .collect()
+    }
+
+    pub fn insert_at_point(&mut self, text: &str) {
+        self.insert(self.point, text);
+    }
+
+    pub fn save(&mut self, make_backup: bool) -> io::Result<&Path> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "buffer has no file"))?;
+        let bytes = fileio::encode(&self.text.to_string(), file.format)?;
+        fileio::write_atomic(&file.path, &bytes, make_backup && !file.backed_up)?;
+        file.backed_up = true;
+        //The auto-save is stale now, usually there isn't one
+        let _ = std::fs::remove_file(fileio::auto_save_path(&file.path));
+        self.save_tick = self.change_tick;
+        Ok(&file.path)
+    }
+
+    //Writes the buffer next to its file as fileio::auto_save_path says, or
+    //to the temp dir if it has no file, leaving the file itself alone.
+    //Doesn't count as saving. Text the file's encoding can't hold is
+    //written as UTF-8 rather than lost.
+    pub fn auto_save(&self) -> io::Result<PathBuf> {
+        let (path, format) = match &self.file {
+            Some(file) => (fileio::auto_save_path(&file.path), file.format),
+            None => (
+                fileio::buffer_auto_save_path(&self.name),
+                FileFormat::default(),
+            ),
+        };
+        let text = self.text.to_string();
+        let bytes = fileio::encode(&text, format).or_else(|_| {
+            let encoding = Encoding::Utf8 { bom: false };
+            fileio::encode(&text, FileFormat { encoding, ..format })
+        })?;
+        fileio::write_atomic(&path, &bytes, false)?;
+        Ok(path)
+    }
+
+    //Throws away the buffer contents and rereads the visited file
+    pub fn revert(&mut self) -> io::Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "buffer has no file"))?;
+        let bytes = fs::read(&file.path)?;
+        let (text, format) = fileio::decode(&bytes);
+        file.format = format;
+        let point = self.point;
+        self.record_change(0, self.text.len_chars(), &text);
+        self.text = Rope::from(text);
+        self.point = point.min(self.text.len_chars());
+        self.mark = None;
+        self.mark_active = false;
+        let len = self.text.len_chars();
+        for marker in self.markers.iter_mut().flatten() {
+            *marker = (*marker).min(len);
+        }
+        //The old properties belonged to text that's gone
+        self.face_properties.clear();
+        if let Some(syntax) = &mut self.syntax {
+            syntax.reparse(&self.text, None);
+        }
+        self.change_tick += 1;
+        self.save_tick = self.change_tick;
+        Ok(())
+    }
+}
@@ -0,0 +1,711 @@
+use std::collections::HashMap;
+
+use crate::{
+    buffer::Buffer, dap, editor::Editor, keymap::Keymap, layout, lsp, syntax::Syntax, theme,
+    window::SplitDirection,
+};
+
+pub type Command = fn(&mut Editor) -> Result<(), String>;
+
+pub(crate) fn register_builtins(
+    commands: &mut HashMap<&'static str, Command>,
+    keymap: &mut Keymap,
+) {
+    let builtins: &[(&'static str, Command, &[&str])] = &[
+        ("save-buffer", save_buffer, &["C-x C-s"]),
+        ("revert-buffer", revert_buffer, &["C-x x g"]),
+        ("normal-mode", normal_mode, &[]),
+        ("newline", newline, &["RET"]),
+        ("delete-backward-char", delete_backward_char, &["DEL"]),
+        ("delete-char", delete_char, &["C-d", "<delete>"]),
+        ("forward-char", forward_char, &["C-f", "<right>"]),
+        ("backward-char", backward_char, &["C-b", "<left>"]),
+        ("beginning-of-line", beginning_of_line, &["C-a", "<home>"]),
+        ("end-of-line", end_of_line, &["C-e", "<end>"]),
+        ("next-line", next_line, &["C-n", "<down>"]),
+        ("previous-line", previous_line, &["C-p", "<up>"]),
+        ("scroll-up-command", scroll_up_command, &["C-v", "<next>"]),
+        (
+            "scroll-down-command",
+            scroll_down_command,
+            &["M-v", "<prior>"],
+        ),
+        ("recenter", recenter, &["C-l"]),
+        ("next-sibling-node", next_sibling_node, &["C-M-n"]),
+        ("previous-sibling-node", previous_sibling_node, &["C-M-p"]),
+        ("expand-region", expand_region, &["C-="]),
+        ("isearch-forward", isearch_forward, &["C-s"]),
+        ("isearch-backward", isearch_backward, &["C-r"]),
+        ("isearch-forward-regexp", isearch_forward_regexp, &["C-M-s"]),
+        (
+            "isearch-backward-regexp",
+            isearch_backward_regexp,
+            &["C-M-r"],
+        ),
+        ("query-replace", query_replace, &["M-%"]),
+        ("query-replace-regexp", query_replace_regexp, &["C-M-%"]),
+        ("project-search", project_search, &["C-x p g"]),
+        ("project-search-again", project_search_again, &[]),
+        ("project-search-goto-match", project_search_goto_match, &[]),
+        ("project-search-next-match", project_search_next_match, &[]),
+        (
+            "project-search-previous-match",
+            project_search_previous_match,
+            &[],
+        ),
+        ("project-search-replace", project_search_replace, &[]),
+        ("lsp", lsp::start, &[]),
+        ("lsp-shutdown", lsp::shutdown, &[]),
+        ("lsp-find-definition", lsp::find_definition, &["M-."]),
+        ("lsp-find-references", lsp::find_references, &["M-?"]),
+        ("lsp-go-back", lsp::go_back, &["M-,"]),
+        ("lsp-hover", lsp::hover, &["C-c C-h"]),
+        ("lsp-rename", lsp::rename, &["C-c C-r"]),
+        ("lsp-format-buffer", lsp::format_buffer, &["C-c C-f"]),
+        ("completion-at-point", lsp::complete_at_point, &["C-M-i"]),
+        ("lsp-next-diagnostic", lsp_next_diagnostic, &["M-g n"]),
+        (
+            "lsp-previous-diagnostic",
+            lsp_previous_diagnostic,
+            &["M-g p"],
+        ),
+        //The C-x C-a keys are the ones GUD uses
+        ("dap-debug", dap::debug, &[]),
+        ("dap-disconnect", dap::disconnect, &[]),
+        (
+            "dap-toggle-breakpoint",
+            dap::toggle_breakpoint,
+            &["C-x C-a C-b"],
+        ),
+        ("dap-continue", dap::continue_execution, &["C-x C-a C-r"]),
+        ("dap-next", dap::next, &["C-x C-a C-n"]),
+        ("dap-step-in", dap::step_in, &["C-x C-a C-s"]),
+        ("dap-step-out", dap::step_out, &["C-x C-a C-f"]),
+        ("dap-pause", dap::pause, &[]),
+        ("dap-up", dap::up, &["C-x C-a <"]),
+        ("dap-down", dap::down, &["C-x C-a >"]),
+        ("dap-eval", dap::eval, &["C-x C-a C-p"]),
+        ("dap-add-watch", dap::add_watch, &["C-x C-a C-w"]),
+        ("dap-remove-watch", dap::remove_watch, &[]),
+        ("dap-goto-frame", dap::goto_frame, &[]),
+        ("dap-toggle-variable", dap::toggle_variable, &[]),
+        ("dap-repl", dap::show_repl, &[]),
+        ("dap-repl-send", dap::repl_send, &[]),
+        ("dap-stack", dap::show_stack_buffer, &[]),
+        ("dap-locals", dap::show_locals_buffer, &[]),
+        ("dap-watch", dap::show_watch_buffer, &[]),
+        ("set-mark-command", set_mark_command, &["C-SPC", "C-@"]),
+        (
+            "exchange-point-and-mark",
+            exchange_point_and_mark,
+            &["C-x C-x"],
+        ),
+        ("keyboard-quit", keyboard_quit, &["C-g"]),
+        (
+            "execute-extended-command",
+            execute_extended_command,
+            &["M-x"],
+        ),
+        ("eval-expression", eval_expression, &["M-:"]),
+        ("describe-variable", describe_variable, &["C-h v"]),
+        ("load-theme", load_theme, &[]),
+        ("disable-theme", disable_theme, &[]),
+        (
+            "text-scale-increase",
+            text_scale_increase,
+            &["C-x C-=", "C-x C-+"],
+        ),
+        ("text-scale-decrease", text_scale_decrease, &["C-x C--"]),
+        ("text-scale-reset", text_scale_reset, &["C-x C-0"]),
+        ("make-frame", make_frame, &["C-x 5 2"]),
+        ("delete-frame", delete_frame, &["C-x 5 0"]),
+        (
+            "save-buffers-kill-terminal",
+            save_buffers_kill_terminal,
+            &["C-x C-c"],
+        ),
+        ("split-window-below", split_window_below, &["C-x 2"]),
+        ("split-window-right", split_window_right, &["C-x 3"]),
+        ("delete-window", delete_window, &["C-x 0"]),
+        ("delete-other-windows", delete_other_windows, &["C-x 1"]),
+        ("other-window", other_window, &["C-x o"]),
+        ("enlarge-window", enlarge_window, &["C-x ^"]),
+        ("shrink-window", shrink_window, &[]),
+        (
+            "enlarge-window-horizontally",
+            enlarge_window_horizontally,
+            &["C-x }"],
+        ),
+        (
+            "shrink-window-horizontally",
+            shrink_window_horizontally,
+            &["C-x {"],
+        ),
+    ];
+    for (name, command, keys) in builtins {
+        commands.insert(name, *command);
+        for kbd in *keys {
+            keymap.bind_kbd(kbd, *name);
+        }
+    }
+}
+
+fn save_buffer(editor: &mut Editor) -> Result<(), String> {
+    let id = editor.current_buffer_id();
+    editor
+        .save_buffer(id)
+        .map_err(|e| format!("Saving failed: {e}"))
+}
+
+fn revert_buffer(editor: &mut Editor) -> Result<(), String> {
+    let id = editor.current_buffer_id();
+    editor
+        .revert_buffer(id)
+        .map_err(|e| format!("Reverting failed: {e}"))
+}
+
+//Picks the major mode again, for after auto-mode-alist changed
+fn normal_mode(editor: &mut Editor) -> Result<(), String> {
+    editor.set_auto_mode()
+}
+
+fn newline(editor: &mut Editor) -> Result<(), String> {
+    editor.insert("\n");
+    Ok(())
+}
+
+fn delete_backward_char(editor: &mut Editor) -> Result<(), String> {
+    match editor.current_buffer().point() {
+        0 => Err("Beginning of buffer".to_owned()),
+        point => {
+            editor.delete_region(point - 1, point);
+            Ok(())
+        }
+    }
+}
+
+fn delete_char(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let point = buffer.point();
+    if point == buffer.len_chars() {
+        Err("End of buffer".to_owned())
+    } else {
+        editor.delete_region(point, point + 1);
+        Ok(())
+    }
+}
+
+fn forward_char(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    if buffer.point() == buffer.len_chars() {
+        return Err("End of buffer".to_owned());
+    }
+    buffer.set_point(buffer.point() + 1);
+    Ok(())
+}
+
+fn backward_char(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    match buffer.point() {
+        0 => Err("Beginning of buffer".to_owned()),
+        point => {
+            buffer.set_point(point - 1);
+            Ok(())
+        }
+    }
+}
+
+fn beginning_of_line(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let line = buffer.text().char_to_line(buffer.point());
+    buffer.set_point(buffer.text().line_to_char(line));
+    Ok(())
+}
+
+fn end_of_line(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let line = buffer.text().char_to_line(buffer.point());
+    let point = line_end(buffer.text(), line);
+    buffer.set_point(point);
+    Ok(())
+}
+
+//Char index of the end of `line`, not counting its newline
+pub(crate) fn line_end(text: &ropey::Rope, line: usize) -> usize {
+    let start = text.line_to_char(line);
+    let slice = text.line(line);
+    let mut len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        len -= 1;
+    }
+    start + len
+}
+
+fn move_lines(editor: &mut Editor, down: bool) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let text = buffer.text();
+    let line = text.char_to_line(buffer.point());
+    let column = buffer.point() - text.line_to_char(line);
+    let target = if down {
+        if line + 1 >= text.len_lines() {
+            return Err("End of buffer".to_owned());
+        }
+        line + 1
+    } else {
+        if line == 0 {
+            return Err("Beginning of buffer".to_owned());
+        }
+        line - 1
+    };
+    let point = (text.line_to_char(target) + column).min(line_end(text, target));
+    buffer.set_point(point);
+    Ok(())
+}
+
+fn next_line(editor: &mut Editor) -> Result<(), String> {
+    move_lines(editor, true)
+}
+
+fn previous_line(editor: &mut Editor) -> Result<(), String> {
+    move_lines(editor, false)
+}
+
+//Emacs keeps this many lines of context when paging
+const NEXT_SCREEN_CONTEXT_LINES: usize = 2;
+
+fn scroll(editor: &mut Editor, down: bool) -> Result<(), String> {
+    let buffer_id = editor.current_buffer_id();
+    let Some(window) = editor.selected_window_mut() else {
+        return Ok(());
+    };
+    let (start, rows) = (window.start_line, window.rows);
+    let amount = rows.saturating_sub(NEXT_SCREEN_CONTEXT_LINES).max(1);
+    let buffer = editor
+        .buffer(buffer_id)
+        .ok_or_else(|| "No current buffer".to_owned())?;
+    let last_line = buffer.text().len_lines() - 1;
+    let new_start = if down {
+        if start >= last_line {
+            return Err("End of buffer".to_owned());
+        }
+        (start + amount).min(last_line)
+    } else {
+        if start == 0 {
+            return Err("Beginning of buffer".to_owned());
+        }
+        start.saturating_sub(amount)
+    };
+    //Drag point along so redisplay doesn't scroll right back
+    let point_line = buffer.text().char_to_line(buffer.point());
+    let new_point_line = point_line.clamp(new_start, (new_start + rows).max(1) - 1);
+    let new_point = if new_point_line == point_line {
+        buffer.point()
+    } else {
+        buffer.text().line_to_char(new_point_line.min(last_line))
+    };
+    if let Some(window) = editor.selected_window_mut() {
+        window.start_line = new_start;
+    }
+    editor.current_buffer_mut().set_point(new_point);
+    Ok(())
+}
+
+fn scroll_up_command(editor: &mut Editor) -> Result<(), String> {
+    scroll(editor, true)
+}
+
+fn scroll_down_command(editor: &mut Editor) -> Result<(), String> {
+    scroll(editor, false)
+}
+
+fn recenter(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let line = buffer.text().char_to_line(buffer.point());
+    let window = editor
+        .selected_frame()
+        .and_then(|f| editor.frame(f))
+        .and_then(|f| f.window(f.selected_window()));
+    let Some(window) = window else {
+        return Ok(());
+    };
+    let (cols, rows) = (window.cols, window.rows);
+    //Metrics don't matter for counting rows, only the grid size does
+    let params = editor.layout_params(
+        buffer,
+        layout::CellMetrics {
+            width: 1.0,
+            height: 1.0,
+            ascent: 1.0,
+        },
+    );
+    let start = layout::recenter(buffer.text(), line, cols, rows, &params);
+    if let Some(window) = editor.selected_window_mut() {
+        window.start_line = start;
+    }
+    Ok(())
+}
+
+fn syntax_tree(buffer: &Buffer) -> Result<&Syntax, String> {
+    buffer
+        .syntax()
+        .ok_or_else(|| "No syntax tree in this buffer".to_owned())
+}
+
+fn next_sibling_node(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let pos = syntax_tree(buffer)?
+        .next_sibling(buffer.text(), buffer.point())
+        .ok_or_else(|| "No next sibling".to_owned())?;
+    buffer.set_point(pos);
+    Ok(())
+}
+
+fn previous_sibling_node(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let pos = syntax_tree(buffer)?
+        .previous_sibling(buffer.text(), buffer.point())
+        .ok_or_else(|| "No previous sibling".to_owned())?;
+    buffer.set_point(pos);
+    Ok(())
+}
+
+//Selects the node around the region, or around point without one, so
+//repeating it grows the selection a level at a time
+fn expand_region(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let (start, end) = buffer.region().unwrap_or((buffer.point(), buffer.point()));
+    let range = syntax_tree(buffer)?
+        .parent_range(buffer.text(), start, end)
+        .ok_or_else(|| "No bigger node".to_owned())?;
+    buffer.set_mark(range.end);
+    buffer.set_point(range.start);
+    Ok(())
+}
+
+fn isearch_forward(editor: &mut Editor) -> Result<(), String> {
+    editor.start_isearch(true, false);
+    Ok(())
+}
+
+fn isearch_backward(editor: &mut Editor) -> Result<(), String> {
+    editor.start_isearch(false, false);
+    Ok(())
+}
+
+fn isearch_forward_regexp(editor: &mut Editor) -> Result<(), String> {
+    editor.start_isearch(true, true);
+    Ok(())
+}
+
+fn isearch_backward_regexp(editor: &mut Editor) -> Result<(), String> {
+    editor.start_isearch(false, true);
+    Ok(())
+}
+
+//Asks what to replace with what, then goes through the matches
+fn read_query_replace(editor: &mut Editor, regex: bool) {
+    let kind = if regex { " regexp" } else { "" };
+    editor.read_string(
+        format!("Query replace{kind}: "),
+        "",
+        Box::new(move |editor, from| {
+            let Some(from) = from else {
+                return;
+            };
+            editor.read_string(
+                format!("Query replace{kind} {from} with: "),
+                "",
+                Box::new(move |editor, to| {
+                    let Some(to) = to else {
+                        return;
+                    };
+                    if let Err(e) = editor.query_replace(&from, &to, regex) {
+                        editor.message(e);
+                    }
+                }),
+            );
+        }),
+    );
+}
+
+fn query_replace(editor: &mut Editor) -> Result<(), String> {
+    read_query_replace(editor, false);
+    Ok(())
+}
+
+fn query_replace_regexp(editor: &mut Editor) -> Result<(), String> {
+    read_query_replace(editor, true);
+    Ok(())
+}
+
+fn project_search(editor: &mut Editor) -> Result<(), String> {
+    editor.read_string(
+        "Project search (regexp): ",
+        "",
+        Box::new(|editor, query| {
+            let Some(query) = query else {
+                return;
+            };
+            if let Err(e) = editor.start_project_search(&query) {
+                editor.message(e);
+            }
+        }),
+    );
+    Ok(())
+}
+
+fn project_search_again(editor: &mut Editor) -> Result<(), String> {
+    editor.project_search_again()
+}
+
+fn project_search_goto_match(editor: &mut Editor) -> Result<(), String> {
+    editor.project_search_goto(true)
+}
+
+fn project_search_next_match(editor: &mut Editor) -> Result<(), String> {
+    editor.project_search_next(1)
+}
+
+fn project_search_previous_match(editor: &mut Editor) -> Result<(), String> {
+    editor.project_search_next(-1)
+}
+
+//Asks what to replace the listed matches with
+fn project_search_replace(editor: &mut Editor) -> Result<(), String> {
+    let query = editor
+        .project_search()
+        .filter(|search| search.buffer() == editor.current_buffer_id())
+        .map(|search| search.query().to_owned())
+        .ok_or_else(|| "Not in project search results".to_owned())?;
+    editor.read_string(
+        format!("Replace {query} with: "),
+        "",
+        Box::new(|editor, to| {
+            let Some(to) = to else {
+                return;
+            };
+            if let Err(e) = editor.project_search_replace(&to) {
+                editor.message(e);
+            }
+        }),
+    );
+    Ok(())
+}
+
+fn lsp_next_diagnostic(editor: &mut Editor) -> Result<(), String> {
+    lsp::next_diagnostic(editor, true)
+}
+
+fn lsp_previous_diagnostic(editor: &mut Editor) -> Result<(), String> {
+    lsp::next_diagnostic(editor, false)
+}
+
+fn set_mark_command(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    buffer.set_mark(buffer.point());
+    editor.message("Mark set");
+    Ok(())
+}
+
+fn exchange_point_and_mark(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_mut();
+    let mark = buffer
+        .mark()
+        .ok_or_else(|| "No mark set in this buffer".to_owned())?;
+    let point = buffer.point();
+    buffer.set_point(mark);
+    buffer.set_mark(point);
+    Ok(())
+}
+
+fn keyboard_quit(editor: &mut Editor) -> Result<(), String> {
+    editor.current_buffer_mut().deactivate_mark();
+    Err("Quit".to_owned())
+}
+
+fn execute_extended_command(editor: &mut Editor) -> Result<(), String> {
+    let mut names: Vec<String> = editor.command_names().map(str::to_owned).collect();
+    names.sort();
+    editor.completing_read(
+        "M-x ",
+        names,
+        true,
+        "",
+        Box::new(|editor, name| {
+            if let Some(name) = name {
+                editor.call_interactively(&name);
+            }
+        }),
+    );
+    Ok(())
+}
+
+//Shows a variable's value and documentation in the echo area
+fn describe_variable(editor: &mut Editor) -> Result<(), String> {
+    let mut names: Vec<String> = editor.variables.names().map(str::to_owned).collect();
+    names.sort();
+    editor.completing_read(
+        "Describe variable: ",
+        names,
+        true,
+        "",
+        Box::new(|editor, name| {
+            let Some(variable) = name.as_deref().and_then(|n| editor.variables.get(n)) else {
+                return;
+            };
+            let name = name.as_deref().unwrap_or_default();
+            let buffer = editor.current_buffer();
+            let default = variable.default_value();
+            let mut description = match buffer.local_variable(name) {
+                Some(local) => format!(
+                    "{name} is {local} in {}, {default} globally.",
+                    buffer.name()
+                ),
+                None => format!("{name} is {default}."),
+            };
+            if !variable.doc.is_empty() {
+                description = format!("{description} {}", variable.doc);
+            }
+            editor.message(description);
+        }),
+    );
+    Ok(())
+}
+
+fn eval_expression(editor: &mut Editor) -> Result<(), String> {
+    editor.read_string(
+        "Eval: ",
+        "",
+        Box::new(|editor, source| {
+            let Some(source) = source else {
+                return;
+            };
+            editor.eval_script(
+                &source,
+                Box::new(|editor, result| match result {
+                    Ok(value) => editor.message(value.to_string()),
+                    Err(e) => editor.message(e),
+                }),
+            );
+        }),
+    );
+    Ok(())
+}
+
+fn load_theme(editor: &mut Editor) -> Result<(), String> {
+    let themes = theme::available(&editor.custom_theme_load_path);
+    editor.completing_read(
+        "Load custom theme: ",
+        themes,
+        false,
+        "",
+        Box::new(|editor, name| {
+            if let Some(Err(e)) = name.map(|name| editor.load_theme(&name)) {
+                editor.message(e);
+            }
+        }),
+    );
+    Ok(())
+}
+
+fn disable_theme(editor: &mut Editor) -> Result<(), String> {
+    let enabled: Vec<String> = editor.faces.enabled_themes().map(str::to_owned).collect();
+    if enabled.is_empty() {
+        return Err("No themes are enabled".to_owned());
+    }
+    editor.completing_read(
+        "Disable custom theme: ",
+        enabled,
+        true,
+        "",
+        Box::new(|editor, name| {
+            if let Some(Err(e)) = name.map(|name| editor.disable_theme(&name)) {
+                editor.message(e);
+            }
+        }),
+    );
+    Ok(())
+}
+
+fn text_scale_message(editor: &mut Editor) {
+    let scale = editor.font().text_scale;
+    editor.message(format!("Text scale {scale:+}"));
+}
+
+fn text_scale_increase(editor: &mut Editor) -> Result<(), String> {
+    editor.text_scale_adjust(1);
+    text_scale_message(editor);
+    Ok(())
+}
+
+fn text_scale_decrease(editor: &mut Editor) -> Result<(), String> {
+    editor.text_scale_adjust(-1);
+    text_scale_message(editor);
+    Ok(())
+}
+
+fn text_scale_reset(editor: &mut Editor) -> Result<(), String> {
+    editor.text_scale_adjust(0);
+    text_scale_message(editor);
+    Ok(())
+}
+
+fn make_frame(editor: &mut Editor) -> Result<(), String> {
+    editor.request_frame();
+    Ok(())
+}
+
+fn delete_frame(editor: &mut Editor) -> Result<(), String> {
+    let frame = editor
+        .selected_frame()
+        .ok_or_else(|| "No selected frame".to_owned())?;
+    if editor.frames().count() == 1 {
+        return Err("Attempt to delete the sole frame".to_owned());
+    }
+    editor.delete_frame_interactively(frame);
+    Ok(())
+}
+
+fn save_buffers_kill_terminal(editor: &mut Editor) -> Result<(), String> {
+    editor.kill_terminal_interactively();
+    Ok(())
+}
+
+fn split_window_below(editor: &mut Editor) -> Result<(), String> {
+    editor.split_window(SplitDirection::Below).map(|_| ())
+}
+
+fn split_window_right(editor: &mut Editor) -> Result<(), String> {
+    editor.split_window(SplitDirection::Right).map(|_| ())
+}
+
+fn delete_window(editor: &mut Editor) -> Result<(), String> {
+    let window = editor
+        .selected_window()
+        .ok_or_else(|| "No selected window".to_owned())?;
+    editor.delete_window(window)
+}
+
+fn delete_other_windows(editor: &mut Editor) -> Result<(), String> {
+    editor.delete_other_windows()
+}
+
+fn other_window(editor: &mut Editor) -> Result<(), String> {
+    editor.other_window()
+}
+
+fn enlarge_window(editor: &mut Editor) -> Result<(), String> {
+    editor.resize_window(SplitDirection::Below, 1)
+}
+
+fn shrink_window(editor: &mut Editor) -> Result<(), String> {
+    editor.resize_window(SplitDirection::Below, -1)
+}
+
+fn enlarge_window_horizontally(editor: &mut Editor) -> Result<(), String> {
+    editor.resize_window(SplitDirection::Right, 1)
+}
+
+fn shrink_window_horizontally(editor: &mut Editor) -> Result<(), String> {
+    editor.resize_window(SplitDirection::Right, -1)
+}
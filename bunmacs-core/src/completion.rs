@@ -0,0 +1,302 @@
+//Matching and ranking of minibuffer candidates. Nothing here knows about
+//the minibuffer, it only looks at strings.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionStyle {
+    //The candidate starts with the input
+    Prefix,
+    //The input shows up anywhere in the candidate
+    Substring,
+    //The input's chars show up in order, with anything in between
+    Flex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    //Index into the candidate list
+    pub index: usize,
+    //Higher is better, only comparable between matches of the same style
+    pub score: i64,
+    //Char indices of the candidate that the input matched
+    pub positions: Vec<usize>,
+}
+
+//What completing-read tries by default, like Emacs' `completion-styles`
+pub const DEFAULT_STYLES: &[CompletionStyle] = &[
+    CompletionStyle::Prefix,
+    CompletionStyle::Substring,
+    CompletionStyle::Flex,
+];
+
+const SCORE_MATCH: i64 = 16;
+//Matching right after a separator or at a camelCase hump
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 6;
+const PENALTY_GAP_START: i64 = 3;
+const PENALTY_GAP_EXTENSION: i64 = 1;
+
+//Smart case: input with no capitals matches any case
+fn ignore_case(input: &str) -> bool {
+    !input.chars().any(char::is_uppercase)
+}
+
+fn chars_eq(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+fn is_boundary(prev: Option<char>, ch: char) -> bool {
+    match prev {
+        None => true,
+        Some(prev) => {
+            matches!(prev, '-' | '_' | ' ' | '/' | '.' | ':')
+                || (prev.is_lowercase() && ch.is_uppercase())
+        }
+    }
+}
+
+impl CompletionStyle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompletionStyle::Prefix => "prefix",
+            CompletionStyle::Substring => "substring",
+            CompletionStyle::Flex => "flex",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            CompletionStyle::Prefix,
+            CompletionStyle::Substring,
+            CompletionStyle::Flex,
+        ]
+        .into_iter()
+        .find(|style| style.name() == name)
+    }
+
+    //Score and matched positions of `candidate`, None if it doesn't match
+    pub fn score(&self, input: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+        let ignore_case = ignore_case(input);
+        let input: Vec<char> = input.chars().collect();
+        let candidate: Vec<char> = candidate.chars().collect();
+        match self {
+            CompletionStyle::Prefix => {
+                let matches = input.len() <= candidate.len()
+                    && input
+                        .iter()
+                        .zip(&candidate)
+                        .all(|(a, b)| chars_eq(*a, *b, ignore_case));
+                matches.then(|| (0, (0..input.len()).collect()))
+            }
+            CompletionStyle::Substring => {
+                let start = (0..=candidate.len().checked_sub(input.len())?).find(|start| {
+                    input
+                        .iter()
+                        .zip(&candidate[*start..])
+                        .all(|(a, b)| chars_eq(*a, *b, ignore_case))
+                })?;
+                //Earlier is better
+                Some((-(start as i64), (start..start + input.len()).collect()))
+            }
+            CompletionStyle::Flex => flex_score(&input, &candidate, ignore_case),
+        }
+    }
+}
+
+//Finds the best way to line up every input char with a candidate char, in
+//order. Each matched char scores, more so at word boundaries and right after
+//another match, and every gap costs a little.
+fn flex_score(input: &[char], candidate: &[char], ignore_case: bool) -> Option<(i64, Vec<usize>)> {
+    if input.is_empty() {
+        return Some((0, vec![]));
+    }
+    let (m, n) = (input.len(), candidate.len());
+    if m > n {
+        return None;
+    }
+    let char_score = |j: usize| {
+        let prev = j.checked_sub(1).map(|p| candidate[p]);
+        SCORE_MATCH
+            + if is_boundary(prev, candidate[j]) {
+                BONUS_BOUNDARY
+            } else {
+                0
+            }
+    };
+    //best[i][j]: best score with input[i] matched at candidate[j], and the
+    //candidate position input[i - 1] was matched at to get it
+    let mut best: Vec<Vec<Option<(i64, usize)>>> = vec![vec![None; n]; m];
+    for j in 0..n {
+        if chars_eq(input[0], candidate[j], ignore_case) {
+            best[0][j] = Some((char_score(j), 0));
+        }
+    }
+    for i in 1..m {
+        for j in i..n {
+            if !chars_eq(input[i], candidate[j], ignore_case) {
+                continue;
+            }
+            best[i][j] = (i - 1..j)
+                .filter_map(|k| {
+                    let (score, _) = best[i - 1][k]?;
+                    let gap = j - k - 1;
+                    let link = if gap == 0 {
+                        BONUS_CONSECUTIVE
+                    } else {
+                        -(PENALTY_GAP_START + PENALTY_GAP_EXTENSION * (gap as i64 - 1))
+                    };
+                    Some((score + link, k))
+                })
+                .max_by_key(|(score, _)| *score)
+                .map(|(score, k)| (score + char_score(j), k));
+        }
+    }
+    let (mut j, (score, _)) = best[m - 1]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, cell)| Some((j, (*cell)?)))
+        .max_by_key(|(_, (score, _))| *score)?;
+    let mut positions = vec![0; m];
+    for i in (0..m).rev() {
+        positions[i] = j;
+        j = best[i][j].map(|(_, prev)| prev).unwrap_or(0);
+    }
+    Some((score, positions))
+}
+
+//Like Emacs' `completion-styles`: styles are tried in order and the first
+//one that matches anything decides the result. Best matches come first,
+//ties go to the shorter candidate and then to the original order.
+pub fn complete<S: AsRef<str>>(
+    input: &str,
+    candidates: &[S],
+    styles: &[CompletionStyle],
+) -> Vec<Match> {
+    for style in styles {
+        let mut matches: Vec<Match> = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| {
+                let (score, positions) = style.score(input, candidate.as_ref())?;
+                Some(Match {
+                    index,
+                    score,
+                    positions,
+                })
+            })
+            .collect();
+        if !matches.is_empty() {
+            matches.sort_by_key(|m| {
+                (
+                    std::cmp::Reverse(m.score),
+                    candidates[m.index].as_ref().chars().count(),
+                )
+            });
+            return matches;
+        }
+    }
+    vec![]
+}
+
+//What TAB fills in: the longest common prefix of `matched`, if that's longer
+//than the input. With a single match that's the whole candidate.
+pub fn try_completion<S: AsRef<str>>(input: &str, matched: &[S]) -> Option<String> {
+    let ignore_case = ignore_case(input);
+    let (first, rest) = matched.split_first()?;
+    let mut prefix: Vec<char> = first.as_ref().chars().collect();
+    for candidate in rest {
+        let common = prefix
+            .iter()
+            .zip(candidate.as_ref().chars())
+            .take_while(|(a, b)| chars_eq(**a, *b, ignore_case))
+            .count();
+        prefix.truncate(common);
+    }
+    let extends_input = prefix.len() > input.chars().count()
+        && CompletionStyle::Prefix
+            .score(input, &prefix.iter().collect::<String>())
+            .is_some();
+    extends_input.then(|| prefix.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The candidates `input` completes to, best first
+    fn ranked<'a>(input: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+        complete(input, candidates, DEFAULT_STYLES)
+            .iter()
+            .map(|m| candidates[m.index])
+            .collect()
+    }
+
+    #[test]
+    fn prefix_matches_win_with_shorter_ones_first() {
+        let candidates = ["find-file", "project-find", "find"];
+        assert_eq!(ranked("find", &candidates), ["find", "find-file"]);
+    }
+
+    #[test]
+    fn substrings_are_tried_without_a_prefix_match() {
+        let candidates = ["write-file-now", "find-file", "kill-buffer"];
+        assert_eq!(ranked("file", &candidates), ["find-file", "write-file-now"]);
+        let matches = complete("file", &candidates, DEFAULT_STYLES);
+        assert_eq!(matches[0].positions, [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn flex_matches_prefer_boundaries() {
+        let candidates = ["informative-nfile", "find-file"];
+        assert_eq!(
+            ranked("fnf", &candidates),
+            ["find-file", "informative-nfile"]
+        );
+        let (_, positions) = CompletionStyle::Flex.score("fnf", "find-file").unwrap();
+        assert_eq!(positions, [0, 2, 5]);
+    }
+
+    #[test]
+    fn flex_matches_prefer_runs() {
+        let (_, positions) = CompletionStyle::Flex.score("fi", "fxxfi").unwrap();
+        assert_eq!(positions, [3, 4]);
+        assert_eq!(CompletionStyle::Flex.score("fif", "fxxfi"), None);
+    }
+
+    #[test]
+    fn capitals_make_matching_case_sensitive() {
+        let candidates = ["find-file", "Find-File"];
+        assert_eq!(ranked("find", &candidates), ["find-file", "Find-File"]);
+        assert_eq!(ranked("Find", &candidates), ["Find-File"]);
+        assert_eq!(ranked("FF", &candidates), ["Find-File"]);
+    }
+
+    #[test]
+    fn nothing_matches_nothing() {
+        assert_eq!(ranked("zzz", &["find-file"]), Vec::<&str>::new());
+        assert_eq!(complete("x", &[] as &[&str], DEFAULT_STYLES), []);
+    }
+
+    #[test]
+    fn tab_fills_in_the_common_prefix() {
+        assert_eq!(
+            try_completion("fi", &["find-file", "find-files"]),
+            Some("find-file".to_owned())
+        );
+        assert_eq!(
+            try_completion("fi", &["find-file"]),
+            Some("find-file".to_owned())
+        );
+        //Nothing longer than what's typed
+        assert_eq!(try_completion("fi", &["find", "fill"]), None);
+        //A substring match doesn't extend the input
+        assert_eq!(try_completion("file", &["find-file"]), None);
+    }
+
+    #[test]
+    fn styles_have_names() {
+        for style in DEFAULT_STYLES {
+            assert_eq!(CompletionStyle::from_name(style.name()), Some(*style));
+        }
+        assert_eq!(CompletionStyle::from_name("orderless"), None);
+    }
+}
@@ -0,0 +1,328 @@
+//A child process spoken to in JSON over its stdin and stdout, each message
+//framed with a Content-Length header. Language servers and debug adapters
+//both work this way. On the async runtime one task writes whatever the
+//editor queues up and another reads what comes back, which the editor
+//picks up with try_recv on its own thread.
+
+use std::{
+    io,
+    path::Path,
+    process::Stdio,
+    sync::mpsc::{self, Receiver},
+};
+
+use serde_json::Value as Json;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::mpsc::{self as async_mpsc, UnboundedSender},
+};
+
+use crate::background::Background;
+
+#[derive(Debug)]
+pub(crate) enum Incoming {
+    Message(Json),
+    //The process closed its stdout, which means it's gone
+    Closed,
+}
+
+pub(crate) struct Connection {
+    //Killed when dropped
+    _child: Child,
+    outgoing: UnboundedSender<Vec<u8>>,
+    incoming: Receiver<Incoming>,
+}
+
+impl Connection {
+    //Starts `command` in `dir`. What it writes to stderr is logged.
+    pub(crate) fn spawn(
+        background: &Background,
+        command: &str,
+        args: &[String],
+        dir: &Path,
+    ) -> Result<Self, String> {
+        let runtime = background.runtime();
+        //Spawning registers the child with the runtime's reaper
+        let _guard = runtime.enter();
+        let mut child = Command::new(command)
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Couldn't start {command}: {e}"))?;
+        let (Some(mut stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(format!("Couldn't talk to {command}"));
+        };
+
+        let (outgoing, mut to_write) = async_mpsc::unbounded_channel::<Vec<u8>>();
+        runtime.spawn(async move {
+            while let Some(message) = to_write.recv().await {
+                if stdin.write_all(&message).await.is_err() || stdin.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (sender, incoming) = mpsc::channel();
+        let name = command.to_owned();
+        let reader_background = background.clone();
+        runtime.spawn(async move {
+            let mut stdout = BufReader::new(stdout);
+            loop {
+                match read_message(&mut stdout).await {
+                    Ok(Some(body)) => match serde_json::from_slice(&body) {
+                        Ok(message) => {
+                            if sender.send(Incoming::Message(message)).is_err() {
+                                return;
+                            }
+                            reader_background.wake();
+                        }
+                        Err(e) => log::warn!("{name} sent bad JSON: {e}"),
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::warn!("Stopped reading from {name}: {e}");
+                        break;
+                    }
+                }
+            }
+            let _ = sender.send(Incoming::Closed);
+            reader_background.wake();
+        });
+
+        let name = command.to_owned();
+        runtime.spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                log::debug!("{name}: {line}");
+            }
+        });
+
+        Ok(Connection {
+            _child: child,
+            outgoing,
+            incoming,
+        })
+    }
+
+    pub(crate) fn send(&self, message: &Json) {
+        let body = message.to_string();
+        let mut framed = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        framed.extend_from_slice(body.as_bytes());
+        //Fails once the writer has given up, which Incoming::Closed reports
+        let _ = self.outgoing.send(framed);
+    }
+
+    //The next message that came in, None if there isn't one yet
+    pub(crate) fn try_recv(&self) -> Option<Incoming> {
+        self.incoming.try_recv().ok()
+    }
+}
+
+//One message's body, None at the end of the stream
+async fn read_message(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length",
+        )
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+//Language servers and debug adapters played by a shell script, for tests
+//to check what the editor sends and how it takes the answers
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::time::{Duration, Instant};
+
+    use serde_json::Value as Json;
+    use tokio::runtime::Runtime;
+
+    use crate::editor::Editor;
+
+    //Replaced with the id of the message last expected
+    pub(crate) const ID: &str = "@ID@";
+
+    const FUNCTIONS: &str = r#"
+CR=$(printf '\r')
+read_message() {
+    len=
+    while IFS= read -r line; do
+        line=${line%"$CR"}
+        [ -z "$line" ] && break
+        case $line in [Cc]ontent-[Ll]ength:*) len=${line#*:}; len=${len# } ;; esac
+    done
+    [ -n "$len" ] || exit 0
+    body=$(dd bs=1 count="$len" 2>/dev/null)
+}
+expect() {
+    while read_message; do
+        found=1
+        for fragment in "$@"; do
+            case $body in *"$fragment"*) ;; *) found= ;; esac
+        done
+        if [ -n "$found" ]; then
+            id=$(printf '%s' "$body" | sed -n "s/.*\"$KEY\":\([0-9]*\).*/\1/p")
+            return
+        fi
+    done
+    exit 0
+}
+send() {
+    body=$(printf '%s' "$1" | sed "s/\"@ID@\"/$id/g")
+    printf 'Content-Length: %s\r\n\r\n%s' "${#body}" "$body"
+}
+"#;
+
+    //What the fake says and when. Messages it isn't waiting for are
+    //ignored, and it exits once the script runs out.
+    pub(crate) struct Script {
+        source: String,
+    }
+
+    impl Script {
+        //`id_key` is where requests keep their id, "id" for JSON-RPC and
+        //"seq" for debug adapters
+        pub(crate) fn new(id_key: &str) -> Self {
+            Script {
+                source: format!("KEY={id_key}\n{FUNCTIONS}"),
+            }
+        }
+
+        //Waits for a message with every one of `fragments` in its JSON,
+        //which has its keys sorted
+        pub(crate) fn expect(mut self, fragments: &[&str]) -> Self {
+            self.source += "expect";
+            for fragment in fragments {
+                self.source += &format!(" {}", quote(fragment));
+            }
+            self.source += "\n";
+            self
+        }
+
+        //Only ASCII, the script counts its length in chars
+        pub(crate) fn send(mut self, message: Json) -> Self {
+            let message = message.to_string();
+            assert!(message.is_ascii(), "{message}");
+            self.source += &format!("send {}\n", quote(&message));
+            self
+        }
+
+        //The command and arguments that run the script
+        pub(crate) fn command(&self) -> (String, Vec<String>) {
+            ("sh".to_owned(), vec!["-c".to_owned(), self.source.clone()])
+        }
+    }
+
+    fn quote(text: &str) -> String {
+        assert!(!text.contains('\''), "{text}");
+        format!("'{text}'")
+    }
+
+    pub(crate) fn runtime() -> Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    //Takes in background events until `done`, failing the test if that
+    //takes too long
+    pub(crate) fn wait_for(editor: &mut Editor, mut done: impl FnMut(&mut Editor) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done(editor) {
+            assert!(
+                Instant::now() < deadline,
+                "timed out, last message {:?}",
+                editor.current_message()
+            );
+            editor.process_background_events();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read_all(mut input: &[u8]) -> io::Result<Vec<Json>> {
+        let runtime = fake::runtime();
+        let mut messages = vec![];
+        while let Some(body) = runtime.block_on(read_message(&mut input))? {
+            messages.push(serde_json::from_slice(&body).unwrap());
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn reads_framed_messages() {
+        let input = b"Content-Length: 8\r\n\r\n{\"a\":1}\ncontent-length:2\r\nContent-Type: application/json\r\n\r\n[]";
+        assert_eq!(read_all(input).unwrap(), [json!({"a": 1}), json!([])]);
+        assert_eq!(read_all(b"").unwrap(), Vec::<Json>::new());
+    }
+
+    #[test]
+    fn needs_a_length() {
+        let error = read_all(b"Content-Type: application/json\r\n\r\n{}").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        //Cut off in the middle of a body
+        let error = read_all(b"Content-Length: 10\r\n\r\n{}").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn talks_to_a_process() {
+        let runtime = fake::runtime();
+        let background = Background::new(runtime.handle().clone(), None);
+        let (command, args) = fake::Script::new("id")
+            .expect(&[r#""method":"ping""#, r#""params":[1,2]"#])
+            .send(json!({"id": fake::ID, "result": "pong"}))
+            .command();
+        let connection =
+            Connection::spawn(&background, &command, &args, &std::env::temp_dir()).unwrap();
+        //Ignored, it's not what the script's waiting for
+        connection.send(&json!({"id": 6, "method": "ping", "params": []}));
+        connection.send(&json!({"id": 7, "method": "ping", "params": [1, 2]}));
+        let mut received = vec![];
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while !matches!(received.last(), Some(Incoming::Closed)) {
+            assert!(std::time::Instant::now() < deadline, "{received:?}");
+            received.extend(connection.try_recv());
+        }
+        assert!(matches!(
+            &received[..],
+            [Incoming::Message(reply), Incoming::Closed]
+                if *reply == json!({"id": 7, "result": "pong"})
+        ));
+    }
+}
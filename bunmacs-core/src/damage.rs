@@ -0,0 +1,281 @@
+//Working out what changed on a frame from one redisplay to the next, so
+//frontends only paint that. Windows, the echo area and the completions
+//are cut into rows, and each row is only repainted where what's drawn on
+//it differs from last time. A cursor blinking only changes its own cell.
+
+use crate::{
+    decoration::Color,
+    editor::FrameDisplay,
+    face::{Slant, Weight},
+    frontend::{self, Cursor, Damage, DrawCommand, Frontend, Recording},
+    layout::Rect,
+};
+
+//What a frame last drew, and whether its cursor was on
+#[derive(Debug)]
+pub(crate) struct Shown {
+    pub(crate) display: FrameDisplay,
+    pub(crate) cursor_on: bool,
+    pub(crate) picture: Picture,
+}
+
+//Everything one picture drew, sorted into rows
+#[derive(Debug, PartialEq)]
+pub(crate) struct Picture {
+    area: Rect,
+    background: Color,
+    rows: Vec<Row>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Row {
+    rect: Rect,
+    marks: Vec<Mark>,
+}
+
+//Something drawn on a row, cut down to the part on that row
+#[derive(Debug, Clone, PartialEq)]
+enum Mark {
+    Fill(Rect, Color),
+    Glyph {
+        rect: Rect,
+        ch: char,
+        color: Color,
+        weight: Weight,
+        slant: Slant,
+    },
+    Cursor(Rect, Cursor),
+}
+
+impl Mark {
+    fn rect(&self) -> Rect {
+        match self {
+            Mark::Fill(rect, _) | Mark::Glyph { rect, .. } | Mark::Cursor(rect, _) => *rect,
+        }
+    }
+}
+
+//The rows of everything `display` draws on
+fn row_rects(display: &FrameDisplay) -> Vec<Rect> {
+    let height = display.echo_area.metrics.height;
+    let windows = display.windows.iter().map(|window| {
+        [
+            window.fringe.as_ref().map(|f| f.text.area),
+            window.mode_line.as_ref().map(|m| m.text.area),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(window.text.area, |area, other| area.union(&other))
+    });
+    let candidates = display.minibuffer.as_ref().map(|m| m.candidates.area);
+    windows
+        .chain(display.dividers.iter().copied())
+        .chain(candidates)
+        .chain([display.echo_area.area])
+        .flat_map(|area| {
+            let rows = if height > 0.0 {
+                (area.height / height).ceil() as usize
+            } else {
+                0
+            };
+            (0..rows).filter_map(move |row| {
+                let y = area.y + row as f32 * height;
+                Some(Rect {
+                    y,
+                    height: height.min(area.y + area.height - y),
+                    ..area
+                })
+                .filter(|rect| rect.width > 0.0 && rect.height > 0.0)
+            })
+        })
+        .collect()
+}
+
+impl Picture {
+    //Sorts the last picture on `recording`, which drew `display`
+    fn new(recording: &Recording, display: &FrameDisplay) -> Self {
+        let mut rows: Vec<Row> = row_rects(display)
+            .into_iter()
+            .map(|rect| Row {
+                rect,
+                marks: vec![],
+            })
+            .collect();
+        let mut background = [0.0; 4];
+        let mut mark = |rect: Rect, mark: &dyn Fn(Rect) -> Mark| {
+            for row in &mut rows {
+                if let Some(part) = rect.intersection(&row.rect) {
+                    row.marks.push(mark(part));
+                }
+            }
+        };
+        for command in recording.last_picture() {
+            match command {
+                DrawCommand::Clear(color, _) => background = *color,
+                DrawCommand::FillRect(rect, color) => mark(*rect, &|part| Mark::Fill(part, *color)),
+                DrawCommand::Glyphs(run) => {
+                    for glyph in &run.glyphs {
+                        let rect = frontend::glyph_rect(glyph, recording.metrics);
+                        mark(rect, &|part| Mark::Glyph {
+                            rect: part,
+                            ch: glyph.ch,
+                            color: run.color,
+                            weight: run.weight,
+                            slant: run.slant,
+                        });
+                    }
+                }
+                DrawCommand::Cursor(cursor) => {
+                    mark(cursor.cell, &|part| Mark::Cursor(part, *cursor))
+                }
+                DrawCommand::Present => (),
+            }
+        }
+        Picture {
+            area: recording.area,
+            background,
+            rows,
+        }
+    }
+
+    //What has to be painted for `self` to turn into `next`
+    fn damage(&self, next: &Picture) -> Damage {
+        let same_rows = self.rows.len() == next.rows.len()
+            && self
+                .rows
+                .iter()
+                .zip(&next.rows)
+                .all(|(a, b)| a.rect == b.rect);
+        if self.area != next.area || self.background != next.background || !same_rows {
+            return Damage::Full;
+        }
+        let mut rects = vec![];
+        for (old, new) in self.rows.iter().zip(&next.rows) {
+            if old.marks == new.marks {
+                continue;
+            }
+            let gone = old.marks.iter().filter(|m| !new.marks.contains(m));
+            let added = new.marks.iter().filter(|m| !old.marks.contains(m));
+            //The same marks in a different order, which can still look
+            //different where they overlap
+            let changed = gone
+                .chain(added)
+                .map(Mark::rect)
+                .reduce(|a, b| a.union(&b))
+                .unwrap_or(new.rect);
+            rects.push(changed);
+        }
+        Damage::Rects(rects)
+    }
+}
+
+//Draws `display` on `frontend`, only painting what changed since `last`
+//was drawn there
+pub(crate) fn show(
+    display: FrameDisplay,
+    cursor_on: bool,
+    last: Option<Picture>,
+    frontend: &mut dyn Frontend,
+) -> Shown {
+    let mut recording = Recording::new(frontend.area(), frontend.cell_metrics());
+    display.draw(&mut recording, cursor_on);
+    let picture = Picture::new(&recording, &display);
+    let damage = match last {
+        Some(last) if frontend.retains_picture() => last.damage(&picture),
+        _ => Damage::Full,
+    };
+    if !damage.is_empty() {
+        recording.replay(frontend, &damage);
+    }
+    Shown {
+        display,
+        cursor_on,
+        picture,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{editor::Editor, frame::FrameId, layout::CellMetrics, testing::editing};
+
+    const METRICS: CellMetrics = CellMetrics {
+        width: 10.0,
+        height: 20.0,
+        ascent: 15.0,
+    };
+
+    fn setup() -> (Editor, FrameId, Recording) {
+        let (editor, frame) = editing("one\ntwo\nthree\n");
+        let area = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 400.0,
+            height: 200.0,
+        };
+        (editor, frame, Recording::new(area, METRICS))
+    }
+
+    fn last_damage(recording: &Recording) -> &Damage {
+        match recording.last_picture().first() {
+            Some(DrawCommand::Clear(_, damage)) => damage,
+            other => panic!("picture starts with {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unchanged_frames_draw_nothing() {
+        let (mut editor, frame, mut recording) = setup();
+        assert!(editor.draw_frame(frame, &mut recording, true));
+        assert_eq!(last_damage(&recording), &Damage::Full);
+        let drawn = recording.commands.len();
+        assert!(editor.draw_frame(frame, &mut recording, true));
+        assert_eq!(recording.commands.len(), drawn);
+    }
+
+    #[test]
+    fn edits_only_damage_their_rows() {
+        let (mut editor, frame, mut recording) = setup();
+        editor.draw_frame(frame, &mut recording, true);
+        editor.current_buffer_mut().insert(4, "X");
+        editor.draw_frame(frame, &mut recording, true);
+        let Damage::Rects(rects) = last_damage(&recording) else {
+            panic!("whole frame repainted");
+        };
+        //Point's on the last line so even the mode line's the same
+        let rows: Vec<f32> = rects.iter().map(|rect| rect.y).collect();
+        assert_eq!(rows, [20.0]);
+        assert!(rects.iter().all(|rect| rect.height == METRICS.height));
+        //Only what's on the damaged rows gets drawn again
+        assert!(recording
+            .last_picture()
+            .iter()
+            .all(|command| match command {
+                DrawCommand::Glyphs(run) => run.glyphs.iter().all(|g| g.baseline != 15.0),
+                _ => true,
+            }));
+    }
+
+    #[test]
+    fn blinking_only_damages_the_cursor() {
+        let (mut editor, frame, mut recording) = setup();
+        editor.draw_frame(frame, &mut recording, true);
+        assert!(editor.redraw_cursor(frame, &mut recording, false));
+        //Point's after the inserted text, on the empty last line
+        let cell = Rect {
+            x: 0.0,
+            y: 60.0,
+            width: METRICS.width,
+            height: METRICS.height,
+        };
+        assert_eq!(last_damage(&recording), &Damage::Rects(vec![cell]));
+        assert!(!recording
+            .last_picture()
+            .iter()
+            .any(|command| matches!(command, DrawCommand::Cursor(_))));
+
+        let drawn = recording.commands.len();
+        assert!(editor.redraw_cursor(frame, &mut recording, false));
+        assert_eq!(recording.commands.len(), drawn);
+    }
+}
@@ -0,0 +1,1592 @@
+//A Debug Adapter Protocol client, what GUD is in Emacs but talking to the
+//same adapters VS Code does. One session runs at a time over a Connection,
+//whose messages Editor::process_background_events hands to `process`.
+//Breakpoints belong to buffers and show in the fringe whether or not
+//anything is being debugged, each session gets told about them as it
+//starts. While the debuggee is stopped the stack, locals and watch buffers
+//show where it is, and the REPL buffer evaluates what's typed into it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug},
+    path::PathBuf,
+};
+
+use bunlang::Value;
+use serde_json::{json, Value as Json};
+
+use crate::{
+    buffer::{BufferId, MarkerId, OverlayId},
+    connection::{Connection, Incoming},
+    editor::Editor,
+    face, project,
+};
+
+pub const REPL_BUFFER: &str = "*dap-repl*";
+pub const STACK_BUFFER: &str = "*dap-stack*";
+pub const LOCALS_BUFFER: &str = "*dap-locals*";
+pub const WATCH_BUFFER: &str = "*dap-watch*";
+pub const REPL_MODE: &str = "dap-repl-mode";
+pub const STACK_MODE: &str = "dap-stack-mode";
+pub const LOCALS_MODE: &str = "dap-locals-mode";
+pub const WATCH_MODE: &str = "dap-watch-mode";
+
+const PROMPT: &str = "> ";
+const BREAKPOINT_MARK: char = '●';
+const STOPPED_MARK: char = '→';
+//Over diagnostics, and the stopped line over breakpoints in the fringe
+const BREAKPOINT_PRIORITY: i64 = 20;
+const STOPPED_PRIORITY: i64 = 30;
+
+//Gets the body of one of our requests' responses, or why it failed
+type ResponseCallback = Box<dyn FnOnce(&mut Editor, Result<Json, String>)>;
+
+//How to start a debug adapter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterConfig {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Launch,
+    Attach,
+}
+
+impl Request {
+    fn command(self) -> &'static str {
+        match self {
+            Request::Launch => "launch",
+            Request::Attach => "attach",
+        }
+    }
+}
+
+//Something to debug and how, like an entry in VS Code's launch.json.
+//${file} and ${workspaceFolder} in string arguments become the current
+//buffer's file and its project root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaunchConfig {
+    pub name: String,
+    pub adapter: String,
+    pub request: Request,
+    pub arguments: Json,
+}
+
+#[derive(Debug)]
+struct Frame {
+    id: i64,
+    name: String,
+    path: Option<PathBuf>,
+    //One based, the way we ask for them
+    line: usize,
+    column: usize,
+}
+
+//A scope or variable in the locals buffer
+#[derive(Debug)]
+struct VariableNode {
+    depth: usize,
+    //Names from the scope down, so expanded variables stay expanded from
+    //one stop to the next
+    path: String,
+    name: String,
+    value: String,
+    kind: Option<String>,
+    //Nonzero if it has children to fetch
+    reference: i64,
+    expanded: bool,
+    children: Vec<usize>,
+}
+
+//The locals buffer's contents as they come in
+#[derive(Debug, Default)]
+struct Locals {
+    //Bumped on every refresh so answers for an earlier one get ignored
+    generation: u64,
+    nodes: Vec<VariableNode>,
+    scopes: Vec<usize>,
+    //Requests still out, it's shown once there are none
+    outstanding: usize,
+}
+
+struct Session {
+    config: LaunchConfig,
+    connection: Connection,
+    next_seq: i64,
+    pending: HashMap<i64, ResponseCallback>,
+    capabilities: Json,
+    //The thread last heard of, stepping and continuing act on it
+    thread: Option<i64>,
+    running: bool,
+    frames: Vec<Frame>,
+    frame: usize,
+    //The line it's stopped at
+    stopped: Option<(BufferId, OverlayId)>,
+    //Bumped on every stop and frame change, the same way
+    generation: u64,
+    locals: Locals,
+    //Set once disconnect was sent
+    finishing: bool,
+}
+
+impl Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("config", &self.config)
+            .field("pending", &self.pending.len())
+            .field("thread", &self.thread)
+            .field("running", &self.running)
+            .field("frames", &self.frames)
+            .finish()
+    }
+}
+
+impl Session {
+    fn send(&mut self, mut message: Json) {
+        message["seq"] = json!(self.next_seq);
+        self.next_seq += 1;
+        self.connection.send(&message);
+    }
+
+    fn request(&mut self, command: &str, arguments: Json, callback: ResponseCallback) {
+        self.pending.insert(self.next_seq, callback);
+        self.send(json!({"type": "request", "command": command, "arguments": arguments}));
+    }
+
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn frame_id(&self) -> Option<i64> {
+        self.frames.get(self.frame).map(|f| f.id)
+    }
+
+    fn clear_locals(&mut self) -> u64 {
+        let generation = self.locals.generation + 1;
+        self.locals = Locals {
+            generation,
+            ..Locals::default()
+        };
+        generation
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Dap {
+    adapters: HashMap<String, AdapterConfig>,
+    configurations: Vec<LaunchConfig>,
+    session: Option<Session>,
+    breakpoints: HashMap<BufferId, Vec<OverlayId>>,
+    watches: Vec<String>,
+    //What each watch came to at the last stop, None until it's known
+    watch_values: Vec<Option<Result<String, String>>>,
+    //Paths of the locals that are the other way round from usual: scopes
+    //start out expanded, variables collapsed
+    toggled: HashSet<String>,
+    //The path of the variable on each line of the locals buffer
+    variable_lines: Vec<Option<String>>,
+    //Where what's being typed into the REPL starts
+    repl_input: Option<(BufferId, MarkerId)>,
+}
+
+impl Dap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_adapter(&mut self, name: &str, config: AdapterConfig) {
+        self.adapters.insert(name.to_owned(), config);
+    }
+
+    //Replaces any configuration with the same name
+    pub fn add_configuration(&mut self, config: LaunchConfig) {
+        self.configurations.retain(|c| c.name != config.name);
+        self.configurations.push(config);
+    }
+
+    pub fn configurations(&self) -> &[LaunchConfig] {
+        &self.configurations
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.session.is_some()
+    }
+
+    //Whether the adapter still owes us an answer
+    pub fn has_pending_requests(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| !s.pending.is_empty())
+    }
+
+    pub fn watches(&self) -> &[String] {
+        &self.watches
+    }
+}
+
+//The lines with breakpoints in `buffer`, zero based
+pub fn breakpoint_lines(editor: &Editor, buffer: BufferId) -> Vec<usize> {
+    let Some(buf) = editor.buffer(buffer) else {
+        return vec![];
+    };
+    let mut lines: Vec<usize> = editor
+        .dap()
+        .breakpoints
+        .get(&buffer)
+        .into_iter()
+        .flatten()
+        .filter_map(|overlay| buf.overlay_range(*overlay))
+        .map(|range| buf.text().char_to_line(range.start))
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+//Picks one of the debug configurations and starts debugging it
+pub(crate) fn debug(editor: &mut Editor) -> Result<(), String> {
+    if let Some(session) = &editor.dap().session {
+        return Err(format!("Already debugging {}", session.name()));
+    }
+    let names: Vec<String> = editor
+        .dap()
+        .configurations
+        .iter()
+        .map(|c| c.name.clone())
+        .collect();
+    if names.is_empty() {
+        return Err("No debug configurations, define-debug-configuration adds them".to_owned());
+    }
+    let initial = if names.len() == 1 {
+        names[0].clone()
+    } else {
+        String::new()
+    };
+    editor.completing_read(
+        "Debug: ",
+        names,
+        true,
+        &initial,
+        Box::new(|editor, name| {
+            if let Some(name) = name {
+                if let Err(e) = start(editor, &name) {
+                    editor.message(e);
+                }
+            }
+        }),
+    );
+    Ok(())
+}
+
+//Starts the adapter for configuration `name` and has it launch or attach
+pub(crate) fn start(editor: &mut Editor, name: &str) -> Result<(), String> {
+    if let Some(session) = &editor.dap().session {
+        return Err(format!("Already debugging {}", session.name()));
+    }
+    let dap = editor.dap();
+    let mut config = dap
+        .configurations
+        .iter()
+        .find(|c| c.name == name)
+        .cloned()
+        .ok_or_else(|| format!("No debug configuration named {name}"))?;
+    let adapter = dap
+        .adapters
+        .get(&config.adapter)
+        .cloned()
+        .ok_or_else(|| format!("No debug adapter named {}", config.adapter))?;
+    let background = editor
+        .background()
+        .cloned()
+        .ok_or_else(|| "Debugging needs the async runtime".to_owned())?;
+    let root = project::project_root(&editor.default_directory());
+    let file = editor
+        .current_buffer()
+        .file_path()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    substitute(
+        &mut config.arguments,
+        &[
+            ("${workspaceFolder}", &root.to_string_lossy()),
+            ("${file}", &file),
+        ],
+    );
+    let connection = Connection::spawn(&background, &adapter.command, &adapter.args, &root)?;
+    let adapter_id = config.adapter.clone();
+    editor.dap_mut().session = Some(Session {
+        config,
+        connection,
+        next_seq: 1,
+        pending: HashMap::new(),
+        capabilities: Json::Null,
+        thread: None,
+        running: true,
+        frames: vec![],
+        frame: 0,
+        stopped: None,
+        generation: 0,
+        locals: Locals::default(),
+        finishing: false,
+    });
+    repl_output(
+        editor,
+        &format!("Debugging {name} with {}\n", adapter.command),
+    );
+    show_panes(editor);
+    editor.message(format!("Starting {}", adapter.command));
+    request(
+        editor,
+        "initialize",
+        json!({
+            "clientID": "bunmacs",
+            "clientName": "Bunmacs",
+            "adapterID": adapter_id,
+            "pathFormat": "path",
+            "linesStartAt1": true,
+            "columnsStartAt1": true,
+            "supportsVariableType": true,
+        }),
+        Box::new(initialized),
+    );
+    Ok(())
+}
+
+fn substitute(value: &mut Json, variables: &[(&str, &str)]) {
+    match value {
+        Json::String(s) => {
+            for (name, replacement) in variables {
+                *s = s.replace(name, replacement);
+            }
+        }
+        Json::Array(items) => items.iter_mut().for_each(|v| substitute(v, variables)),
+        Json::Object(fields) => fields.values_mut().for_each(|v| substitute(v, variables)),
+        _ => {}
+    }
+}
+
+fn initialized(editor: &mut Editor, result: Result<Json, String>) {
+    let capabilities = match result {
+        Ok(capabilities) => capabilities,
+        Err(e) => {
+            finished(editor, &format!("Couldn't start the debug adapter: {e}"));
+            return;
+        }
+    };
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    session.capabilities = capabilities;
+    let (request_kind, arguments) = (session.config.request, session.config.arguments.clone());
+    //Most adapters only answer once configurationDone is sent, after the
+    //initialized event, so this is just for failures
+    request(
+        editor,
+        request_kind.command(),
+        arguments,
+        Box::new(move |editor, result| {
+            if let Err(e) = result {
+                finished(editor, &format!("Couldn't {}: {e}", request_kind.command()));
+            }
+        }),
+    );
+}
+
+fn request(editor: &mut Editor, command: &str, arguments: Json, callback: ResponseCallback) {
+    if let Some(session) = editor.dap_mut().session.as_mut() {
+        session.request(command, arguments, callback);
+    }
+}
+
+//Handles everything the adapter sent since last time. True if there was
+//anything.
+pub(crate) fn process(editor: &mut Editor) -> bool {
+    let mut any = false;
+    while let Some(session) = &editor.dap().session {
+        let Some(incoming) = session.connection.try_recv() else {
+            break;
+        };
+        any = true;
+        match incoming {
+            Incoming::Message(message) => handle_message(editor, message),
+            Incoming::Closed => {
+                let finishing = editor.dap().session.as_ref().is_some_and(|s| s.finishing);
+                finished(
+                    editor,
+                    if finishing {
+                        "Debugging finished"
+                    } else {
+                        "Debug adapter exited"
+                    },
+                );
+            }
+        }
+    }
+    any
+}
+
+fn handle_message(editor: &mut Editor, message: Json) {
+    match message["type"].as_str() {
+        Some("response") => {
+            let callback = message["request_seq"].as_i64().and_then(|seq| {
+                editor
+                    .dap_mut()
+                    .session
+                    .as_mut()
+                    .and_then(|s| s.pending.remove(&seq))
+            });
+            let Some(callback) = callback else {
+                log::warn!("Response to a request we didn't make: {message}");
+                return;
+            };
+            let result = if message["success"].as_bool() == Some(true) {
+                Ok(message.get("body").cloned().unwrap_or(Json::Null))
+            } else {
+                Err(message["body"]["error"]["format"]
+                    .as_str()
+                    .or(message["message"].as_str())
+                    .unwrap_or("Failed")
+                    .to_owned())
+            };
+            callback(editor, result);
+        }
+        Some("event") => {
+            let name = message["event"].as_str().unwrap_or_default();
+            event(editor, name, &message["body"]);
+        }
+        //Reverse requests like runInTerminal, none of which we do
+        Some("request") => {
+            if let Some(session) = editor.dap_mut().session.as_mut() {
+                session.send(json!({
+                    "type": "response",
+                    "request_seq": message["seq"],
+                    "command": message["command"],
+                    "success": false,
+                    "message": "Not supported",
+                }));
+            }
+        }
+        _ => log::warn!("Not a debug adapter message: {message}"),
+    }
+}
+
+fn event(editor: &mut Editor, name: &str, body: &Json) {
+    match name {
+        "initialized" => configure(editor),
+        "stopped" => {
+            let thread = body["threadId"]
+                .as_i64()
+                .or_else(|| editor.dap().session.as_ref()?.thread);
+            let reason = body["description"]
+                .as_str()
+                .or(body["reason"].as_str())
+                .unwrap_or("paused");
+            let text = body["text"]
+                .as_str()
+                .map_or(String::new(), |t| format!(": {t}"));
+            editor.message(format!("Stopped ({reason}{text})"));
+            match thread {
+                Some(thread) => stopped(editor, thread),
+                //Stopped without saying which thread, so take the first
+                None => request(
+                    editor,
+                    "threads",
+                    json!({}),
+                    Box::new(|editor, result| {
+                        match result.map(|body| body["threads"][0]["id"].as_i64()) {
+                            Ok(Some(thread)) => stopped(editor, thread),
+                            Ok(None) => editor.message("Stopped, but there are no threads"),
+                            Err(e) => editor.message(e),
+                        }
+                    }),
+                ),
+            }
+        }
+        "continued" => running(editor),
+        "output" => {
+            if body["category"].as_str() != Some("telemetry") {
+                repl_output(editor, body["output"].as_str().unwrap_or_default());
+            }
+        }
+        "exited" => {
+            let code = body["exitCode"].as_i64().unwrap_or_default();
+            repl_output(editor, &format!("Exited with code {code}\n"));
+        }
+        //The debuggee is done, the adapter goes once it's disconnected
+        "terminated" => {
+            if let Err(e) = disconnect(editor) {
+                editor.message(e);
+            }
+        }
+        _ => log::debug!("Debug adapter event {name}: {body}"),
+    }
+}
+
+//Tells a new session about the breakpoints and that it can start
+fn configure(editor: &mut Editor) {
+    let buffers: Vec<BufferId> = editor.dap().breakpoints.keys().copied().collect();
+    for buffer in buffers {
+        send_breakpoints(editor, buffer);
+    }
+    let done = editor
+        .dap()
+        .session
+        .as_ref()
+        .is_some_and(|s| s.capabilities["supportsConfigurationDoneRequest"] == json!(true));
+    if done {
+        request(
+            editor,
+            "configurationDone",
+            json!({}),
+            Box::new(|editor, result| {
+                if let Err(e) = result {
+                    editor.message(e);
+                }
+            }),
+        );
+    }
+}
+
+//Replaces the adapter's breakpoints for `buffer`'s file with the ones in
+//the buffer, and marks the ones it couldn't place
+fn send_breakpoints(editor: &mut Editor, buffer: BufferId) {
+    if editor.dap().session.is_none() {
+        return;
+    }
+    let Some(path) = editor.buffer(buffer).and_then(|b| b.file_path()) else {
+        return;
+    };
+    let source = json!({
+        "path": path,
+        "name": path.file_name().map(|n| n.to_string_lossy().into_owned()),
+    });
+    let lines = breakpoint_lines(editor, buffer);
+    let breakpoints: Vec<Json> = lines.iter().map(|l| json!({"line": l + 1})).collect();
+    request(
+        editor,
+        "setBreakpoints",
+        json!({"source": source, "breakpoints": breakpoints, "lines": lines.iter().map(|l| l + 1).collect::<Vec<_>>()}),
+        Box::new(move |editor, result| {
+            let body = match result {
+                Ok(body) => body,
+                Err(e) => {
+                    editor.message(format!("Couldn't set breakpoints: {e}"));
+                    return;
+                }
+            };
+            let verified: HashSet<usize> = lines
+                .iter()
+                .zip(body["breakpoints"].as_array().into_iter().flatten())
+                .filter(|(_, b)| b["verified"].as_bool() != Some(false))
+                .map(|(line, _)| *line)
+                .collect();
+            mark_breakpoints(editor, buffer, |line| verified.contains(&line));
+        }),
+    );
+}
+
+//Redoes the fringe marks of `buffer`'s breakpoints, telling the ones the
+//adapter placed from the ones it couldn't
+fn mark_breakpoints(editor: &mut Editor, buffer: BufferId, verified: impl Fn(usize) -> bool) {
+    let overlays = editor
+        .dap()
+        .breakpoints
+        .get(&buffer)
+        .cloned()
+        .unwrap_or_default();
+    let Some(buf) = editor.buffer_mut(buffer) else {
+        return;
+    };
+    for overlay in overlays {
+        let Some(range) = buf.overlay_range(overlay) else {
+            continue;
+        };
+        let face = if verified(buf.text().char_to_line(range.start)) {
+            face::DAP_BREAKPOINT
+        } else {
+            face::DAP_BREAKPOINT_UNVERIFIED
+        };
+        buf.set_overlay_fringe(overlay, BREAKPOINT_MARK, face);
+    }
+    editor.request_redraw();
+}
+
+//Gives `buffer` a fringe if it has none, for the debugger's marks
+fn show_fringe(editor: &mut Editor, buffer: BufferId) {
+    editor.with_current_buffer(buffer, |editor| {
+        if editor.variable("left-fringe-width") == Some(&Value::Number(0)) {
+            if let Err(e) = editor.set_local("left-fringe-width", Value::Number(1)) {
+                editor.message(e);
+            }
+        }
+    });
+}
+
+//Sets a breakpoint on the line with point, or clears the ones there
+pub(crate) fn toggle_breakpoint(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let id = buffer.id();
+    if buffer.file_path().is_none() {
+        return Err("Breakpoints go in buffers visiting files".to_owned());
+    }
+    let text = buffer.text();
+    let line = text.char_to_line(buffer.point());
+    let line_start = text.line_to_char(line);
+    let existing: Vec<OverlayId> = editor
+        .dap()
+        .breakpoints
+        .get(&id)
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|overlay| {
+            buffer
+                .overlay_range(*overlay)
+                .is_some_and(|range| text.char_to_line(range.start) == line)
+        })
+        .collect();
+    if existing.is_empty() {
+        let buf = editor.current_buffer_mut();
+        let overlay = buf.make_overlay(
+            line_start,
+            line_start,
+            face::DAP_BREAKPOINT,
+            BREAKPOINT_PRIORITY,
+        );
+        buf.set_overlay_fringe(overlay, BREAKPOINT_MARK, face::DAP_BREAKPOINT);
+        editor
+            .dap_mut()
+            .breakpoints
+            .entry(id)
+            .or_default()
+            .push(overlay);
+        show_fringe(editor, id);
+        editor.message(format!("Breakpoint at line {}", line + 1));
+    } else {
+        for overlay in &existing {
+            editor.current_buffer_mut().delete_overlay(*overlay);
+        }
+        if let Some(overlays) = editor.dap_mut().breakpoints.get_mut(&id) {
+            overlays.retain(|o| !existing.contains(o));
+        }
+        editor.message(format!("Removed breakpoint at line {}", line + 1));
+    }
+    editor.request_redraw();
+    send_breakpoints(editor, id);
+    Ok(())
+}
+
+pub(crate) fn buffer_killed(editor: &mut Editor, id: BufferId) {
+    let dap = editor.dap_mut();
+    dap.breakpoints.remove(&id);
+    if dap.repl_input.is_some_and(|(buffer, _)| buffer == id) {
+        dap.repl_input = None;
+    }
+    if let Some(session) = dap.session.as_mut() {
+        if session.stopped.is_some_and(|(buffer, _)| buffer == id) {
+            session.stopped = None;
+        }
+    }
+}
+
+fn stopped(editor: &mut Editor, thread: i64) {
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    session.thread = Some(thread);
+    session.running = false;
+    session.generation += 1;
+    let generation = session.generation;
+    request(
+        editor,
+        "stackTrace",
+        json!({"threadId": thread}),
+        Box::new(move |editor, result| {
+            let body = match result {
+                Ok(body) => body,
+                Err(e) => {
+                    editor.message(format!("Couldn't get the stack: {e}"));
+                    return;
+                }
+            };
+            let Some(session) = editor.dap_mut().session.as_mut() else {
+                return;
+            };
+            if session.generation != generation {
+                return;
+            }
+            session.frames = body["stackFrames"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|frame| Frame {
+                    id: frame["id"].as_i64().unwrap_or_default(),
+                    name: frame["name"].as_str().unwrap_or("??").to_owned(),
+                    path: frame["source"]["path"].as_str().map(PathBuf::from),
+                    line: frame["line"].as_u64().unwrap_or(1) as usize,
+                    column: frame["column"].as_u64().unwrap_or(1) as usize,
+                })
+                .collect();
+            select_frame(editor, 0);
+        }),
+    );
+}
+
+//Forgets where it was stopped once the debuggee runs again
+fn running(editor: &mut Editor) {
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    session.running = true;
+    session.frames.clear();
+    session.frame = 0;
+    session.generation += 1;
+    session.clear_locals();
+    let stopped = session.stopped.take();
+    let dap = editor.dap_mut();
+    dap.watch_values = vec![None; dap.watches.len()];
+    dap.variable_lines.clear();
+    if let Some((buffer, overlay)) = stopped {
+        if let Some(buffer) = editor.buffer_mut(buffer) {
+            buffer.delete_overlay(overlay);
+        }
+    }
+    show_panes(editor);
+}
+
+fn select_frame(editor: &mut Editor, frame: usize) {
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    session.frame = frame;
+    session.generation += 1;
+    show_source(editor);
+    refresh_locals(editor);
+    refresh_watches(editor);
+    show_panes(editor);
+}
+
+//Shows the selected frame's line with an arrow in the fringe
+fn show_source(editor: &mut Editor) {
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    let old = session.stopped.take();
+    let location = session
+        .frames
+        .get(session.frame)
+        .map(|f| (f.path.clone(), f.line, f.column));
+    if let Some((buffer, overlay)) = old {
+        if let Some(buffer) = editor.buffer_mut(buffer) {
+            buffer.delete_overlay(overlay);
+        }
+    }
+    let Some((path, line, column)) = location else {
+        return;
+    };
+    let Some(path) = path else {
+        editor.message("No source for this frame");
+        return;
+    };
+    let buffer = match editor.find_file_noselect(&path) {
+        Ok(buffer) => buffer,
+        Err(e) => {
+            editor.message(format!("Could not visit {}: {e}", path.display()));
+            return;
+        }
+    };
+    let buf = editor.buffer_mut(buffer).expect("visited buffer");
+    let text = buf.text();
+    let line = (line.max(1) - 1).min(text.len_lines() - 1);
+    let start = text.line_to_char(line);
+    let end = text.line_to_char((line + 1).min(text.len_lines()));
+    let point = (start + column.max(1) - 1).min(start + text.line(line).len_chars());
+    let overlay = buf.make_overlay(start, end, face::DAP_STOPPED_LINE, STOPPED_PRIORITY);
+    buf.set_overlay_fringe(overlay, STOPPED_MARK, face::DAP_STOPPED);
+    buf.set_point(point);
+    if let Some(session) = editor.dap_mut().session.as_mut() {
+        session.stopped = Some((buffer, overlay));
+    }
+    show_fringe(editor, buffer);
+    //Stays out of the way of the debugger's own buffers
+    let in_pane = [REPL_MODE, STACK_MODE, LOCALS_MODE, WATCH_MODE]
+        .contains(&editor.current_buffer().major_mode());
+    if in_pane {
+        if let Err(e) = editor.display_buffer_other_window(buffer, false) {
+            editor.message(e);
+        }
+    } else {
+        editor.switch_to_buffer(buffer);
+    }
+    editor.request_redraw();
+}
+
+//The session's thread if it's stopped
+fn stopped_thread(editor: &Editor) -> Result<i64, String> {
+    let session = editor
+        .dap()
+        .session
+        .as_ref()
+        .ok_or_else(|| "Not debugging anything".to_owned())?;
+    match session.thread {
+        Some(thread) if !session.running => Ok(thread),
+        _ => Err(format!("{} is running", session.name())),
+    }
+}
+
+//continue, next, stepIn or stepOut on the stopped thread
+fn resume(editor: &mut Editor, command: &str) -> Result<(), String> {
+    let thread = stopped_thread(editor)?;
+    request(
+        editor,
+        command,
+        json!({"threadId": thread}),
+        Box::new(|editor, result| {
+            if let Err(e) = result {
+                editor.message(e);
+            }
+        }),
+    );
+    //Adapters don't send continued for what we asked for
+    running(editor);
+    Ok(())
+}
+
+pub(crate) fn continue_execution(editor: &mut Editor) -> Result<(), String> {
+    resume(editor, "continue")
+}
+
+pub(crate) fn next(editor: &mut Editor) -> Result<(), String> {
+    resume(editor, "next")
+}
+
+pub(crate) fn step_in(editor: &mut Editor) -> Result<(), String> {
+    resume(editor, "stepIn")
+}
+
+pub(crate) fn step_out(editor: &mut Editor) -> Result<(), String> {
+    resume(editor, "stepOut")
+}
+
+pub(crate) fn pause(editor: &mut Editor) -> Result<(), String> {
+    let session = editor
+        .dap()
+        .session
+        .as_ref()
+        .ok_or_else(|| "Not debugging anything".to_owned())?;
+    if !session.running {
+        return Err(format!("{} is already stopped", session.name()));
+    }
+    //Adapters take any thread to mean all of them when there's one
+    let thread = session.thread.unwrap_or(1);
+    request(
+        editor,
+        "pause",
+        json!({"threadId": thread}),
+        Box::new(|editor, result| {
+            if let Err(e) = result {
+                editor.message(e);
+            }
+        }),
+    );
+    Ok(())
+}
+
+//Moves `count` frames towards the caller, towards the callee if negative
+fn move_frame(editor: &mut Editor, count: isize) -> Result<(), String> {
+    stopped_thread(editor)?;
+    let session = editor.dap().session.as_ref().expect("session");
+    let frame = session.frame as isize + count;
+    if frame < 0 {
+        return Err("Bottom of stack".to_owned());
+    }
+    if frame as usize >= session.frames.len() {
+        return Err("Top of stack".to_owned());
+    }
+    select_frame(editor, frame as usize);
+    Ok(())
+}
+
+pub(crate) fn up(editor: &mut Editor) -> Result<(), String> {
+    move_frame(editor, 1)
+}
+
+pub(crate) fn down(editor: &mut Editor) -> Result<(), String> {
+    move_frame(editor, -1)
+}
+
+//Ends the session, asking the adapter to stop what it launched. Asking
+//again doesn't wait for it.
+pub(crate) fn disconnect(editor: &mut Editor) -> Result<(), String> {
+    let session = editor
+        .dap_mut()
+        .session
+        .as_mut()
+        .ok_or_else(|| "Not debugging anything".to_owned())?;
+    if session.finishing {
+        finished(editor, "Debugging finished");
+        return Ok(());
+    }
+    session.finishing = true;
+    let terminate = session.config.request == Request::Launch;
+    request(
+        editor,
+        "disconnect",
+        json!({"terminateDebuggee": terminate}),
+        Box::new(|editor, _| finished(editor, "Debugging finished")),
+    );
+    Ok(())
+}
+
+//Drops the session, which kills the adapter if it's still going
+fn finished(editor: &mut Editor, message: &str) {
+    running(editor);
+    if editor.dap_mut().session.take().is_none() {
+        return;
+    }
+    let buffers: Vec<BufferId> = editor.dap().breakpoints.keys().copied().collect();
+    for buffer in buffers {
+        mark_breakpoints(editor, buffer, |_| true);
+    }
+    repl_output(editor, &format!("{message}\n"));
+    show_panes(editor);
+    editor.message(message);
+}
+
+//Evaluates the region, or the word at point, in the selected frame
+pub(crate) fn eval(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let range = match buffer.region() {
+        Some((start, end)) => start..end,
+        None => crate::lsp::symbol_at_point(editor),
+    };
+    let expression = buffer.text().slice(range).to_string();
+    if expression.trim().is_empty() {
+        return Err("Nothing to evaluate".to_owned());
+    }
+    evaluate(
+        editor,
+        &expression.clone(),
+        "watch",
+        move |editor, result| match result {
+            Ok(value) => editor.message(format!("{expression} = {value}")),
+            Err(e) => editor.message(format!("{expression}: {e}")),
+        },
+    )
+}
+
+fn evaluate(
+    editor: &mut Editor,
+    expression: &str,
+    context: &str,
+    callback: impl FnOnce(&mut Editor, Result<String, String>) + 'static,
+) -> Result<(), String> {
+    let session = editor
+        .dap()
+        .session
+        .as_ref()
+        .ok_or_else(|| "Not debugging anything".to_owned())?;
+    let mut arguments = json!({"expression": expression, "context": context});
+    if let Some(frame) = session.frame_id().filter(|_| !session.running) {
+        arguments["frameId"] = json!(frame);
+    }
+    request(
+        editor,
+        "evaluate",
+        arguments,
+        Box::new(move |editor, result| {
+            callback(
+                editor,
+                result.map(|body| body["result"].as_str().unwrap_or_default().to_owned()),
+            )
+        }),
+    );
+    Ok(())
+}
+
+//Fetches the selected frame's scopes and whatever's expanded in them,
+//showing them in the locals buffer once they're all in
+fn refresh_locals(editor: &mut Editor) {
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    let generation = session.clear_locals();
+    let Some(frame) = session.frame_id() else {
+        return;
+    };
+    session.locals.outstanding += 1;
+    request(
+        editor,
+        "scopes",
+        json!({"frameId": frame}),
+        Box::new(move |editor, result| {
+            let dap = editor.dap_mut();
+            let Some(session) = dap
+                .session
+                .as_mut()
+                .filter(|s| s.locals.generation == generation)
+            else {
+                return;
+            };
+            session.locals.outstanding -= 1;
+            let scopes = match result {
+                Ok(body) => body["scopes"].as_array().cloned().unwrap_or_default(),
+                Err(e) => {
+                    editor.message(format!("Couldn't get the locals: {e}"));
+                    return;
+                }
+            };
+            let mut fetch = vec![];
+            for scope in scopes {
+                let name = scope["name"].as_str().unwrap_or("Scope").to_owned();
+                let expensive = scope["expensive"].as_bool() == Some(true);
+                let node = VariableNode {
+                    depth: 0,
+                    expanded: expensive == dap.toggled.contains(&name),
+                    path: name.clone(),
+                    name,
+                    value: String::new(),
+                    kind: None,
+                    reference: scope["variablesReference"].as_i64().unwrap_or_default(),
+                    children: vec![],
+                };
+                let i = session.locals.nodes.len();
+                if node.expanded && node.reference > 0 {
+                    fetch.push(i);
+                }
+                session.locals.nodes.push(node);
+                session.locals.scopes.push(i);
+            }
+            for node in fetch {
+                fetch_variables(editor, generation, node);
+            }
+            if editor
+                .dap()
+                .session
+                .as_ref()
+                .is_some_and(|s| s.locals.outstanding == 0)
+            {
+                show_locals(editor);
+            }
+        }),
+    );
+}
+
+fn fetch_variables(editor: &mut Editor, generation: u64, parent: usize) {
+    let Some(session) = editor.dap_mut().session.as_mut() else {
+        return;
+    };
+    let reference = session.locals.nodes[parent].reference;
+    session.locals.outstanding += 1;
+    request(
+        editor,
+        "variables",
+        json!({"variablesReference": reference}),
+        Box::new(move |editor, result| {
+            let dap = editor.dap_mut();
+            let Some(session) = dap
+                .session
+                .as_mut()
+                .filter(|s| s.locals.generation == generation)
+            else {
+                return;
+            };
+            session.locals.outstanding -= 1;
+            let variables = result
+                .map(|body| body["variables"].as_array().cloned().unwrap_or_default())
+                .unwrap_or_default();
+            let (depth, path) = {
+                let parent = &session.locals.nodes[parent];
+                (parent.depth + 1, parent.path.clone())
+            };
+            let mut fetch = vec![];
+            for variable in variables {
+                let name = variable["name"].as_str().unwrap_or_default().to_owned();
+                let path = format!("{path}/{name}");
+                let reference = variable["variablesReference"].as_i64().unwrap_or_default();
+                let i = session.locals.nodes.len();
+                let expanded = dap.toggled.contains(&path);
+                if expanded && reference > 0 {
+                    fetch.push(i);
+                }
+                session.locals.nodes.push(VariableNode {
+                    depth,
+                    path,
+                    name,
+                    value: variable["value"].as_str().unwrap_or_default().to_owned(),
+                    kind: variable["type"]
+                        .as_str()
+                        .filter(|t| !t.is_empty())
+                        .map(str::to_owned),
+                    reference,
+                    expanded,
+                    children: vec![],
+                });
+                session.locals.nodes[parent].children.push(i);
+            }
+            for node in fetch {
+                fetch_variables(editor, generation, node);
+            }
+            if editor
+                .dap()
+                .session
+                .as_ref()
+                .is_some_and(|s| s.locals.outstanding == 0)
+            {
+                show_locals(editor);
+            }
+        }),
+    );
+}
+
+fn refresh_watches(editor: &mut Editor) {
+    let dap = editor.dap_mut();
+    dap.watch_values = vec![None; dap.watches.len()];
+    let Some(generation) = dap
+        .session
+        .as_ref()
+        .filter(|s| !s.running)
+        .map(|s| s.generation)
+    else {
+        return;
+    };
+    for (i, watch) in dap.watches.clone().into_iter().enumerate() {
+        let _ = evaluate(editor, &watch, "watch", move |editor, result| {
+            let dap = editor.dap_mut();
+            if dap.session.as_ref().map(|s| s.generation) != Some(generation) {
+                return;
+            }
+            if let Some(value) = dap.watch_values.get_mut(i) {
+                *value = Some(result);
+            }
+            show_watches(editor);
+        });
+    }
+}
+
+//Asks for an expression to show in the watch buffer at every stop
+pub(crate) fn add_watch(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let initial = match buffer.region() {
+        Some((start, end)) => buffer.text().slice(start..end).to_string(),
+        None => buffer
+            .text()
+            .slice(crate::lsp::symbol_at_point(editor))
+            .to_string(),
+    };
+    editor.read_string(
+        "Watch: ",
+        &initial,
+        Box::new(|editor, expression| {
+            let Some(expression) = expression.filter(|e| !e.trim().is_empty()) else {
+                return;
+            };
+            editor.dap_mut().watches.push(expression);
+            refresh_watches(editor);
+            show_watches(editor);
+            let watch = pane(editor, WATCH_BUFFER, WATCH_MODE);
+            if let Err(e) = editor.display_buffer_other_window(watch, false) {
+                editor.message(e);
+            }
+        }),
+    );
+    Ok(())
+}
+
+//Stops watching the expression on the line with point
+pub(crate) fn remove_watch(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    if buffer.name() != WATCH_BUFFER {
+        return Err("Not in the watch buffer".to_owned());
+    }
+    let line = buffer.text().char_to_line(buffer.point());
+    let dap = editor.dap_mut();
+    if line >= dap.watches.len() {
+        return Err("No watch on this line".to_owned());
+    }
+    dap.watches.remove(line);
+    if line < dap.watch_values.len() {
+        dap.watch_values.remove(line);
+    }
+    show_watches(editor);
+    Ok(())
+}
+
+//Expands or collapses the variable on the line with point
+pub(crate) fn toggle_variable(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let line = buffer.text().char_to_line(buffer.point());
+    let dap = editor.dap_mut();
+    let path = dap
+        .variable_lines
+        .get(line)
+        .cloned()
+        .flatten()
+        .ok_or_else(|| "Nothing to expand on this line".to_owned())?;
+    if !dap.toggled.remove(&path) {
+        dap.toggled.insert(path);
+    }
+    refresh_locals(editor);
+    Ok(())
+}
+
+//Selects the frame on the line with point in the stack buffer
+pub(crate) fn goto_frame(editor: &mut Editor) -> Result<(), String> {
+    stopped_thread(editor)?;
+    let buffer = editor.current_buffer();
+    let line = buffer.text().char_to_line(buffer.point());
+    let frames = editor.dap().session.as_ref().map_or(0, |s| s.frames.len());
+    if line >= frames {
+        return Err("No frame on this line".to_owned());
+    }
+    select_frame(editor, line);
+    Ok(())
+}
+
+//Finds or makes the buffer called `name` in `mode`
+fn pane(editor: &mut Editor, name: &str, mode: &str) -> BufferId {
+    let existing = editor.buffers().find(|b| b.name() == name).map(|b| b.id());
+    let buffer = existing.unwrap_or_else(|| editor.create_buffer(name));
+    if editor
+        .buffer(buffer)
+        .is_some_and(|b| b.major_mode() != mode)
+    {
+        editor.with_current_buffer(buffer, |editor| {
+            if let Err(e) = editor.set_major_mode(mode) {
+                editor.message(e);
+            }
+        });
+    }
+    buffer
+}
+
+//Replaces the text of pane `name`, keeping point on the same line
+fn set_pane_text(editor: &mut Editor, name: &str, mode: &str, text: &str) -> BufferId {
+    let buffer = pane(editor, name, mode);
+    let buf = editor.buffer_mut(buffer).expect("pane");
+    let line = buf.text().char_to_line(buf.point());
+    buf.delete(0, buf.len_chars());
+    buf.insert(0, text);
+    let text = buf.text();
+    let line = line.min(text.len_lines() - 1);
+    buf.set_point(text.line_to_char(line));
+    editor.request_redraw();
+    buffer
+}
+
+fn show_panes(editor: &mut Editor) {
+    show_stack(editor);
+    show_locals(editor);
+    show_watches(editor);
+}
+
+fn show_stack(editor: &mut Editor) {
+    let text = match &editor.dap().session {
+        Some(session) if !session.running => session
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let current = if i == session.frame { '*' } else { ' ' };
+                let location = match &frame.path {
+                    Some(path) => format!(" at {}:{}", path.display(), frame.line),
+                    None => String::new(),
+                };
+                format!("{current}{i:>3} {}{location}\n", frame.name)
+            })
+            .collect(),
+        Some(session) => format!("{} is running\n", session.name()),
+        None => "Not debugging anything\n".to_owned(),
+    };
+    set_pane_text(editor, STACK_BUFFER, STACK_MODE, &text);
+}
+
+fn show_locals(editor: &mut Editor) {
+    let dap = editor.dap();
+    let mut text = String::new();
+    let mut lines = vec![];
+    match &dap.session {
+        Some(session) if !session.running => {
+            let locals = &session.locals;
+            let mut stack: Vec<usize> = locals.scopes.iter().rev().copied().collect();
+            while let Some(i) = stack.pop() {
+                let node = &locals.nodes[i];
+                let toggle = match (node.reference > 0, node.expanded) {
+                    (false, _) => "  ",
+                    (true, false) => "+ ",
+                    (true, true) => "- ",
+                };
+                let indent = "  ".repeat(node.depth);
+                let line = match (&node.kind, node.depth) {
+                    (_, 0) => node.name.clone(),
+                    (Some(kind), _) => format!("{}: {kind} = {}", node.name, node.value),
+                    (None, _) => format!("{} = {}", node.name, node.value),
+                };
+                //Values can be several lines, the buffer shows one per
+                //variable
+                let line = line.replace('\n', " ");
+                text.push_str(&format!("{indent}{toggle}{line}\n"));
+                lines.push((node.reference > 0).then(|| node.path.clone()));
+                if node.expanded {
+                    stack.extend(node.children.iter().rev());
+                }
+            }
+        }
+        Some(session) => text = format!("{} is running\n", session.name()),
+        None => text = "Not debugging anything\n".to_owned(),
+    }
+    editor.dap_mut().variable_lines = lines;
+    set_pane_text(editor, LOCALS_BUFFER, LOCALS_MODE, &text);
+}
+
+fn show_watches(editor: &mut Editor) {
+    let dap = editor.dap();
+    let text: String = dap
+        .watches
+        .iter()
+        .enumerate()
+        .map(|(i, watch)| match dap.watch_values.get(i) {
+            Some(Some(Ok(value))) => format!("{watch} = {}\n", value.replace('\n', " ")),
+            Some(Some(Err(e))) => format!("{watch}: {e}\n"),
+            _ => format!("{watch}\n"),
+        })
+        .collect();
+    set_pane_text(editor, WATCH_BUFFER, WATCH_MODE, &text);
+}
+
+//The REPL buffer, made with a prompt at the end if there isn't one
+fn repl(editor: &mut Editor) -> (BufferId, MarkerId) {
+    if let Some(repl) = editor.dap().repl_input {
+        if editor.buffer(repl.0).is_some() {
+            return repl;
+        }
+    }
+    let buffer = pane(editor, REPL_BUFFER, REPL_MODE);
+    let buf = editor.buffer_mut(buffer).expect("repl");
+    buf.insert(buf.len_chars(), PROMPT);
+    let input = buf.make_marker(buf.len_chars());
+    buf.set_point(buf.len_chars());
+    editor.dap_mut().repl_input = Some((buffer, input));
+    (buffer, input)
+}
+
+//Adds `text` to the REPL above the prompt
+fn repl_output(editor: &mut Editor, text: &str) {
+    let (buffer, input) = repl(editor);
+    let buf = editor.buffer_mut(buffer).expect("repl");
+    let Some(input) = buf.marker(input) else {
+        return;
+    };
+    buf.insert(input - PROMPT.chars().count(), text);
+    editor.request_redraw();
+}
+
+//Shows one of the debugger's buffers in another window
+fn display_pane(editor: &mut Editor, buffer: BufferId, select: bool) -> Result<(), String> {
+    if editor.current_buffer_id() == buffer {
+        return Ok(());
+    }
+    editor.display_buffer_other_window(buffer, select)
+}
+
+pub(crate) fn show_repl(editor: &mut Editor) -> Result<(), String> {
+    let (buffer, _) = repl(editor);
+    display_pane(editor, buffer, true)
+}
+
+pub(crate) fn show_stack_buffer(editor: &mut Editor) -> Result<(), String> {
+    show_stack(editor);
+    let buffer = pane(editor, STACK_BUFFER, STACK_MODE);
+    display_pane(editor, buffer, false)
+}
+
+pub(crate) fn show_locals_buffer(editor: &mut Editor) -> Result<(), String> {
+    show_locals(editor);
+    let buffer = pane(editor, LOCALS_BUFFER, LOCALS_MODE);
+    display_pane(editor, buffer, false)
+}
+
+pub(crate) fn show_watch_buffer(editor: &mut Editor) -> Result<(), String> {
+    show_watches(editor);
+    let buffer = pane(editor, WATCH_BUFFER, WATCH_MODE);
+    display_pane(editor, buffer, false)
+}
+
+//Evaluates what's been typed after the REPL's prompt
+pub(crate) fn repl_send(editor: &mut Editor) -> Result<(), String> {
+    let (buffer, input) = repl(editor);
+    if editor.current_buffer_id() != buffer {
+        return Err("Not in the debugger REPL".to_owned());
+    }
+    let buf = editor.current_buffer_mut();
+    let start = buf.marker(input).unwrap_or(buf.len_chars());
+    let expression = buf.text().slice(start..).to_string();
+    let expression = expression.trim().to_owned();
+    buf.insert(buf.len_chars(), &format!("\n{PROMPT}"));
+    let end = buf.len_chars();
+    buf.set_marker(input, end);
+    buf.set_point(end);
+    if expression.is_empty() {
+        return Ok(());
+    }
+    evaluate(editor, &expression, "repl", |editor, result| {
+        let output = match result {
+            Ok(value) => value,
+            Err(e) => format!("Error: {e}"),
+        };
+        repl_output(editor, &format!("{output}\n"));
+        //Whatever's shown might have changed
+        if editor.dap().session.as_ref().is_some_and(|s| !s.running) {
+            refresh_locals(editor);
+            refresh_watches(editor);
+        }
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::connection::fake::{self, Script, ID};
+    use std::fs;
+    use tokio::runtime::Runtime;
+
+    const SOURCE: &str = "fn main() {\n    let x = 1;\n}\n";
+
+    //An editor visiting main.txt in a fresh directory with a "run"
+    //configuration for an adapter that follows `script`
+    fn debugging(name: &str, script: impl FnOnce(&str) -> Script) -> (Editor, Runtime, BufferId) {
+        let dir = std::env::temp_dir().join(format!("bunmacs-dap-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.txt");
+        fs::write(&path, SOURCE).unwrap();
+        let (command, args) = script(&path.to_string_lossy()).command();
+
+        let runtime = fake::runtime();
+        let mut editor = Editor::new();
+        editor.set_async_runtime(runtime.handle().clone(), None);
+        let dap = editor.dap_mut();
+        dap.set_adapter("fake", AdapterConfig { command, args });
+        dap.add_configuration(LaunchConfig {
+            name: "run".to_owned(),
+            adapter: "fake".to_owned(),
+            request: Request::Launch,
+            arguments: json!({"program": "${file}"}),
+        });
+        let buffer = editor.find_file(&path).unwrap();
+        (editor, runtime, buffer)
+    }
+
+    fn response(body: Json) -> Json {
+        json!({"type": "response", "request_seq": ID, "success": true, "body": body})
+    }
+
+    fn event(name: &str, body: Json) -> Json {
+        json!({"type": "event", "event": name, "body": body})
+    }
+
+    fn pane_text(editor: &Editor, name: &str) -> String {
+        editor
+            .buffers()
+            .find(|b| b.name() == name)
+            .map(|b| b.text().to_string())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_runs_to_the_end() {
+        let (mut editor, _runtime, buffer) = debugging("session", |path| {
+            Script::new("seq")
+                .expect(&[r#""command":"initialize""#, r#""adapterID":"fake""#])
+                .send(response(json!({"supportsConfigurationDoneRequest": true})))
+                .expect(&[r#""command":"launch""#, &format!(r#""program":"{path}""#)])
+                .send(event("initialized", json!({})))
+                .expect(&[r#""command":"setBreakpoints""#, r#""lines":[2]"#])
+                .send(response(
+                    json!({"breakpoints": [{"verified": true, "line": 2}]}),
+                ))
+                .expect(&[r#""command":"configurationDone""#])
+                .send(response(json!({})))
+                .send(event(
+                    "stopped",
+                    json!({"reason": "breakpoint", "threadId": 1}),
+                ))
+                .expect(&[r#""command":"stackTrace""#, r#""threadId":1"#])
+                .send(response(json!({"stackFrames": [{
+                    "id": 1000,
+                    "name": "main",
+                    "source": {"path": path},
+                    "line": 2,
+                    "column": 9,
+                }]})))
+                .expect(&[r#""command":"scopes""#, r#""frameId":1000"#])
+                .send(response(
+                    json!({"scopes": [{"name": "Locals", "variablesReference": 5}]}),
+                ))
+                .expect(&[r#""command":"variables""#, r#""variablesReference":5"#])
+                .send(response(json!({"variables": [{
+                    "name": "x",
+                    "value": "1",
+                    "type": "i32",
+                    "variablesReference": 0,
+                }]})))
+                .expect(&[r#""command":"continue""#, r#""threadId":1"#])
+                .send(response(json!({})))
+                .send(event(
+                    "output",
+                    json!({"category": "stdout", "output": "hello\n"}),
+                ))
+                .send(event("exited", json!({"exitCode": 0})))
+                .send(event("terminated", json!({})))
+                .expect(&[r#""command":"disconnect""#, r#""terminateDebuggee":true"#])
+                .send(response(json!({})))
+        });
+        //A breakpoint on the second line
+        editor.current_buffer_mut().set_point(14);
+        editor.run_command("dap-toggle-breakpoint").unwrap();
+        assert_eq!(breakpoint_lines(&editor, buffer), [1]);
+
+        start(&mut editor, "run").unwrap();
+        assert!(editor.dap().is_running());
+        fake::wait_for(&mut editor, |editor| {
+            pane_text(editor, LOCALS_BUFFER).contains("x: i32 = 1")
+        });
+        assert_eq!(editor.current_message(), Some("Stopped (breakpoint)"));
+        assert_eq!(editor.current_buffer_id(), buffer);
+        //Line 2, column 9
+        assert_eq!(editor.current_buffer().point(), 20);
+        assert!(pane_text(&editor, STACK_BUFFER).starts_with("*  0 main at "));
+
+        editor.run_command("dap-continue").unwrap();
+        fake::wait_for(&mut editor, |editor| !editor.dap().is_running());
+        assert_eq!(editor.current_message(), Some("Debugging finished"));
+        let repl = pane_text(&editor, REPL_BUFFER);
+        assert!(
+            repl.ends_with("hello\nExited with code 0\nDebugging finished\n> "),
+            "{repl:?}"
+        );
+        //Still there for next time
+        assert_eq!(breakpoint_lines(&editor, buffer), [1]);
+    }
+
+    #[test]
+    fn reports_adapters_that_fail_to_start() {
+        let (mut editor, _runtime, _) = debugging("failure", |_| {
+            Script::new("seq")
+                .expect(&[r#""command":"initialize""#])
+                .send(json!({
+                    "type": "response",
+                    "request_seq": ID,
+                    "success": false,
+                    "message": "unsupported client",
+                }))
+                .expect(&[r#""command":"disconnect""#])
+        });
+        start(&mut editor, "run").unwrap();
+        fake::wait_for(&mut editor, |editor| !editor.dap().is_running());
+        assert_eq!(
+            editor.current_message(),
+            Some("Couldn't start the debug adapter: unsupported client")
+        );
+        assert!(start(&mut editor, "missing").is_err());
+    }
+}
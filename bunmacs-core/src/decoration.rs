@@ -0,0 +1,249 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    face::{self, FaceSpans, Faces},
+    layout::{Layout, Rect},
+};
+
+pub type Color = [f32; 4];
+
+//Everything that isn't text is drawn as flat colored rectangles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quad {
+    pub rect: Rect,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorStyle {
+    #[default]
+    Box,
+    Bar,
+    Underline,
+    //Outline of a box, what windows other than the selected one show
+    Hollow,
+}
+
+impl CursorStyle {
+    //From the cursor-type variable
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(CursorStyle::Box),
+            "bar" => Some(CursorStyle::Bar),
+            "underline" => Some(CursorStyle::Underline),
+            "hollow" => Some(CursorStyle::Hollow),
+            _ => None,
+        }
+    }
+}
+
+//Thickness of bar and underline cursors in pixels
+const THIN_CURSOR: f32 = 2.0;
+
+//Colors of what isn't text, looked up from faces at redisplay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub background: Color,
+    pub cursor: Color,
+    pub region: Color,
+    pub current_line: Color,
+    pub divider: Color,
+    pub completions_current: Color,
+}
+
+impl Palette {
+    pub fn new(faces: &Faces) -> Self {
+        let background = |name| faces.resolve(&[name]).background;
+        Palette {
+            background: background(face::DEFAULT),
+            cursor: background(face::CURSOR),
+            region: background(face::REGION),
+            current_line: background(face::HL_LINE),
+            divider: faces.resolve(&[face::VERTICAL_BORDER]).foreground,
+            completions_current: background(face::COMPLETIONS_CURRENT),
+        }
+    }
+}
+
+//The cells under the cursor at `point`, None if point is off screen
+pub fn cursor_cell(layout: &Layout, point: usize) -> Option<Rect> {
+    let cursor = layout.cursor_position(point)?;
+    Some(Rect {
+        x: cursor.x,
+        y: cursor.y,
+        width: cursor.cells as f32 * layout.metrics.width,
+        height: layout.metrics.height,
+    })
+}
+
+//What a cursor on `cell` looks like when drawn with rectangles
+pub fn cursor_rects(cell: Rect, style: CursorStyle) -> Vec<Rect> {
+    match style {
+        CursorStyle::Box => vec![cell],
+        CursorStyle::Hollow => outline_rects(cell).to_vec(),
+        CursorStyle::Bar => vec![Rect {
+            width: THIN_CURSOR,
+            ..cell
+        }],
+        CursorStyle::Underline => vec![Rect {
+            y: cell.y + cell.height - THIN_CURSOR,
+            height: THIN_CURSOR,
+            ..cell
+        }],
+    }
+}
+
+//The four edges of `rect`, for drawing a hollow cursor
+pub fn outline_rects(rect: Rect) -> [Rect; 4] {
+    let thickness = THIN_CURSOR.min(rect.width / 2.0).min(rect.height / 2.0);
+    [
+        Rect {
+            height: thickness,
+            ..rect
+        },
+        Rect {
+            y: rect.y + rect.height - thickness,
+            height: thickness,
+            ..rect
+        },
+        Rect {
+            width: thickness,
+            ..rect
+        },
+        Rect {
+            x: rect.x + rect.width - thickness,
+            width: thickness,
+            ..rect
+        },
+    ]
+}
+
+//Backgrounds of text whose face has a different one than `faces.base`,
+//then underlines
+pub fn face_quads(layout: &Layout, faces: &FaceSpans) -> Vec<Quad> {
+    let backgrounds = faces
+        .spans
+        .iter()
+        .filter(|(_, face)| face.background != faces.base.background)
+        .flat_map(|(range, face)| {
+            region_rects(layout, range.start, range.end)
+                .into_iter()
+                .map(|rect| Quad {
+                    rect,
+                    color: face.background,
+                })
+        });
+    let underlines = faces
+        .spans
+        .iter()
+        .filter(|(_, face)| face.underline)
+        .flat_map(|(range, face)| {
+            region_rects(layout, range.start, range.end)
+                .into_iter()
+                .map(|rect| Quad {
+                    rect: Rect {
+                        y: rect.y + layout.metrics.ascent + 1.0,
+                        height: 1.0,
+                        ..rect
+                    },
+                    color: face.foreground,
+                })
+        });
+    backgrounds.chain(underlines).collect()
+}
+
+//One rectangle per screen row covering the chars in [start, end). A newline
+//inside the range shows up as one extra cell.
+pub fn region_rects(layout: &Layout, start: usize, end: usize) -> Vec<Rect> {
+    let in_range = |offset: usize| offset >= start && offset < end;
+    layout
+        .display_rows
+        .iter()
+        .filter_map(|row| {
+            let mut cols = row
+                .glyphs
+                .iter()
+                .filter(|g| in_range(g.offset))
+                .map(|g| (g.col, g.col + g.cells))
+                .chain(
+                    row.eol
+                        .filter(|(offset, _)| in_range(*offset))
+                        .map(|(_, col)| (col, col + 1)),
+                );
+            let first = cols.next()?;
+            let (from, to) = cols.fold(first, |(from, to), (a, b)| (from.min(a), to.max(b)));
+            Some(Rect {
+                x: layout.area.x + from as f32 * layout.metrics.width,
+                y: row.y,
+                width: (to - from) as f32 * layout.metrics.width,
+                height: layout.metrics.height,
+            })
+        })
+        .collect()
+}
+
+//Full width rectangles behind every screen row of point's logical line
+pub fn current_line_rects(layout: &Layout, point: usize) -> Vec<Rect> {
+    let Some(cursor) = layout.cursor_position(point) else {
+        return vec![];
+    };
+    let line = layout.display_rows[cursor.row].line;
+    layout
+        .display_rows
+        .iter()
+        .filter(|row| row.line == line)
+        .map(|row| Rect {
+            x: layout.area.x,
+            y: row.y,
+            width: layout.area.width,
+            height: layout.metrics.height,
+        })
+        .collect()
+}
+
+//blink-cursor-mode: the cursor sits still for `delay` after input, then
+//blinks `blinks` times and stays on
+#[derive(Debug, Clone, Copy)]
+pub struct Blink {
+    pub delay: Duration,
+    pub interval: Duration,
+    pub blinks: u32,
+    epoch: Instant,
+}
+
+impl Blink {
+    pub fn new(now: Instant) -> Self {
+        Blink {
+            delay: Duration::from_millis(500),
+            interval: Duration::from_millis(500),
+            blinks: 10,
+            epoch: now,
+        }
+    }
+
+    pub fn reset(&mut self, now: Instant) {
+        self.epoch = now;
+    }
+
+    fn phase(&self, now: Instant) -> Option<u32> {
+        let elapsed = now.saturating_duration_since(self.epoch);
+        let blinking = elapsed.checked_sub(self.delay)?;
+        let phase = (blinking.as_nanos() / self.interval.as_nanos().max(1)) as u32;
+        //Each blink is an off phase followed by an on phase
+        (phase < self.blinks * 2).then_some(phase)
+    }
+
+    pub fn is_on(&self, now: Instant) -> bool {
+        self.phase(now).is_none_or(|phase| phase % 2 == 1)
+    }
+
+    //When the cursor next changes state, None once it's done blinking
+    pub fn next_change(&self, now: Instant) -> Option<Instant> {
+        let elapsed = now.saturating_duration_since(self.epoch);
+        if elapsed < self.delay {
+            return Some(self.epoch + self.delay);
+        }
+        let phase = self.phase(now)?;
+        Some(self.epoch + self.delay + self.interval * (phase + 1))
+    }
+}
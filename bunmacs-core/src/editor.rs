@@ -0,0 +1,2551 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    io,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use bunlang::Value;
+use ropey::Rope;
+use tokio::runtime::Handle;
+
+use crate::{
+    background::{Background, Waker},
+    buffer::{Buffer, BufferId},
+    commands::{self, Command},
+    completion::{self, CompletionStyle},
+    damage,
+    dap::{self, Dap},
+    decoration::{self, CursorStyle, Palette, Quad},
+    face::{self, FaceSpans, Faces},
+    font::FontConfig,
+    frame::{Frame, FrameId},
+    frontend::{self, Cursor, Damage, Frontend},
+    hook::{self, Hooks, IdleTimer, TimerId},
+    isearch::{Isearch, IsearchResult},
+    keymap::{self, Key, Keymap, Lookup},
+    layout::{self, CellMetrics, Layout, LayoutParams, Rect, Wrap},
+    lsp::{self, Lsp},
+    minibuffer::{self, Minibuffer},
+    mode::{self, Modes},
+    modeline::{self, ModeLineFormat},
+    project::{self, ProjectSearch},
+    replace::QueryReplace,
+    script::{self, ScriptCallback, ScriptEngine},
+    search::{self, SearchPattern},
+    syntax::SyntaxLanguage,
+    theme,
+    variable::{Variables, Watcher},
+    window::{self, CellRect, SplitDirection, Window, WindowId, WindowTree},
+};
+
+//Things only the frontend can do, queued up by the editor and drained by the
+//frontend after it feeds in an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrontendRequest {
+    //Open an OS window for a frame the editor just made
+    MakeFrame(FrameId),
+    DeleteFrame(FrameId),
+    //Something every frame shows changed, like the theme
+    RedrawAll,
+    //`Editor::font` changed, so glyphs and cell metrics need redoing
+    FontChanged,
+}
+
+#[derive(Debug)]
+pub struct Editor {
+    buffers: HashMap<BufferId, Buffer>,
+    next_buffer_id: u64,
+    current: BufferId,
+    frames: HashMap<FrameId, Frame>,
+    next_frame_id: u64,
+    selected_frame: Option<FrameId>,
+    next_window_id: u64,
+    minibuffer: Option<Minibuffer>,
+    //Takes keys over like the minibuffer while it's going
+    isearch: Option<Isearch>,
+    //The query-replace whose next match the minibuffer is asking about
+    query_replace: Option<QueryReplace>,
+    //What C-s C-s and C-M-s C-M-s search for again
+    last_search: Option<String>,
+    last_regexp_search: Option<String>,
+    //None until the frontend hands over its async runtime
+    background: Option<Background>,
+    //The last project-search, kept after it's done for visiting matches
+    project_search: Option<ProjectSearch>,
+    lsp: Lsp,
+    dap: Dap,
+    frontend_requests: Vec<FrontendRequest>,
+    commands: HashMap<&'static str, Command>,
+    global_keymap: Keymap,
+    pending_keys: Vec<Key>,
+    modes: Modes,
+    hooks: Hooks,
+    //What the running hooks were called with, innermost last
+    hook_args: Vec<Vec<Value>>,
+    //Set while change hooks run so their own edits don't run them again
+    inhibit_change_hooks: bool,
+    idle_timers: Vec<IdleTimer>,
+    next_timer_id: u64,
+    last_input: Instant,
+    //What the last command left behind, to tell when buffer-switch-hook and
+    //window-configuration-change-hook are due. None before the first one.
+    last_buffer: Option<BufferId>,
+    last_window_configuration: Option<WindowConfiguration>,
+    //Echo area contents, cleared on the next key press
+    message: Option<String>,
+    script: ScriptEngine,
+    pub variables: Variables,
+    pub completion_styles: Vec<CompletionStyle>,
+    pub mode_line_format: ModeLineFormat,
+    pub faces: Faces,
+    //Directories load-theme looks in
+    pub custom_theme_load_path: Vec<PathBuf>,
+    //Change with set_font and text_scale_adjust so frontends hear about it
+    font: FontConfig,
+}
+
+//Every frame's window tree and what each window shows, compared after
+//commands to tell when window-configuration-change-hook should run
+type WindowConfiguration = Vec<(FrameId, WindowTree, Vec<(WindowId, BufferId)>)>;
+
+//What one window should show
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowDisplay {
+    pub window: WindowId,
+    pub text: Layout,
+    //By buffer position, like `text`'s glyphs
+    pub faces: FaceSpans,
+    pub point: usize,
+    //The cells under the cursor, None if point is off screen
+    pub cursor: Option<Rect>,
+    pub cursor_style: CursorStyle,
+    pub region: Vec<Rect>,
+    pub current_line: Vec<Rect>,
+    //None if left-fringe-width is 0
+    pub fringe: Option<FringeDisplay>,
+    //None if the window is too short to have one
+    pub mode_line: Option<ModeLineDisplay>,
+}
+
+//The columns left of a window's text, one line per display row with the
+//mark for that row if it's the first of its line
+#[derive(Debug, Clone, PartialEq)]
+pub struct FringeDisplay {
+    pub text: Layout,
+    pub faces: FaceSpans,
+}
+
+//The row at the bottom of a window describing its buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeLineDisplay {
+    pub text: Layout,
+    pub faces: FaceSpans,
+    //Whether this is the selected window's mode line
+    pub active: bool,
+}
+
+//The active minibuffer, drawn over the echo area and the rows above it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinibufferDisplay {
+    //Offset of the cursor in the echo area's text
+    pub point: usize,
+    pub cursor: Option<Rect>,
+    pub cursor_style: CursorStyle,
+    //One candidate per row
+    pub candidates: Layout,
+    pub candidate_faces: FaceSpans,
+    pub selected: Option<Rect>,
+}
+
+//What a frame should show, ready for a frontend to draw
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameDisplay {
+    pub windows: Vec<WindowDisplay>,
+    pub dividers: Vec<Rect>,
+    pub echo_area: Layout,
+    pub echo_faces: FaceSpans,
+    pub minibuffer: Option<MinibufferDisplay>,
+    pub palette: Palette,
+}
+
+impl ModeLineDisplay {
+    fn draw_background(&self, frontend: &mut dyn Frontend) {
+        frontend.fill_rect(self.text.area, self.faces.base.background);
+        fill_quads(frontend, decoration::face_quads(&self.text, &self.faces));
+    }
+}
+
+impl FringeDisplay {
+    fn draw_background(&self, frontend: &mut dyn Frontend) {
+        frontend.fill_rect(self.text.area, self.faces.base.background);
+        fill_quads(frontend, decoration::face_quads(&self.text, &self.faces));
+    }
+}
+
+fn fill_quads(frontend: &mut dyn Frontend, quads: Vec<Quad>) {
+    for quad in quads {
+        frontend.fill_rect(quad.rect, quad.color);
+    }
+}
+
+impl WindowDisplay {
+    //Backgrounds in drawing order, the cursor goes last so it's on top of
+    //the region. Only the selected window's cursor blinks.
+    fn draw_background(&self, frontend: &mut dyn Frontend, cursor_on: bool, palette: &Palette) {
+        for rect in &self.current_line {
+            frontend.fill_rect(*rect, palette.current_line);
+        }
+        fill_quads(frontend, decoration::face_quads(&self.text, &self.faces));
+        for rect in &self.region {
+            frontend.fill_rect(*rect, palette.region);
+        }
+        if let Some(cell) = self.cursor {
+            if cursor_on || self.cursor_style == CursorStyle::Hollow {
+                frontend.draw_cursor(Cursor {
+                    cell,
+                    style: self.cursor_style,
+                    color: palette.cursor,
+                });
+            }
+        }
+        if let Some(fringe) = &self.fringe {
+            fringe.draw_background(frontend);
+        }
+        if let Some(mode_line) = &self.mode_line {
+            mode_line.draw_background(frontend);
+        }
+    }
+
+    fn draw_text(&self, frontend: &mut dyn Frontend, cursor_on: bool) {
+        let inverse = (cursor_on && self.cursor_style == CursorStyle::Box).then_some(self.point);
+        for run in frontend::glyph_runs(&self.text, &self.faces, inverse) {
+            frontend.draw_glyphs(run);
+        }
+        if let Some(fringe) = &self.fringe {
+            for run in frontend::glyph_runs(&fringe.text, &fringe.faces, None) {
+                frontend.draw_glyphs(run);
+            }
+        }
+        if let Some(mode_line) = &self.mode_line {
+            for run in frontend::glyph_runs(&mode_line.text, &mode_line.faces, None) {
+                frontend.draw_glyphs(run);
+            }
+        }
+    }
+}
+
+impl FrameDisplay {
+    //Everything but text first, then the text on top of it
+    pub fn draw(&self, frontend: &mut dyn Frontend, cursor_on: bool) {
+        let palette = &self.palette;
+        frontend.clear(palette.background, &Damage::Full);
+        for window in &self.windows {
+            window.draw_background(frontend, cursor_on, palette);
+        }
+        for rect in &self.dividers {
+            frontend.fill_rect(*rect, palette.divider);
+        }
+        fill_quads(
+            frontend,
+            decoration::face_quads(&self.echo_area, &self.echo_faces),
+        );
+        if let Some(minibuffer) = &self.minibuffer {
+            if let Some(selected) = minibuffer.selected {
+                frontend.fill_rect(selected, palette.completions_current);
+            }
+            if let Some(cell) = minibuffer.cursor.filter(|_| cursor_on) {
+                frontend.draw_cursor(Cursor {
+                    cell,
+                    style: minibuffer.cursor_style,
+                    color: palette.cursor,
+                });
+            }
+        }
+
+        for window in &self.windows {
+            window.draw_text(frontend, cursor_on);
+        }
+        let echo_inverse = self
+            .minibuffer
+            .as_ref()
+            .filter(|m| cursor_on && m.cursor.is_some() && m.cursor_style == CursorStyle::Box)
+            .map(|m| m.point);
+        let mut runs = frontend::glyph_runs(&self.echo_area, &self.echo_faces, echo_inverse);
+        if let Some(minibuffer) = &self.minibuffer {
+            runs.extend(frontend::glyph_runs(
+                &minibuffer.candidates,
+                &minibuffer.candidate_faces,
+                None,
+            ));
+        }
+        for run in runs {
+            frontend.draw_glyphs(run);
+        }
+        frontend.present();
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let mut editor = Editor {
+            buffers: HashMap::new(),
+            next_buffer_id: 0,
+            current: BufferId(0),
+            frames: HashMap::new(),
+            next_frame_id: 0,
+            selected_frame: None,
+            next_window_id: 0,
+            minibuffer: None,
+            isearch: None,
+            query_replace: None,
+            last_search: None,
+            last_regexp_search: None,
+            background: None,
+            project_search: None,
+            lsp: Lsp::new(),
+            dap: Dap::new(),
+            frontend_requests: vec![],
+            commands: HashMap::new(),
+            global_keymap: Keymap::new(),
+            pending_keys: vec![],
+            modes: Modes::new(),
+            hooks: Hooks::new(),
+            hook_args: vec![],
+            inhibit_change_hooks: false,
+            idle_timers: vec![],
+            next_timer_id: 0,
+            last_input: Instant::now(),
+            last_buffer: None,
+            last_window_configuration: None,
+            message: None,
+            script: ScriptEngine::new(),
+            variables: Variables::new(),
+            completion_styles: completion::DEFAULT_STYLES.to_vec(),
+            mode_line_format: ModeLineFormat::default(),
+            faces: Faces::new(),
+            custom_theme_load_path: theme::default_load_path(),
+            font: FontConfig::default(),
+        };
+        commands::register_builtins(&mut editor.commands, &mut editor.global_keymap);
+        editor.current = editor.create_buffer("*scratch*");
+        if let Err(e) = editor.eval_immediately(mode::BUILTIN_MODES) {
+            log::error!("Couldn't define the builtin modes: {e}");
+        }
+        editor
+    }
+
+    fn alloc_buffer_id(&mut self) -> BufferId {
+        let id = BufferId(self.next_buffer_id);
+        self.next_buffer_id += 1;
+        id
+    }
+
+    //Appends <2>, <3>... until the name is free, same as Emacs
+    fn unique_buffer_name(&self, base: &str) -> String {
+        let taken = |name: &str| self.buffers.values().any(|b| b.name() == name);
+        if !taken(base) {
+            return base.to_owned();
+        }
+        (2..)
+            .map(|n| format!("{base}<{n}>"))
+            .find(|name| !taken(name))
+            .expect("ran out of buffer names")
+    }
+
+    pub fn create_buffer(&mut self, name: &str) -> BufferId {
+        let id = self.alloc_buffer_id();
+        let name = self.unique_buffer_name(name);
+        self.buffers.insert(id, Buffer::new(id, name));
+        id
+    }
+
+    pub fn buffer(&self, id: BufferId) -> Option<&Buffer> {
+        self.buffers.get(&id)
+    }
+
+    pub fn buffer_mut(&mut self, id: BufferId) -> Option<&mut Buffer> {
+        self.buffers.get_mut(&id)
+    }
+
+    pub fn buffers(&self) -> impl Iterator<Item = &Buffer> {
+        self.buffers.values()
+    }
+
+    pub fn current_buffer_id(&self) -> BufferId {
+        self.current
+    }
+
+    pub fn current_buffer(&self) -> &Buffer {
+        &self.buffers[&self.current]
+    }
+
+    pub fn current_buffer_mut(&mut self) -> &mut Buffer {
+        self.buffers
+            .get_mut(&self.current)
+            .expect("current buffer was killed")
+    }
+
+    //Shows `id` in the selected window
+    pub fn switch_to_buffer(&mut self, id: BufferId) {
+        if !self.buffers.contains_key(&id) {
+            return;
+        }
+        if let Some((frame, window)) = self.live_window() {
+            let old = self.frames[&frame].windows[&window].buffer();
+            if old != id {
+                self.set_window_buffer(frame, window, id);
+            }
+        }
+        self.current = id;
+    }
+
+    pub fn kill_buffer(&mut self, id: BufferId) {
+        if self.buffers.remove(&id).is_none() {
+            return;
+        }
+        self.hooks.kill_local(id);
+        lsp::buffer_killed(self, id);
+        dap::buffer_killed(self, id);
+        let replacement = match self.buffers.keys().min() {
+            Some(other) => *other,
+            None => self.create_buffer("*scratch*"),
+        };
+        let showing: Vec<(FrameId, WindowId)> = self
+            .frames
+            .values()
+            .flat_map(|f| f.windows().map(move |w| (f.id(), w.id(), w.buffer())))
+            .filter(|(_, _, buffer)| *buffer == id)
+            .map(|(f, w, _)| (f, w))
+            .collect();
+        for (frame, window) in showing {
+            self.set_window_buffer(frame, window, replacement);
+        }
+        if self.current == id {
+            self.current = replacement;
+        }
+    }
+
+    pub fn modified_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        //Buffers without files are scratch space, nothing to lose by closing
+        self.buffers
+            .values()
+            .filter(|b| b.file().is_some() && b.is_modified())
+    }
+
+    fn find_buffer_visiting(&self, path: &Path) -> Option<BufferId> {
+        self.buffers
+            .values()
+            .find(|b| b.file_path() == Some(path))
+            .map(Buffer::id)
+    }
+
+    //Visits `path` in a buffer, reusing one that already visits it
+    pub fn find_file(&mut self, path: impl AsRef<Path>) -> io::Result<BufferId> {
+        let id = self.find_file_noselect(path)?;
+        self.switch_to_buffer(id);
+        Ok(id)
+    }
+
+    //Like find_file without showing the buffer
+    pub fn find_file_noselect(&mut self, path: impl AsRef<Path>) -> io::Result<BufferId> {
+        let path = absolute_path(path.as_ref())?;
+        if let Some(id) = self.find_buffer_visiting(&path) {
+            return Ok(id);
+        }
+        let base = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        let name = self.unique_buffer_name(&base);
+        let id = self.alloc_buffer_id();
+        let buffer = Buffer::visit(id, name, path)?;
+        if buffer.len_chars() == 0 && !buffer.file_path().is_some_and(Path::exists) {
+            self.message("(New file)");
+        }
+        self.buffers.insert(id, buffer);
+        self.with_current_buffer(id, |editor| {
+            if let Err(e) = editor.set_auto_mode() {
+                editor.message(e);
+            }
+        });
+        lsp::buffer_visited(self, id);
+        Ok(id)
+    }
+
+    //Where relative file names in the current buffer start from: its
+    //file's directory, otherwise the default-directory variable, like the
+    //project of search results, otherwise the working directory
+    pub fn default_directory(&self) -> PathBuf {
+        let buffer = self.current_buffer();
+        if let Some(dir) = buffer.file_path().and_then(Path::parent) {
+            return dir.to_owned();
+        }
+        match self.variable("default-directory") {
+            Some(Value::Str(dir)) if !dir.is_empty() => PathBuf::from(dir),
+            _ => std::env::current_dir().unwrap_or_default(),
+        }
+    }
+
+    pub fn save_buffer(&mut self, id: BufferId) -> io::Result<()> {
+        let make_backup = match self.buffers.get(&id) {
+            Some(buffer) => self.variables.flag("make-backup-files", buffer),
+            None => false,
+        };
+        let buffer = self
+            .buffers
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such buffer"))?;
+        if !buffer.is_modified() {
+            self.message("(No changes need to be saved)");
+            return Ok(());
+        }
+        let path = buffer.save(make_backup)?;
+        let message = format!("Wrote {}", path.display());
+        self.message(message);
+        lsp::buffer_saved(self, id);
+        self.with_current_buffer(id, |editor| editor.run_hooks(hook::AFTER_SAVE_HOOK));
+        Ok(())
+    }
+
+    //Auto-saves every modified buffer, for when the editor is about to go
+    //down and can't ask about them. Returns where they were written.
+    pub fn do_auto_save(&self) -> Vec<PathBuf> {
+        self.buffers
+            .values()
+            .filter(|buffer| buffer.is_modified())
+            .filter_map(|buffer| match buffer.auto_save() {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    log::error!("Couldn't auto-save {}: {e}", buffer.name());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn revert_buffer(&mut self, id: BufferId) -> io::Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such buffer"))?;
+        buffer.revert()?;
+        let message = format!("Reverted {}", buffer.name());
+        self.message(message);
+        Ok(())
+    }
+
+    pub fn make_frame(&mut self) -> FrameId {
+        let id = FrameId(self.next_frame_id);
+        self.next_frame_id += 1;
+        let window = self.make_window(self.current);
+        self.frames.insert(id, Frame::new(id, window));
+        if self.selected_frame.is_none() {
+            self.selected_frame = Some(id);
+        }
+        id
+    }
+
+    //make-frame: a new frame on the selected window's buffer, which the
+    //frontend then opens a window for
+    pub fn request_frame(&mut self) -> FrameId {
+        let id = self.make_frame();
+        self.select_frame(id);
+        self.frontend_requests.push(FrontendRequest::MakeFrame(id));
+        id
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.values()
+    }
+
+    pub fn frame(&self, id: FrameId) -> Option<&Frame> {
+        self.frames.get(&id)
+    }
+
+    pub fn selected_frame(&self) -> Option<FrameId> {
+        self.selected_frame
+    }
+
+    pub fn select_frame(&mut self, id: FrameId) {
+        if self.frames.contains_key(&id) {
+            self.store_window_point();
+            self.selected_frame = Some(id);
+            self.load_window_point();
+        }
+    }
+
+    //Deletes the frame without asking, the frontend is told to close its window
+    pub fn delete_frame(&mut self, id: FrameId) {
+        let Some(frame) = self.frames.remove(&id) else {
+            return;
+        };
+        for window in frame.windows.values() {
+            if let Some(buffer) = self.buffers.get_mut(&window.buffer()) {
+                buffer.delete_marker(window.point);
+            }
+        }
+        if self.selected_frame == Some(id) {
+            self.selected_frame = None;
+            if let Some(other) = self.frames.keys().min().copied() {
+                self.select_frame(other);
+            }
+        }
+        self.frontend_requests
+            .push(FrontendRequest::DeleteFrame(id));
+    }
+
+    //Modified file buffers nobody could get back to once `id` is gone. When
+    //the last frame goes the editor exits, so that's every modified buffer.
+    pub fn unsaved_buffers_lost_by_deleting(&self, id: FrameId) -> Vec<BufferId> {
+        let Some(frame) = self.frames.get(&id) else {
+            return vec![];
+        };
+        let mut lost: Vec<BufferId> = if self.frames.len() == 1 {
+            self.modified_buffers().map(Buffer::id).collect()
+        } else {
+            frame
+                .displayed_buffers()
+                .into_iter()
+                .filter(|b| {
+                    self.frames
+                        .values()
+                        .filter(|other| other.id() != id)
+                        .all(|other| !other.displayed_buffers().contains(b))
+                })
+                .filter(|b| self.modified_buffers().any(|m| m.id() == *b))
+                .collect()
+        };
+        lost.sort();
+        lost.dedup();
+        lost
+    }
+
+    //What closing a window from the window manager does. Asks about each
+    //unsaved buffer that would be lost before deleting the frame.
+    pub fn delete_frame_interactively(&mut self, id: FrameId) {
+        let mut remaining = self.unsaved_buffers_lost_by_deleting(id);
+        remaining.reverse();
+        self.confirm_delete_frames(vec![id], remaining, vec![]);
+    }
+
+    //save-buffers-kill-terminal: asks about every unsaved buffer, then
+    //deletes every frame, which makes the frontend exit
+    pub fn kill_terminal_interactively(&mut self) {
+        let mut frames: Vec<FrameId> = self.frames.keys().copied().collect();
+        frames.sort();
+        let mut remaining: Vec<BufferId> = self.modified_buffers().map(Buffer::id).collect();
+        remaining.sort();
+        remaining.reverse();
+        self.confirm_delete_frames(frames, remaining, vec![]);
+    }
+
+    fn confirm_delete_frames(
+        &mut self,
+        frames: Vec<FrameId>,
+        mut remaining: Vec<BufferId>,
+        discarded: Vec<BufferId>,
+    ) {
+        let Some(buffer) = remaining.pop() else {
+            for frame in frames {
+                self.delete_frame(frame);
+            }
+            for id in discarded {
+                self.kill_buffer(id);
+            }
+            return;
+        };
+        let Some(path) = self.buffers.get(&buffer).and_then(Buffer::file_path) else {
+            return self.confirm_delete_frames(frames, remaining, discarded);
+        };
+        let prompt = format!(
+            "Save file {}? (y = save, n = discard, ! = save all, q = cancel) ",
+            path.display()
+        );
+        self.read_choice(
+            prompt,
+            &['y', 'n', '!', 'q'],
+            Box::new(move |editor, choice| {
+                let mut discarded = discarded;
+                let to_save = match choice {
+                    Some('y') => vec![buffer],
+                    Some('n') => {
+                        discarded.push(buffer);
+                        vec![]
+                    }
+                    Some('!') => {
+                        let mut all = remaining.clone();
+                        all.push(buffer);
+                        remaining.clear();
+                        all
+                    }
+                    _ => {
+                        editor.message("Quit");
+                        return;
+                    }
+                };
+                for id in to_save {
+                    if let Err(e) = editor.save_buffer(id) {
+                        editor.message(format!("Saving failed: {e}"));
+                        return;
+                    }
+                }
+                editor.confirm_delete_frames(frames, remaining, discarded);
+            }),
+        );
+    }
+
+    fn make_window(&mut self, buffer: BufferId) -> Window {
+        let id = WindowId(self.next_window_id);
+        self.next_window_id += 1;
+        let buffer_ref = self.buffers.get_mut(&buffer).expect("no such buffer");
+        let point = buffer_ref.make_marker(buffer_ref.point());
+        Window::new(id, buffer, point)
+    }
+
+    fn set_window_buffer(&mut self, frame: FrameId, window: WindowId, buffer: BufferId) {
+        let Some(window) = self
+            .frames
+            .get_mut(&frame)
+            .and_then(|f| f.window_mut(window))
+        else {
+            return;
+        };
+        if let Some(old) = self.buffers.get_mut(&window.buffer()) {
+            old.delete_marker(window.point);
+        }
+        let new = self.buffers.get_mut(&buffer).expect("no such buffer");
+        window.set_buffer(buffer, new.make_marker(new.point()));
+    }
+
+    //The selected window of the selected frame. Its point is the buffer's
+    //point so commands can just move that, every other window keeps its
+    //point in a marker.
+    fn live_window(&self) -> Option<(FrameId, WindowId)> {
+        let frame = self.selected_frame?;
+        Some((frame, self.frames.get(&frame)?.selected_window()))
+    }
+
+    fn store_window_point(&mut self) {
+        let Some((frame, window)) = self.live_window() else {
+            return;
+        };
+        let window = &self.frames[&frame].windows[&window];
+        if let Some(buffer) = self.buffers.get_mut(&window.buffer()) {
+            buffer.set_marker(window.point, buffer.point());
+        }
+    }
+
+    fn load_window_point(&mut self) {
+        let Some((frame, window)) = self.live_window() else {
+            return;
+        };
+        let window = &self.frames[&frame].windows[&window];
+        if let Some(buffer) = self.buffers.get_mut(&window.buffer()) {
+            if let Some(point) = buffer.marker(window.point) {
+                buffer.set_point(point);
+            }
+            self.current = window.buffer();
+        }
+    }
+
+    pub fn selected_window(&self) -> Option<WindowId> {
+        self.live_window().map(|(_, window)| window)
+    }
+
+    pub(crate) fn selected_window_mut(&mut self) -> Option<&mut Window> {
+        let (frame, window) = self.live_window()?;
+        self.frames.get_mut(&frame)?.window_mut(window)
+    }
+
+    fn selected_frame_ref(&self) -> Result<&Frame, String> {
+        self.selected_frame
+            .and_then(|f| self.frames.get(&f))
+            .ok_or_else(|| "No selected frame".to_owned())
+    }
+
+    pub fn select_window(&mut self, id: WindowId) -> Result<(), String> {
+        if self.selected_frame_ref()?.window(id).is_none() {
+            return Err("No such window in the selected frame".to_owned());
+        }
+        self.store_window_point();
+        if let Some(frame) = self.selected_frame.and_then(|f| self.frames.get_mut(&f)) {
+            frame.selected_window = id;
+        }
+        self.load_window_point();
+        Ok(())
+    }
+
+    //Selects the next window in tree order, wrapping around
+    pub fn other_window(&mut self) -> Result<(), String> {
+        let frame = self.selected_frame_ref()?;
+        let leaves = frame.root().leaves();
+        let i = leaves
+            .iter()
+            .position(|w| *w == frame.selected_window())
+            .unwrap_or(0);
+        let next = leaves[(i + 1) % leaves.len()];
+        self.select_window(next)
+    }
+
+    //Shows `id` in some other window of the selected frame, splitting the
+    //selected one if it's alone, and selects that window if `select`
+    pub fn display_buffer_other_window(
+        &mut self,
+        id: BufferId,
+        select: bool,
+    ) -> Result<(), String> {
+        let frame = self.selected_frame_ref()?;
+        let frame_id = frame.id();
+        let selected = frame.selected_window();
+        let other = frame.root().leaves().into_iter().find(|w| *w != selected);
+        let other = match other {
+            Some(other) => other,
+            None => self.split_window(SplitDirection::Below)?,
+        };
+        self.set_window_buffer(frame_id, other, id);
+        if select {
+            self.select_window(other)?;
+        }
+        Ok(())
+    }
+
+    //Splits the selected window in two, both showing its buffer. The
+    //original window stays selected.
+    pub fn split_window(&mut self, direction: SplitDirection) -> Result<WindowId, String> {
+        let frame = self.selected_frame_ref()?;
+        let frame_id = frame.id();
+        let selected = &frame.windows[&frame.selected_window()];
+        let (buffer, start_line) = (selected.buffer(), selected.start_line());
+        let cells = frame
+            .root()
+            .layout(frame.grid)
+            .windows
+            .into_iter()
+            .find(|(w, _)| *w == selected.id())
+            .map(|(_, cells)| cells)
+            .unwrap_or_default();
+        let size = match direction {
+            SplitDirection::Below => cells.rows,
+            SplitDirection::Right => cells.cols,
+        };
+        //A frame that was never displayed doesn't know its size yet
+        let displayed = frame.grid != CellRect::default();
+        if displayed && size < direction.min_size() * 2 + direction.gap() {
+            return Err("Window too small for splitting".to_owned());
+        }
+        let mut window = self.make_window(buffer);
+        window.start_line = start_line;
+        let id = window.id();
+        let frame = self.frames.get_mut(&frame_id).expect("selected frame");
+        frame.root.split(frame.selected_window, direction, id);
+        frame.windows.insert(id, window);
+        Ok(id)
+    }
+
+    //Deletes a window of the selected frame, its space goes to a neighbour
+    pub fn delete_window(&mut self, id: WindowId) -> Result<(), String> {
+        let frame = self.selected_frame_ref()?;
+        let frame_id = frame.id();
+        let leaves = frame.root().leaves();
+        let index = leaves
+            .iter()
+            .position(|w| *w == id)
+            .ok_or_else(|| "No such window in the selected frame".to_owned())?;
+        let was_selected = frame.selected_window() == id;
+        let frame = self.frames.get_mut(&frame_id).expect("selected frame");
+        if !frame.root.remove(id) {
+            return Err("Attempt to delete the sole window".to_owned());
+        }
+        let window = frame.windows.remove(&id).expect("window in tree");
+        if was_selected {
+            frame.selected_window = frame.root.leaves()[index.saturating_sub(1)];
+        }
+        if let Some(buffer) = self.buffers.get_mut(&window.buffer()) {
+            buffer.delete_marker(window.point);
+        }
+        if was_selected {
+            self.load_window_point();
+        }
+        Ok(())
+    }
+
+    //Makes the selected window fill its frame
+    pub fn delete_other_windows(&mut self) -> Result<(), String> {
+        let frame = self.selected_frame_ref()?;
+        let frame_id = frame.id();
+        let selected = frame.selected_window();
+        let frame = self.frames.get_mut(&frame_id).expect("selected frame");
+        frame.root = WindowTree::Leaf(selected);
+        let others: Vec<WindowId> = frame
+            .windows
+            .keys()
+            .copied()
+            .filter(|id| *id != selected)
+            .collect();
+        for id in others {
+            let window = frame.windows.remove(&id).expect("window in frame");
+            if let Some(buffer) = self.buffers.get_mut(&window.buffer()) {
+                buffer.delete_marker(window.point);
+            }
+        }
+        Ok(())
+    }
+
+    //Grows the selected window by `delta` lines or columns at the expense of
+    //a neighbour, shrinking it if `delta` is negative
+    pub fn resize_window(&mut self, direction: SplitDirection, delta: isize) -> Result<(), String> {
+        let frame = self.selected_frame_ref()?;
+        let frame_id = frame.id();
+        let frame = self.frames.get_mut(&frame_id).expect("selected frame");
+        let (selected, grid) = (frame.selected_window, frame.grid);
+        frame.root.resize(selected, direction, delta, grid)
+    }
+
+    //How text in `buffer` gets laid out, going by its tab-width and
+    //truncate-lines
+    pub fn layout_params(&self, buffer: &Buffer, metrics: CellMetrics) -> LayoutParams {
+        layout_params(&self.variables, buffer, metrics)
+    }
+
+    //Lays out every window of frame `id` in `area`, scrolling them first if
+    //point went off screen. The bottom row is the echo area.
+    pub fn redisplay(
+        &mut self,
+        id: FrameId,
+        area: Rect,
+        metrics: CellMetrics,
+    ) -> Option<FrameDisplay> {
+        //The echo area, candidates and mode lines are one line each
+        let echo_params = LayoutParams {
+            wrap: Wrap::Truncate,
+            ..self.layout_params(self.current_buffer(), metrics)
+        };
+        let completions = self.minibuffer.as_ref().and_then(Minibuffer::completions);
+        //Candidates go in the rows above the echo area, as many as fit
+        let candidate_rows = completions.map_or(0, |c| {
+            let (_, rows) = layout::grid_size(area, metrics);
+            let completion_rows = self
+                .variables
+                .number("completion-rows", self.current_buffer());
+            c.visible(completion_rows.min(rows.saturating_sub(2))).len()
+        });
+        let echo_height = metrics.height.min(area.height);
+        let candidates_height =
+            (candidate_rows as f32 * metrics.height).min(area.height - echo_height);
+        let text_area = Rect {
+            height: area.height - echo_height - candidates_height,
+            ..area
+        };
+        let candidates_rect = Rect {
+            y: area.y + text_area.height,
+            height: candidates_height,
+            ..area
+        };
+        let echo_rect = Rect {
+            y: candidates_rect.y + candidates_height,
+            height: echo_height,
+            ..area
+        };
+        let (cols, rows) = layout::grid_size(text_area, metrics);
+        let grid = CellRect {
+            col: 0,
+            row: 0,
+            cols,
+            rows,
+        };
+        let is_selected_frame = self.selected_frame == Some(id);
+        //The cursor moves to the minibuffer while it's active
+        let minibuffer_active = is_selected_frame && self.minibuffer.is_some();
+        let frame = self.frames.get_mut(&id)?;
+        frame.grid = grid;
+        let tree = frame.root.layout(grid);
+        let selected = frame.selected_window;
+        let mut windows = Vec::with_capacity(tree.windows.len());
+        for (window_id, cells) in tree.windows {
+            let window = frame.windows.get_mut(&window_id)?;
+            let buffer = self.buffers.get(&window.buffer())?;
+            let params = layout_params(&self.variables, buffer, metrics);
+            let live = is_selected_frame && window_id == selected;
+            let point = if live {
+                buffer.point()
+            } else {
+                buffer.marker(window.point).unwrap_or(0)
+            };
+            //The last row is the mode line unless that would leave no text
+            let has_mode_line = cells.rows >= 2;
+            //Always leaves at least a column for the text
+            let fringe_cols = self
+                .variables
+                .number("left-fringe-width", buffer)
+                .min(cells.cols.saturating_sub(1));
+            window.cols = cells.cols - fringe_cols;
+            window.rows = cells.rows - has_mode_line as usize;
+            window.start_line = layout::scroll_to_point(
+                buffer.text(),
+                window.start_line,
+                point,
+                window.cols,
+                window.rows,
+                &params,
+            );
+            let mut area = window::cell_area(cells, grid, text_area, metrics);
+            let mode_line = has_mode_line.then(|| {
+                area.height -= metrics.height;
+                let runs = modeline::format_mode_line(
+                    &mut self.mode_line_format,
+                    &self.modes,
+                    buffer,
+                    point,
+                    live,
+                );
+                let base = if live {
+                    face::MODE_LINE
+                } else {
+                    face::MODE_LINE_INACTIVE
+                };
+                let (text, faces) = modeline::styled_text(&runs, &self.faces, base);
+                let rect = Rect {
+                    y: area.y + area.height,
+                    height: metrics.height,
+                    ..area
+                };
+                ModeLineDisplay {
+                    text: Layout::new(&ropey::Rope::from(text), 0, rect, &echo_params),
+                    faces,
+                    active: live,
+                }
+            });
+            //The mode line goes under the fringe as well as the text
+            let fringe_area = Rect {
+                width: (fringe_cols as f32 * metrics.width).min(area.width),
+                ..area
+            };
+            area.x += fringe_area.width;
+            area.width -= fringe_area.width;
+            let text = window.text_layout(buffer.text(), area, &params);
+            let faces = match (text.display_rows.first(), text.display_rows.last()) {
+                (Some(first), Some(last)) => {
+                    //Matches of a search going on in this buffer
+                    let visible = first.start..last.end.min(buffer.len_chars());
+                    let lazy = self.variables.flag("lazy-highlight", buffer);
+                    let highlights = match (&mut self.isearch, &mut self.query_replace) {
+                        (Some(isearch), _) if isearch.buffer() == buffer.id() => {
+                            isearch.highlights(buffer.text(), visible, lazy)
+                        }
+                        (_, Some(replace)) if replace.buffer() == buffer.id() => {
+                            replace.highlights(buffer.text(), visible, lazy)
+                        }
+                        _ => vec![],
+                    };
+                    //One past the end so a face on the last newline shows
+                    text_faces(buffer, &self.faces, first.start..last.end + 1, &highlights)
+                }
+                _ => FaceSpans::new(self.faces.resolve(&[])),
+            };
+            //Only the selected window shows the region and current line
+            let region = buffer
+                .region()
+                .filter(|_| live)
+                .map(|(start, end)| decoration::region_rects(&text, start, end))
+                .unwrap_or_default();
+            let current_line = if live && self.variables.flag("highlight-current-line", buffer) {
+                decoration::current_line_rects(&text, point)
+            } else {
+                vec![]
+            };
+            let fringe = (fringe_cols > 0)
+                .then(|| fringe_display(buffer, &text, fringe_area, &self.faces, &echo_params));
+            let cursor_style = if live && !minibuffer_active {
+                cursor_style(&self.variables, buffer)
+            } else {
+                CursorStyle::Hollow
+            };
+            windows.push(WindowDisplay {
+                window: window_id,
+                cursor: decoration::cursor_cell(&text, point),
+                cursor_style,
+                region,
+                current_line,
+                text,
+                faces,
+                point,
+                fringe,
+                mode_line,
+            });
+        }
+        let dividers = tree
+            .dividers
+            .into_iter()
+            .map(|cells| window::cell_area(cells, grid, text_area, metrics))
+            .collect();
+        //isearch shows what it's looking for where the minibuffer would
+        let prompt = match (&self.minibuffer, &self.isearch) {
+            (Some(m), _) => Some((m.prompt().to_owned(), m.contents().to_owned())),
+            (None, Some(isearch)) => {
+                let query = match isearch.error() {
+                    Some(e) => format!("{} [{e}]", isearch.query()),
+                    None => isearch.query().to_owned(),
+                };
+                Some((isearch.prompt(), query))
+            }
+            (None, None) => None,
+        };
+        let echo = match (&prompt, &self.message) {
+            (Some((prompt, contents)), Some(message)) => {
+                format!("{prompt}{contents} [{message}]")
+            }
+            (Some((prompt, contents)), None) => format!("{prompt}{contents}"),
+            (None, message) => message.clone().unwrap_or_default(),
+        };
+        let echo_area = Layout::new(&ropey::Rope::from(echo), 0, echo_rect, &echo_params);
+        let mut echo_faces = FaceSpans::new(self.faces.resolve(&[]));
+        if let Some((prompt, _)) = &prompt {
+            echo_faces.spans.push((
+                0..prompt.chars().count(),
+                self.faces.resolve(&[face::MINIBUFFER_PROMPT]),
+            ));
+        }
+        let minibuffer = self.minibuffer.as_ref().map(|m| {
+            let point = m.prompt().chars().count() + m.cursor();
+            let (candidates, selected) = match m.completions() {
+                Some(c) => {
+                    let visible = c.visible(candidate_rows);
+                    let lines = c.matches()[visible.clone()]
+                        .iter()
+                        .map(|m| c.candidate(m))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    let candidates =
+                        Layout::new(&ropey::Rope::from(lines), 0, candidates_rect, &echo_params);
+                    let selected = c.selected().filter(|s| visible.contains(s)).map(|s| Rect {
+                        y: candidates_rect.y + (s - visible.start) as f32 * metrics.height,
+                        height: metrics.height,
+                        ..candidates_rect
+                    });
+                    (candidates, selected)
+                }
+                None => (
+                    Layout::new(&ropey::Rope::new(), 0, candidates_rect, &echo_params),
+                    None,
+                ),
+            };
+            let cursor_style = cursor_style(&self.variables, &self.buffers[&self.current]);
+            MinibufferDisplay {
+                candidate_faces: FaceSpans::new(self.faces.resolve(&[])),
+                point,
+                cursor: is_selected_frame
+                    .then(|| decoration::cursor_cell(&echo_area, point))
+                    .flatten(),
+                cursor_style,
+                candidates,
+                selected,
+            }
+        });
+        Some(FrameDisplay {
+            windows,
+            dividers,
+            echo_area,
+            echo_faces,
+            minibuffer,
+            palette: Palette::new(&self.faces),
+        })
+    }
+
+    //Lays frame `id` out to fit `frontend` and draws whatever changed since
+    //it was last drawn there. False if there's no such frame.
+    pub fn draw_frame(
+        &mut self,
+        id: FrameId,
+        frontend: &mut dyn Frontend,
+        cursor_on: bool,
+    ) -> bool {
+        let Some(display) = self.redisplay(id, frontend.area(), frontend.cell_metrics()) else {
+            return false;
+        };
+        let frame = self.frames.get_mut(&id).expect("redisplayed frame");
+        let last = frame.shown.take().map(|shown| shown.picture);
+        frame.shown = Some(damage::show(display, cursor_on, last, frontend));
+        true
+    }
+
+    //Turns the cursor of frame `id` on or off without laying anything out
+    //again, for blinking. Only the cursor's cells get painted. False if the
+    //frame hasn't been drawn yet.
+    pub fn redraw_cursor(
+        &mut self,
+        id: FrameId,
+        frontend: &mut dyn Frontend,
+        cursor_on: bool,
+    ) -> bool {
+        let Some(frame) = self.frames.get_mut(&id) else {
+            return false;
+        };
+        let Some(shown) = frame.shown.take() else {
+            return false;
+        };
+        frame.shown = Some(if shown.cursor_on == cursor_on {
+            shown
+        } else {
+            damage::show(shown.display, cursor_on, Some(shown.picture), frontend)
+        });
+        true
+    }
+
+    //Makes the next draw_frame paint all of frame `id`, for when the
+    //frontend lost what it showed or draws it differently now
+    pub fn invalidate_frame(&mut self, id: FrameId) {
+        if let Some(frame) = self.frames.get_mut(&id) {
+            frame.shown = None;
+        }
+    }
+
+    //Finds theme `name` on the load path, or at that path, and enables it
+    //on top of any others
+    pub fn load_theme(&mut self, name: &str) -> Result<(), String> {
+        let path = theme::find(&self.custom_theme_load_path, name)
+            .ok_or_else(|| format!("Unable to find theme file for {name}"))?;
+        let theme = theme::load(&path)?;
+        self.faces.enable_theme(theme);
+        self.frontend_requests.push(FrontendRequest::RedrawAll);
+        Ok(())
+    }
+
+    pub fn disable_theme(&mut self, name: &str) -> Result<(), String> {
+        if !self.faces.disable_theme(name) {
+            return Err(format!("{name} is not enabled"));
+        }
+        self.frontend_requests.push(FrontendRequest::RedrawAll);
+        Ok(())
+    }
+
+    pub fn font(&self) -> &FontConfig {
+        &self.font
+    }
+
+    pub fn set_font(&mut self, font: FontConfig) {
+        if font != self.font {
+            self.font = font;
+            self.frontend_requests.push(FrontendRequest::FontChanged);
+        }
+    }
+
+    //Makes text `steps` steps bigger, or smaller if negative. Zero goes
+    //back to the configured size. Steps past the smallest or biggest size
+    //are ignored.
+    pub fn text_scale_adjust(&mut self, steps: i32) {
+        let mut font = self.font.clone();
+        if steps == 0 {
+            font.text_scale = 0;
+        }
+        for _ in 0..steps.unsigned_abs() {
+            let size = font.scaled_size();
+            font.text_scale += steps.signum();
+            if font.scaled_size() == size {
+                font.text_scale -= steps.signum();
+                break;
+            }
+        }
+        self.set_font(font);
+    }
+
+    pub fn take_frontend_requests(&mut self) -> Vec<FrontendRequest> {
+        std::mem::take(&mut self.frontend_requests)
+    }
+
+    //Only one minibuffer at a time. Whoever was waiting on the old one gets
+    //told it was quit.
+    fn set_minibuffer(&mut self, minibuffer: Minibuffer) {
+        while let Some(old) = self.minibuffer.take() {
+            old.quit()(self);
+        }
+        self.minibuffer = Some(minibuffer);
+    }
+
+    pub fn read_choice(
+        &mut self,
+        prompt: String,
+        choices: &[char],
+        callback: minibuffer::ChoiceCallback,
+    ) {
+        self.set_minibuffer(Minibuffer::choice(prompt, choices.to_vec(), callback));
+    }
+
+    pub fn read_string(
+        &mut self,
+        prompt: impl Into<String>,
+        initial: &str,
+        callback: minibuffer::InputCallback,
+    ) {
+        self.set_minibuffer(Minibuffer::input(prompt.into(), initial, callback));
+    }
+
+    //Reads one of `candidates`, or anything at all unless `require_match`
+    pub fn completing_read(
+        &mut self,
+        prompt: impl Into<String>,
+        candidates: Vec<String>,
+        require_match: bool,
+        initial: &str,
+        callback: minibuffer::InputCallback,
+    ) {
+        let styles = self.completion_styles.clone();
+        self.set_minibuffer(Minibuffer::completing(
+            prompt.into(),
+            initial,
+            candidates,
+            styles,
+            require_match,
+            callback,
+        ));
+    }
+
+    //Runs bunlang `source`. `done` gets the result once the script finishes,
+    //which might be after it has asked the user something.
+    pub fn eval_script(&mut self, source: &str, done: ScriptCallback) {
+        if self.script.is_busy() {
+            done(self, Err("Another script is still running".to_owned()));
+            return;
+        }
+        self.script.start(source.to_owned(), done);
+        self.pump_script();
+    }
+
+    //Serves the running script's calls until it finishes or waits on the
+    //minibuffer
+    fn pump_script(&mut self) {
+        loop {
+            match self.script.next_event() {
+                script::Event::Call { name, args } => {
+                    let reply = match script::parse_prompt(&name, &args) {
+                        Some(Ok(prompt)) => {
+                            self.script_prompt(prompt);
+                            return;
+                        }
+                        Some(Err(e)) => Some(Err(e)),
+                        None => script::call_builtin(self, &name, args),
+                    };
+                    self.script.reply(reply);
+                }
+                script::Event::Done(result) => {
+                    if let Some(done) = self.script.finish() {
+                        done(self, result);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    fn script_prompt(&mut self, prompt: script::Prompt) {
+        let callback: minibuffer::InputCallback = Box::new(|editor, input| {
+            let reply = input.map(Value::Str).ok_or_else(|| "Quit".to_owned());
+            editor.script.reply(Some(reply));
+            editor.pump_script();
+        });
+        match prompt {
+            script::Prompt::ReadString { prompt, initial } => {
+                self.read_string(prompt, &initial, callback)
+            }
+            script::Prompt::CompletingRead {
+                prompt,
+                candidates,
+                require_match,
+                initial,
+            } => self.completing_read(prompt, candidates, require_match, &initial, callback),
+        }
+    }
+
+    pub fn minibuffer(&self) -> Option<&Minibuffer> {
+        self.minibuffer.as_ref()
+    }
+
+    pub fn message(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        log::info!("{message}");
+        self.message = Some(message);
+    }
+
+    pub fn current_message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    //Starts searching the current buffer incrementally, what C-s, C-r,
+    //C-M-s and C-M-r do
+    pub fn start_isearch(&mut self, forward: bool, regex: bool) {
+        let buffer = self.current_buffer();
+        let case_fold_search = self.variables.flag("case-fold-search", buffer);
+        let previous = if regex {
+            self.last_regexp_search.clone()
+        } else {
+            self.last_search.clone()
+        };
+        let isearch = Isearch::new(buffer, forward, regex, case_fold_search, previous);
+        self.isearch = Some(isearch);
+    }
+
+    pub fn isearch(&self) -> Option<&Isearch> {
+        self.isearch.as_ref()
+    }
+
+    //Feeds `key` to `isearch`. False if the key ended the search and still
+    //has to run like any other key.
+    fn isearch_key(&mut self, isearch: Isearch, key: Key) -> bool {
+        let Some(buffer) = self.buffers.get_mut(&isearch.buffer()) else {
+            return false;
+        };
+        match isearch.handle_key(key, buffer) {
+            IsearchResult::Searching(isearch) => {
+                self.isearch = Some(isearch);
+                true
+            }
+            IsearchResult::Quit => {
+                self.message("Quit");
+                true
+            }
+            IsearchResult::Exit(isearch, unread) => {
+                self.isearch_done(isearch);
+                unread.is_none()
+            }
+        }
+    }
+
+    //Remembers what was searched for, and leaves the mark where the search
+    //started so C-x C-x goes back there
+    fn isearch_done(&mut self, isearch: Isearch) {
+        if !isearch.query().is_empty() {
+            let last = if isearch.is_regex() {
+                &mut self.last_regexp_search
+            } else {
+                &mut self.last_search
+            };
+            *last = Some(isearch.query().to_owned());
+        }
+        let Some(buffer) = self.buffers.get_mut(&isearch.buffer()) else {
+            return;
+        };
+        if buffer.point() != isearch.origin() {
+            buffer.set_mark(isearch.origin());
+            buffer.deactivate_mark();
+            self.message("Mark saved where search started");
+        }
+    }
+
+    //Moves point past the next match of `query` in the current buffer, to
+    //its end going forward and its start going backward. The match can't
+    //go past `bound`. Point stays put if there's no match.
+    pub fn search(
+        &mut self,
+        query: &str,
+        regex: bool,
+        forward: bool,
+        bound: Option<usize>,
+    ) -> Result<Option<Range<usize>>, String> {
+        let buffer = self.current_buffer();
+        let case_fold_search = self.variables.flag("case-fold-search", buffer);
+        let mut pattern = SearchPattern::new(
+            query,
+            regex,
+            search::case_fold(query, regex, case_fold_search),
+        )?;
+        let (text, point, len) = (buffer.text(), buffer.point(), buffer.len_chars());
+        let found = if forward {
+            let limit = bound.unwrap_or(len).clamp(point, len);
+            pattern.find_forward(text, point, limit)?
+        } else {
+            let limit = bound.unwrap_or(0).min(point);
+            pattern.find_backward(text, point, limit)?
+        };
+        if let Some(found) = &found {
+            let point = if forward { found.end } else { found.start };
+            self.current_buffer_mut().set_point(point);
+        }
+        Ok(found)
+    }
+
+    //Replaces `from` with `to` after point, or in the region if it's
+    //active, asking about each match first. `to` can have $1 and the like
+    //in it for groups when `regex`.
+    pub fn query_replace(&mut self, from: &str, to: &str, regex: bool) -> Result<(), String> {
+        if from.is_empty() {
+            return Err("Nothing to replace".to_owned());
+        }
+        let case_fold_search = self
+            .variables
+            .flag("case-fold-search", self.current_buffer());
+        let replace =
+            QueryReplace::new(self.current_buffer_mut(), from, to, regex, case_fold_search)?;
+        self.query_replace_next(replace);
+        Ok(())
+    }
+
+    //Asks about the next match, or replaces it right away after !
+    fn query_replace_next(&mut self, mut replace: QueryReplace) {
+        loop {
+            if replace.buffer() != self.current {
+                return self.finish_query_replace(replace);
+            }
+            let found = match replace.next_match(self.current_buffer()) {
+                Ok(Some(found)) => found,
+                Ok(None) => return self.finish_query_replace(replace),
+                Err(e) => {
+                    self.finish_query_replace(replace);
+                    return self.message(e);
+                }
+            };
+            self.current_buffer_mut().set_point(found.end);
+            if !replace.replace_all {
+                //Asking first, so quitting a query-replace that was
+                //already asking can't finish this one
+                self.read_choice(
+                    replace.prompt(),
+                    &['y', ' ', 'n', '!', '.', 'q'],
+                    Box::new(|editor, choice| editor.query_replace_answer(choice)),
+                );
+                self.query_replace = Some(replace);
+                return;
+            }
+            self.replace_match(&mut replace);
+        }
+    }
+
+    //y or SPC replaces the match, n skips it, ! replaces the rest without
+    //asking, . replaces it and stops, anything else just stops
+    fn query_replace_answer(&mut self, choice: Option<char>) {
+        let Some(mut replace) = self.query_replace.take() else {
+            return;
+        };
+        match choice {
+            Some('y' | ' ') => {
+                self.replace_match(&mut replace);
+                self.query_replace_next(replace);
+            }
+            Some('n') => {
+                replace.skip();
+                self.query_replace_next(replace);
+            }
+            Some('!') => {
+                replace.replace_all = true;
+                self.replace_match(&mut replace);
+                self.query_replace_next(replace);
+            }
+            Some('.') => {
+                self.replace_match(&mut replace);
+                self.finish_query_replace(replace);
+            }
+            _ => self.finish_query_replace(replace),
+        }
+    }
+
+    fn replace_match(&mut self, replace: &mut QueryReplace) {
+        let Some(found) = replace.current() else {
+            return;
+        };
+        let replacement = replace.replacement(self.current_buffer());
+        self.delete_region(found.start, found.end);
+        self.current_buffer_mut().set_point(found.start);
+        self.insert(&replacement);
+        replace.did_replace(found.start + replacement.chars().count());
+    }
+
+    fn finish_query_replace(&mut self, replace: QueryReplace) {
+        let replaced = match self.buffers.get_mut(&replace.buffer()) {
+            Some(buffer) => replace.finish(buffer),
+            None => replace.replaced(),
+        };
+        let plural = if replaced == 1 { "" } else { "s" };
+        self.message(format!("Replaced {replaced} occurrence{plural}"));
+    }
+
+    pub fn lsp(&self) -> &Lsp {
+        &self.lsp
+    }
+
+    pub(crate) fn lsp_mut(&mut self) -> &mut Lsp {
+        &mut self.lsp
+    }
+
+    pub fn dap(&self) -> &Dap {
+        &self.dap
+    }
+
+    pub(crate) fn dap_mut(&mut self) -> &mut Dap {
+        &mut self.dap
+    }
+
+    pub fn project_search(&self) -> Option<&ProjectSearch> {
+        self.project_search.as_ref()
+    }
+
+    //Searches the files of the current buffer's project for regex `query`,
+    //listing the matches in the *project-search* buffer as they're found
+    pub fn start_project_search(&mut self, query: &str) -> Result<(), String> {
+        let root = project::project_root(&self.default_directory());
+        self.search_project(root, query)
+    }
+
+    fn search_project(&mut self, root: PathBuf, query: &str) -> Result<(), String> {
+        if query.is_empty() {
+            return Err("Nothing to search for".to_owned());
+        }
+        let background = self
+            .background
+            .clone()
+            .ok_or_else(|| "Project search needs the async runtime".to_owned())?;
+        let case_fold_search = self
+            .variables
+            .flag("case-fold-search", self.current_buffer());
+        let case_fold = search::case_fold(query, true, case_fold_search);
+        let id = match self
+            .buffers
+            .values()
+            .find(|b| b.name() == project::RESULTS_BUFFER)
+        {
+            Some(buffer) => buffer.id(),
+            None => self.create_buffer(project::RESULTS_BUFFER),
+        };
+        let buffer = self.buffers.get_mut(&id).expect("results buffer");
+        let search = ProjectSearch::start(&background, buffer, root.clone(), query, case_fold)?;
+        //Dropping the last one stops it if it's still going
+        self.project_search = Some(search);
+        self.switch_to_buffer(id);
+        if self.current_buffer().major_mode() != project::RESULTS_MODE {
+            self.set_major_mode(project::RESULTS_MODE)?;
+        }
+        self.set_local(
+            "default-directory",
+            Value::Str(root.to_string_lossy().into_owned()),
+        )
+    }
+
+    //Runs the last project-search again from the results buffer
+    pub fn project_search_again(&mut self) -> Result<(), String> {
+        let search = self.current_project_search()?;
+        let (root, query) = (search.root().to_owned(), search.query().to_owned());
+        self.search_project(root, &query)
+    }
+
+    //The project-search whose results are in the current buffer
+    fn current_project_search(&self) -> Result<&ProjectSearch, String> {
+        self.project_search
+            .as_ref()
+            .filter(|search| search.buffer() == self.current)
+            .ok_or_else(|| "Not in project search results".to_owned())
+    }
+
+    //Visits the match on the results line at point in another window,
+    //selecting it if `select`. Works on any buffer of path:line:column:
+    //lines relative to its default-directory.
+    pub fn project_search_goto(&mut self, select: bool) -> Result<(), String> {
+        let buffer = self.current_buffer();
+        let text = buffer.text();
+        let line = text.line(text.char_to_line(buffer.point())).to_string();
+        let (path, row, column) =
+            project::parse_result_line(&line).ok_or_else(|| "No match on this line".to_owned())?;
+        let path = self.default_directory().join(path);
+        let id = self
+            .find_file_noselect(&path)
+            .map_err(|e| format!("Could not visit {}: {e}", path.display()))?;
+        let buffer = self.buffers.get_mut(&id).expect("visited buffer");
+        let text = buffer.text();
+        let row = row.min(text.len_lines() - 1);
+        let line_end = text.line_to_char(row) + text.line(row).len_chars();
+        let point = (text.line_to_char(row) + column).min(line_end);
+        buffer.set_point(point);
+        self.display_buffer_other_window(id, select)
+    }
+
+    //Moves point `count` matches down the results, up if it's negative,
+    //and shows that match in another window
+    pub fn project_search_next(&mut self, count: isize) -> Result<(), String> {
+        let buffer = self.current_buffer();
+        let text = buffer.text();
+        let mut line = text.char_to_line(buffer.point());
+        for _ in 0..count.unsigned_abs() {
+            let next = if count > 0 {
+                (line + 1..text.len_lines()).find(|l| is_result_line(text, *l))
+            } else {
+                (0..line).rev().find(|l| is_result_line(text, *l))
+            };
+            line = next.ok_or_else(|| "No more matches".to_owned())?;
+        }
+        let point = text.line_to_char(line);
+        self.current_buffer_mut().set_point(point);
+        self.project_search_goto(false)
+    }
+
+    //Replaces every match still listed in the results with `to`, which can
+    //have $1 and the like in it. The files are edited in their buffers and
+    //saved unless they already had unsaved changes.
+    pub fn project_search_replace(&mut self, to: &str) -> Result<(), String> {
+        let search = self.current_project_search()?;
+        let (root, query, case_fold) = (
+            search.root().to_owned(),
+            search.query().to_owned(),
+            search.case_fold(),
+        );
+        let regex = search::regex(&query, case_fold)?;
+        let mut pattern = SearchPattern::new(&query, true, case_fold)?;
+        //Deleted lines are left alone, so it's the results text that says
+        //what gets replaced
+        let mut files: BTreeMap<PathBuf, Vec<(usize, usize)>> = BTreeMap::new();
+        for line in self.current_buffer().text().lines() {
+            if let Some((path, row, column)) = project::parse_result_line(&line.to_string()) {
+                files.entry(path).or_default().push((row, column));
+            }
+        }
+        let (mut replaced, mut edited, mut errors) = (0, 0, vec![]);
+        for (path, mut spots) in files {
+            let path = root.join(path);
+            let id = match self.find_file_noselect(&path) {
+                Ok(id) => id,
+                Err(e) => {
+                    errors.push(format!("{}: {e}", path.display()));
+                    continue;
+                }
+            };
+            let was_modified = self.buffers[&id].is_modified();
+            //From the end so earlier positions stay put
+            spots.sort_unstable_by(|a, b| b.cmp(a));
+            spots.dedup();
+            let mut count = 0;
+            self.with_current_buffer(id, |editor| {
+                for (row, column) in spots {
+                    let text = editor.current_buffer().text();
+                    if row >= text.len_lines() || column > text.line(row).len_chars() {
+                        continue;
+                    }
+                    //Only where it still matches, the file might have
+                    //changed since
+                    let start = text.line_to_char(row) + column;
+                    let found = match pattern.find_forward(text, start, text.len_chars()) {
+                        Ok(Some(found)) if found.start == start => found,
+                        _ => continue,
+                    };
+                    let replacement = search::expand(&regex, text, &found, to);
+                    editor.replace_region(found, &replacement);
+                    count += 1;
+                }
+            });
+            if count == 0 {
+                continue;
+            }
+            replaced += count;
+            edited += 1;
+            if !was_modified {
+                if let Err(e) = self.save_buffer(id) {
+                    errors.push(format!("Saving {} failed: {e}", path.display()));
+                }
+            }
+        }
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let mut message = format!(
+            "Replaced {replaced} occurrence{} in {edited} file{}",
+            plural(replaced),
+            plural(edited)
+        );
+        for error in errors {
+            message = format!("{message}; {error}");
+        }
+        self.message(message);
+        Ok(())
+    }
+
+    //Replaces char range `range` of the current buffer with `text`, point
+    //staying where it was relative to the text around it
+    pub(crate) fn replace_region(&mut self, range: Range<usize>, text: &str) {
+        let buffer = self.current_buffer_mut();
+        let point = buffer.make_marker(buffer.point());
+        self.delete_region(range.start, range.end);
+        self.current_buffer_mut().set_point(range.start);
+        self.insert(text);
+        let buffer = self.current_buffer_mut();
+        if let Some(point) = buffer.marker(point) {
+            buffer.set_point(point);
+        }
+        buffer.delete_marker(point);
+    }
+
+    //Runs the command called `name`. Every mode is a command too, major
+    //modes switch to themselves and minor modes toggle.
+    pub fn run_command(&mut self, name: &str) -> Result<(), String> {
+        if let Some(command) = self.commands.get(name) {
+            return command(self);
+        }
+        if self.modes.major(name).is_some() {
+            return self.set_major_mode(name);
+        }
+        if self.modes.minor(name).is_some() {
+            let on = !self.is_minor_mode_on(name);
+            self.set_minor_mode(name, on)?;
+            let state = if on { "enabled" } else { "disabled" };
+            self.message(format!("{name} {state}"));
+            return Ok(());
+        }
+        Err(format!("No command named {name}"))
+    }
+
+    pub fn command_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.commands.keys().copied().chain(self.modes.names())
+    }
+
+    //Runs bunlang `source` to the end right away. It can't ask for input,
+    //which is what mode bodies and hooks need since they run in the middle
+    //of other things, maybe even another script.
+    pub fn eval_immediately(&mut self, source: &str) -> Result<Value, String> {
+        script::eval_immediately(self, source)
+    }
+
+    pub fn modes(&self) -> &Modes {
+        &self.modes
+    }
+
+    pub fn modes_mut(&mut self) -> &mut Modes {
+        &mut self.modes
+    }
+
+    //Puts the current buffer in major mode `name`. Every body from the
+    //root ancestor down runs before any of their hooks, so hooks see the
+    //mode fully set up.
+    pub fn set_major_mode(&mut self, name: &str) -> Result<(), String> {
+        let lineage = self.modes.lineage(name)?;
+        let language = lineage
+            .iter()
+            .rev()
+            .find_map(|mode| mode.syntax.as_deref())
+            .and_then(SyntaxLanguage::named);
+        let bodies: Vec<(String, String)> = lineage
+            .iter()
+            .flat_map(|mode| {
+                mode.body
+                    .iter()
+                    .map(|body| (mode.name.clone(), body.clone()))
+            })
+            .collect();
+        let names: Vec<String> = lineage.iter().map(|mode| mode.name.clone()).collect();
+        let dropped = self.current_buffer_mut().set_major_mode(name, language);
+        for variable in dropped {
+            self.run_variable_watchers(&variable);
+        }
+        self.hooks.kill_local(self.current);
+        for (mode, body) in bodies {
+            self.run_isolated(&mode, &body);
+        }
+        for mode in names {
+            self.run_hooks(&format!("{mode}-hook"));
+        }
+        Ok(())
+    }
+
+    //Picks the current buffer's major mode from the file it visits
+    pub fn set_auto_mode(&mut self) -> Result<(), String> {
+        let mode = match self.current_buffer().file_path() {
+            Some(path) => self.modes.mode_for_file(&path.to_string_lossy()),
+            None => mode::FUNDAMENTAL_MODE,
+        }
+        .to_owned();
+        self.set_major_mode(&mode)
+    }
+
+    pub fn is_minor_mode_on(&self, name: &str) -> bool {
+        self.modes.is_global_minor_mode_on(name)
+            || self
+                .current_buffer()
+                .minor_modes()
+                .iter()
+                .any(|m| m == name)
+    }
+
+    //Turns minor mode `name` on or off in the current buffer, or everywhere
+    //for a global one. Its body and hook run either way, like Emacs.
+    pub fn set_minor_mode(&mut self, name: &str, on: bool) -> Result<(), String> {
+        let mode = self
+            .modes
+            .minor(name)
+            .ok_or_else(|| format!("No minor mode named {name}"))?;
+        let (global, body) = (mode.global, mode.body.clone());
+        let enabled = if global {
+            self.modes.global_minor_modes_mut()
+        } else {
+            self.buffers
+                .get_mut(&self.current)
+                .expect("current buffer was killed")
+                .minor_modes_mut()
+        };
+        enabled.retain(|m| m != name);
+        if on {
+            enabled.push(name.to_owned());
+        }
+        for body in body {
+            self.run_isolated(name, &body);
+        }
+        self.run_hooks(&format!("{name}-hook"));
+        Ok(())
+    }
+
+    //Adds bunlang `source` to `hook`, lower `depth` running earlier. A
+    //`local` one only runs while the current buffer is.
+    pub fn add_hook(&mut self, hook: &str, source: &str, depth: i64, local: bool) {
+        let buffer = local.then_some(self.current);
+        self.hooks.add(hook, source, depth, buffer);
+    }
+
+    pub fn remove_hook(&mut self, hook: &str, source: &str, local: bool) {
+        let buffer = local.then_some(self.current);
+        self.hooks.remove(hook, source, buffer);
+    }
+
+    pub fn run_hooks(&mut self, hook: &str) {
+        self.run_hook_with_args(hook, vec![]);
+    }
+
+    //Runs everything on `hook` for the current buffer, `args` being what
+    //hook-args gives them
+    pub fn run_hook_with_args(&mut self, hook: &str, args: Vec<Value>) {
+        let functions = self.hooks.functions(hook, self.current);
+        if functions.is_empty() {
+            return;
+        }
+        self.hook_args.push(args);
+        for source in functions {
+            self.run_isolated(hook, &source);
+        }
+        self.hook_args.pop();
+    }
+
+    pub(crate) fn hook_args(&self) -> Vec<Value> {
+        self.hook_args.last().cloned().unwrap_or_default()
+    }
+
+    //Runs `source` on behalf of `what`, like a hook or a timer. Whatever
+    //goes wrong, even a panic, gets logged and shown instead of reaching
+    //the frontend's event loop, so one broken hook can't take the editor
+    //down.
+    fn run_isolated(&mut self, what: &str, source: &str) {
+        self.isolate(what, |editor| editor.eval_immediately(source));
+    }
+
+    fn isolate(&mut self, what: &str, f: impl FnOnce(&mut Self) -> Result<Value, String>) {
+        ISOLATING.set(ISOLATING.get() + 1);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        ISOLATING.set(ISOLATING.get() - 1);
+        let error = match result {
+            Ok(Ok(_)) => return,
+            Ok(Err(e)) => e,
+            Err(panic) => panic_message(panic),
+        };
+        log::error!("Error in {what}: {error}");
+        self.message(format!("Error in {what}: {error}"));
+    }
+
+    //Runs `f` with buffer `id` current, then goes back unless the old
+    //buffer is gone
+    pub(crate) fn with_current_buffer(&mut self, id: BufferId, f: impl FnOnce(&mut Self)) {
+        let old = std::mem::replace(&mut self.current, id);
+        f(self);
+        if self.buffers.contains_key(&old) {
+            self.current = old;
+        }
+    }
+
+    fn run_change_hook(&mut self, hook: &str, args: &[usize]) {
+        if self.inhibit_change_hooks {
+            return;
+        }
+        self.inhibit_change_hooks = true;
+        let args = args.iter().map(|n| Value::Number(*n as i64)).collect();
+        self.run_hook_with_args(hook, args);
+        self.inhibit_change_hooks = false;
+    }
+
+    //Inserts `text` at point in the current buffer, with the change hooks
+    //run around it
+    pub fn insert(&mut self, text: &str) {
+        let start = self.current_buffer().point();
+        let end = start + text.chars().count();
+        self.run_change_hook(hook::BEFORE_CHANGE_FUNCTIONS, &[start, start]);
+        self.current_buffer_mut().insert(start, text);
+        self.run_change_hook(hook::AFTER_CHANGE_FUNCTIONS, &[start, end, 0]);
+    }
+
+    //Deletes [start, end) from the current buffer, with the change hooks
+    //run around it
+    pub fn delete_region(&mut self, start: usize, end: usize) {
+        let end = end.min(self.current_buffer().len_chars());
+        if start >= end {
+            return;
+        }
+        self.run_change_hook(hook::BEFORE_CHANGE_FUNCTIONS, &[start, end]);
+        self.current_buffer_mut().delete(start, end);
+        self.run_change_hook(hook::AFTER_CHANGE_FUNCTIONS, &[start, start, end - start]);
+    }
+
+    //For frontends to call when one of their windows gains or loses focus
+    pub fn focus_changed(&mut self, focused: bool) {
+        let hook = if focused {
+            hook::FOCUS_IN_HOOK
+        } else {
+            hook::FOCUS_OUT_HOOK
+        };
+        self.run_hooks(hook);
+        self.run_deferred_hooks();
+    }
+
+    //Runs bunlang `source` once there's been no input for `idle`, and every
+    //time after that the user goes idle again if `repeat` is set
+    pub fn run_with_idle_timer(&mut self, idle: Duration, repeat: bool, source: &str) -> TimerId {
+        let id = TimerId(self.next_timer_id);
+        self.next_timer_id += 1;
+        self.idle_timers.push(IdleTimer {
+            id,
+            idle,
+            repeat,
+            source: source.to_owned(),
+            fired: false,
+        });
+        id
+    }
+
+    pub fn cancel_timer(&mut self, id: TimerId) {
+        self.idle_timers.retain(|timer| timer.id != id);
+    }
+
+    //When the frontend should call run_timers next, if anything's waiting
+    pub fn next_timer(&self) -> Option<Instant> {
+        self.idle_timers
+            .iter()
+            .filter_map(|timer| timer.deadline(self.last_input))
+            .min()
+    }
+
+    //Runs the idle timers that are due at `now`. True if there were any,
+    //since they might have changed anything.
+    pub fn run_timers(&mut self, now: Instant) -> bool {
+        let last_input = self.last_input;
+        let due: Vec<(TimerId, String)> = self
+            .idle_timers
+            .iter_mut()
+            .filter(|timer| timer.deadline(last_input).is_some_and(|when| when <= now))
+            .map(|timer| {
+                timer.fired = true;
+                (timer.id, timer.source.clone())
+            })
+            .collect();
+        if due.is_empty() {
+            return false;
+        }
+        self.idle_timers
+            .retain(|timer| timer.repeat || !due.iter().any(|(id, _)| *id == timer.id));
+        for (_, source) in due {
+            self.run_isolated("an idle timer", &source);
+        }
+        self.run_deferred_hooks();
+        true
+    }
+
+    //Lets background work like project-search run on `runtime`. `waker`
+    //gets called from other threads whenever there's something for
+    //process_background_events.
+    pub fn set_async_runtime(&mut self, runtime: Handle, waker: Option<Waker>) {
+        self.background = Some(Background::new(runtime, waker));
+    }
+
+    pub(crate) fn background(&self) -> Option<&Background> {
+        self.background.as_ref()
+    }
+
+    //Whether there's background work with results still to come, for
+    //frontends without a waker to know to keep calling
+    //process_background_events. Servers can also speak up unasked, which
+    //only a waker hears about.
+    pub fn has_background_work(&self) -> bool {
+        self.project_search
+            .as_ref()
+            .is_some_and(ProjectSearch::is_running)
+            || self.lsp.has_pending_requests()
+            || self.dap.has_pending_requests()
+    }
+
+    //Takes in what background work came up with since the last call. True
+    //if that changed anything on screen.
+    pub fn process_background_events(&mut self) -> bool {
+        let changed = self.process_project_search() | lsp::process(self) | dap::process(self);
+        //Edits the servers asked for
+        lsp::send_changes(self);
+        changed
+    }
+
+    fn process_project_search(&mut self) -> bool {
+        let Some(search) = &mut self.project_search else {
+            return false;
+        };
+        let Some(buffer) = self.buffers.get_mut(&search.buffer()) else {
+            //The results buffer was killed, which stops the search
+            self.project_search = None;
+            return false;
+        };
+        let was_running = search.is_running();
+        let changed = search.process(buffer);
+        if was_running && !search.is_running() {
+            let summary = search.summary();
+            self.message(format!("Project search finished with {summary}"));
+        }
+        changed
+    }
+
+    fn window_configuration(&self) -> WindowConfiguration {
+        let mut configuration: WindowConfiguration = self
+            .frames
+            .values()
+            .map(|frame| {
+                let mut windows: Vec<(WindowId, BufferId)> =
+                    frame.windows().map(|w| (w.id(), w.buffer())).collect();
+                windows.sort();
+                (frame.id(), frame.root().clone(), windows)
+            })
+            .collect();
+        configuration.sort_by_key(|(id, _, _)| *id);
+        configuration
+    }
+
+    //Runs the hooks about what a whole command changed rather than each
+    //step of it
+    fn run_deferred_hooks(&mut self) {
+        let buffer = Some(self.current);
+        if self.last_buffer.is_some_and(|last| Some(last) != buffer) {
+            self.run_hooks(hook::BUFFER_SWITCH_HOOK);
+        }
+        self.last_buffer = Some(self.current);
+        let configuration = Some(self.window_configuration());
+        if self.last_window_configuration.is_some()
+            && self.last_window_configuration != configuration
+        {
+            self.run_hooks(hook::WINDOW_CONFIGURATION_CHANGE_HOOK);
+        }
+        self.last_window_configuration = Some(self.window_configuration());
+    }
+
+    pub(crate) fn request_redraw(&mut self) {
+        self.frontend_requests.push(FrontendRequest::RedrawAll);
+    }
+
+    //The value of variable `name` in the current buffer
+    pub fn variable(&self, name: &str) -> Option<&Value> {
+        self.variables.value(name, self.current_buffer())
+    }
+
+    //Sets `name` the way setq does: the current buffer's own value if it
+    //has one or the variable is automatically buffer-local, otherwise the
+    //global default
+    pub fn set_variable(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let local = self.current_buffer().local_variable(name).is_some()
+            || self.variables.get(name).is_some_and(|v| v.local_if_set);
+        if local {
+            self.set_local(name, value)
+        } else {
+            self.set_default(name, value)
+        }
+    }
+
+    pub fn set_default(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.variables.set_default(name, value)?;
+        self.run_variable_watchers(name);
+        Ok(())
+    }
+
+    //Gives the current buffer its own value of `name`
+    pub fn set_local(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.variables.check(name, &value)?;
+        self.current_buffer_mut().set_local_variable(name, value);
+        self.run_variable_watchers(name);
+        Ok(())
+    }
+
+    //Makes the current buffer go back to the global value of `name`
+    pub fn kill_local_variable(&mut self, name: &str) {
+        if self
+            .current_buffer_mut()
+            .kill_local_variable(name)
+            .is_some()
+        {
+            self.run_variable_watchers(name);
+        }
+    }
+
+    //Binds `name` to `value` while `f` runs, like let on a special variable
+    //in Emacs. Whichever value setq would change gets `value` and is put
+    //back afterwards, in the buffer it was in even if `f` switched away.
+    pub fn let_variable<T>(
+        &mut self,
+        name: &str,
+        value: Value,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> Result<T, String> {
+        let variable = self
+            .variables
+            .get(name)
+            .ok_or_else(|| format!("{name} isn't a variable"))?;
+        let default = variable.default_value().clone();
+        let old_local = self.current_buffer().local_variable(name).cloned();
+        let local = old_local.is_some() || variable.local_if_set;
+        let buffer = self.current;
+        self.set_variable(name, value)?;
+        let result = f(self);
+        if !local {
+            //The old value already fit, unless `f` redefined the variable
+            let _ = self.set_default(name, default);
+        } else if self.buffers.contains_key(&buffer) {
+            self.with_current_buffer(buffer, |editor| match old_local {
+                Some(old) => {
+                    let _ = editor.set_local(name, old);
+                }
+                None => editor.kill_local_variable(name),
+            });
+        }
+        Ok(result)
+    }
+
+    fn run_variable_watchers(&mut self, name: &str) {
+        for watcher in self.variables.watchers(name) {
+            match watcher {
+                Watcher::Native(watch) => watch(self, name),
+                Watcher::Script(source) => self.run_isolated(name, &source),
+            }
+        }
+    }
+
+    //Looks `keys` up in the current buffer's mode keymaps, then the global
+    //one. The first keymap that knows the keys decides.
+    fn lookup_key(&self, keys: &[Key]) -> Lookup<'_> {
+        self.modes
+            .active_keymaps(self.current_buffer())
+            .into_iter()
+            .chain([&self.global_keymap])
+            .map(|keymap| keymap.lookup(keys))
+            .find(|lookup| *lookup != Lookup::Unbound)
+            .unwrap_or(Lookup::Unbound)
+    }
+
+    pub fn global_keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.global_keymap
+    }
+
+    //Feeds one key press through the keymap, running a command once a full
+    //binding has been typed
+    pub fn handle_key(&mut self, key: Key) {
+        self.last_input = Instant::now();
+        for timer in &mut self.idle_timers {
+            timer.fired = false;
+        }
+        self.dispatch_key(key);
+        self.run_deferred_hooks();
+        lsp::send_changes(self);
+    }
+
+    fn dispatch_key(&mut self, key: Key) {
+        self.message = None;
+        if let Some(minibuffer) = self.minibuffer.take() {
+            match minibuffer.handle_key(key) {
+                minibuffer::KeyResult::Answered(answer) => answer(self),
+                minibuffer::KeyResult::Pending(minibuffer, message) => {
+                    self.minibuffer = Some(minibuffer);
+                    if let Some(message) = message {
+                        self.message(message);
+                    }
+                }
+            }
+            return;
+        }
+        if let Some(isearch) = self.isearch.take() {
+            if self.isearch_key(isearch, key) {
+                return;
+            }
+        }
+        self.pending_keys.push(key);
+        let keys = std::mem::take(&mut self.pending_keys);
+        let command = match self.lookup_key(&keys) {
+            Lookup::Command(command) => command.to_owned(),
+            Lookup::Prefix => {
+                self.message(format!("{}-", keymap::format_sequence(&keys)));
+                self.pending_keys = keys;
+                return;
+            }
+            Lookup::Unbound => match (keys.len(), key.self_insert_char()) {
+                (1, Some(c)) => {
+                    self.insert(c.encode_utf8(&mut [0; 4]));
+                    self.current_buffer_mut().deactivate_mark();
+                    return;
+                }
+                _ => {
+                    self.message(format!("{} is undefined", keymap::format_sequence(&keys)));
+                    return;
+                }
+            },
+        };
+        self.call_interactively(&command);
+    }
+
+    //Runs a command the way a key binding or M-x does, reporting errors in
+    //the echo area
+    pub fn call_interactively(&mut self, command: &str) {
+        let tick = self.current_buffer().change_tick();
+        if let Err(e) = self.run_command(command) {
+            self.message(e);
+        }
+        //Editing deactivates the mark, same as transient-mark-mode
+        if self.current_buffer().change_tick() != tick {
+            self.current_buffer_mut().deactivate_mark();
+        }
+    }
+}
+
+fn layout_params(variables: &Variables, buffer: &Buffer, metrics: CellMetrics) -> LayoutParams {
+    LayoutParams {
+        metrics,
+        wrap: if variables.flag("truncate-lines", buffer) {
+            Wrap::Truncate
+        } else {
+            Wrap::Wrap
+        },
+        tab_width: variables.number("tab-width", buffer).max(1),
+    }
+}
+
+fn cursor_style(variables: &Variables, buffer: &Buffer) -> CursorStyle {
+    CursorStyle::from_name(variables.symbol("cursor-type", buffer)).unwrap_or_default()
+}
+
+//Whether line `line` of project-search results is about a match
+fn is_result_line(text: &Rope, line: usize) -> bool {
+    project::parse_result_line(&text.line(line).to_string()).is_some()
+}
+
+thread_local! {
+    //How deep in run_isolated this thread is
+    static ISOLATING: Cell<usize> = const { Cell::new(0) };
+}
+
+//Whether a panic on this thread right now gets caught and reported by the
+//editor, which carries on afterwards. Frontends' panic hooks check this so
+//they only tear down for panics that end the program.
+pub fn isolating_panics() -> bool {
+    ISOLATING.get() > 0
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "panicked".to_owned(),
+        },
+    }
+}
+
+//The fringe beside `text`, with the marks of the lines it shows
+fn fringe_display(
+    buffer: &Buffer,
+    text: &Layout,
+    area: Rect,
+    faces: &Faces,
+    params: &LayoutParams,
+) -> FringeDisplay {
+    let lines = match (text.display_rows.first(), text.display_rows.last()) {
+        (Some(first), Some(last)) => first.line..last.line + 1,
+        _ => 0..0,
+    };
+    let marks = buffer.fringe_marks(lines);
+    let mut rows = String::new();
+    let mut spans = FaceSpans::new(faces.resolve(&[face::FRINGE]));
+    let mut offset = 0;
+    for row in &text.display_rows {
+        let first_row = row.start == buffer.text().line_to_char(row.line);
+        match marks.get(&row.line).filter(|_| first_row) {
+            Some((symbol, face)) => {
+                rows.push(*symbol);
+                spans
+                    .spans
+                    .push((offset..offset + 1, faces.resolve(&[face, face::FRINGE])));
+            }
+            None => rows.push(' '),
+        }
+        rows.push('\n');
+        offset += 2;
+    }
+    let params = LayoutParams {
+        wrap: Wrap::Truncate,
+        ..*params
+    };
+    FringeDisplay {
+        text: Layout::new(&ropey::Rope::from(rows), 0, area, &params),
+        faces: spans,
+    }
+}
+
+//Faces of the text in `range` from its properties and overlays, with
+//`transient` ones on top
+fn text_faces(
+    buffer: &Buffer,
+    faces: &Faces,
+    range: Range<usize>,
+    transient: &[(Range<usize>, &str)],
+) -> FaceSpans {
+    let mut spans = FaceSpans::new(faces.resolve(&[]));
+    spans.spans = buffer
+        .face_runs(range, transient)
+        .into_iter()
+        .map(|(range, names)| (range, faces.resolve(&names)))
+        .collect();
+    spans
+}
+
+fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    //canonicalize fails on files that don't exist yet, so fall back to
+    //resolving just the parent
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(_) => {
+            let path = if path.is_absolute() {
+                path.to_owned()
+            } else {
+                std::env::current_dir()?.join(path)
+            };
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => Ok(parent
+                    .canonicalize()
+                    .unwrap_or_else(|_| parent.to_owned())
+                    .join(name)),
+                _ => Ok(path),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{fileio, testing::editing};
+
+    fn key(description: &str) -> Key {
+        Key::parse(description).unwrap()
+    }
+
+    //An editor visiting an unsaved, modified file in `frames` frames
+    fn unsaved(name: &str, frames: usize) -> (Editor, BufferId, Vec<FrameId>) {
+        let path = std::env::temp_dir().join(format!("bunmacs-{name}-{}.txt", std::process::id()));
+        let (mut editor, first) = editing("");
+        let buffer = editor.find_file(&path).unwrap();
+        editor.insert("unsaved");
+        let frames = std::iter::once(first)
+            .chain((1..frames).map(|_| editor.make_frame()))
+            .collect();
+        editor.take_frontend_requests();
+        (editor, buffer, frames)
+    }
+
+    #[test]
+    fn auto_saving_writes_modified_buffers_only() {
+        let (mut editor, _, _) = unsaved("auto-save", 1);
+        let visiting = editor.current_buffer().file_path().unwrap().to_owned();
+        let latin1 = visiting.with_file_name(format!("bunmacs-latin1-{}.txt", std::process::id()));
+        fs::write(&latin1, b"caf\xe9\n").unwrap();
+        editor.find_file(&latin1).unwrap();
+        //Latin-1 can't hold this, so the auto-save is UTF-8 instead
+        editor.insert("✓");
+        let scratch = editor.create_buffer("notes/today");
+        editor.switch_to_buffer(scratch);
+        editor.insert("no file");
+        editor.create_buffer("untouched");
+
+        let mut saved = editor.do_auto_save();
+        saved.sort();
+        let mut expected = vec![
+            fileio::auto_save_path(&visiting),
+            fileio::auto_save_path(&latin1),
+            fileio::buffer_auto_save_path("notes/today"),
+        ];
+        expected.sort();
+        assert_eq!(saved, expected);
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!(read(&fileio::auto_save_path(&visiting)), "unsaved");
+        assert_eq!(read(&fileio::auto_save_path(&latin1)), "✓café\n");
+        assert_eq!(
+            read(&fileio::buffer_auto_save_path("notes/today")),
+            "no file"
+        );
+        //Auto-saving isn't saving
+        assert!(!visiting.exists());
+        assert_eq!(fs::read(&latin1).unwrap(), b"caf\xe9\n");
+        for path in saved.iter().chain([&latin1]) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn buffers_shown_elsewhere_are_not_lost() {
+        let (editor, _, frames) = unsaved("shown-elsewhere", 2);
+        assert_eq!(editor.unsaved_buffers_lost_by_deleting(frames[0]), []);
+        let (editor, buffer, frames) = unsaved("shown-once", 1);
+        assert_eq!(editor.unsaved_buffers_lost_by_deleting(frames[0]), [buffer]);
+    }
+
+    #[test]
+    fn deleting_a_frame_asks_about_unsaved_buffers() {
+        let (mut editor, buffer, frames) = unsaved("discard", 1);
+        editor.delete_frame_interactively(frames[0]);
+        assert!(editor.frame(frames[0]).is_some());
+        editor.handle_key(key("n"));
+        assert!(editor.frame(frames[0]).is_none());
+        assert!(editor.buffer(buffer).is_none());
+        assert_eq!(
+            editor.take_frontend_requests(),
+            [FrontendRequest::DeleteFrame(frames[0])]
+        );
+    }
+
+    #[test]
+    fn quitting_keeps_the_frame() {
+        let (mut editor, buffer, frames) = unsaved("quit", 1);
+        editor.delete_frame_interactively(frames[0]);
+        editor.handle_key(key("q"));
+        assert!(editor.frame(frames[0]).is_some());
+        assert!(editor.buffer(buffer).is_some_and(Buffer::is_modified));
+        assert_eq!(editor.take_frontend_requests(), []);
+    }
+
+    #[test]
+    fn frames_losing_nothing_go_straight_away() {
+        let (mut editor, _, frames) = unsaved("shared", 2);
+        editor.delete_frame_interactively(frames[0]);
+        assert!(editor.frame(frames[0]).is_none());
+        assert_eq!(editor.selected_frame(), Some(frames[1]));
+    }
+
+    #[test]
+    fn panics_in_hooks_are_isolated() {
+        let mut editor = Editor::new();
+        let isolated = Cell::new(false);
+        editor.isolate("a hook", |_| {
+            isolated.set(isolating_panics());
+            panic!("boom")
+        });
+        assert!(isolated.get());
+        assert!(!isolating_panics());
+        assert_eq!(editor.current_message(), Some("Error in a hook: boom"));
+        //And the editor carries on
+        editor.insert("still here");
+        assert_eq!(editor.current_buffer().text().to_string(), "still here");
+    }
+}
@@ -0,0 +1,587 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{decoration::Color, theme::Theme};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weight {
+    #[default]
+    Normal,
+    Bold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Slant {
+    #[default]
+    Normal,
+    Italic,
+}
+
+//A named text style. Attributes left as None come from the faces it
+//inherits from, and in the end from `default`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Face {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub weight: Option<Weight>,
+    pub slant: Option<Slant>,
+    pub underline: Option<bool>,
+    //Earlier faces win over later ones
+    pub inherit: Vec<String>,
+}
+
+//Every attribute filled in, ready to draw
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedFace {
+    pub foreground: Color,
+    pub background: Color,
+    pub weight: Weight,
+    pub slant: Slant,
+    pub underline: bool,
+}
+
+//What `default` falls back to for anything it leaves out
+const FALLBACK: ResolvedFace = ResolvedFace {
+    foreground: [1.0, 1.0, 1.0, 1.0],
+    background: [0.1, 0.2, 0.3, 1.0],
+    weight: Weight::Normal,
+    slant: Slant::Normal,
+    underline: false,
+};
+
+//Faces the editor itself draws with
+pub const DEFAULT: &str = "default";
+pub const CURSOR: &str = "cursor";
+pub const REGION: &str = "region";
+pub const HL_LINE: &str = "hl-line";
+pub const VERTICAL_BORDER: &str = "vertical-border";
+pub const FRINGE: &str = "fringe";
+pub const MODE_LINE: &str = "mode-line";
+pub const MODE_LINE_INACTIVE: &str = "mode-line-inactive";
+pub const MINIBUFFER_PROMPT: &str = "minibuffer-prompt";
+pub const COMPLETIONS_CURRENT: &str = "completions-current";
+//The match isearch is at and the other ones on screen
+pub const ISEARCH: &str = "isearch";
+pub const LAZY_HIGHLIGHT: &str = "lazy-highlight";
+pub const QUERY_REPLACE: &str = "query-replace";
+//Matches listed in search results
+pub const MATCH: &str = "match";
+//Language server diagnostics
+pub const DIAGNOSTIC_ERROR: &str = "diagnostic-error";
+pub const DIAGNOSTIC_WARNING: &str = "diagnostic-warning";
+pub const DIAGNOSTIC_NOTE: &str = "diagnostic-note";
+//Debugger breakpoints, and the line the debuggee is stopped at
+pub const DAP_BREAKPOINT: &str = "dap-breakpoint";
+pub const DAP_BREAKPOINT_UNVERIFIED: &str = "dap-breakpoint-unverified";
+pub const DAP_STOPPED: &str = "dap-stopped";
+pub const DAP_STOPPED_LINE: &str = "dap-stopped-line";
+//What syntax highlighting colors code with
+pub const FONT_LOCK_FACES: &[&str] = &[
+    "font-lock-comment-face",
+    "font-lock-string-face",
+    "font-lock-keyword-face",
+    "font-lock-builtin-face",
+    "font-lock-function-name-face",
+    "font-lock-variable-name-face",
+    "font-lock-type-face",
+    "font-lock-constant-face",
+    "font-lock-number-face",
+];
+
+impl Face {
+    pub fn foreground(color: Color) -> Self {
+        Face {
+            foreground: Some(color),
+            ..Face::default()
+        }
+    }
+
+    pub fn background(color: Color) -> Self {
+        Face {
+            background: Some(color),
+            ..Face::default()
+        }
+    }
+
+    pub fn inheriting(parent: &str) -> Self {
+        Face {
+            inherit: vec![parent.to_owned()],
+            ..Face::default()
+        }
+    }
+
+    //Attributes of `self`, with anything it leaves out taken from `under`
+    pub fn merge(&self, under: &Face) -> Face {
+        Face {
+            foreground: self.foreground.or(under.foreground),
+            background: self.background.or(under.background),
+            weight: self.weight.or(under.weight),
+            slant: self.slant.or(under.slant),
+            underline: self.underline.or(under.underline),
+            inherit: vec![],
+        }
+    }
+
+    fn resolve(&self) -> ResolvedFace {
+        ResolvedFace {
+            foreground: self.foreground.unwrap_or(FALLBACK.foreground),
+            background: self.background.unwrap_or(FALLBACK.background),
+            weight: self.weight.unwrap_or(FALLBACK.weight),
+            slant: self.slant.unwrap_or(FALLBACK.slant),
+            underline: self.underline.unwrap_or(FALLBACK.underline),
+        }
+    }
+}
+
+//"#rrggbb" or "#rrggbbaa", or the short "#rgb" and "#rgba"
+pub fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+    if !hex.is_ascii() {
+        return None;
+    }
+    let digits = match hex.len() {
+        3 | 4 => 1,
+        6 | 8 => 2,
+        _ => return None,
+    };
+    let channel = |i: usize| {
+        let digits = hex.get(i * digits..(i + 1) * digits)?;
+        let value = u8::from_str_radix(digits, 16).ok()? as f32;
+        Some(if digits.len() == 1 {
+            value / 15.0
+        } else {
+            value / 255.0
+        })
+    };
+    let alpha = if hex.len() == 4 * digits {
+        channel(3)?
+    } else {
+        1.0
+    };
+    Some([channel(0)?, channel(1)?, channel(2)?, alpha])
+}
+
+//Every face by name. Enabled themes override the faces they define, the
+//last one enabled wins.
+#[derive(Debug, Clone)]
+pub struct Faces {
+    faces: HashMap<String, Face>,
+    themes: Vec<Theme>,
+}
+
+impl Default for Faces {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Faces {
+    pub fn new() -> Self {
+        let mut faces = Faces {
+            faces: HashMap::new(),
+            themes: vec![],
+        };
+        faces.define(
+            DEFAULT,
+            Face {
+                foreground: Some(FALLBACK.foreground),
+                background: Some(FALLBACK.background),
+                weight: Some(FALLBACK.weight),
+                slant: Some(FALLBACK.slant),
+                underline: Some(FALLBACK.underline),
+                inherit: vec![],
+            },
+        );
+        faces.define(
+            "bold",
+            Face {
+                weight: Some(Weight::Bold),
+                ..Face::default()
+            },
+        );
+        faces.define(
+            "italic",
+            Face {
+                slant: Some(Slant::Italic),
+                ..Face::default()
+            },
+        );
+        faces.define(
+            "underline",
+            Face {
+                underline: Some(true),
+                ..Face::default()
+            },
+        );
+        faces.define(CURSOR, Face::background([0.9, 0.9, 0.9, 1.0]));
+        faces.define(REGION, Face::background([0.25, 0.35, 0.55, 1.0]));
+        faces.define(HL_LINE, Face::background([0.15, 0.25, 0.36, 1.0]));
+        faces.define(VERTICAL_BORDER, Face::foreground([0.3, 0.3, 0.3, 1.0]));
+        faces.define(FRINGE, Face::background([0.13, 0.23, 0.34, 1.0]));
+        faces.define(
+            MODE_LINE,
+            Face {
+                foreground: Some([1.0, 1.0, 1.0, 1.0]),
+                background: Some([0.35, 0.4, 0.5, 1.0]),
+                ..Face::default()
+            },
+        );
+        faces.define(
+            MODE_LINE_INACTIVE,
+            Face {
+                foreground: Some([0.7, 0.7, 0.7, 1.0]),
+                background: Some([0.18, 0.22, 0.28, 1.0]),
+                ..Face::inheriting(MODE_LINE)
+            },
+        );
+        faces.define(
+            "mode-line-buffer-id",
+            Face {
+                foreground: Some([1.0, 0.85, 0.5, 1.0]),
+                ..Face::inheriting("bold")
+            },
+        );
+        faces.define(MINIBUFFER_PROMPT, Face::foreground([0.55, 0.75, 1.0, 1.0]));
+        faces.define(COMPLETIONS_CURRENT, Face::inheriting(REGION));
+        faces.define(
+            ISEARCH,
+            Face {
+                foreground: Some([0.1, 0.1, 0.1, 1.0]),
+                background: Some([0.95, 0.6, 0.3, 1.0]),
+                ..Face::default()
+            },
+        );
+        faces.define(LAZY_HIGHLIGHT, Face::background([0.4, 0.35, 0.2, 1.0]));
+        faces.define(QUERY_REPLACE, Face::inheriting(ISEARCH));
+        faces.define(MATCH, Face::inheriting(LAZY_HIGHLIGHT));
+        faces.define(
+            DIAGNOSTIC_ERROR,
+            Face {
+                background: Some([0.4, 0.15, 0.15, 1.0]),
+                underline: Some(true),
+                ..Face::default()
+            },
+        );
+        faces.define(
+            DIAGNOSTIC_WARNING,
+            Face {
+                background: Some([0.35, 0.3, 0.1, 1.0]),
+                underline: Some(true),
+                ..Face::default()
+            },
+        );
+        faces.define(
+            DIAGNOSTIC_NOTE,
+            Face {
+                underline: Some(true),
+                ..Face::default()
+            },
+        );
+        faces.define(DAP_BREAKPOINT, Face::foreground([0.9, 0.3, 0.3, 1.0]));
+        faces.define(
+            DAP_BREAKPOINT_UNVERIFIED,
+            Face::foreground([0.55, 0.55, 0.55, 1.0]),
+        );
+        faces.define(DAP_STOPPED, Face::foreground([1.0, 0.85, 0.3, 1.0]));
+        faces.define(DAP_STOPPED_LINE, Face::background([0.3, 0.3, 0.15, 1.0]));
+        faces.define(
+            "error",
+            Face {
+                foreground: Some([1.0, 0.45, 0.45, 1.0]),
+                ..Face::inheriting("bold")
+            },
+        );
+        faces.define(
+            "warning",
+            Face {
+                foreground: Some([1.0, 0.7, 0.3, 1.0]),
+                ..Face::inheriting("bold")
+            },
+        );
+        faces.define(
+            "success",
+            Face {
+                foreground: Some([0.5, 0.9, 0.5, 1.0]),
+                ..Face::inheriting("bold")
+            },
+        );
+        let font_lock_colors = [
+            [0.55, 0.6, 0.65, 1.0],
+            [0.6, 0.85, 0.55, 1.0],
+            [0.8, 0.6, 1.0, 1.0],
+            [0.55, 0.8, 0.95, 1.0],
+            [0.5, 0.75, 1.0, 1.0],
+            [0.95, 0.8, 0.6, 1.0],
+            [0.5, 0.9, 0.85, 1.0],
+            [0.95, 0.65, 0.6, 1.0],
+            [0.95, 0.65, 0.6, 1.0],
+        ];
+        for (name, color) in FONT_LOCK_FACES.iter().zip(font_lock_colors) {
+            faces.define(name, Face::foreground(color));
+        }
+        if let Some(comment) = faces.get_mut("font-lock-comment-face") {
+            comment.slant = Some(Slant::Italic);
+        }
+        faces
+    }
+
+    //Adds or replaces face `name`. Themes still win over it.
+    pub fn define(&mut self, name: &str, face: Face) {
+        self.faces.insert(name.to_owned(), face);
+    }
+
+    //Face `name` as it's drawn, with themes applied
+    pub fn get(&self, name: &str) -> Option<&Face> {
+        self.themes
+            .iter()
+            .rev()
+            .find_map(|theme| theme.faces.get(name))
+            .or_else(|| self.faces.get(name))
+    }
+
+    //The face `name` outside of any theme
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Face> {
+        self.faces.get_mut(name)
+    }
+
+    //Every face, including ones only themes define
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .faces
+            .keys()
+            .chain(self.themes.iter().flat_map(|theme| theme.faces.keys()))
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    //Enables `theme` on top of the others, replacing any enabled theme
+    //with the same name
+    pub fn enable_theme(&mut self, theme: Theme) {
+        self.disable_theme(&theme.name);
+        self.themes.push(theme);
+    }
+
+    //False if no theme called `name` was enabled
+    pub fn disable_theme(&mut self, name: &str) -> bool {
+        let before = self.themes.len();
+        self.themes.retain(|theme| theme.name != name);
+        self.themes.len() != before
+    }
+
+    //Names of the enabled themes, the one that wins first
+    pub fn enabled_themes(&self) -> impl Iterator<Item = &str> {
+        self.themes.iter().rev().map(|theme| theme.name.as_str())
+    }
+
+    //`name` with everything it inherits merged in. Unknown faces and
+    //inheritance cycles contribute nothing.
+    fn flatten<'a>(&'a self, name: &'a str, seen: &mut Vec<&'a str>) -> Face {
+        let Some(face) = self.get(name) else {
+            return Face::default();
+        };
+        if seen.contains(&name) {
+            return Face::default();
+        }
+        seen.push(name);
+        let merged = face
+            .inherit
+            .iter()
+            .fold(face.merge(&Face::default()), |acc, parent| {
+                acc.merge(&self.flatten(parent, seen))
+            });
+        seen.pop();
+        merged
+    }
+
+    //Merges `names`, earlier ones first, on top of `default`. This is how
+    //text in several faces at once gets drawn.
+    pub fn resolve(&self, names: &[&str]) -> ResolvedFace {
+        names
+            .iter()
+            .chain(std::iter::once(&DEFAULT))
+            .fold(Face::default(), |acc, name| {
+                acc.merge(&self.flatten(name, &mut vec![]))
+            })
+            .resolve()
+    }
+}
+
+//Faces of stretches of some text by char offset, sorted. Anything not
+//covered is drawn in `base`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaceSpans {
+    pub base: ResolvedFace,
+    pub spans: Vec<(Range<usize>, ResolvedFace)>,
+}
+
+impl FaceSpans {
+    pub fn new(base: ResolvedFace) -> Self {
+        FaceSpans {
+            base,
+            spans: vec![],
+        }
+    }
+
+    pub fn face_at(&self, offset: usize) -> ResolvedFace {
+        let i = self.spans.partition_point(|(range, _)| range.end <= offset);
+        match self.spans.get(i) {
+            Some((range, face)) if range.contains(&offset) => *face,
+            _ => self.base,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = [1.0, 0.0, 0.0, 1.0];
+    const GREEN: Color = [0.0, 1.0, 0.0, 1.0];
+    const BLUE: Color = [0.0, 0.0, 1.0, 1.0];
+    const BLACK: Color = [0.0, 0.0, 0.0, 1.0];
+
+    fn faces() -> Faces {
+        let mut faces = Faces::new();
+        faces.define(
+            DEFAULT,
+            Face {
+                foreground: Some(BLACK),
+                background: Some(BLACK),
+                ..Face::default()
+            },
+        );
+        faces.define(
+            "bold",
+            Face {
+                weight: Some(Weight::Bold),
+                ..Face::default()
+            },
+        );
+        faces.define("red", Face::foreground(RED));
+        faces
+    }
+
+    #[test]
+    fn unset_attributes_come_from_default() {
+        let face = faces().resolve(&["red"]);
+        assert_eq!(face.foreground, RED);
+        assert_eq!(face.background, BLACK);
+        assert_eq!(face.weight, Weight::Normal);
+        assert_eq!(faces().resolve(&["no-such-face"]), faces().resolve(&[]));
+    }
+
+    #[test]
+    fn faces_inherit_what_they_leave_out() {
+        let mut faces = faces();
+        faces.define(
+            "warning",
+            Face {
+                inherit: vec!["bold".to_owned(), "red".to_owned()],
+                ..Face::background(BLUE)
+            },
+        );
+        faces.define(
+            "error",
+            Face {
+                foreground: Some(GREEN),
+                ..Face::inheriting("warning")
+            },
+        );
+        let error = faces.resolve(&["error"]);
+        assert_eq!(error.foreground, GREEN);
+        assert_eq!(error.background, BLUE);
+        assert_eq!(error.weight, Weight::Bold);
+    }
+
+    #[test]
+    fn earlier_parents_win() {
+        let mut faces = faces();
+        faces.define("green", Face::foreground(GREEN));
+        faces.define(
+            "both",
+            Face {
+                inherit: vec!["green".to_owned(), "red".to_owned()],
+                ..Face::default()
+            },
+        );
+        assert_eq!(faces.resolve(&["both"]).foreground, GREEN);
+    }
+
+    #[test]
+    fn inheritance_cycles_stop() {
+        let mut faces = faces();
+        faces.define(
+            "a",
+            Face {
+                underline: Some(true),
+                ..Face::inheriting("b")
+            },
+        );
+        faces.define(
+            "b",
+            Face {
+                inherit: vec!["a".to_owned(), "red".to_owned()],
+                ..Face::default()
+            },
+        );
+        let face = faces.resolve(&["a"]);
+        assert!(face.underline);
+        assert_eq!(face.foreground, RED);
+    }
+
+    #[test]
+    fn several_faces_merge_earlier_first() {
+        let mut faces = faces();
+        faces.define("highlight", Face::background(BLUE));
+        let face = faces.resolve(&["red", "highlight", "bold"]);
+        assert_eq!(face.foreground, RED);
+        assert_eq!(face.background, BLUE);
+        assert_eq!(face.weight, Weight::Bold);
+        faces.define("green", Face::foreground(GREEN));
+        assert_eq!(faces.resolve(&["green", "red"]).foreground, GREEN);
+    }
+
+    #[test]
+    fn themes_override_faces_until_disabled() {
+        let mut faces = faces();
+        let theme = |name: &str, color| Theme {
+            name: name.to_owned(),
+            faces: HashMap::from([("red".to_owned(), Face::foreground(color))]),
+        };
+        faces.enable_theme(theme("one", GREEN));
+        faces.enable_theme(theme("two", BLUE));
+        assert_eq!(faces.resolve(&["red"]).foreground, BLUE);
+        assert_eq!(faces.enabled_themes().collect::<Vec<_>>(), ["two", "one"]);
+        assert!(faces.disable_theme("two"));
+        assert_eq!(faces.resolve(&["red"]).foreground, GREEN);
+        assert!(faces.disable_theme("one"));
+        assert!(!faces.disable_theme("one"));
+        assert_eq!(faces.resolve(&["red"]).foreground, RED);
+    }
+
+    #[test]
+    fn colors_parse_in_every_length() {
+        assert_eq!(parse_color("#ff0000"), Some(RED));
+        assert_eq!(parse_color("#f00"), Some(RED));
+        assert_eq!(parse_color("#0000ff00"), Some([0.0, 0.0, 1.0, 0.0]));
+        assert_eq!(parse_color("#0f0f"), Some(GREEN));
+        assert_eq!(parse_color("#ff00"), Some([1.0, 1.0, 0.0, 0.0]));
+        assert_eq!(parse_color("ff0000"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("#ff000"), None);
+    }
+
+    #[test]
+    fn spans_fall_back_to_the_base_face() {
+        let faces = faces();
+        let mut spans = FaceSpans::new(faces.resolve(&[]));
+        spans.spans.push((2..4, faces.resolve(&["red"])));
+        spans.spans.push((6..7, faces.resolve(&["bold"])));
+        assert_eq!(spans.face_at(1), spans.base);
+        assert_eq!(spans.face_at(3).foreground, RED);
+        assert_eq!(spans.face_at(4), spans.base);
+        assert_eq!(spans.face_at(6).weight, Weight::Bold);
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8 { bom: bool },
+    Utf16Le { bom: bool },
+    Utf16Be { bom: bool },
+    Latin1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+//How a file looked on disk so we can write it back out the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFormat {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        FileFormat {
+            encoding: Encoding::Utf8 { bom: false },
+            line_ending: if cfg!(windows) {
+                LineEnding::CrLf
+            } else {
+                LineEnding::Lf
+            },
+        }
+    }
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 { bom: false } => "utf-8",
+            Encoding::Utf8 { bom: true } => "utf-8-with-signature",
+            Encoding::Utf16Le { .. } => "utf-16le",
+            Encoding::Utf16Be { .. } => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "unix",
+            LineEnding::CrLf => "dos",
+        }
+    }
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+fn detect_encoding(bytes: &[u8]) -> Encoding {
+    if bytes.starts_with(UTF8_BOM) {
+        return Encoding::Utf8 { bom: true };
+    }
+    if bytes.starts_with(UTF16LE_BOM) {
+        return Encoding::Utf16Le { bom: true };
+    }
+    if bytes.starts_with(UTF16BE_BOM) {
+        return Encoding::Utf16Be { bom: true };
+    }
+    //BOM-less UTF-16 has a NUL in every other byte for ASCII text. That's
+    //checked before UTF-8 since UTF-16 holding non-ASCII is rarely valid
+    //UTF-8 and would otherwise end up as Latin-1
+    if bytes.len() >= 2 && bytes.len().is_multiple_of(2) {
+        let (even, odd) = bytes.chunks_exact(2).fold((0, 0), |(even, odd), pair| {
+            (
+                even + (pair[0] == 0) as usize,
+                odd + (pair[1] == 0) as usize,
+            )
+        });
+        let half = bytes.len() / 2;
+        if odd * 10 > half * 4 && even == 0 {
+            return Encoding::Utf16Le { bom: false };
+        }
+        if even * 10 > half * 4 && odd == 0 {
+            return Encoding::Utf16Be { bom: false };
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return Encoding::Utf8 { bom: false };
+    }
+    //Every byte sequence is valid Latin-1 so it's our last resort
+    Encoding::Latin1
+}
+
+fn detect_line_ending(text: &str) -> LineEnding {
+    let mut crlf = 0usize;
+    let mut lf = 0usize;
+    let bytes = text.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'\n' {
+            if i > 0 && bytes[i - 1] == b'\r' {
+                crlf += 1;
+            } else {
+                lf += 1;
+            }
+        }
+    }
+    //Only a file that's CRLF throughout is treated as DOS. Mixed files keep
+    //their stray CRs in the text so saving writes back exactly what was read
+    if crlf > 0 && lf == 0 {
+        LineEnding::CrLf
+    } else if crlf == 0 && lf == 0 {
+        FileFormat::default().line_ending
+    } else {
+        LineEnding::Lf
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|c| from_bytes([c[0], c[1]]));
+    let mut text: String = char::decode_utf16(units)
+        .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    if !bytes.len().is_multiple_of(2) {
+        text.push(char::REPLACEMENT_CHARACTER);
+    }
+    text
+}
+
+//Decodes raw file contents into text with LF line endings, along with the
+//format needed to write it back out unchanged
+pub fn decode(bytes: &[u8]) -> (String, FileFormat) {
+    let encoding = detect_encoding(bytes);
+    let text = match encoding {
+        Encoding::Utf8 { bom } => {
+            let body = if bom { &bytes[UTF8_BOM.len()..] } else { bytes };
+            String::from_utf8_lossy(body).into_owned()
+        }
+        Encoding::Utf16Le { bom } => {
+            let body = if bom { &bytes[2..] } else { bytes };
+            decode_utf16(body, u16::from_le_bytes)
+        }
+        Encoding::Utf16Be { bom } => {
+            let body = if bom { &bytes[2..] } else { bytes };
+            decode_utf16(body, u16::from_be_bytes)
+        }
+        Encoding::Latin1 => bytes.iter().map(|b| *b as char).collect(),
+    };
+    let line_ending = detect_line_ending(&text);
+    let text = match line_ending {
+        LineEnding::Lf => text,
+        LineEnding::CrLf => text.replace("\r\n", "\n"),
+    };
+    (
+        text,
+        FileFormat {
+            encoding,
+            line_ending,
+        },
+    )
+}
+
+pub fn encode(text: &str, format: FileFormat) -> io::Result<Vec<u8>> {
+    let crlf;
+    let text = match format.line_ending {
+        LineEnding::Lf => text,
+        LineEnding::CrLf => {
+            crlf = text.replace('\n', "\r\n");
+            &crlf
+        }
+    };
+    let mut out = Vec::with_capacity(text.len() + 3);
+    match format.encoding {
+        Encoding::Utf8 { bom } => {
+            if bom {
+                out.extend_from_slice(UTF8_BOM);
+            }
+            out.extend_from_slice(text.as_bytes());
+        }
+        Encoding::Utf16Le { bom } => {
+            if bom {
+                out.extend_from_slice(UTF16LE_BOM);
+            }
+            text.encode_utf16()
+                .for_each(|u| out.extend_from_slice(&u.to_le_bytes()));
+        }
+        Encoding::Utf16Be { bom } => {
+            if bom {
+                out.extend_from_slice(UTF16BE_BOM);
+            }
+            text.encode_utf16()
+                .for_each(|u| out.extend_from_slice(&u.to_be_bytes()));
+        }
+        Encoding::Latin1 => {
+            for c in text.chars() {
+                match u8::try_from(c) {
+                    Ok(b) => out.push(b),
+                    Err(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{c:?} cannot be encoded as latin-1"),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push("~");
+    path.with_file_name(name)
+}
+
+//Follows `path` through any symlinks to the file they end at, which needn't
+//exist yet
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    //Gives up on loops the way the OS does
+    for _ in 0..40 {
+        let Ok(link) = fs::read_link(&path) else {
+            break;
+        };
+        path = match path.parent() {
+            Some(dir) => dir.join(link),
+            None => link,
+        };
+    }
+    path
+}
+
+//Emacs' #name#, where unsaved changes to `path` go when the editor has to
+//go down without asking
+pub fn auto_save_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from("#");
+    name.push(path.file_name().unwrap_or_default());
+    name.push("#");
+    path.with_file_name(name)
+}
+
+//Where unsaved changes to the buffer called `name`, which visits no file,
+//go. Buffer names can have slashes in them, so those and the % used to
+//encode them are percent-encoded.
+pub fn buffer_auto_save_path(name: &str) -> PathBuf {
+    let mut file_name = String::from("#%");
+    for c in name.chars() {
+        match c {
+            '%' | '/' | '\\' => file_name.push_str(&format!("%{:02X}", c as u32)),
+            c => file_name.push(c),
+        }
+    }
+    file_name.push_str(&format!("#{}#", std::process::id()));
+    std::env::temp_dir().join(file_name)
+}
+
+//Writes to a temp file next to `path` and renames it into place so a crash
+//mid-write never leaves a truncated file behind. If `backup` is set the
+//previous contents are kept at `backup_path(path)`. If `path` is a symlink
+//the file it points at is the one replaced, the link stays a link.
+pub fn write_atomic(path: &Path, contents: &[u8], backup: bool) -> io::Result<()> {
+    let path = &resolve_links(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".bunmacs-{}.tmp", std::process::id()));
+    let tmp_path = dir.join(tmp_name);
+
+    let existing = fs::metadata(path).ok();
+
+    let write_tmp = || -> io::Result<()> {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(contents)?;
+        if let Some(meta) = &existing {
+            tmp.set_permissions(meta.permissions())?;
+        }
+        tmp.sync_all()
+    };
+    if let Err(e) = write_tmp() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    if backup && existing.is_some() {
+        //Copy rather than rename so `path` never stops existing
+        if let Err(e) = fs::copy(path, backup_path(path)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    }
+
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bunmacs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    //Decodes `bytes`, checks what was found, then checks encoding gives
+    //back the same bytes
+    fn round_trip(bytes: &[u8], text: &str, encoding: Encoding, line_ending: LineEnding) {
+        let (decoded, format) = decode(bytes);
+        assert_eq!(decoded, text);
+        assert_eq!(format.encoding, encoding);
+        assert_eq!(format.line_ending, line_ending);
+        assert_eq!(encode(&decoded, format).unwrap(), bytes);
+    }
+
+    fn utf16(text: &str, bom: &[u8], to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = bom.to_vec();
+        text.encode_utf16()
+            .for_each(|u| bytes.extend_from_slice(&to_bytes(u)));
+        bytes
+    }
+
+    #[test]
+    fn utf8_round_trips_with_and_without_a_bom() {
+        let utf8 = Encoding::Utf8 { bom: false };
+        round_trip("héllo ✓\n".as_bytes(), "héllo ✓\n", utf8, LineEnding::Lf);
+        let mut bytes = UTF8_BOM.to_vec();
+        bytes.extend_from_slice("héllo ✓\n".as_bytes());
+        let utf8_bom = Encoding::Utf8 { bom: true };
+        round_trip(&bytes, "héllo ✓\n", utf8_bom, LineEnding::Lf);
+    }
+
+    #[test]
+    fn utf16_round_trips_with_a_bom() {
+        let text = "héllo 𝄞\n";
+        let le = utf16(text, UTF16LE_BOM, u16::to_le_bytes);
+        round_trip(&le, text, Encoding::Utf16Le { bom: true }, LineEnding::Lf);
+        let be = utf16(text, UTF16BE_BOM, u16::to_be_bytes);
+        round_trip(&be, text, Encoding::Utf16Be { bom: true }, LineEnding::Lf);
+    }
+
+    #[test]
+    fn utf16_without_a_bom_is_found_by_its_nuls() {
+        //Mostly ASCII with a few characters whose bytes aren't valid UTF-8
+        let text = "naïve café ↯\n";
+        let le = utf16(text, &[], u16::to_le_bytes);
+        assert!(std::str::from_utf8(&le).is_err());
+        round_trip(&le, text, Encoding::Utf16Le { bom: false }, LineEnding::Lf);
+        let be = utf16(text, &[], u16::to_be_bytes);
+        assert!(std::str::from_utf8(&be).is_err());
+        round_trip(&be, text, Encoding::Utf16Be { bom: false }, LineEnding::Lf);
+    }
+
+    #[test]
+    fn invalid_utf8_round_trips_as_latin1() {
+        let bytes: Vec<u8> = (0x80..=0xFF).chain(*b"\n").collect();
+        let text: String = bytes.iter().map(|b| *b as char).collect();
+        round_trip(&bytes, &text, Encoding::Latin1, LineEnding::Lf);
+        assert!(encode(
+            "✓",
+            FileFormat {
+                encoding: Encoding::Latin1,
+                line_ending: LineEnding::Lf,
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn crlf_files_are_read_as_lf_and_written_as_crlf() {
+        let utf8 = Encoding::Utf8 { bom: false };
+        round_trip(b"one\r\ntwo\r\n", "one\ntwo\n", utf8, LineEnding::CrLf);
+        let le = utf16("one\r\ntwo", UTF16LE_BOM, u16::to_le_bytes);
+        round_trip(
+            &le,
+            "one\ntwo",
+            Encoding::Utf16Le { bom: true },
+            LineEnding::CrLf,
+        );
+    }
+
+    #[test]
+    fn mixed_line_endings_are_kept_as_they_are() {
+        let utf8 = Encoding::Utf8 { bom: false };
+        round_trip(
+            b"one\r\ntwo\r\nthree\n",
+            "one\r\ntwo\r\nthree\n",
+            utf8,
+            LineEnding::Lf,
+        );
+        round_trip(b"one\ntwo\r\n", "one\ntwo\r\n", utf8, LineEnding::Lf);
+    }
+
+    #[test]
+    fn buffer_auto_save_paths_stay_in_the_temp_dir() {
+        let path = buffer_auto_save_path("a/b\\c 100%");
+        assert_eq!(path.parent(), Some(std::env::temp_dir().as_path()));
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let pid = std::process::id();
+        assert_eq!(name, format!("#%a%2Fb%5Cc 100%25#{pid}#"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_keeps_symlinks() {
+        let dir = scratch_dir("symlink");
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+
+        write_atomic(&link, b"new", true).unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_to_string(backup_path(&target)).unwrap(), "old");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_atomic_replaces_contents() {
+        let dir = scratch_dir("replace");
+        let path = dir.join("file.txt");
+        write_atomic(&path, b"one", false).unwrap();
+        write_atomic(&path, b"two", false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+//Which fonts frontends draw text with. Frontends that can't pick fonts,
+//like a terminal, ignore all of this.
+
+//How much bigger each step of text-scale-increase makes text, like Emacs'
+//text-scale-mode-step
+pub const TEXT_SCALE_STEP: f32 = 1.2;
+
+//Points are 1/72 inch, and a window with a scale factor of 1 has 96 pixels
+//per inch
+const PIXELS_PER_POINT: f32 = 96.0 / 72.0;
+
+const DEFAULT_SIZE: f32 = 12.0;
+//Sizes text scaling stops at, in points
+const MIN_SIZE: f32 = 3.0;
+const MAX_SIZE: f32 = 192.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontConfig {
+    //None picks the system's monospace font
+    pub family: Option<String>,
+    //Points, before text scaling
+    pub size: f32,
+    //Families tried in order for glyphs `family` doesn't have, before the
+    //frontend's own fallbacks
+    pub fallbacks: Vec<String>,
+    //Steps of text-scale-increase, negative for smaller
+    pub text_scale: i32,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        FontConfig {
+            family: None,
+            size: DEFAULT_SIZE,
+            fallbacks: vec![],
+            text_scale: 0,
+        }
+    }
+}
+
+impl FontConfig {
+    //Points text is actually drawn at
+    pub fn scaled_size(&self) -> f32 {
+        (self.size * TEXT_SCALE_STEP.powi(self.text_scale)).clamp(MIN_SIZE, MAX_SIZE)
+    }
+
+    //Pixels per em on a window with `scale_factor`
+    pub fn pixel_size(&self, scale_factor: f64) -> f32 {
+        points_to_pixels(self.scaled_size(), scale_factor)
+    }
+}
+
+pub fn points_to_pixels(points: f32, scale_factor: f64) -> f32 {
+    points * PIXELS_PER_POINT * scale_factor as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_scale_with_the_window() {
+        assert_eq!(points_to_pixels(12.0, 1.0), 16.0);
+        assert_eq!(points_to_pixels(12.0, 1.5), 24.0);
+        assert_eq!(points_to_pixels(12.0, 2.0), 32.0);
+        assert_eq!(points_to_pixels(9.0, 2.0), 24.0);
+    }
+
+    #[test]
+    fn text_scale_steps_multiply() {
+        let mut config = FontConfig {
+            size: 10.0,
+            ..FontConfig::default()
+        };
+        config.text_scale = 2;
+        assert!((config.scaled_size() - 14.4).abs() < 1e-4);
+        config.text_scale = -1;
+        assert!((config.pixel_size(1.5) - 10.0 / TEXT_SCALE_STEP * 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn text_scale_stops_at_the_limits() {
+        let mut config = FontConfig {
+            text_scale: -100,
+            ..FontConfig::default()
+        };
+        assert_eq!(config.scaled_size(), MIN_SIZE);
+        config.text_scale = 100;
+        assert_eq!(config.scaled_size(), MAX_SIZE);
+        assert_eq!(config.pixel_size(2.0), MAX_SIZE * PIXELS_PER_POINT * 2.0);
+    }
+}
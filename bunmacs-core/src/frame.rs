@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use crate::{
+    buffer::BufferId,
+    damage::Shown,
+    window::{CellRect, Window, WindowId, WindowTree},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FrameId(pub(crate) u64);
+
+//A frame is what the OS calls a window. The frontend owns the actual
+//window, the editor only tracks what it shows.
+#[derive(Debug)]
+pub struct Frame {
+    id: FrameId,
+    pub(crate) root: WindowTree,
+    pub(crate) windows: HashMap<WindowId, Window>,
+    pub(crate) selected_window: WindowId,
+    //Cells the window tree got as of the last redisplay
+    pub(crate) grid: CellRect,
+    //What was last drawn, None if the next drawing has to start over
+    pub(crate) shown: Option<Shown>,
+}
+
+impl Frame {
+    pub(crate) fn new(id: FrameId, window: Window) -> Self {
+        let selected_window = window.id();
+        Frame {
+            id,
+            root: WindowTree::Leaf(selected_window),
+            windows: HashMap::from([(selected_window, window)]),
+            selected_window,
+            grid: CellRect::default(),
+            shown: None,
+        }
+    }
+
+    pub fn id(&self) -> FrameId {
+        self.id
+    }
+
+    //The buffer in the selected window
+    pub fn buffer(&self) -> BufferId {
+        self.windows[&self.selected_window].buffer()
+    }
+
+    pub fn root(&self) -> &WindowTree {
+        &self.root
+    }
+
+    pub fn selected_window(&self) -> WindowId {
+        self.selected_window
+    }
+
+    pub fn window(&self, id: WindowId) -> Option<&Window> {
+        self.windows.get(&id)
+    }
+
+    pub(crate) fn window_mut(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.get_mut(&id)
+    }
+
+    //Windows in tree order, top to bottom and left to right
+    pub fn windows(&self) -> impl Iterator<Item = &Window> {
+        self.root
+            .leaves()
+            .into_iter()
+            .filter_map(|id| self.windows.get(&id))
+    }
+
+    pub fn displayed_buffers(&self) -> Vec<BufferId> {
+        let mut buffers: Vec<BufferId> = self.windows().map(Window::buffer).collect();
+        buffers.sort();
+        buffers.dedup();
+        buffers
+    }
+}
@@ -0,0 +1,398 @@
+//What the editor draws frames through, see Editor::draw_frame. The GUI
+//draws with wgpu, Recording keeps the calls, both so the editor can tell
+//what changed from one picture to the next and so tests can look at them.
+
+use crate::{
+    decoration::{Color, CursorStyle},
+    face::{FaceSpans, Slant, Weight},
+    layout::{CellMetrics, Layout, PositionedGlyph, Rect},
+};
+
+pub trait Frontend {
+    //Where the frame goes, in the same units as the metrics
+    fn area(&self) -> Rect;
+    fn cell_metrics(&self) -> CellMetrics;
+    //Starts a new picture, everything else is drawn on top. Only `damage`
+    //has to be painted over with `background`, and only what's inside it
+    //gets drawn until the picture is presented.
+    fn clear(&mut self, background: Color, damage: &Damage);
+    fn fill_rect(&mut self, rect: Rect, color: Color);
+    //Always drawn after every rectangle
+    fn draw_glyphs(&mut self, run: GlyphRun);
+    fn draw_cursor(&mut self, cursor: Cursor);
+    //The picture is done
+    fn present(&mut self);
+    //Whether a presented picture stays on screen, so the next one only
+    //has to paint what changed. Otherwise every picture is a whole one.
+    fn retains_picture(&self) -> bool {
+        true
+    }
+}
+
+//What a picture paints over
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Damage {
+    //Everything, as if nothing was there before
+    #[default]
+    Full,
+    //Only these, everything else still shows the last picture
+    Rects(Vec<Rect>),
+}
+
+impl Damage {
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Damage::Rects(rects) if rects.is_empty())
+    }
+
+    pub fn intersects(&self, rect: Rect) -> bool {
+        match self {
+            Damage::Full => true,
+            Damage::Rects(rects) => rects.iter().any(|r| r.intersects(&rect)),
+        }
+    }
+}
+
+//Glyphs drawn in one style, positioned by the layout they came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphRun {
+    pub glyphs: Vec<PositionedGlyph>,
+    pub color: Color,
+    pub weight: Weight,
+    pub slant: Slant,
+    //Nothing is drawn outside of it
+    pub clip: Rect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    //The cells under the cursor, see decoration::cursor_rects for what
+    //to draw there
+    pub cell: Rect,
+    pub style: CursorStyle,
+    pub color: Color,
+}
+
+//Each row of `layout` split wherever the style changes, leaving out
+//whitespace. Glyphs at `inverse` are drawn in their background color so
+//they show up on top of a box cursor.
+pub fn glyph_runs(layout: &Layout, faces: &FaceSpans, inverse: Option<usize>) -> Vec<GlyphRun> {
+    let mut runs: Vec<GlyphRun> = vec![];
+    for row in &layout.display_rows {
+        let mut run: Option<GlyphRun> = None;
+        for glyph in row.glyphs.iter().filter(|g| !g.ch.is_whitespace()) {
+            let face = faces.face_at(glyph.offset);
+            let color = if Some(glyph.offset) == inverse {
+                face.background
+            } else {
+                face.foreground
+            };
+            match &mut run {
+                Some(run)
+                    if (run.color, run.weight, run.slant) == (color, face.weight, face.slant) =>
+                {
+                    run.glyphs.push(glyph.clone());
+                }
+                _ => {
+                    runs.extend(run.take());
+                    run = Some(GlyphRun {
+                        glyphs: vec![glyph.clone()],
+                        color,
+                        weight: face.weight,
+                        slant: face.slant,
+                        clip: layout.area,
+                    });
+                }
+            }
+        }
+        runs.extend(run);
+    }
+    runs
+}
+
+//The cells `glyph` covers
+pub fn glyph_rect(glyph: &PositionedGlyph, metrics: CellMetrics) -> Rect {
+    Rect {
+        x: glyph.x,
+        y: glyph.baseline - metrics.ascent,
+        width: glyph.cells as f32 * metrics.width,
+        height: metrics.height,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCommand {
+    Clear(Color, Damage),
+    FillRect(Rect, Color),
+    Glyphs(GlyphRun),
+    Cursor(Cursor),
+    Present,
+}
+
+//A frontend that only remembers what it was told to draw
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub area: Rect,
+    pub metrics: CellMetrics,
+    pub commands: Vec<DrawCommand>,
+}
+
+impl Recording {
+    pub fn new(area: Rect, metrics: CellMetrics) -> Self {
+        Recording {
+            area,
+            metrics,
+            commands: vec![],
+        }
+    }
+
+    //Everything since the last clear
+    pub fn last_picture(&self) -> &[DrawCommand] {
+        let start = self
+            .commands
+            .iter()
+            .rposition(|command| matches!(command, DrawCommand::Clear(..)))
+            .unwrap_or(0);
+        &self.commands[start..]
+    }
+
+    //Draws the last picture on `frontend`, leaving out whatever is
+    //entirely outside of `damage`
+    pub fn replay(&self, frontend: &mut dyn Frontend, damage: &Damage) {
+        for command in self.last_picture() {
+            match command {
+                DrawCommand::Clear(background, _) => frontend.clear(*background, damage),
+                DrawCommand::FillRect(rect, color) => {
+                    if damage.intersects(*rect) {
+                        frontend.fill_rect(*rect, *color)
+                    }
+                }
+                DrawCommand::Glyphs(run) => {
+                    if run
+                        .glyphs
+                        .iter()
+                        .any(|g| damage.intersects(glyph_rect(g, self.metrics)))
+                    {
+                        frontend.draw_glyphs(run.clone())
+                    }
+                }
+                DrawCommand::Cursor(cursor) => {
+                    if damage.intersects(cursor.cell) {
+                        frontend.draw_cursor(*cursor)
+                    }
+                }
+                DrawCommand::Present => frontend.present(),
+            }
+        }
+    }
+
+    //Every glyph of the last picture on the row at `y`, left to right
+    pub fn row_text(&self, y: f32) -> String {
+        let mut glyphs: Vec<&PositionedGlyph> = self
+            .last_picture()
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::Glyphs(run) => Some(run),
+                _ => None,
+            })
+            .flat_map(|run| &run.glyphs)
+            .filter(|glyph| glyph.baseline > y && glyph.baseline <= y + self.metrics.height)
+            .collect();
+        glyphs.sort_by(|a, b| a.x.total_cmp(&b.x));
+        glyphs.iter().map(|glyph| glyph.ch).collect()
+    }
+}
+
+impl Frontend for Recording {
+    fn area(&self) -> Rect {
+        self.area
+    }
+
+    fn cell_metrics(&self) -> CellMetrics {
+        self.metrics
+    }
+
+    fn clear(&mut self, background: Color, damage: &Damage) {
+        self.commands
+            .push(DrawCommand::Clear(background, damage.clone()));
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: Color) {
+        self.commands.push(DrawCommand::FillRect(rect, color));
+    }
+
+    fn draw_glyphs(&mut self, run: GlyphRun) {
+        self.commands.push(DrawCommand::Glyphs(run));
+    }
+
+    fn draw_cursor(&mut self, cursor: Cursor) {
+        self.commands.push(DrawCommand::Cursor(cursor));
+    }
+
+    fn present(&mut self) {
+        self.commands.push(DrawCommand::Present);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keymap::Key, testing::editing};
+
+    const METRICS: CellMetrics = CellMetrics {
+        width: 10.0,
+        height: 20.0,
+        ascent: 15.0,
+    };
+
+    const AREA: Rect = Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 400.0,
+        height: 200.0,
+    };
+
+    fn cursors(commands: &[DrawCommand]) -> Vec<Cursor> {
+        commands
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::Cursor(cursor) => Some(*cursor),
+                _ => None,
+            })
+            .collect()
+    }
+
+    //Drops the picture after presenting it, like a terminal or a GUI
+    //without a retained surface
+    struct Forgetful(Recording);
+
+    impl Frontend for Forgetful {
+        fn area(&self) -> Rect {
+            self.0.area()
+        }
+
+        fn cell_metrics(&self) -> CellMetrics {
+            self.0.cell_metrics()
+        }
+
+        fn clear(&mut self, background: Color, damage: &Damage) {
+            self.0.clear(background, damage)
+        }
+
+        fn fill_rect(&mut self, rect: Rect, color: Color) {
+            self.0.fill_rect(rect, color)
+        }
+
+        fn draw_glyphs(&mut self, run: GlyphRun) {
+            self.0.draw_glyphs(run)
+        }
+
+        fn draw_cursor(&mut self, cursor: Cursor) {
+            self.0.draw_cursor(cursor)
+        }
+
+        fn present(&mut self) {
+            self.0.present()
+        }
+
+        fn retains_picture(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn pictures_are_cleared_drawn_and_presented() {
+        let (mut editor, frame) = editing("one\ntwo");
+        let mut recording = Recording::new(AREA, METRICS);
+        editor.draw_frame(frame, &mut recording, true);
+        let commands = &recording.commands;
+        assert!(matches!(
+            commands.first(),
+            Some(DrawCommand::Clear(_, Damage::Full))
+        ));
+        assert_eq!(commands.last(), Some(&DrawCommand::Present));
+        assert_eq!(recording.last_picture(), &commands[..]);
+        assert_eq!(recording.row_text(0.0), "one");
+        assert_eq!(recording.row_text(20.0), "two");
+        //Point's after "two"
+        let cursor = cursors(commands);
+        assert_eq!(cursor.len(), 1);
+        assert_eq!((cursor[0].cell.x, cursor[0].cell.y), (30.0, 20.0));
+        //Everything's on the frame
+        assert!(commands.iter().all(|command| match command {
+            DrawCommand::FillRect(rect, _) => AREA.intersection(rect) == Some(*rect),
+            DrawCommand::Glyphs(run) => run.glyphs.iter().all(|g| g.x < AREA.width),
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn blinked_off_cursors_are_not_drawn() {
+        let (mut editor, frame) = editing("one");
+        let mut recording = Recording::new(AREA, METRICS);
+        editor.draw_frame(frame, &mut recording, false);
+        assert_eq!(cursors(&recording.commands), []);
+        assert_eq!(recording.row_text(0.0), "one");
+    }
+
+    #[test]
+    fn box_cursors_invert_the_glyph_under_them() {
+        let (mut editor, frame) = editing("ab");
+        editor.handle_key(Key::parse("C-b").unwrap());
+        let mut recording = Recording::new(AREA, METRICS);
+        editor.draw_frame(frame, &mut recording, true);
+        let color_of = |ch: char| {
+            recording.commands.iter().find_map(|command| match command {
+                DrawCommand::Glyphs(run) if run.glyphs.iter().any(|g| g.ch == ch) => {
+                    Some(run.color)
+                }
+                _ => None,
+            })
+        };
+        assert_ne!(color_of('a'), color_of('b'));
+        assert_eq!(cursors(&recording.commands)[0].cell.x, 10.0);
+    }
+
+    #[test]
+    fn replays_leave_out_what_is_not_damaged() {
+        let (mut editor, frame) = editing("one\ntwo");
+        let mut recording = Recording::new(AREA, METRICS);
+        editor.draw_frame(frame, &mut recording, true);
+        let second_line = Rect {
+            y: 20.0,
+            height: 20.0,
+            ..AREA
+        };
+        let damage = Damage::Rects(vec![second_line]);
+        let mut replayed = Recording::new(AREA, METRICS);
+        recording.replay(&mut replayed, &damage);
+        assert!(matches!(
+            replayed.commands.first(),
+            Some(DrawCommand::Clear(_, cleared)) if *cleared == damage
+        ));
+        assert_eq!(replayed.commands.last(), Some(&DrawCommand::Present));
+        assert_eq!(replayed.row_text(0.0), "");
+        assert_eq!(replayed.row_text(20.0), "two");
+        assert_eq!(cursors(&replayed.commands).len(), 1);
+
+        //Nothing damaged, nothing drawn but the clear and present
+        let mut replayed = Recording::new(AREA, METRICS);
+        recording.replay(&mut replayed, &Damage::Rects(vec![]));
+        assert_eq!(replayed.commands.len(), 2);
+    }
+
+    #[test]
+    fn frontends_that_forget_get_whole_pictures() {
+        let (mut editor, frame) = editing("one\ntwo");
+        let mut frontend = Forgetful(Recording::new(AREA, METRICS));
+        editor.draw_frame(frame, &mut frontend, true);
+        editor.insert("!");
+        editor.draw_frame(frame, &mut frontend, true);
+        let recording = &frontend.0;
+        assert!(matches!(
+            recording.last_picture().first(),
+            Some(DrawCommand::Clear(_, Damage::Full))
+        ));
+        assert_eq!(recording.row_text(0.0), "one");
+        assert_eq!(recording.row_text(20.0), "two!");
+    }
+}
@@ -0,0 +1,191 @@
+//.gitignore files, for leaving out what git leaves out when walking a
+//project. Patterns follow gitignore(5): * and ? stay within a path
+//component, ** crosses them, a leading or middle / anchors the pattern to
+//the file's directory and a trailing / only matches directories.
+
+use std::{fs, path::Path};
+
+use regex::Regex;
+
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    //A ! pattern, bringing back something an earlier one ignored
+    negated: bool,
+    dir_only: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Gitignore {
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    //An empty Gitignore if the file can't be read
+    pub fn from_file(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .map(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let rules = text.lines().filter_map(parse_rule).collect();
+        Gitignore { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    //Some(true) if `path`, relative to the .gitignore's directory with /
+    //between components, is ignored, Some(false) if a ! pattern brings it
+    //back and None if no pattern is about it. The last matching pattern
+    //wins.
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.regex.is_match(path))
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = trim_trailing_spaces(line.strip_suffix('\r').unwrap_or(line));
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    //\# and \! are how a pattern starts with those
+    let line = line
+        .strip_prefix('\\')
+        .filter(|rest| rest.starts_with(['#', '!']))
+        .unwrap_or(line);
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    if line.is_empty() {
+        return None;
+    }
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    let body = translate(line);
+    let regex = if anchored {
+        format!("^{body}$")
+    } else {
+        format!("^(?:.*/)?{body}$")
+    };
+    Some(Rule {
+        regex: Regex::new(&regex).ok()?,
+        negated,
+        dir_only,
+    })
+}
+
+//Trailing spaces don't count unless they're escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') && !line[..end - 1].ends_with('\\') {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+//The regex matching what glob `pattern` does
+fn translate(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let after_slash = i == 0 || chars[i - 1] == '/';
+                let before_slash = chars.get(i + 2).is_none_or(|c| *c == '/');
+                if after_slash && before_slash {
+                    match chars.get(i + 2) {
+                        //**/ is any number of directories, none included
+                        Some(_) => {
+                            regex.push_str("(?:.*/)?");
+                            i += 3;
+                        }
+                        //A trailing /** is everything inside
+                        None => {
+                            regex.push_str(".*");
+                            i += 2;
+                        }
+                    }
+                } else {
+                    regex.push_str("[^/]*");
+                    i += 2;
+                }
+            }
+            '*' => {
+                regex.push_str("[^/]*");
+                i += 1;
+            }
+            '?' => {
+                regex.push_str("[^/]");
+                i += 1;
+            }
+            '[' => match class(&chars[i + 1..]) {
+                Some((class, len)) => {
+                    regex.push_str(&class);
+                    i += len + 1;
+                }
+                None => {
+                    regex.push_str("\\[");
+                    i += 1;
+                }
+            },
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+            }
+            c => {
+                regex.push_str(&regex::escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    regex
+}
+
+//A [...] character class starting right after the [, and how many chars
+//it takes up including the ]. None if it's never closed.
+fn class(chars: &[char]) -> Option<(String, usize)> {
+    let mut class = String::from("[");
+    let mut i = 0;
+    if let Some('!' | '^') = chars.first() {
+        class.push('^');
+        i += 1;
+    }
+    //A ] right at the start is part of the class
+    let start = i;
+    while i < chars.len() {
+        match chars[i] {
+            ']' if i > start => {
+                class.push(']');
+                return Some((class, i + 1));
+            }
+            '\\' if i + 1 < chars.len() => {
+                if !chars[i + 1].is_alphanumeric() {
+                    class.push('\\');
+                }
+                class.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            c @ ('[' | ']' | '&' | '~' | '^') => {
+                class.push('\\');
+                class.push(c);
+            }
+            c => class.push(c),
+        }
+        i += 1;
+    }
+    None
+}
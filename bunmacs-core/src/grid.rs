@@ -0,0 +1,295 @@
+//A frame drawn as a grid of character cells, what terminal frontends show.
+//Frames are laid out with one "pixel" per cell so every rectangle in a
+//FrameDisplay lands on whole cells.
+
+use crate::{
+    decoration::{self, Color, CursorStyle},
+    editor::FrameDisplay,
+    face::{FaceSpans, ResolvedFace, Slant, Weight},
+    layout::{CellMetrics, Layout, Rect},
+};
+
+pub const CELL_METRICS: CellMetrics = CellMetrics {
+    width: 1.0,
+    height: 1.0,
+    ascent: 1.0,
+};
+
+//What the cells a wide char spills into hold
+pub const WIDE_CONTINUATION: char = '\0';
+
+//Vertical dividers between side by side windows
+const DIVIDER: char = '│';
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Cell {
+    pub fn blank(face: &ResolvedFace) -> Self {
+        Cell {
+            ch: ' ',
+            foreground: face.foreground,
+            background: face.background,
+            bold: false,
+            italic: false,
+            underline: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub cols: usize,
+    pub rows: usize,
+    cells: Vec<Cell>,
+    //Where the terminal's own cursor goes, None to hide it
+    pub cursor: Option<(usize, usize)>,
+    pub cursor_style: CursorStyle,
+}
+
+//Paints `over` on top of `under`, for colors with some transparency
+fn blend(over: Color, under: Color) -> Color {
+    let a = over[3];
+    [
+        over[0] * a + under[0] * (1.0 - a),
+        over[1] * a + under[1] * (1.0 - a),
+        over[2] * a + under[2] * (1.0 - a),
+        1.0,
+    ]
+}
+
+impl Grid {
+    pub fn new(cols: usize, rows: usize, fill: Cell) -> Self {
+        Grid {
+            cols,
+            rows,
+            cells: vec![fill; cols * rows],
+            cursor: None,
+            cursor_style: CursorStyle::default(),
+        }
+    }
+
+    pub fn get(&self, col: usize, row: usize) -> Option<&Cell> {
+        (col < self.cols && row < self.rows).then(|| &self.cells[row * self.cols + col])
+    }
+
+    fn get_mut(&mut self, col: usize, row: usize) -> Option<&mut Cell> {
+        (col < self.cols && row < self.rows).then(|| &mut self.cells[row * self.cols + col])
+    }
+
+    pub fn row(&self, row: usize) -> &[Cell] {
+        &self.cells[row * self.cols..(row + 1) * self.cols]
+    }
+
+    //The characters on `row` as a terminal would show them
+    pub fn row_text(&self, row: usize) -> String {
+        self.row(row)
+            .iter()
+            .map(|cell| cell.ch)
+            .filter(|ch| *ch != WIDE_CONTINUATION)
+            .collect()
+    }
+
+    //`display` has to have been laid out with CELL_METRICS in an area
+    //`cols` by `rows` big
+    pub fn from_display(display: &FrameDisplay, cols: usize, rows: usize) -> Self {
+        let palette = &display.palette;
+        let default = display.echo_faces.base;
+        let mut grid = Grid::new(
+            cols,
+            rows,
+            Cell::blank(&ResolvedFace {
+                background: palette.background,
+                ..default
+            }),
+        );
+        for window in &display.windows {
+            for rect in &window.current_line {
+                grid.fill(*rect, palette.current_line);
+            }
+            grid.text(&window.text, &window.faces);
+            for rect in &window.region {
+                grid.fill(*rect, palette.region);
+            }
+            //A terminal only has the one cursor, the selected window's
+            if window.cursor_style != CursorStyle::Hollow {
+                grid.place_cursor(window.cursor, window.cursor_style);
+            }
+            if let Some(mode_line) = &window.mode_line {
+                grid.fill(mode_line.text.area, mode_line.faces.base.background);
+                grid.text(&mode_line.text, &mode_line.faces);
+            }
+        }
+        for rect in &display.dividers {
+            for (col, row) in grid.cells_in(*rect) {
+                if let Some(cell) = grid.get_mut(col, row) {
+                    cell.ch = DIVIDER;
+                    cell.foreground = palette.divider;
+                }
+            }
+        }
+        grid.text(&display.echo_area, &display.echo_faces);
+        if let Some(minibuffer) = &display.minibuffer {
+            if let Some(selected) = minibuffer.selected {
+                grid.fill(selected, palette.completions_current);
+            }
+            grid.text(&minibuffer.candidates, &minibuffer.candidate_faces);
+            grid.place_cursor(minibuffer.cursor, minibuffer.cursor_style);
+        }
+        grid
+    }
+
+    fn place_cursor(&mut self, cell: Option<Rect>, style: CursorStyle) {
+        if let Some(cell) = cell {
+            self.cursor = Some((cell.x.round() as usize, cell.y.round() as usize));
+            self.cursor_style = style;
+        }
+    }
+
+    //Cells whose centers are inside `rect`
+    fn cells_in(&self, rect: Rect) -> impl Iterator<Item = (usize, usize)> {
+        let span = |start: f32, len: f32, max: usize| {
+            let first = start.round().max(0.0) as usize;
+            let last = ((start + len).round().max(0.0) as usize).min(max);
+            first..last.max(first)
+        };
+        let cols = span(rect.x, rect.width, self.cols);
+        span(rect.y, rect.height, self.rows)
+            .flat_map(move |row| cols.clone().map(move |col| (col, row)))
+    }
+
+    fn fill(&mut self, rect: Rect, color: Color) {
+        for (col, row) in self.cells_in(rect) {
+            if let Some(cell) = self.get_mut(col, row) {
+                cell.background = blend(color, cell.background);
+            }
+        }
+    }
+
+    //Backgrounds of faces that have their own, then the glyphs over them,
+    //like decoration::face_quads does for pixels
+    fn text(&mut self, layout: &Layout, faces: &FaceSpans) {
+        for (range, face) in &faces.spans {
+            if face.background != faces.base.background {
+                for rect in decoration::region_rects(layout, range.start, range.end) {
+                    self.fill(rect, face.background);
+                }
+            }
+        }
+        for row in &layout.display_rows {
+            let y = row.y.round() as usize;
+            for glyph in &row.glyphs {
+                let x = glyph.x.round() as usize;
+                let face = faces.face_at(glyph.offset);
+                let Some(cell) = self.get_mut(x, y) else {
+                    continue;
+                };
+                cell.ch = glyph.ch;
+                cell.foreground = face.foreground;
+                cell.bold = face.weight == Weight::Bold;
+                cell.italic = face.slant == Slant::Italic;
+                cell.underline = face.underline;
+                for col in x + 1..x + glyph.cells {
+                    if let Some(cell) = self.get_mut(col, y) {
+                        cell.ch = WIDE_CONTINUATION;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{editor::Editor, testing::editing, window::SplitDirection};
+
+    const COLS: usize = 40;
+    const ROWS: usize = 6;
+
+    //What a COLS by ROWS terminal shows of the selected frame
+    fn screen(editor: &mut Editor) -> Grid {
+        let frame = editor.selected_frame().unwrap();
+        let area = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: COLS as f32,
+            height: ROWS as f32,
+        };
+        let display = editor.redisplay(frame, area, CELL_METRICS).unwrap();
+        Grid::from_display(&display, COLS, ROWS)
+    }
+
+    #[test]
+    fn text_mode_line_and_echo_area_land_on_their_rows() {
+        let (mut editor, _) = editing("one\n日本");
+        editor.message("hello");
+        let grid = screen(&mut editor);
+        assert!(grid.row_text(0).starts_with("one "));
+        assert!(grid.row_text(1).starts_with("日本 "));
+        assert!(grid.row_text(4).starts_with(" **  *scratch*"));
+        assert!(grid.row_text(5).starts_with("hello "));
+        //Every row is as wide as the terminal
+        assert!((0..ROWS).all(|row| grid.row_text(row).chars().count() <= COLS));
+        assert_eq!(grid.row_text(0).chars().count(), COLS);
+    }
+
+    #[test]
+    fn wide_chars_take_two_cells() {
+        let (mut editor, _) = editing("日本");
+        let grid = screen(&mut editor);
+        let chars: Vec<char> = grid.row(0)[..5].iter().map(|cell| cell.ch).collect();
+        assert_eq!(
+            chars,
+            ['日', WIDE_CONTINUATION, '本', WIDE_CONTINUATION, ' ']
+        );
+        //Point's after them
+        assert_eq!(grid.cursor, Some((4, 0)));
+    }
+
+    #[test]
+    fn the_current_line_is_highlighted() {
+        let (mut editor, _) = editing("one\ntwo");
+        let grid = screen(&mut editor);
+        let background = |row: usize| grid.get(10, row).unwrap().background;
+        assert_ne!(background(1), background(0));
+        assert_eq!(background(2), background(0));
+    }
+
+    #[test]
+    fn side_by_side_windows_share_a_divider() {
+        let (mut editor, _) = editing("one\ntwo");
+        editor.split_window(SplitDirection::Right).unwrap();
+        let grid = screen(&mut editor);
+        let divider = COLS / 2;
+        for row in 0..ROWS - 1 {
+            assert_eq!(grid.get(divider, row).unwrap().ch, DIVIDER, "row {row}");
+        }
+        assert!(grid.row_text(0).starts_with("one"));
+        assert_eq!(
+            &grid.row_text(0)[divider + DIVIDER.len_utf8()..][..3],
+            "one"
+        );
+        //The terminal's one cursor follows the selected window
+        assert_eq!(grid.cursor, Some((3, 1)));
+        editor.other_window().unwrap();
+        assert_eq!(screen(&mut editor).cursor, Some((divider + 4, 1)));
+    }
+
+    #[test]
+    fn the_minibuffer_takes_the_cursor() {
+        let (mut editor, _) = editing("one");
+        editor.handle_key(crate::keymap::Key::parse("M-x").unwrap());
+        let grid = screen(&mut editor);
+        let prompt = grid.row_text(ROWS - 1);
+        assert!(prompt.starts_with("M-x "), "{prompt:?}");
+        assert_eq!(grid.cursor.map(|(_, row)| row), Some(ROWS - 1));
+    }
+}
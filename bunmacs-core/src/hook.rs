@@ -0,0 +1,292 @@
+//Hooks, named lists of bunlang source the editor runs when something
+//happens. A function can be global or local to one buffer, and runs in
+//order of its depth like the DEPTH of Emacs' add-hook.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::buffer::BufferId;
+
+//Run with (START END) before text changes and (START END OLD-LENGTH)
+//after, see `hook-args`
+pub const BEFORE_CHANGE_FUNCTIONS: &str = "before-change-functions";
+pub const AFTER_CHANGE_FUNCTIONS: &str = "after-change-functions";
+pub const AFTER_SAVE_HOOK: &str = "after-save-hook";
+//After a command leaves a different buffer current
+pub const BUFFER_SWITCH_HOOK: &str = "buffer-switch-hook";
+//After windows were split, deleted, resized or shown another buffer
+pub const WINDOW_CONFIGURATION_CHANGE_HOOK: &str = "window-configuration-change-hook";
+pub const FOCUS_IN_HOOK: &str = "focus-in-hook";
+pub const FOCUS_OUT_HOOK: &str = "focus-out-hook";
+
+#[derive(Debug, Clone)]
+struct HookFunction {
+    source: String,
+    //Lower runs first, 0 unless given
+    depth: i64,
+    //Only runs while this buffer is current
+    buffer: Option<BufferId>,
+}
+
+#[derive(Debug, Default)]
+pub struct Hooks {
+    hooks: HashMap<String, Vec<HookFunction>>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Adding a function that's already there moves it to `depth`
+    pub fn add(&mut self, hook: &str, source: &str, depth: i64, buffer: Option<BufferId>) {
+        self.remove(hook, source, buffer);
+        self.hooks
+            .entry(hook.to_owned())
+            .or_default()
+            .push(HookFunction {
+                source: source.to_owned(),
+                depth,
+                buffer,
+            });
+    }
+
+    pub fn remove(&mut self, hook: &str, source: &str, buffer: Option<BufferId>) {
+        if let Some(functions) = self.hooks.get_mut(hook) {
+            functions.retain(|f| f.source != source || f.buffer != buffer);
+        }
+    }
+
+    //Drops every function local to `buffer`, for when it's killed or
+    //changes major mode
+    pub(crate) fn kill_local(&mut self, buffer: BufferId) {
+        for functions in self.hooks.values_mut() {
+            functions.retain(|f| f.buffer != Some(buffer));
+        }
+    }
+
+    //What runs for `hook` with `buffer` current, lowest depth first and in
+    //the order they were added between equal depths
+    pub fn functions(&self, hook: &str, buffer: BufferId) -> Vec<String> {
+        let Some(functions) = self.hooks.get(hook) else {
+            return vec![];
+        };
+        let mut functions: Vec<&HookFunction> = functions
+            .iter()
+            .filter(|f| f.buffer.is_none_or(|b| b == buffer))
+            .collect();
+        functions.sort_by_key(|f| f.depth);
+        functions.into_iter().map(|f| f.source.clone()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(pub(crate) u64);
+
+//bunlang source run once the user has been idle long enough
+#[derive(Debug)]
+pub(crate) struct IdleTimer {
+    pub(crate) id: TimerId,
+    pub(crate) idle: Duration,
+    //Runs again every time the user goes idle, not just the first
+    pub(crate) repeat: bool,
+    pub(crate) source: String,
+    //Already ran since the last input
+    pub(crate) fired: bool,
+}
+
+impl IdleTimer {
+    //When it fires if there's no input before then
+    pub(crate) fn deadline(&self, last_input: Instant) -> Option<Instant> {
+        (!self.fired).then(|| last_input + self.idle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bunlang::Value;
+
+    use super::*;
+    use crate::{editor::Editor, keymap::Key, testing::editing};
+
+    //Hook or timer source that counts its runs in variable `name`
+    fn counting(name: &str) -> String {
+        format!("(setq-default {name} (+ (symbol-value {name}) 1))")
+    }
+
+    fn counted(editor: &Editor, name: &str) -> Value {
+        editor.variable(name).cloned().unwrap_or(Value::Number(0))
+    }
+
+    fn counter(editor: &mut Editor, name: &str) {
+        editor.set_default(name, Value::Number(0)).unwrap();
+    }
+
+    fn press(editor: &mut Editor, keys: &str) {
+        for key in Key::parse_sequence(keys).unwrap() {
+            editor.handle_key(key);
+        }
+    }
+
+    #[test]
+    fn functions_run_by_depth_then_in_the_order_added() {
+        let mut hooks = Hooks::new();
+        let buffer = BufferId(0);
+        hooks.add("h", "late", 10, None);
+        hooks.add("h", "first", -5, None);
+        hooks.add("h", "one", 0, None);
+        hooks.add("h", "two", 0, None);
+        assert_eq!(
+            hooks.functions("h", buffer),
+            ["first", "one", "two", "late"]
+        );
+        //Adding it again moves it rather than running it twice
+        hooks.add("h", "late", -10, None);
+        assert_eq!(
+            hooks.functions("h", buffer),
+            ["late", "first", "one", "two"]
+        );
+        assert!(hooks.functions("other", buffer).is_empty());
+    }
+
+    #[test]
+    fn local_functions_run_around_the_global_ones_in_their_buffer() {
+        let mut hooks = Hooks::new();
+        let (mine, other) = (BufferId(0), BufferId(1));
+        hooks.add("h", "global", 0, None);
+        hooks.add("h", "after", 5, Some(mine));
+        hooks.add("h", "before", -5, Some(mine));
+        hooks.add("h", "theirs", 0, Some(other));
+        assert_eq!(hooks.functions("h", mine), ["before", "global", "after"]);
+        assert_eq!(hooks.functions("h", other), ["global", "theirs"]);
+
+        hooks.kill_local(mine);
+        assert_eq!(hooks.functions("h", mine), ["global"]);
+        assert_eq!(hooks.functions("h", other), ["global", "theirs"]);
+    }
+
+    #[test]
+    fn removing_takes_only_the_function_where_it_was_added() {
+        let mut hooks = Hooks::new();
+        let buffer = BufferId(0);
+        hooks.add("h", "same", 0, None);
+        hooks.add("h", "same", 1, Some(buffer));
+        hooks.add("h", "kept", 2, None);
+        hooks.remove("h", "same", None);
+        assert_eq!(hooks.functions("h", buffer), ["same", "kept"]);
+        hooks.remove("h", "same", Some(buffer));
+        assert_eq!(hooks.functions("h", buffer), ["kept"]);
+        hooks.remove("h", "missing", None);
+        assert_eq!(hooks.functions("h", buffer), ["kept"]);
+    }
+
+    #[test]
+    fn editor_hooks_run_with_their_args() {
+        let (mut editor, _) = editing("");
+        counter(&mut editor, "ran");
+        editor.add_hook("h", &counting("ran"), 0, false);
+        editor.add_hook("h", "(setq-default args (hook-args))", 1, true);
+        editor.run_hook_with_args("h", vec![Value::Number(3), Value::Number(4)]);
+        assert_eq!(counted(&editor, "ran"), Value::Number(1));
+        let args = Value::List(vec![Value::Number(3), Value::Number(4)]);
+        assert_eq!(editor.variable("args"), Some(&args));
+
+        editor.remove_hook("h", &counting("ran"), false);
+        editor.run_hooks("h");
+        assert_eq!(counted(&editor, "ran"), Value::Number(1));
+        assert_eq!(editor.variable("args"), Some(&Value::List(vec![])));
+    }
+
+    #[test]
+    fn idle_timers_fire_once_per_idle_spell() {
+        let (mut editor, _) = editing("");
+        counter(&mut editor, "once");
+        counter(&mut editor, "again");
+        let idle = Duration::from_secs(2);
+        editor.run_with_idle_timer(idle, false, &counting("once"));
+        editor.run_with_idle_timer(idle * 2, true, &counting("again"));
+
+        let first = editor.next_timer().unwrap();
+        assert!(!editor.run_timers(first - Duration::from_millis(1)));
+        assert_eq!(counted(&editor, "once"), Value::Number(0));
+        assert!(editor.run_timers(first));
+        assert_eq!(counted(&editor, "once"), Value::Number(1));
+
+        //Only the repeating one is left, and it waits for its own delay
+        let second = editor.next_timer().unwrap();
+        assert_eq!(second, first + idle);
+        assert!(editor.run_timers(second));
+        assert_eq!(counted(&editor, "again"), Value::Number(1));
+        //Staying idle doesn't fire them again
+        assert_eq!(editor.next_timer(), None);
+        assert!(!editor.run_timers(second + idle * 10));
+        assert_eq!(counted(&editor, "once"), Value::Number(1));
+        assert_eq!(counted(&editor, "again"), Value::Number(1));
+    }
+
+    #[test]
+    fn input_restarts_idle_timers() {
+        let (mut editor, _) = editing("");
+        counter(&mut editor, "ran");
+        let idle = Duration::from_secs(2);
+        let id = editor.run_with_idle_timer(idle, true, &counting("ran"));
+        let deadline = editor.next_timer().unwrap();
+        assert!(editor.run_timers(deadline));
+
+        press(&mut editor, "a");
+        let next = editor.next_timer().unwrap();
+        assert!(next > deadline);
+        //Typing before it's due pushes it back
+        press(&mut editor, "b");
+        assert!(editor.next_timer().unwrap() >= next);
+        assert!(!editor.run_timers(next - Duration::from_millis(1)));
+        let next = editor.next_timer().unwrap();
+        assert!(editor.run_timers(next));
+        assert_eq!(counted(&editor, "ran"), Value::Number(2));
+
+        editor.cancel_timer(id);
+        press(&mut editor, "c");
+        assert_eq!(editor.next_timer(), None);
+    }
+
+    #[test]
+    fn switch_and_window_hooks_wait_for_the_command_to_finish() {
+        let (mut editor, _) = editing("");
+        counter(&mut editor, "switched");
+        counter(&mut editor, "reshaped");
+        editor.add_hook(BUFFER_SWITCH_HOOK, &counting("switched"), 0, false);
+        editor.add_hook(
+            WINDOW_CONFIGURATION_CHANGE_HOOK,
+            &counting("reshaped"),
+            0,
+            false,
+        );
+        //The first command only notes where things stand
+        press(&mut editor, "C-g");
+        let scratch = editor.current_buffer_id();
+        let other = editor.create_buffer("other");
+
+        //Nothing runs mid-command, and a command that ends up back where
+        //it started changed nothing
+        editor.switch_to_buffer(other);
+        editor.switch_to_buffer(scratch);
+        assert_eq!(counted(&editor, "switched"), Value::Number(0));
+        press(&mut editor, "C-g");
+        assert_eq!(counted(&editor, "switched"), Value::Number(0));
+
+        editor.switch_to_buffer(other);
+        assert_eq!(counted(&editor, "switched"), Value::Number(0));
+        press(&mut editor, "C-g");
+        assert_eq!(counted(&editor, "switched"), Value::Number(1));
+        assert_eq!(counted(&editor, "reshaped"), Value::Number(1));
+
+        //Splitting changes the windows but not the buffer
+        press(&mut editor, "C-x 2");
+        assert_eq!(counted(&editor, "reshaped"), Value::Number(2));
+        assert_eq!(counted(&editor, "switched"), Value::Number(1));
+        press(&mut editor, "C-g");
+        assert_eq!(counted(&editor, "reshaped"), Value::Number(2));
+    }
+}
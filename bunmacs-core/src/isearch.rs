@@ -0,0 +1,503 @@
+//Incremental search, C-s and C-r. Like the minibuffer it takes the
+//keyboard over until it's done, except the cursor stays in the buffer and
+//moves to the next match with every key.
+
+use std::ops::Range;
+
+use ropey::Rope;
+
+use crate::{
+    buffer::{Buffer, BufferId},
+    face,
+    keymap::Key,
+    search::{self, SearchPattern},
+};
+
+//Where the search is at. DEL goes back to the one before the last key.
+#[derive(Debug, Clone)]
+struct State {
+    query: String,
+    forward: bool,
+    regex: bool,
+    //Set with M-c, None goes by case-fold-search and the query's case
+    case_fold: Option<bool>,
+    //The match point is at, None while failing
+    found: Option<Range<usize>>,
+    //Where searching for a longer query starts: the start of the last
+    //match going forward, its end going backward
+    anchor: usize,
+    //Went around the end of the buffer after failing
+    wrapped: bool,
+    point: usize,
+}
+
+#[derive(Debug)]
+pub struct Isearch {
+    buffer: BufferId,
+    //Where point was when the search started, C-g goes back there
+    origin: usize,
+    //case-fold-search as of when the search started
+    case_fold_search: bool,
+    //What C-s or C-r searches for with nothing typed yet
+    previous: Option<String>,
+    state: State,
+    history: Vec<State>,
+    //None while the query isn't a valid regex
+    pattern: Option<SearchPattern>,
+    error: Option<String>,
+}
+
+pub(crate) enum IsearchResult {
+    Searching(Isearch),
+    //Done with point where the search left it. The key that ended the
+    //search still needs running if it wasn't one of isearch's own.
+    Exit(Isearch, Option<Key>),
+    //C-g with nothing failing, point is back at the origin
+    Quit,
+}
+
+impl Isearch {
+    pub(crate) fn new(
+        buffer: &Buffer,
+        forward: bool,
+        regex: bool,
+        case_fold_search: bool,
+        previous: Option<String>,
+    ) -> Self {
+        let point = buffer.point();
+        let mut isearch = Isearch {
+            buffer: buffer.id(),
+            origin: point,
+            case_fold_search,
+            previous,
+            state: State {
+                query: String::new(),
+                forward,
+                regex,
+                case_fold: None,
+                found: None,
+                anchor: point,
+                wrapped: false,
+                point,
+            },
+            history: vec![],
+            pattern: None,
+            error: None,
+        };
+        isearch.compile();
+        isearch
+    }
+
+    pub fn buffer(&self) -> BufferId {
+        self.buffer
+    }
+
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    pub fn query(&self) -> &str {
+        &self.state.query
+    }
+
+    pub fn is_regex(&self) -> bool {
+        self.state.regex
+    }
+
+    pub fn found(&self) -> Option<Range<usize>> {
+        self.state.found.clone()
+    }
+
+    fn is_failing(&self) -> bool {
+        self.state.found.is_none() && !self.state.query.is_empty()
+    }
+
+    //What the echo area says, e.g. "Failing regexp I-search backward: "
+    pub fn prompt(&self) -> String {
+        let state = &self.state;
+        let mut words = vec![];
+        if self.is_failing() {
+            words.push("failing");
+        }
+        if state.wrapped {
+            words.push("wrapped");
+        }
+        match state.case_fold {
+            Some(true) => words.push("case-insensitive"),
+            Some(false) => words.push("case-sensitive"),
+            None => (),
+        }
+        if state.regex {
+            words.push("regexp");
+        }
+        words.push("I-search");
+        if !state.forward {
+            words.push("backward");
+        }
+        let prompt = words.join(" ");
+        let mut chars = prompt.chars();
+        let first = chars.next().map(|c| c.to_uppercase().to_string());
+        format!("{}{}: ", first.unwrap_or_default(), chars.as_str())
+    }
+
+    //What follows the query in the echo area, like Emacs'
+    //"[incomplete input]"
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    fn compile(&mut self) {
+        let state = &self.state;
+        let case_fold = state
+            .case_fold
+            .unwrap_or_else(|| search::case_fold(&state.query, state.regex, self.case_fold_search));
+        match SearchPattern::new(&state.query, state.regex, case_fold) {
+            Ok(pattern) => {
+                self.pattern = Some(pattern);
+                self.error = None;
+            }
+            Err(_) => {
+                self.pattern = None;
+                self.error = Some("incomplete input".to_owned());
+            }
+        }
+    }
+
+    //Searches from `from` in the current direction, moving point to the
+    //far end of the match if there is one
+    fn search(&mut self, buffer: &mut Buffer, from: usize) {
+        let Some(pattern) = &mut self.pattern else {
+            return;
+        };
+        let text = buffer.text();
+        let state = &mut self.state;
+        let found = if state.forward {
+            pattern.find_forward(text, from, text.len_chars())
+        } else {
+            pattern.find_backward(text, from, 0)
+        };
+        state.found = match found {
+            Ok(found) => found,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        };
+        if let Some(found) = &state.found {
+            (state.point, state.anchor) = if state.forward {
+                (found.end, found.start)
+            } else {
+                (found.start, found.end)
+            };
+            buffer.set_point(state.point);
+        }
+    }
+
+    //The query, the regex flag or case folding changed, so the match
+    //might be somewhere else
+    fn search_again(&mut self, buffer: &mut Buffer) {
+        self.compile();
+        if self.state.query.is_empty() {
+            self.state.found = None;
+            return;
+        }
+        //Going backward, the longer query can still match where the last
+        //one did, like Emacs it stays there unless it'd end past where the
+        //search started
+        if !self.state.forward {
+            let state = &mut self.state;
+            let limit = if state.wrapped {
+                buffer.len_chars()
+            } else {
+                self.origin.max(state.point)
+            };
+            if let Some(pattern) = &mut self.pattern {
+                if let Ok(Some(found)) = pattern.find_forward(buffer.text(), state.point, limit) {
+                    if found.start == state.point {
+                        state.anchor = found.end;
+                        state.found = Some(found);
+                        return;
+                    }
+                }
+            }
+        }
+        self.search(buffer, self.state.anchor);
+    }
+
+    //C-s going forward or C-r going backward: the next match. The other
+    //one turns the search around, to the other end of the current match.
+    fn repeat(&mut self, buffer: &mut Buffer, forward: bool) {
+        if self.state.query.is_empty() {
+            let Some(previous) = self.previous.clone() else {
+                return;
+            };
+            self.state.query = previous;
+            self.state.forward = forward;
+            self.search_again(buffer);
+            return;
+        }
+        let len = buffer.len_chars();
+        let state = &mut self.state;
+        let from = if state.forward != forward {
+            state.forward = forward;
+            match &state.found {
+                Some(found) if forward => found.start,
+                Some(found) => found.end,
+                None => state.point,
+            }
+        } else {
+            match state.found.clone() {
+                //Failing again wraps around to the other end
+                None => {
+                    state.wrapped = true;
+                    if forward {
+                        0
+                    } else {
+                        len
+                    }
+                }
+                //An empty match would just be found again
+                Some(found) if found.is_empty() && forward => {
+                    if found.end == len {
+                        state.found = None;
+                        return;
+                    }
+                    found.end + 1
+                }
+                Some(found) if found.is_empty() => {
+                    if found.start == 0 {
+                        state.found = None;
+                        return;
+                    }
+                    found.start - 1
+                }
+                Some(found) if forward => found.end,
+                Some(found) => found.start,
+            }
+        };
+        self.search(buffer, from);
+    }
+
+    pub(crate) fn handle_key(mut self, key: Key, buffer: &mut Buffer) -> IsearchResult {
+        let is = |kbd: &str| Key::parse(kbd) == Some(key);
+        if is("C-g") {
+            if !self.is_failing() {
+                buffer.set_point(self.origin);
+                return IsearchResult::Quit;
+            }
+            //Drops what couldn't be found, leaving what could
+            while self.is_failing() {
+                let Some(state) = self.history.pop() else {
+                    break;
+                };
+                self.state = state;
+            }
+            self.compile();
+            buffer.set_point(self.state.point);
+            return IsearchResult::Searching(self);
+        }
+        if is("RET") {
+            return IsearchResult::Exit(self, None);
+        }
+        if is("DEL") {
+            if let Some(state) = self.history.pop() {
+                self.state = state;
+                self.compile();
+                buffer.set_point(self.state.point);
+            }
+            return IsearchResult::Searching(self);
+        }
+        self.history.push(self.state.clone());
+        if is("C-s") {
+            self.repeat(buffer, true);
+        } else if is("C-r") {
+            self.repeat(buffer, false);
+        } else if is("M-r") {
+            self.state.regex = !self.state.regex;
+            self.search_again(buffer);
+        } else if is("M-c") {
+            let folding = self.state.case_fold.unwrap_or_else(|| {
+                search::case_fold(&self.state.query, self.state.regex, self.case_fold_search)
+            });
+            self.state.case_fold = Some(!folding);
+            self.search_again(buffer);
+        } else if let Some(c) = key.self_insert_char() {
+            self.state.query.push(c);
+            self.search_again(buffer);
+        } else {
+            self.history.pop();
+            return IsearchResult::Exit(self, Some(key));
+        }
+        IsearchResult::Searching(self)
+    }
+
+    //Faces for the matches in `range` of `text`: the current one in
+    //`isearch`, and every other one in `lazy-highlight` if `lazy`
+    pub(crate) fn highlights(
+        &mut self,
+        text: &Rope,
+        range: Range<usize>,
+        lazy: bool,
+    ) -> Vec<(Range<usize>, &'static str)> {
+        let found = self.state.found.clone();
+        let mut highlights = vec![];
+        if lazy && !self.state.query.is_empty() {
+            if let Some(pattern) = &mut self.pattern {
+                let matches = pattern.find_all(text, range).unwrap_or_default();
+                highlights.extend(
+                    matches
+                        .into_iter()
+                        .filter(|m| !m.is_empty() && Some(m) != found.as_ref())
+                        .map(|m| (m, face::LAZY_HIGHLIGHT)),
+                );
+            }
+        }
+        highlights.extend(found.map(|found| (found, face::ISEARCH)));
+        highlights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str, point: usize) -> Buffer {
+        let mut buffer = Buffer::new(BufferId(0), "isearch".to_owned());
+        buffer.insert(0, text);
+        buffer.set_point(point);
+        buffer
+    }
+
+    //Feeds `keys` to `isearch`, none of which should end it
+    fn type_keys(mut isearch: Isearch, buffer: &mut Buffer, keys: &[&str]) -> Isearch {
+        for key in keys {
+            match isearch.handle_key(Key::parse(key).unwrap(), buffer) {
+                IsearchResult::Searching(next) => isearch = next,
+                _ => panic!("{key} ended the search"),
+            }
+        }
+        isearch
+    }
+
+    const TEXT: &str = "foo bar foo baz foo";
+
+    #[test]
+    fn c_s_finds_the_next_match_and_wraps_after_failing() {
+        let mut buffer = buffer(TEXT, 0);
+        let isearch = Isearch::new(&buffer, true, false, true, None);
+        let isearch = type_keys(isearch, &mut buffer, &["f", "o", "o"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(0..3), 3));
+        let isearch = type_keys(isearch, &mut buffer, &["C-s", "C-s"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(16..19), 19));
+        assert_eq!(isearch.prompt(), "I-search: ");
+
+        let isearch = type_keys(isearch, &mut buffer, &["C-s"]);
+        assert_eq!((isearch.found(), buffer.point()), (None, 19));
+        assert_eq!(isearch.prompt(), "Failing I-search: ");
+        let isearch = type_keys(isearch, &mut buffer, &["C-s"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(0..3), 3));
+        assert_eq!(isearch.prompt(), "Wrapped I-search: ");
+    }
+
+    #[test]
+    fn c_r_searches_backward() {
+        let mut buffer = buffer(TEXT, TEXT.len());
+        let isearch = Isearch::new(&buffer, false, false, true, None);
+        //"fo" and "foo" still match where "f" did
+        let isearch = type_keys(isearch, &mut buffer, &["f", "o", "o"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(16..19), 16));
+        let isearch = type_keys(isearch, &mut buffer, &["C-r"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(8..11), 8));
+        assert_eq!(isearch.prompt(), "I-search backward: ");
+        //Turning around goes to the other end of the match
+        let isearch = type_keys(isearch, &mut buffer, &["C-s"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(8..11), 11));
+    }
+
+    #[test]
+    fn backward_matches_end_before_the_origin() {
+        let mut buffer = buffer("foo foo", 5);
+        let isearch = Isearch::new(&buffer, false, false, true, None);
+        let isearch = type_keys(isearch, &mut buffer, &["f"]);
+        assert_eq!(isearch.found(), Some(4..5));
+        let isearch = type_keys(isearch, &mut buffer, &["o"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(0..2), 0));
+    }
+
+    #[test]
+    fn del_unwinds_one_key_at_a_time() {
+        let mut buffer = buffer(TEXT, 0);
+        let isearch = Isearch::new(&buffer, true, false, true, None);
+        let isearch = type_keys(isearch, &mut buffer, &["f", "o", "C-s"]);
+        assert_eq!((isearch.found(), buffer.point()), (Some(8..10), 10));
+        let isearch = type_keys(isearch, &mut buffer, &["DEL"]);
+        assert_eq!((isearch.query(), isearch.found()), ("fo", Some(0..2)));
+        assert_eq!(buffer.point(), 2);
+        let isearch = type_keys(isearch, &mut buffer, &["DEL"]);
+        assert_eq!((isearch.query(), buffer.point()), ("f", 1));
+        let isearch = type_keys(isearch, &mut buffer, &["DEL", "DEL"]);
+        assert_eq!((isearch.query(), isearch.found()), ("", None));
+        assert_eq!(buffer.point(), 0);
+    }
+
+    #[test]
+    fn c_g_drops_what_fails_then_quits() {
+        let mut buffer = buffer(TEXT, 4);
+        let isearch = Isearch::new(&buffer, true, false, true, None);
+        let isearch = type_keys(isearch, &mut buffer, &["b", "a", "x", "y"]);
+        assert_eq!(isearch.found(), None);
+        let isearch = type_keys(isearch, &mut buffer, &["C-g"]);
+        assert_eq!((isearch.query(), isearch.found()), ("ba", Some(4..6)));
+        assert_eq!(buffer.point(), 6);
+        let isearch = type_keys(isearch, &mut buffer, &["C-s"]);
+        assert_eq!(buffer.point(), 14);
+        assert!(matches!(
+            isearch.handle_key(Key::parse("C-g").unwrap(), &mut buffer),
+            IsearchResult::Quit
+        ));
+        assert_eq!(buffer.point(), 4);
+    }
+
+    #[test]
+    fn other_keys_end_the_search() {
+        let mut buffer = buffer(TEXT, 0);
+        let isearch = Isearch::new(&buffer, true, false, true, None);
+        let isearch = type_keys(isearch, &mut buffer, &["b", "a"]);
+        let key = Key::parse("C-f").unwrap();
+        match isearch.handle_key(key, &mut buffer) {
+            IsearchResult::Exit(isearch, unread) => {
+                assert_eq!(unread, Some(key));
+                assert_eq!(isearch.query(), "ba");
+            }
+            _ => panic!("still searching"),
+        }
+        assert_eq!(buffer.point(), 6);
+    }
+
+    #[test]
+    fn c_s_first_searches_for_the_last_query() {
+        let mut buffer = buffer(TEXT, 1);
+        let isearch = Isearch::new(&buffer, true, false, true, Some("foo".to_owned()));
+        let isearch = type_keys(isearch, &mut buffer, &["C-s"]);
+        assert_eq!((isearch.query(), isearch.found()), ("foo", Some(8..11)));
+    }
+
+    #[test]
+    fn regexes_and_case_folding_can_be_toggled() {
+        let mut buffer = buffer("Foo f.o fxo", 0);
+        let isearch = Isearch::new(&buffer, true, false, true, None);
+        let isearch = type_keys(isearch, &mut buffer, &["f", ".", "o"]);
+        assert_eq!(isearch.found(), Some(4..7));
+        let isearch = type_keys(isearch, &mut buffer, &["M-r"]);
+        assert_eq!(isearch.prompt(), "Regexp I-search: ");
+        assert_eq!(isearch.found(), Some(4..7));
+        //DEL takes back M-r like any other key
+        let isearch = type_keys(isearch, &mut buffer, &["DEL"]);
+        assert_eq!(isearch.prompt(), "I-search: ");
+        let isearch = type_keys(isearch, &mut buffer, &["DEL", "DEL"]);
+        assert_eq!((isearch.query(), isearch.found()), ("f", Some(0..1)));
+        let isearch = type_keys(isearch, &mut buffer, &["M-c"]);
+        assert_eq!(isearch.prompt(), "Case-sensitive I-search: ");
+        assert_eq!(isearch.found(), Some(4..5));
+    }
+}
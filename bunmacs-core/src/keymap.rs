@@ -0,0 +1,182 @@
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub meta: bool,
+    pub shift: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Char(char),
+    Return,
+    Backspace,
+    Delete,
+    Tab,
+    Escape,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub mods: Modifiers,
+}
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("RET", KeyCode::Return),
+    ("DEL", KeyCode::Backspace),
+    ("<delete>", KeyCode::Delete),
+    ("TAB", KeyCode::Tab),
+    ("ESC", KeyCode::Escape),
+    ("SPC", KeyCode::Char(' ')),
+    ("<left>", KeyCode::Left),
+    ("<right>", KeyCode::Right),
+    ("<up>", KeyCode::Up),
+    ("<down>", KeyCode::Down),
+    ("<home>", KeyCode::Home),
+    ("<end>", KeyCode::End),
+    ("<prior>", KeyCode::PageUp),
+    ("<next>", KeyCode::PageDown),
+];
+
+impl Key {
+    pub fn plain(code: KeyCode) -> Self {
+        Key {
+            code,
+            mods: Modifiers::default(),
+        }
+    }
+
+    pub fn ctrl(c: char) -> Self {
+        Key {
+            code: KeyCode::Char(c),
+            mods: Modifiers {
+                ctrl: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    //Parses Emacs `kbd` notation for a single key, e.g. "C-x", "M-<left>"
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut mods = Modifiers::default();
+        let mut rest = s;
+        loop {
+            if rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+                match rest.as_bytes()[0] {
+                    b'C' => mods.ctrl = true,
+                    b'M' => mods.meta = true,
+                    b'S' => mods.shift = true,
+                    _ => break,
+                }
+                rest = &rest[2..];
+            } else {
+                break;
+            }
+        }
+        let code = match NAMED_KEYS.iter().find(|(name, _)| *name == rest) {
+            Some((_, code)) => *code,
+            None => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        };
+        Some(Key { code, mods })
+    }
+
+    //Parses a space separated key sequence, e.g. "C-x C-s"
+    pub fn parse_sequence(s: &str) -> Option<Vec<Key>> {
+        s.split_whitespace().map(Key::parse).collect()
+    }
+
+    //The character this key inserts when it isn't bound to anything
+    pub fn self_insert_char(&self) -> Option<char> {
+        match (self.code, self.mods.ctrl || self.mods.meta) {
+            (KeyCode::Char(c), false) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.mods.ctrl {
+            f.write_str("C-")?;
+        }
+        if self.mods.meta {
+            f.write_str("M-")?;
+        }
+        if self.mods.shift {
+            f.write_str("S-")?;
+        }
+        match NAMED_KEYS.iter().find(|(_, code)| *code == self.code) {
+            Some((name, _)) => f.write_str(name),
+            None => match self.code {
+                KeyCode::Char(c) => write!(f, "{c}"),
+                _ => unreachable!("every non char key is named"),
+            },
+        }
+    }
+}
+
+pub fn format_sequence(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|k| k.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<'a> {
+    Command(&'a str),
+    Prefix,
+    Unbound,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, String>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&mut self, keys: Vec<Key>, command: impl Into<String>) {
+        self.bindings.insert(keys, command.into());
+    }
+
+    //Convenience for static bindings, panics on malformed key descriptions
+    pub fn bind_kbd(&mut self, kbd: &str, command: impl Into<String>) {
+        let keys = Key::parse_sequence(kbd).unwrap_or_else(|| panic!("bad key sequence {kbd}"));
+        self.bind(keys, command);
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<'_> {
+        if let Some(command) = self.bindings.get(keys) {
+            return Lookup::Command(command);
+        }
+        if self
+            .bindings
+            .keys()
+            .any(|bound| bound.len() > keys.len() && bound.starts_with(keys))
+        {
+            Lookup::Prefix
+        } else {
+            Lookup::Unbound
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use std::{collections::HashMap, rc::Rc};
+
+use ropey::Rope;
+use unicode_width::UnicodeWidthChar;
+
+//Size of one cell of the monospace grid, in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellMetrics {
+    pub width: f32,
+    pub height: f32,
+    //Distance from the top of a cell to the baseline
+    pub ascent: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    //The part of both rectangles, None if they don't overlap
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (right > x && bottom > y).then_some(Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    //The smallest rectangle with both in it
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Wrap {
+    #[default]
+    Wrap,
+    Truncate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayoutParams {
+    pub metrics: CellMetrics,
+    pub wrap: Wrap,
+    pub tab_width: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionedGlyph {
+    //Char index into the buffer this glyph displays. Tabs and control
+    //characters display as several glyphs sharing one offset.
+    pub offset: usize,
+    pub ch: char,
+    pub col: usize,
+    pub cells: usize,
+    pub x: f32,
+    pub baseline: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayRow {
+    pub row: usize,
+    pub y: f32,
+    pub line: usize,
+    //Buffer range shown on this row, not counting the newline
+    pub start: usize,
+    pub end: usize,
+    pub glyphs: Vec<PositionedGlyph>,
+    //Where the cursor goes when it sits on the newline or end of buffer
+    pub eol: Option<(usize, usize)>,
+    //Continues on the next row because it didn't fit
+    pub wrapped: bool,
+    //Cut off at the right edge, only happens with Wrap::Truncate
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorPosition {
+    pub row: usize,
+    pub col: usize,
+    pub x: f32,
+    pub y: f32,
+    pub cells: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub area: Rect,
+    pub metrics: CellMetrics,
+    pub cols: usize,
+    pub rows: usize,
+    pub start: usize,
+    //First char that didn't fit
+    pub end: usize,
+    pub display_rows: Vec<DisplayRow>,
+}
+
+pub fn grid_size(area: Rect, metrics: CellMetrics) -> (usize, usize) {
+    //A little slack so areas made of whole cells don't lose one to rounding
+    let cols = (area.width / metrics.width + 1e-3).floor().max(0.0) as usize;
+    let rows = (area.height / metrics.height + 1e-3).floor().max(0.0) as usize;
+    (cols, rows)
+}
+
+//How `ch` shows up on screen when it starts at column `col`
+fn display_glyphs(ch: char, col: usize, tab_width: usize) -> DisplayChar {
+    match ch {
+        '\t' => {
+            let tab_width = tab_width.max(1);
+            DisplayChar::Spaces(tab_width - col % tab_width)
+        }
+        c if c.is_control() => {
+            //Emacs style ^A for control characters
+            let shown = char::from_u32((c as u32 ^ 0x40) & 0x7F).unwrap_or('?');
+            DisplayChar::Caret(shown)
+        }
+        c => DisplayChar::Char(c, c.width().unwrap_or(1).max(1)),
+    }
+}
+
+enum DisplayChar {
+    Char(char, usize),
+    Spaces(usize),
+    Caret(char),
+}
+
+impl DisplayChar {
+    fn cells(&self) -> usize {
+        match self {
+            DisplayChar::Char(_, w) => *w,
+            DisplayChar::Spaces(n) => *n,
+            DisplayChar::Caret(_) => 2,
+        }
+    }
+
+    fn glyphs(&self) -> Vec<(char, usize)> {
+        match self {
+            DisplayChar::Char(c, w) => vec![(*c, *w)],
+            DisplayChar::Spaces(n) => vec![(' ', 1); *n],
+            DisplayChar::Caret(c) => vec![('^', 1), (*c, 1)],
+        }
+    }
+}
+
+fn content_len(text: &Rope, line: usize) -> usize {
+    let slice = text.line(line);
+    let len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        len - 1
+    } else {
+        len
+    }
+}
+
+//Width of a logical line in cells
+pub fn line_width(text: &Rope, line: usize, tab_width: usize) -> usize {
+    text.line(line)
+        .chars()
+        .take(content_len(text, line))
+        .fold(0, |col, ch| {
+            col + display_glyphs(ch, col, tab_width).cells()
+        })
+}
+
+//Number of screen rows a logical line takes up
+pub fn line_rows(text: &Rope, line: usize, cols: usize, params: &LayoutParams) -> usize {
+    match params.wrap {
+        Wrap::Truncate => 1,
+        Wrap::Wrap => {
+            if cols == 0 {
+                return 1;
+            }
+            let mut rows = 1;
+            let mut col = 0;
+            for ch in text.line(line).chars().take(content_len(text, line)) {
+                let mut cells = display_glyphs(ch, col, params.tab_width).cells();
+                if col + cells > cols && col > 0 {
+                    rows += 1;
+                    col = 0;
+                    //Tabs are narrower at the start of a row
+                    cells = display_glyphs(ch, col, params.tab_width).cells();
+                }
+                col += cells;
+            }
+            //A cursor at the end of a completely full row wraps onto its own
+            if col >= cols {
+                rows += 1;
+            }
+            rows
+        }
+    }
+}
+
+//One logical line laid out on its own. Offsets are from the start of the
+//line and nothing is positioned yet, so it fits wherever the line ends up.
+#[derive(Debug)]
+struct LineRows {
+    rows: Vec<LineRow>,
+}
+
+#[derive(Debug)]
+struct LineRow {
+    start: usize,
+    end: usize,
+    //Offset, glyph, column and cells
+    glyphs: Vec<(usize, char, usize, usize)>,
+    //Column of the cursor on the newline, only on the last row of a line
+    //that isn't truncated
+    eol: Option<usize>,
+    wrapped: bool,
+    truncated: bool,
+}
+
+//What a line's rows depend on, besides the font which only moves them
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LineKey {
+    text: String,
+    cols: usize,
+    tab_width: usize,
+    wrap: Wrap,
+}
+
+//Lines as the last layout laid them out, so the next one only has to lay
+//out lines that changed. Lines that weren't used by the last layout are
+//dropped.
+#[derive(Debug, Default)]
+pub struct LineCache {
+    lines: HashMap<LineKey, Rc<LineRows>>,
+}
+
+impl LineRows {
+    fn new(key: &LineKey) -> Self {
+        let cols = key.cols;
+        let mut rows = vec![];
+        let mut row = LineRow::new(0);
+        let mut col = 0;
+        for (offset, ch) in key.text.chars().enumerate() {
+            let mut shown = display_glyphs(ch, col, key.tab_width);
+            if col + shown.cells() > cols && col > 0 {
+                match key.wrap {
+                    Wrap::Truncate => {
+                        row.truncated = true;
+                        break;
+                    }
+                    Wrap::Wrap => {
+                        row.end = offset;
+                        row.wrapped = true;
+                        rows.push(row);
+                        row = LineRow::new(offset);
+                        col = 0;
+                        //Tabs come out a different width at the start of a row
+                        shown = display_glyphs(ch, col, key.tab_width);
+                    }
+                }
+            }
+            for (glyph, cells) in shown.glyphs() {
+                row.glyphs.push((offset, glyph, col, cells));
+                col += cells;
+            }
+            row.end = offset + 1;
+        }
+        let len = key.text.chars().count();
+        if !row.truncated {
+            //A cursor at the end of a completely full row wraps onto its own
+            if col >= cols && key.wrap == Wrap::Wrap {
+                row.wrapped = true;
+                row.end = len;
+                rows.push(row);
+                row = LineRow::new(len);
+                col = 0;
+            }
+            row.eol = Some(col);
+        }
+        row.end = len;
+        rows.push(row);
+        LineRows { rows }
+    }
+}
+
+impl LineRow {
+    fn new(start: usize) -> Self {
+        LineRow {
+            start,
+            end: start,
+            glyphs: vec![],
+            eol: None,
+            wrapped: false,
+            truncated: false,
+        }
+    }
+}
+
+impl LineCache {
+    fn rows(&self, used: &mut HashMap<LineKey, Rc<LineRows>>, key: LineKey) -> Rc<LineRows> {
+        if let Some(rows) = used.get(&key) {
+            return rows.clone();
+        }
+        let rows = match self.lines.get(&key) {
+            Some(rows) => rows.clone(),
+            None => Rc::new(LineRows::new(&key)),
+        };
+        used.insert(key, rows.clone());
+        rows
+    }
+}
+
+impl Layout {
+    //Lays out `text` into `area` starting from logical line `start_line`
+    pub fn new(text: &Rope, start_line: usize, area: Rect, params: &LayoutParams) -> Self {
+        Layout::with_cache(text, start_line, area, params, &mut LineCache::default())
+    }
+
+    //The same as `new`, but lines `cache` has seen before aren't laid out
+    //again
+    pub fn with_cache(
+        text: &Rope,
+        start_line: usize,
+        area: Rect,
+        params: &LayoutParams,
+        cache: &mut LineCache,
+    ) -> Self {
+        let metrics = params.metrics;
+        let (cols, rows) = grid_size(area, metrics);
+        let start_line = start_line.min(text.len_lines().saturating_sub(1));
+        let start = text.line_to_char(start_line);
+        let mut layout = Layout {
+            area,
+            metrics,
+            cols,
+            rows,
+            start,
+            end: start,
+            display_rows: vec![],
+        };
+        if cols == 0 || rows == 0 {
+            return layout;
+        }
+
+        let mut used = HashMap::new();
+        let mut line = start_line;
+        'lines: while line < text.len_lines() {
+            let line_start = text.line_to_char(line);
+            let key = LineKey {
+                text: text
+                    .line(line)
+                    .chars()
+                    .take(content_len(text, line))
+                    .collect(),
+                cols,
+                tab_width: params.tab_width,
+                wrap: params.wrap,
+            };
+            let line_rows = cache.rows(&mut used, key);
+            for line_row in &line_rows.rows {
+                if layout.display_rows.len() == rows {
+                    //Cut off partway through the line
+                    layout.end = line_start + line_row.start;
+                    break 'lines;
+                }
+                let mut row = layout.new_row(line, line_start + line_row.start);
+                row.end = line_start + line_row.end;
+                row.wrapped = line_row.wrapped;
+                row.truncated = line_row.truncated;
+                row.eol = line_row.eol.map(|col| (row.end, col));
+                row.glyphs = line_row
+                    .glyphs
+                    .iter()
+                    .map(|&(offset, ch, col, cells)| PositionedGlyph {
+                        offset: line_start + offset,
+                        ch,
+                        col,
+                        cells,
+                        x: area.x + col as f32 * metrics.width,
+                        baseline: row.y + metrics.ascent,
+                    })
+                    .collect();
+                layout.display_rows.push(row);
+            }
+            layout.end = text.line_to_char((line + 1).min(text.len_lines()));
+            if layout.display_rows.len() == rows {
+                break;
+            }
+            line += 1;
+        }
+        cache.lines = used;
+        layout
+    }
+
+    fn new_row(&self, line: usize, start: usize) -> DisplayRow {
+        let row = self.display_rows.len();
+        DisplayRow {
+            row,
+            y: self.area.y + row as f32 * self.metrics.height,
+            line,
+            start,
+            end: start,
+            glyphs: vec![],
+            eol: None,
+            wrapped: false,
+            truncated: false,
+        }
+    }
+
+    pub fn glyphs_for_offset(&self, offset: usize) -> impl Iterator<Item = &PositionedGlyph> {
+        self.display_rows
+            .iter()
+            .filter(move |r| r.start <= offset && offset <= r.end)
+            .flat_map(|r| r.glyphs.iter())
+            .filter(move |g| g.offset == offset)
+    }
+
+    //Where a cursor sitting at `offset` is drawn, None if it's off screen
+    pub fn cursor_position(&self, offset: usize) -> Option<CursorPosition> {
+        for row in &self.display_rows {
+            if offset < row.start || offset > row.end {
+                continue;
+            }
+            let mut glyphs = row.glyphs.iter().filter(|g| g.offset == offset);
+            if let Some(first) = glyphs.next() {
+                let cells = first.cells + glyphs.map(|g| g.cells).sum::<usize>();
+                return Some(self.cell(row, first.col, cells));
+            }
+            if let Some((eol, col)) = row.eol {
+                if eol == offset {
+                    return Some(self.cell(row, col, 1));
+                }
+            }
+        }
+        None
+    }
+
+    fn cell(&self, row: &DisplayRow, col: usize, cells: usize) -> CursorPosition {
+        CursorPosition {
+            row: row.row,
+            col,
+            x: self.area.x + col as f32 * self.metrics.width,
+            y: row.y,
+            cells,
+        }
+    }
+
+    //Buffer offset under the pixel position, clamped to the row contents
+    pub fn offset_at(&self, x: f32, y: f32) -> Option<usize> {
+        if !self.area.contains(x, y) {
+            return None;
+        }
+        let row_idx = ((y - self.area.y) / self.metrics.height) as usize;
+        let row = self
+            .display_rows
+            .get(row_idx)
+            .or(self.display_rows.last())?;
+        let col = ((x - self.area.x) / self.metrics.width) as usize;
+        row.glyphs
+            .iter()
+            .find(|g| col < g.col + g.cells)
+            .map(|g| g.offset)
+            .or(row.eol.map(|(offset, _)| offset))
+            .or(Some(row.end))
+    }
+
+    pub fn is_visible(&self, offset: usize) -> bool {
+        self.cursor_position(offset).is_some()
+    }
+}
+
+//The start line that keeps `point` on screen, moving as little as possible
+//from `start_line`
+pub fn scroll_to_point(
+    text: &Rope,
+    start_line: usize,
+    point: usize,
+    cols: usize,
+    rows: usize,
+    params: &LayoutParams,
+) -> usize {
+    let point_line = text.char_to_line(point.min(text.len_chars()));
+    if point_line < start_line || rows == 0 {
+        return point_line;
+    }
+    let mut start = start_line;
+    //Rows from the top of `start` down to and including point's line
+    let mut used: usize = (start_line..=point_line)
+        .map(|l| line_rows(text, l, cols, params))
+        .sum();
+    while used > rows && start < point_line {
+        used -= line_rows(text, start, cols, params);
+        start += 1;
+    }
+    start
+}
+
+//Start line that puts `line` roughly in the middle of `rows`
+pub fn recenter(
+    text: &Rope,
+    line: usize,
+    cols: usize,
+    rows: usize,
+    params: &LayoutParams,
+) -> usize {
+    let mut above = 0;
+    let mut start = line;
+    while start > 0 {
+        let prev = line_rows(text, start - 1, cols, params);
+        if above + prev > rows / 2 {
+            break;
+        }
+        above += prev;
+        start -= 1;
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS: CellMetrics = CellMetrics {
+        width: 10.0,
+        height: 20.0,
+        ascent: 15.0,
+    };
+
+    fn params(wrap: Wrap) -> LayoutParams {
+        LayoutParams {
+            metrics: METRICS,
+            wrap,
+            tab_width: 4,
+        }
+    }
+
+    fn area(cols: usize, rows: usize) -> Rect {
+        Rect {
+            x: 5.0,
+            y: 0.0,
+            width: cols as f32 * METRICS.width,
+            height: rows as f32 * METRICS.height,
+        }
+    }
+
+    //What each row shows
+    fn rows(layout: &Layout) -> Vec<String> {
+        layout
+            .display_rows
+            .iter()
+            .map(|row| row.glyphs.iter().map(|g| g.ch).collect())
+            .collect()
+    }
+
+    #[test]
+    fn long_lines_wrap() {
+        let text = Rope::from("abcdefghij\nk");
+        let layout = Layout::new(&text, 0, area(4, 5), &params(Wrap::Wrap));
+        assert_eq!(rows(&layout), ["abcd", "efgh", "ij", "k"]);
+        let wrapped: Vec<bool> = layout.display_rows.iter().map(|r| r.wrapped).collect();
+        assert_eq!(wrapped, [true, true, false, false]);
+        assert_eq!(line_rows(&text, 0, 4, &params(Wrap::Wrap)), 3);
+        assert_eq!(layout.end, text.len_chars());
+    }
+
+    #[test]
+    fn full_rows_wrap_for_the_cursor() {
+        let text = Rope::from("abcd\n");
+        let layout = Layout::new(&text, 0, area(4, 5), &params(Wrap::Wrap));
+        assert_eq!(line_rows(&text, 0, 4, &params(Wrap::Wrap)), 2);
+        let cursor = layout.cursor_position(4).unwrap();
+        assert_eq!((cursor.row, cursor.col), (1, 0));
+    }
+
+    #[test]
+    fn truncated_lines_stop_at_the_edge() {
+        let text = Rope::from("abcdefghij\nk");
+        let layout = Layout::new(&text, 0, area(4, 5), &params(Wrap::Truncate));
+        assert_eq!(rows(&layout), ["abcd", "k"]);
+        assert!(layout.display_rows[0].truncated);
+        assert_eq!(line_rows(&text, 0, 4, &params(Wrap::Truncate)), 1);
+    }
+
+    #[test]
+    fn tabs_and_control_characters_take_several_cells() {
+        let text = Rope::from("a\tb\u{1}c");
+        let layout = Layout::new(&text, 0, area(10, 1), &params(Wrap::Wrap));
+        assert_eq!(rows(&layout), ["a   b^Ac"]);
+        let tab = layout.cursor_position(1).unwrap();
+        assert_eq!((tab.col, tab.cells, tab.x), (1, 3, 15.0));
+        let control = layout.cursor_position(3).unwrap();
+        assert_eq!((control.col, control.cells), (5, 2));
+        assert_eq!(line_width(&text, 0, 4), 8);
+    }
+
+    #[test]
+    fn wide_characters_wrap_whole() {
+        let text = Rope::from("abc漢字");
+        let layout = Layout::new(&text, 0, area(4, 3), &params(Wrap::Wrap));
+        //And the row they fill leaves the end of the line to the next
+        assert_eq!(rows(&layout), ["abc", "漢字", ""]);
+        assert_eq!(layout.cursor_position(4).unwrap().col, 2);
+    }
+
+    #[test]
+    fn offsets_round_trip_through_pixels() {
+        let text = Rope::from("ab\tc\nde");
+        let layout = Layout::new(&text, 0, area(8, 3), &params(Wrap::Wrap));
+        for offset in 0..text.len_chars() {
+            let cursor = layout.cursor_position(offset).unwrap();
+            assert_eq!(layout.offset_at(cursor.x, cursor.y), Some(offset));
+        }
+        //Past the end of a row is the end of its line
+        assert_eq!(layout.offset_at(75.0, 25.0), Some(7));
+        assert_eq!(layout.offset_at(0.0, 0.0), None);
+    }
+
+    #[test]
+    fn layouts_stop_when_the_area_is_full() {
+        let text = Rope::from("abcdefgh\nij\nkl");
+        let layout = Layout::new(&text, 0, area(4, 1), &params(Wrap::Wrap));
+        assert_eq!(rows(&layout), ["abcd"]);
+        assert_eq!(layout.end, 4);
+        assert!(!layout.is_visible(5));
+        let layout = Layout::new(&text, 1, area(4, 3), &params(Wrap::Wrap));
+        assert_eq!(rows(&layout), ["ij", "kl"]);
+        assert_eq!(layout.start, 9);
+    }
+
+    #[test]
+    fn scrolling_keeps_point_on_screen() {
+        let text = Rope::from("1\n2\n3\nlong line\n5\n");
+        let params = params(Wrap::Wrap);
+        assert_eq!(scroll_to_point(&text, 0, 0, 4, 3, &params), 0);
+        //Line 3 takes three rows, so it has the screen to itself
+        assert_eq!(scroll_to_point(&text, 0, 6, 4, 3, &params), 3);
+        assert_eq!(scroll_to_point(&text, 3, 2, 4, 3, &params), 1);
+        assert_eq!(recenter(&text, 2, 4, 3, &params), 1);
+    }
+
+    #[test]
+    fn cached_layouts_match_fresh_ones() {
+        let params = params(Wrap::Wrap);
+        let mut cache = LineCache::default();
+        let mut text = Rope::from("one\n\ttwo\nthree four five\n");
+        for edit in ["x", "\t", "漢"] {
+            let cached = Layout::with_cache(&text, 0, area(6, 8), &params, &mut cache);
+            let fresh = Layout::new(&text, 0, area(6, 8), &params);
+            assert_eq!(cached.display_rows, fresh.display_rows);
+            assert_eq!(cached.end, fresh.end);
+            text.insert(5, edit);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+pub mod background;
+pub mod buffer;
+pub mod commands;
+pub mod completion;
+pub mod connection;
+pub mod damage;
+pub mod dap;
+pub mod decoration;
+pub mod editor;
+pub mod face;
+pub mod fileio;
+pub mod font;
+pub mod frame;
+pub mod frontend;
+pub mod gitignore;
+pub mod grid;
+pub mod hook;
+pub mod isearch;
+pub mod keymap;
+pub mod layout;
+pub mod lsp;
+pub mod minibuffer;
+pub mod mode;
+pub mod modeline;
+pub mod project;
+pub mod replace;
+pub mod script;
+pub mod search;
+pub mod syntax;
+#[cfg(test)]
+mod testing;
+pub mod theme;
+pub mod variable;
+pub mod window;
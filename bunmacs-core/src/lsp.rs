@@ -0,0 +1,1575 @@
+//A Language Server Protocol client. A server is started per project and
+//major mode and speaks JSON-RPC over a Connection, whose messages
+//Editor::process_background_events hands to `process` on the editor
+//thread.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use bunlang::Value;
+use ropey::Rope;
+use serde_json::{json, Value as Json};
+
+use crate::{
+    background::Background,
+    buffer::{self, BufferId, MarkerId, OverlayId, TextChange, TextPosition},
+    connection::{Connection, Incoming},
+    editor::Editor,
+    face, fileio, project,
+};
+
+pub const REFERENCES_BUFFER: &str = "*lsp-references*";
+pub const REFERENCES_MODE: &str = "lsp-references-mode";
+const HOVER_BUFFER: &str = "*lsp-hover*";
+
+//JSON-RPC's code for a method we don't handle
+const METHOD_NOT_FOUND: i64 = -32601;
+
+//Gets the result of one of our requests, or the error the server sent back
+type ResponseCallback = Box<dyn FnOnce(&mut Editor, Result<Json, String>)>;
+
+//How to start the server for a major mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ServerId(u64);
+
+//What the server counts columns in. UTF-16 unless it picks one of the
+//others we offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+impl Encoding {
+    fn column(self, position: &TextPosition) -> usize {
+        match self {
+            Encoding::Utf8 => position.bytes,
+            Encoding::Utf16 => position.utf16,
+            Encoding::Utf32 => position.chars,
+        }
+    }
+}
+
+//How the server wants to hear about edits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DocumentSync {
+    None,
+    Full,
+    Incremental,
+}
+
+#[derive(Debug)]
+struct Diagnostic {
+    overlay: OverlayId,
+    message: String,
+}
+
+//A buffer the server has been told about
+#[derive(Debug)]
+struct Document {
+    uri: String,
+    version: i64,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct LanguageServer {
+    id: ServerId,
+    config: ServerConfig,
+    root: PathBuf,
+    mode: String,
+    connection: Connection,
+    next_request: i64,
+    pending: HashMap<i64, ResponseCallback>,
+    //None until the server answers initialize. Documents opened before
+    //that get told about once it has.
+    capabilities: Option<Json>,
+    encoding: Encoding,
+    sync: DocumentSync,
+    documents: HashMap<BufferId, Document>,
+    shutting_down: bool,
+}
+
+impl Debug for LanguageServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LanguageServer")
+            .field("config", &self.config)
+            .field("root", &self.root)
+            .field("mode", &self.mode)
+            .field("pending", &self.pending.len())
+            .field("documents", &self.documents)
+            .finish()
+    }
+}
+
+impl LanguageServer {
+    fn spawn(
+        background: &Background,
+        id: ServerId,
+        config: ServerConfig,
+        root: PathBuf,
+        mode: String,
+    ) -> Result<Self, String> {
+        let connection = Connection::spawn(background, &config.command, &config.args, &root)?;
+        Ok(LanguageServer {
+            id,
+            config,
+            root,
+            mode,
+            connection,
+            next_request: 0,
+            pending: HashMap::new(),
+            capabilities: None,
+            encoding: Encoding::Utf16,
+            sync: DocumentSync::Full,
+            documents: HashMap::new(),
+            shutting_down: false,
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.config.command
+    }
+
+    fn send(&self, message: Json) {
+        self.connection.send(&message);
+    }
+
+    fn notify(&self, method: &str, params: Json) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+
+    fn request(&mut self, method: &str, params: Json, callback: ResponseCallback) {
+        let id = self.next_request;
+        self.next_request += 1;
+        self.pending.insert(id, callback);
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+    }
+
+    fn respond(&self, id: Json, result: Result<Json, (i64, String)>) {
+        self.send(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => {
+                json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+            }
+        });
+    }
+
+    fn document_uri(&self, buffer: BufferId) -> Option<&str> {
+        self.documents.get(&buffer).map(|d| d.uri.as_str())
+    }
+
+    fn did_open(&self, uri: &str, mode: &str, text: &Rope) {
+        let language = mode.strip_suffix("-mode").unwrap_or(mode);
+        self.notify(
+            "textDocument/didOpen",
+            json!({"textDocument": {
+                "uri": uri,
+                "languageId": language,
+                "version": 0,
+                "text": text.to_string(),
+            }}),
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Lsp {
+    //By major mode. Modes without one use their nearest ancestor's.
+    configs: HashMap<String, ServerConfig>,
+    servers: Vec<LanguageServer>,
+    next_server: u64,
+    //Where lsp-find-definition jumped from, latest last
+    jumps: Vec<(BufferId, MarkerId)>,
+}
+
+impl Lsp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_server_config(&mut self, mode: &str, config: ServerConfig) {
+        self.configs.insert(mode.to_owned(), config);
+    }
+
+    pub fn server_config(&self, mode: &str) -> Option<&ServerConfig> {
+        self.configs.get(mode)
+    }
+
+    //Whether any server still owes us an answer
+    pub fn has_pending_requests(&self) -> bool {
+        self.servers.iter().any(|s| !s.pending.is_empty())
+    }
+
+    //Whether a server knows about `buffer`
+    pub fn is_attached(&self, buffer: BufferId) -> bool {
+        self.servers
+            .iter()
+            .any(|s| s.documents.contains_key(&buffer))
+    }
+
+    fn server(&self, id: ServerId) -> Option<&LanguageServer> {
+        self.servers.iter().find(|s| s.id == id)
+    }
+
+    fn server_mut(&mut self, id: ServerId) -> Option<&mut LanguageServer> {
+        self.servers.iter_mut().find(|s| s.id == id)
+    }
+
+    fn server_for(&self, buffer: BufferId) -> Option<&LanguageServer> {
+        self.servers
+            .iter()
+            .find(|s| s.documents.contains_key(&buffer))
+    }
+}
+
+//The server config for `mode` and the mode it's configured for
+fn config_for(editor: &Editor, mode: &str) -> Option<(String, ServerConfig)> {
+    let lineage = editor.modes().lineage(mode).ok()?;
+    lineage.iter().rev().find_map(|m| {
+        let config = editor.lsp().server_config(&m.name)?;
+        Some((m.name.clone(), config.clone()))
+    })
+}
+
+//Connects the current buffer to the language server for its project and
+//major mode, starting the server if it isn't running yet
+pub(crate) fn start(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let id = buffer.id();
+    if let Some(server) = editor.lsp().server_for(id) {
+        return Err(format!("Already connected to {}", server.name()));
+    }
+    let path = buffer
+        .file_path()
+        .ok_or_else(|| "Buffer isn't visiting a file".to_owned())?;
+    let root = project::project_root(path.parent().unwrap_or(path));
+    let major_mode = buffer.major_mode();
+    let (mode, config) = config_for(editor, major_mode)
+        .ok_or_else(|| format!("No language server for {major_mode}"))?;
+    let running = editor
+        .lsp()
+        .servers
+        .iter()
+        .find(|s| s.root == root && s.mode == mode && !s.shutting_down)
+        .map(|s| s.id);
+    if let Some(server) = running {
+        attach(editor, server, id);
+        let name = editor.lsp().server(server).map(|s| s.name().to_owned());
+        editor.message(format!("Connected to {}", name.unwrap_or_default()));
+        return Ok(());
+    }
+    let background = editor
+        .background()
+        .cloned()
+        .ok_or_else(|| "Language servers need the async runtime".to_owned())?;
+    let lsp = editor.lsp_mut();
+    let server = ServerId(lsp.next_server);
+    lsp.next_server += 1;
+    let spawned = LanguageServer::spawn(&background, server, config, root.clone(), mode)?;
+    editor.message(format!("Starting {} in {}", spawned.name(), root.display()));
+    let root_uri = path_to_uri(&root);
+    let name = root
+        .file_name()
+        .map_or_else(|| root_uri.clone(), |n| n.to_string_lossy().into_owned());
+    let params = json!({
+        "processId": std::process::id(),
+        "clientInfo": {"name": "bunmacs"},
+        "rootPath": root,
+        "rootUri": root_uri,
+        "workspaceFolders": [{"uri": root_uri, "name": name}],
+        "capabilities": {
+            "general": {"positionEncodings": ["utf-32", "utf-8", "utf-16"]},
+            "workspace": {
+                "applyEdit": true,
+                "configuration": true,
+                "workspaceFolders": true,
+                "workspaceEdit": {"documentChanges": true},
+            },
+            "textDocument": {
+                "synchronization": {"didSave": true},
+                "publishDiagnostics": {},
+                "hover": {"contentFormat": ["plaintext", "markdown"]},
+                "definition": {"linkSupport": true},
+                "references": {},
+                "rename": {},
+                "formatting": {},
+                "completion": {"completionItem": {"snippetSupport": false}},
+            },
+        },
+    });
+    let lsp = editor.lsp_mut();
+    lsp.servers.push(spawned);
+    let spawned = lsp.servers.last_mut().expect("just pushed");
+    spawned.request(
+        "initialize",
+        params,
+        Box::new(move |editor, result| initialized(editor, server, result)),
+    );
+    attach(editor, server, id);
+    Ok(())
+}
+
+fn initialized(editor: &mut Editor, id: ServerId, result: Result<Json, String>) {
+    let capabilities = match result {
+        Ok(result) => result["capabilities"].clone(),
+        Err(e) => {
+            let name = editor.lsp().server(id).map(|s| s.name().to_owned());
+            editor.message(format!("Couldn't start {}: {e}", name.unwrap_or_default()));
+            disconnect(editor, id);
+            return;
+        }
+    };
+    let Some(server) = editor.lsp_mut().server_mut(id) else {
+        return;
+    };
+    server.encoding = match capabilities["positionEncoding"].as_str() {
+        Some("utf-8") => Encoding::Utf8,
+        Some("utf-32") => Encoding::Utf32,
+        _ => Encoding::Utf16,
+    };
+    let sync = &capabilities["textDocumentSync"];
+    server.sync = match sync.as_i64().or_else(|| sync["change"].as_i64()) {
+        Some(0) => DocumentSync::None,
+        Some(2) => DocumentSync::Incremental,
+        _ => DocumentSync::Full,
+    };
+    server.capabilities = Some(capabilities);
+    server.notify("initialized", json!({}));
+    let (mode, documents): (String, Vec<(BufferId, String)>) = (
+        server.mode.clone(),
+        server
+            .documents
+            .iter()
+            .map(|(buffer, document)| (*buffer, document.uri.clone()))
+            .collect(),
+    );
+    for (buffer, uri) in documents {
+        let Some(buffer) = editor.buffer_mut(buffer) else {
+            continue;
+        };
+        //didOpen sends the whole text as it is now
+        buffer.take_changes();
+        let text = buffer.text().clone();
+        if let Some(server) = editor.lsp_mut().server_mut(id) {
+            server.did_open(&uri, &mode, &text);
+        }
+    }
+}
+
+//Tells server `id` about `buffer` and starts keeping track of its edits
+fn attach(editor: &mut Editor, id: ServerId, buffer: BufferId) {
+    let Some(buf) = editor.buffer_mut(buffer) else {
+        return;
+    };
+    let Some(path) = buf.file_path() else {
+        return;
+    };
+    let uri = path_to_uri(path);
+    buf.track_changes(true);
+    let text = buf.text().clone();
+    let Some(server) = editor.lsp_mut().server_mut(id) else {
+        return;
+    };
+    if server.capabilities.is_some() {
+        server.did_open(&uri, &server.mode, &text);
+    }
+    server.documents.insert(
+        buffer,
+        Document {
+            uri,
+            version: 0,
+            diagnostics: vec![],
+        },
+    );
+}
+
+//Connects a newly visited file to the server already running for its
+//project and major mode, if there is one
+pub(crate) fn buffer_visited(editor: &mut Editor, id: BufferId) {
+    let Some(buffer) = editor.buffer(id) else {
+        return;
+    };
+    let Some(path) = buffer.file_path() else {
+        return;
+    };
+    let root = project::project_root(path.parent().unwrap_or(path));
+    let Some((mode, _)) = config_for(editor, buffer.major_mode()) else {
+        return;
+    };
+    let running = editor
+        .lsp()
+        .servers
+        .iter()
+        .find(|s| s.root == root && s.mode == mode && !s.shutting_down)
+        .map(|s| s.id);
+    if let Some(server) = running {
+        if !editor.lsp().is_attached(id) {
+            attach(editor, server, id);
+        }
+    }
+}
+
+pub(crate) fn buffer_killed(editor: &mut Editor, id: BufferId) {
+    let lsp = editor.lsp_mut();
+    for server in &mut lsp.servers {
+        if let Some(document) = server.documents.remove(&id) {
+            if server.capabilities.is_some() {
+                server.notify(
+                    "textDocument/didClose",
+                    json!({"textDocument": {"uri": document.uri}}),
+                );
+            }
+        }
+    }
+    lsp.jumps.retain(|(buffer, _)| *buffer != id);
+}
+
+pub(crate) fn buffer_saved(editor: &mut Editor, id: BufferId) {
+    send_changes(editor);
+    let Some(server) = editor.lsp().server_for(id) else {
+        return;
+    };
+    let Some(capabilities) = &server.capabilities else {
+        return;
+    };
+    let save = &capabilities["textDocumentSync"]["save"];
+    if save.as_bool() == Some(true) || save.is_object() {
+        let uri = server.document_uri(id).unwrap_or_default();
+        server.notify(
+            "textDocument/didSave",
+            json!({"textDocument": {"uri": uri}}),
+        );
+    }
+}
+
+//Sends didChange for every edit to a connected buffer since last time
+pub(crate) fn send_changes(editor: &mut Editor) {
+    let documents: Vec<(ServerId, BufferId)> = editor
+        .lsp()
+        .servers
+        .iter()
+        .filter(|s| s.capabilities.is_some())
+        .flat_map(|s| s.documents.keys().map(move |buffer| (s.id, *buffer)))
+        .collect();
+    for (id, buffer) in documents {
+        let Some(buf) = editor.buffer_mut(buffer) else {
+            continue;
+        };
+        let changes = buf.take_changes();
+        if changes.is_empty() {
+            continue;
+        }
+        let text = buf.text().to_string();
+        let Some(server) = editor.lsp_mut().server_mut(id) else {
+            continue;
+        };
+        let encoding = server.encoding;
+        let content_changes = match server.sync {
+            DocumentSync::None => continue,
+            DocumentSync::Full => json!([{ "text": text }]),
+            DocumentSync::Incremental => Json::Array(
+                changes
+                    .iter()
+                    .map(|change| content_change(change, encoding))
+                    .collect(),
+            ),
+        };
+        let Some(document) = server.documents.get_mut(&buffer) else {
+            continue;
+        };
+        document.version += 1;
+        let params = json!({
+            "textDocument": {"uri": document.uri, "version": document.version},
+            "contentChanges": content_changes,
+        });
+        server.notify("textDocument/didChange", params);
+    }
+}
+
+fn content_change(change: &TextChange, encoding: Encoding) -> Json {
+    let position = |p: &TextPosition| json!({"line": p.line, "character": encoding.column(p)});
+    json!({
+        "range": {"start": position(&change.start), "end": position(&change.end)},
+        "text": change.text,
+    })
+}
+
+//Handles everything the servers sent since last time. True if there was
+//anything.
+pub(crate) fn process(editor: &mut Editor) -> bool {
+    let ids: Vec<ServerId> = editor.lsp().servers.iter().map(|s| s.id).collect();
+    let mut any = false;
+    for id in ids {
+        while let Some(server) = editor.lsp().server(id) {
+            let Some(incoming) = server.connection.try_recv() else {
+                break;
+            };
+            any = true;
+            match incoming {
+                Incoming::Message(message) => handle_message(editor, id, message),
+                Incoming::Closed => {
+                    closed(editor, id);
+                    break;
+                }
+            }
+        }
+    }
+    any
+}
+
+fn handle_message(editor: &mut Editor, id: ServerId, message: Json) {
+    let method = message["method"].as_str().map(str::to_owned);
+    match (method, message.get("id")) {
+        (Some(method), Some(request)) => {
+            let result = server_request(editor, id, &method, &message["params"]);
+            if let Some(server) = editor.lsp().server(id) {
+                server.respond(request.clone(), result);
+            }
+        }
+        (Some(method), None) => notification(editor, id, &method, &message["params"]),
+        (None, Some(request)) => {
+            let callback = request.as_i64().and_then(|request| {
+                editor
+                    .lsp_mut()
+                    .server_mut(id)
+                    .and_then(|s| s.pending.remove(&request))
+            });
+            let Some(callback) = callback else {
+                log::warn!("Response to a request we didn't make: {message}");
+                return;
+            };
+            let result = match message.get("error") {
+                Some(error) => Err(error["message"]
+                    .as_str()
+                    .unwrap_or("Unknown error")
+                    .to_owned()),
+                None => Ok(message.get("result").cloned().unwrap_or(Json::Null)),
+            };
+            callback(editor, result);
+        }
+        (None, None) => log::warn!("Not a JSON-RPC message: {message}"),
+    }
+}
+
+fn server_request(
+    editor: &mut Editor,
+    id: ServerId,
+    method: &str,
+    params: &Json,
+) -> Result<Json, (i64, String)> {
+    match method {
+        //No settings of our own, servers use their defaults
+        "workspace/configuration" => {
+            let items = params["items"].as_array().map_or(0, Vec::len);
+            Ok(Json::Array(vec![Json::Null; items]))
+        }
+        "workspace/workspaceFolders" => {
+            let root = editor.lsp().server(id).map(|s| s.root.clone());
+            Ok(match root {
+                Some(root) => {
+                    json!([{"uri": path_to_uri(&root), "name": root.display().to_string()}])
+                }
+                None => Json::Null,
+            })
+        }
+        "workspace/applyEdit" => Ok(match apply_workspace_edit(editor, id, &params["edit"]) {
+            Ok(_) => json!({"applied": true}),
+            Err(e) => json!({"applied": false, "failureReason": e}),
+        }),
+        "window/showMessageRequest" => {
+            show_message(editor, id, params);
+            Ok(Json::Null)
+        }
+        "client/registerCapability"
+        | "client/unregisterCapability"
+        | "window/workDoneProgress/create" => Ok(Json::Null),
+        _ => Err((METHOD_NOT_FOUND, format!("{method} isn't supported"))),
+    }
+}
+
+fn notification(editor: &mut Editor, id: ServerId, method: &str, params: &Json) {
+    match method {
+        "textDocument/publishDiagnostics" => publish_diagnostics(editor, id, params),
+        "window/showMessage" => show_message(editor, id, params),
+        "window/logMessage" => {
+            let name = editor.lsp().server(id).map(|s| s.name().to_owned());
+            log::info!(
+                "{}: {}",
+                name.unwrap_or_default(),
+                params["message"].as_str().unwrap_or_default()
+            );
+        }
+        _ => (),
+    }
+}
+
+fn show_message(editor: &mut Editor, id: ServerId, params: &Json) {
+    let name = editor.lsp().server(id).map(|s| s.name().to_owned());
+    let message = params["message"].as_str().unwrap_or_default();
+    editor.message(format!("[{}] {message}", name.unwrap_or_default()));
+}
+
+//Replaces the diagnostics of a document with the ones in `params`
+fn publish_diagnostics(editor: &mut Editor, id: ServerId, params: &Json) {
+    let Some(server) = editor.lsp().server(id) else {
+        return;
+    };
+    let uri = params["uri"].as_str().unwrap_or_default();
+    let Some(buffer) = server
+        .documents
+        .iter()
+        .find(|(_, d)| d.uri == uri)
+        .map(|(buffer, _)| *buffer)
+    else {
+        return;
+    };
+    let encoding = server.encoding;
+    let old = editor
+        .lsp_mut()
+        .server_mut(id)
+        .and_then(|s| s.documents.get_mut(&buffer))
+        .map(|d| std::mem::take(&mut d.diagnostics))
+        .unwrap_or_default();
+    let Some(buf) = editor.buffer_mut(buffer) else {
+        return;
+    };
+    for diagnostic in old {
+        buf.delete_overlay(diagnostic.overlay);
+    }
+    let mut diagnostics = vec![];
+    for diagnostic in params["diagnostics"].as_array().into_iter().flatten() {
+        let Some(mut range) = char_range(buf.text(), &diagnostic["range"], encoding) else {
+            continue;
+        };
+        //Something to underline even when it's about a spot
+        if range.is_empty() {
+            range.end = (range.start + 1).min(buf.len_chars());
+        }
+        let face = match diagnostic["severity"].as_i64() {
+            Some(2) => face::DIAGNOSTIC_WARNING,
+            Some(3 | 4) => face::DIAGNOSTIC_NOTE,
+            _ => face::DIAGNOSTIC_ERROR,
+        };
+        let message = diagnostic["message"].as_str().unwrap_or_default();
+        diagnostics.push(Diagnostic {
+            overlay: buf.make_overlay(range.start, range.end, face, 0),
+            message: match diagnostic["source"].as_str() {
+                Some(source) => format!("{source}: {message}"),
+                None => message.to_owned(),
+            },
+        });
+    }
+    if let Some(document) = editor
+        .lsp_mut()
+        .server_mut(id)
+        .and_then(|s| s.documents.get_mut(&buffer))
+    {
+        document.diagnostics = diagnostics;
+    }
+    editor.request_redraw();
+}
+
+//Forgets everything about server `id` and the buffers it was connected to
+fn disconnect(editor: &mut Editor, id: ServerId) -> Option<LanguageServer> {
+    let lsp = editor.lsp_mut();
+    let i = lsp.servers.iter().position(|s| s.id == id)?;
+    let server = lsp.servers.remove(i);
+    for (buffer, document) in &server.documents {
+        if let Some(buffer) = editor.buffer_mut(*buffer) {
+            buffer.track_changes(false);
+            for diagnostic in &document.diagnostics {
+                buffer.delete_overlay(diagnostic.overlay);
+            }
+        }
+    }
+    editor.request_redraw();
+    Some(server)
+}
+
+fn closed(editor: &mut Editor, id: ServerId) {
+    if let Some(server) = disconnect(editor, id) {
+        let name = server.name();
+        if server.shutting_down {
+            editor.message(format!("{name} shut down"));
+        } else {
+            editor.message(format!("{name} exited"));
+        }
+    }
+}
+
+//Asks the server for the current buffer to shut down
+pub(crate) fn shutdown(editor: &mut Editor) -> Result<(), String> {
+    let buffer = editor.current_buffer_id();
+    let id = editor
+        .lsp()
+        .server_for(buffer)
+        .map(|s| s.id)
+        .ok_or_else(|| "No language server for this buffer".to_owned())?;
+    let server = editor.lsp_mut().server_mut(id).expect("server");
+    server.shutting_down = true;
+    if server.capabilities.is_none() {
+        //Never got going, no point being polite
+        disconnect(editor, id);
+        return Ok(());
+    }
+    server.request(
+        "shutdown",
+        Json::Null,
+        Box::new(move |editor, _| {
+            if let Some(server) = editor.lsp().server(id) {
+                server.notify("exit", Json::Null);
+            }
+        }),
+    );
+    Ok(())
+}
+
+//The server for the current buffer, once it's ready for requests, and
+//where point is the way requests give a position
+fn text_document_position(editor: &mut Editor) -> Result<(ServerId, Json), String> {
+    send_changes(editor);
+    let buffer = editor.current_buffer();
+    let server = editor
+        .lsp()
+        .server_for(buffer.id())
+        .ok_or_else(|| "No language server for this buffer".to_owned())?;
+    if server.capabilities.is_none() {
+        return Err(format!("{} is still starting", server.name()));
+    }
+    let uri = server.document_uri(buffer.id()).unwrap_or_default();
+    let position = lsp_position(buffer.text(), buffer.point(), server.encoding);
+    Ok((
+        server.id,
+        json!({"textDocument": {"uri": uri}, "position": position}),
+    ))
+}
+
+fn request(
+    editor: &mut Editor,
+    id: ServerId,
+    method: &str,
+    params: Json,
+    callback: ResponseCallback,
+) {
+    send_changes(editor);
+    if let Some(server) = editor.lsp_mut().server_mut(id) {
+        server.request(method, params, callback);
+    }
+}
+
+fn server_encoding(editor: &Editor, id: ServerId) -> Encoding {
+    editor
+        .lsp()
+        .server(id)
+        .map_or(Encoding::Utf16, |s| s.encoding)
+}
+
+//A file and a position in it, from a Location or LocationLink
+type Location = (PathBuf, Json);
+
+//Locations from a definition or references response, which can be one
+//Location, a list of them, a list of LocationLinks or null
+fn locations(result: &Json) -> Vec<Location> {
+    let items = match result {
+        Json::Array(items) => items.iter().collect(),
+        Json::Null => vec![],
+        single => vec![single],
+    };
+    items
+        .into_iter()
+        .filter_map(|item| {
+            let (uri, range) = match item.get("targetUri") {
+                Some(uri) => (uri, &item["targetSelectionRange"]),
+                None => (&item["uri"], &item["range"]),
+            };
+            Some((uri_to_path(uri.as_str()?)?, range.clone()))
+        })
+        .collect()
+}
+
+pub(crate) fn find_definition(editor: &mut Editor) -> Result<(), String> {
+    let (id, params) = text_document_position(editor)?;
+    let from = (editor.current_buffer_id(), editor.current_buffer().point());
+    request(
+        editor,
+        id,
+        "textDocument/definition",
+        params,
+        Box::new(move |editor, result| match result.map(|r| locations(&r)) {
+            Err(e) => editor.message(e),
+            Ok(found) => match &found[..] {
+                [] => editor.message("No definition found"),
+                [(path, range)] => {
+                    let encoding = server_encoding(editor, id);
+                    if let Err(e) = visit_location(editor, path, range, encoding) {
+                        editor.message(e);
+                        return;
+                    }
+                    if let Some(buffer) = editor.buffer_mut(from.0) {
+                        let marker = buffer.make_marker(from.1);
+                        editor.lsp_mut().jumps.push((from.0, marker));
+                    }
+                }
+                _ => show_locations(editor, id, "Definitions", &found),
+            },
+        }),
+    );
+    Ok(())
+}
+
+//Goes back to where the last lsp-find-definition jumped from
+pub(crate) fn go_back(editor: &mut Editor) -> Result<(), String> {
+    while let Some((buffer, marker)) = editor.lsp_mut().jumps.pop() {
+        let Some(buf) = editor.buffer_mut(buffer) else {
+            continue;
+        };
+        let Some(point) = buf.marker(marker) else {
+            continue;
+        };
+        buf.delete_marker(marker);
+        buf.set_point(point);
+        editor.switch_to_buffer(buffer);
+        return Ok(());
+    }
+    Err("No previous location".to_owned())
+}
+
+pub(crate) fn find_references(editor: &mut Editor) -> Result<(), String> {
+    let (id, mut params) = text_document_position(editor)?;
+    params["context"] = json!({"includeDeclaration": true});
+    request(
+        editor,
+        id,
+        "textDocument/references",
+        params,
+        Box::new(move |editor, result| match result.map(|r| locations(&r)) {
+            Err(e) => editor.message(e),
+            Ok(found) if found.is_empty() => editor.message("No references found"),
+            Ok(found) => show_locations(editor, id, "References", &found),
+        }),
+    );
+    Ok(())
+}
+
+//Visits `path` in the selected window with point at the start of `range`
+fn visit_location(
+    editor: &mut Editor,
+    path: &Path,
+    range: &Json,
+    encoding: Encoding,
+) -> Result<(), String> {
+    let buffer = editor
+        .find_file(path)
+        .map_err(|e| format!("Could not visit {}: {e}", path.display()))?;
+    let buffer = editor.buffer_mut(buffer).expect("visited buffer");
+    if let Some(point) = char_index(buffer.text(), &range["start"], encoding) {
+        buffer.set_point(point);
+    }
+    Ok(())
+}
+
+//Lists `found` in the references buffer the way project-search lists
+//matches, so RET, n and p work the same
+fn show_locations(editor: &mut Editor, id: ServerId, title: &str, found: &[Location]) {
+    let Some(server) = editor.lsp().server(id) else {
+        return;
+    };
+    let (root, encoding) = (server.root.clone(), server.encoding);
+    let mut texts: HashMap<&Path, Option<Rope>> = HashMap::new();
+    let mut lines = vec![];
+    for (path, range) in found {
+        let text = texts.entry(path).or_insert_with(|| {
+            if let Some(buffer) = editor.buffers().find(|b| b.file_path() == Some(path)) {
+                return Some(buffer.text().clone());
+            }
+            let bytes = fs::read(path).ok()?;
+            Some(Rope::from(fileio::decode(&bytes).0))
+        });
+        let Some(text) = text else {
+            continue;
+        };
+        let Some(range) = char_range(text, range, encoding) else {
+            continue;
+        };
+        let line = text.char_to_line(range.start);
+        let line_start = text.line_to_char(line);
+        let line_text = text.slice(line_start..line_end(text, line));
+        let relative = path.strip_prefix(&root).unwrap_or(path);
+        let prefix = format!(
+            "{}:{}:{}:",
+            relative.to_string_lossy().replace('\\', "/"),
+            line + 1,
+            range.start - line_start + 1
+        );
+        let highlight = range.start - line_start
+            ..range.end.min(line_start + line_text.len_chars()) - line_start;
+        lines.push((prefix, line_text.to_string(), highlight));
+    }
+
+    let existing = editor.buffers().find(|b| b.name() == REFERENCES_BUFFER);
+    let buffer = match existing.map(|b| b.id()) {
+        Some(buffer) => buffer,
+        None => editor.create_buffer(REFERENCES_BUFFER),
+    };
+    let buf = editor.buffer_mut(buffer).expect("references buffer");
+    buf.delete(0, buf.len_chars());
+    buf.insert(0, &format!("{title} in {}\n\n", root.display()));
+    for (prefix, text, highlight) in lines {
+        let start = buf.len_chars() + prefix.chars().count();
+        buf.insert(buf.len_chars(), &format!("{prefix}{text}\n"));
+        buf.put_face(start + highlight.start, start + highlight.end, face::MATCH);
+    }
+    buf.set_point(0);
+    editor.switch_to_buffer(buffer);
+    if editor.current_buffer().major_mode() != REFERENCES_MODE {
+        if let Err(e) = editor.set_major_mode(REFERENCES_MODE) {
+            editor.message(e);
+        }
+    }
+    let root = Value::Str(root.to_string_lossy().into_owned());
+    if let Err(e) = editor.set_local("default-directory", root) {
+        editor.message(e);
+    }
+}
+
+pub(crate) fn hover(editor: &mut Editor) -> Result<(), String> {
+    let (id, params) = text_document_position(editor)?;
+    request(
+        editor,
+        id,
+        "textDocument/hover",
+        params,
+        Box::new(|editor, result| {
+            let text = match result {
+                Ok(result) => hover_text(&result["contents"]),
+                Err(e) => return editor.message(e),
+            };
+            let text = text.trim();
+            if text.is_empty() {
+                editor.message("No information at point");
+            } else if !text.contains('\n') {
+                editor.message(text);
+            } else {
+                let existing = editor.buffers().find(|b| b.name() == HOVER_BUFFER);
+                let buffer = match existing.map(|b| b.id()) {
+                    Some(buffer) => buffer,
+                    None => editor.create_buffer(HOVER_BUFFER),
+                };
+                let buf = editor.buffer_mut(buffer).expect("hover buffer");
+                buf.delete(0, buf.len_chars());
+                buf.insert(0, text);
+                buf.set_point(0);
+                if let Err(e) = editor.display_buffer_other_window(buffer, false) {
+                    editor.message(e);
+                }
+            }
+        }),
+    );
+    Ok(())
+}
+
+//Hover contents can be MarkupContent, a MarkedString or a list of those
+fn hover_text(contents: &Json) -> String {
+    match contents {
+        Json::String(text) => text.clone(),
+        Json::Array(items) => items
+            .iter()
+            .map(hover_text)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        Json::Object(object) => match object.get("value") {
+            Some(Json::String(value)) => value.clone(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+//The word at or just before point, as a char range
+pub(crate) fn symbol_at_point(editor: &Editor) -> Range<usize> {
+    let buffer = editor.current_buffer();
+    let text = buffer.text();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut start = buffer.point();
+    while start > 0 && is_word(text.char(start - 1)) {
+        start -= 1;
+    }
+    let mut end = buffer.point();
+    while end < text.len_chars() && is_word(text.char(end)) {
+        end += 1;
+    }
+    start..end
+}
+
+pub(crate) fn rename(editor: &mut Editor) -> Result<(), String> {
+    let (id, params) = text_document_position(editor)?;
+    let symbol = editor
+        .current_buffer()
+        .text()
+        .slice(symbol_at_point(editor))
+        .to_string();
+    editor.read_string(
+        format!("Rename {symbol} to: "),
+        &symbol.clone(),
+        Box::new(move |editor, answer| {
+            let Some(new_name) = answer.filter(|name| !name.is_empty()) else {
+                return;
+            };
+            let mut params = params;
+            params["newName"] = json!(new_name);
+            request(
+                editor,
+                id,
+                "textDocument/rename",
+                params,
+                Box::new(move |editor, result| {
+                    match result.and_then(|edit| apply_workspace_edit(editor, id, &edit)) {
+                        Ok(0) => editor.message("Nothing to rename"),
+                        Ok(files) => editor.message(format!(
+                            "Renamed {symbol} to {new_name} in {files} file{}",
+                            if files == 1 { "" } else { "s" }
+                        )),
+                        Err(e) => editor.message(e),
+                    }
+                }),
+            );
+        }),
+    );
+    Ok(())
+}
+
+//Makes the edits of a WorkspaceEdit, saving the files that didn't have
+//unsaved changes already. Returns how many files changed.
+fn apply_workspace_edit(editor: &mut Editor, id: ServerId, edit: &Json) -> Result<usize, String> {
+    let mut files: Vec<(&str, &[Json])> = vec![];
+    if let Some(changes) = edit["documentChanges"].as_array() {
+        for change in changes {
+            let (Some(uri), Some(edits)) = (
+                change["textDocument"]["uri"].as_str(),
+                change["edits"].as_array(),
+            ) else {
+                return Err(format!("Can't {} files", change["kind"]));
+            };
+            files.push((uri, edits));
+        }
+    } else if let Some(changes) = edit["changes"].as_object() {
+        for (uri, edits) in changes {
+            files.push((uri, edits.as_array().map_or(&[][..], Vec::as_slice)));
+        }
+    }
+    let encoding = server_encoding(editor, id);
+    let mut errors = vec![];
+    let mut changed = 0;
+    for (uri, edits) in files {
+        let path = uri_to_path(uri).ok_or_else(|| format!("Can't edit {uri}"))?;
+        let buffer = editor
+            .find_file_noselect(&path)
+            .map_err(|e| format!("Could not visit {}: {e}", path.display()))?;
+        let was_modified = editor.buffer(buffer).is_some_and(|b| b.is_modified());
+        if apply_text_edits(editor, buffer, edits, encoding, None) == 0 {
+            continue;
+        }
+        changed += 1;
+        if !was_modified {
+            if let Err(e) = editor.save_buffer(buffer) {
+                errors.push(format!("Saving {} failed: {e}", path.display()));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(changed)
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+//Makes a list of TextEdits to `buffer`, whose ranges are all from before
+//any of them. Point ends up after edit `select`'s text if given. Returns
+//how many edits there were.
+fn apply_text_edits(
+    editor: &mut Editor,
+    buffer: BufferId,
+    edits: &[Json],
+    encoding: Encoding,
+    select: Option<usize>,
+) -> usize {
+    let Some(buf) = editor.buffer(buffer) else {
+        return 0;
+    };
+    let mut edits: Vec<(usize, Range<usize>, &str)> = edits
+        .iter()
+        .enumerate()
+        .filter_map(|(i, edit)| {
+            let range = char_range(buf.text(), &edit["range"], encoding)?;
+            Some((i, range, edit["newText"].as_str()?))
+        })
+        .collect();
+    let count = edits.len();
+    //From the end so the ranges still to go stay put. Edits at the same
+    //spot go in in the order they came.
+    edits.reverse();
+    edits.sort_by_key(|(_, range, _)| std::cmp::Reverse(range.start));
+    editor.with_current_buffer(buffer, |editor| {
+        for (i, range, text) in edits {
+            let start = range.start;
+            editor.replace_region(range, text);
+            if select == Some(i) {
+                let point = start + text.chars().count();
+                editor.current_buffer_mut().set_point(point);
+            }
+        }
+    });
+    count
+}
+
+pub(crate) fn format_buffer(editor: &mut Editor) -> Result<(), String> {
+    let (id, params) = text_document_position(editor)?;
+    let buffer = editor.current_buffer_id();
+    let tab_size = match editor.variable("tab-width") {
+        Some(Value::Number(width)) => *width,
+        _ => 8,
+    };
+    let params = json!({
+        "textDocument": params["textDocument"],
+        "options": {"tabSize": tab_size, "insertSpaces": true},
+    });
+    request(
+        editor,
+        id,
+        "textDocument/formatting",
+        params,
+        Box::new(move |editor, result| {
+            let edits = match result {
+                Ok(Json::Array(edits)) => edits,
+                Ok(_) => vec![],
+                Err(e) => return editor.message(e),
+            };
+            let encoding = server_encoding(editor, id);
+            if apply_text_edits(editor, buffer, &edits, encoding, None) == 0 {
+                editor.message("Already formatted");
+            } else {
+                editor.message("Formatted");
+            }
+        }),
+    );
+    Ok(())
+}
+
+pub(crate) fn complete_at_point(editor: &mut Editor) -> Result<(), String> {
+    let (id, params) = text_document_position(editor)?;
+    let buffer = editor.current_buffer_id();
+    let point = editor.current_buffer().point();
+    let word = symbol_at_point(editor);
+    let prefix_start = word.start;
+    let prefix = editor
+        .current_buffer()
+        .text()
+        .slice(prefix_start..point)
+        .to_string();
+    request(
+        editor,
+        id,
+        "textDocument/completion",
+        params,
+        Box::new(move |editor, result| {
+            let items = match result {
+                Ok(Json::Array(items)) => items,
+                Ok(Json::Object(mut list)) => match list.remove("items") {
+                    Some(Json::Array(items)) => items,
+                    _ => vec![],
+                },
+                Ok(_) => vec![],
+                Err(e) => return editor.message(e),
+            };
+            //Typing on since makes the answer stale
+            if editor.current_buffer_id() != buffer || editor.current_buffer().point() != point {
+                return;
+            }
+            let labels: Vec<String> = items
+                .iter()
+                .filter_map(|item| Some(item["label"].as_str()?.to_owned()))
+                .collect();
+            let insert = move |editor: &mut Editor, item: &Json| {
+                let encoding = server_encoding(editor, id);
+                insert_completion(editor, buffer, prefix_start..point, item, encoding);
+            };
+            match items.len() {
+                0 => editor.message("No completions"),
+                1 => insert(editor, &items[0]),
+                _ => editor.completing_read(
+                    "Complete: ",
+                    labels,
+                    true,
+                    &prefix,
+                    Box::new(move |editor, answer| {
+                        let chosen = answer.and_then(|label| {
+                            items
+                                .iter()
+                                .find(|item| item["label"].as_str() == Some(&label))
+                        });
+                        if let Some(item) = chosen {
+                            insert(editor, item);
+                        }
+                    }),
+                ),
+            }
+        }),
+    );
+    Ok(())
+}
+
+//Puts a CompletionItem in, replacing the typed `prefix` unless the item
+//says what to replace
+fn insert_completion(
+    editor: &mut Editor,
+    buffer: BufferId,
+    prefix: Range<usize>,
+    item: &Json,
+    encoding: Encoding,
+) {
+    let Some(buf) = editor.buffer(buffer) else {
+        return;
+    };
+    let snippet = item["insertTextFormat"].as_i64() == Some(2);
+    let text_edit = &item["textEdit"];
+    let (range, text) = match text_edit["newText"].as_str() {
+        //InsertReplaceEdits have an insert and a replace range instead of
+        //a range, inserting is the usual default
+        Some(text) => {
+            let range = match text_edit.get("range") {
+                Some(range) => range.clone(),
+                None => text_edit["insert"].clone(),
+            };
+            (range, text)
+        }
+        None => {
+            let text = item["insertText"]
+                .as_str()
+                .or_else(|| item["label"].as_str())
+                .unwrap_or_default();
+            let range = json!({
+                "start": lsp_position(buf.text(), prefix.start, encoding),
+                "end": lsp_position(buf.text(), prefix.end, encoding),
+            });
+            (range, text)
+        }
+    };
+    let text = if snippet {
+        snippet_text(text)
+    } else {
+        text.to_owned()
+    };
+    let mut edits = vec![json!({"range": range, "newText": text})];
+    edits.extend(
+        item["additionalTextEdits"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    apply_text_edits(editor, buffer, &edits, encoding, Some(0));
+}
+
+//A snippet's text without its tab stops and placeholders: ${1:name}
+//becomes name, $0 and $1 go away
+fn snippet_text(snippet: &str) -> String {
+    let mut text = String::new();
+    let mut chars = snippet.chars().peekable();
+    //How many ${ we're inside
+    let mut placeholders = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.peek().is_some_and(char::is_ascii_digit) {
+                    chars.next();
+                }
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    chars.next();
+                }
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                }
+                placeholders += 1;
+            }
+            '}' if placeholders > 0 => placeholders -= 1,
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+//Moves point to the next diagnostic after it, or the one before it if
+//`forward` isn't set, and shows what it says
+pub(crate) fn next_diagnostic(editor: &mut Editor, forward: bool) -> Result<(), String> {
+    let buffer = editor.current_buffer();
+    let document = editor
+        .lsp()
+        .server_for(buffer.id())
+        .and_then(|s| s.documents.get(&buffer.id()))
+        .ok_or_else(|| "No language server for this buffer".to_owned())?;
+    let point = buffer.point();
+    let mut diagnostics: Vec<(usize, &str)> = document
+        .diagnostics
+        .iter()
+        .filter_map(|d| Some((buffer.overlay_range(d.overlay)?.start, d.message.as_str())))
+        .collect();
+    diagnostics.sort_by_key(|(start, _)| *start);
+    let found = if forward {
+        diagnostics.iter().find(|(start, _)| *start > point)
+    } else {
+        diagnostics.iter().rev().find(|(start, _)| *start < point)
+    };
+    let (start, message) = found.ok_or_else(|| "No more diagnostics".to_owned())?;
+    let (start, message) = (*start, message.to_string());
+    editor.current_buffer_mut().set_point(start);
+    editor.message(message);
+    Ok(())
+}
+
+//Where `pos` is as an LSP Position
+fn lsp_position(text: &Rope, pos: usize, encoding: Encoding) -> Json {
+    let position = buffer::text_position(text, pos);
+    json!({"line": position.line, "character": encoding.column(&position)})
+}
+
+//The char index of an LSP Position. Columns past the end of the line mean
+//its end, lines past the end of the text mean the end of that.
+fn char_index(text: &Rope, position: &Json, encoding: Encoding) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let column = position["character"].as_u64()? as usize;
+    if line >= text.len_lines() {
+        return Some(text.len_chars());
+    }
+    let start = text.line_to_char(line);
+    let end = line_end(text, line);
+    Some(match encoding {
+        Encoding::Utf32 => (start + column).min(end),
+        Encoding::Utf8 => {
+            let byte = (text.char_to_byte(start) + column).min(text.char_to_byte(end));
+            text.byte_to_char(byte)
+        }
+        Encoding::Utf16 => {
+            let unit = (text.char_to_utf16_cu(start) + column).min(text.char_to_utf16_cu(end));
+            text.utf16_cu_to_char(unit)
+        }
+    })
+}
+
+fn char_range(text: &Rope, range: &Json, encoding: Encoding) -> Option<Range<usize>> {
+    let start = char_index(text, &range["start"], encoding)?;
+    let end = char_index(text, &range["end"], encoding)?;
+    Some(start.min(end)..start.max(end))
+}
+
+//Where line `line` ends, before its line break
+fn line_end(text: &Rope, line: usize) -> usize {
+    let start = text.line_to_char(line);
+    let line_text = text.line(line);
+    let mut len = line_text.len_chars();
+    if len > 0 && line_text.char(len - 1) == '\n' {
+        len -= 1;
+    }
+    if len > 0 && line_text.char(len - 1) == '\r' {
+        len -= 1;
+    }
+    start + len
+}
+
+//file:// URIs, percent-encoding everything but the unreserved characters
+//and slashes
+pub fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    //Windows paths start with a drive letter
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~:".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (encoded[i], hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    //file:///C:/x is C:/x
+    if cfg!(windows) && path.as_bytes().get(2) == Some(&b':') {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::connection::fake::{self, Script, ID};
+    use tokio::runtime::Runtime;
+
+    //An editor visiting main.txt in a fresh directory, connected to a
+    //language server that follows `script`. Scripts wait for a shutdown
+    //at the end, so the server's exiting doesn't replace the message
+    //being checked.
+    fn visiting(name: &str, script: impl FnOnce(&str) -> Script) -> (Editor, Runtime) {
+        let dir = std::env::temp_dir().join(format!("bunmacs-lsp-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.txt");
+        fs::write(&path, "let x = 1;\n").unwrap();
+        let (command, args) = script(&path_to_uri(&path)).command();
+
+        let runtime = fake::runtime();
+        let mut editor = Editor::new();
+        editor.set_async_runtime(runtime.handle().clone(), None);
+        editor
+            .lsp_mut()
+            .set_server_config("text-mode", ServerConfig { command, args });
+        editor.find_file(&path).unwrap();
+        editor.run_command("lsp").unwrap();
+        assert!(editor.lsp().is_attached(editor.current_buffer_id()));
+        (editor, runtime)
+    }
+
+    fn initialize(capabilities: Json) -> Script {
+        Script::new("id")
+            .expect(&[
+                r#""method":"initialize""#,
+                r#""positionEncodings":["utf-32","utf-8","utf-16"]"#,
+            ])
+            .send(json!({"jsonrpc": "2.0", "id": ID, "result": {"capabilities": capabilities}}))
+            .expect(&[r#""method":"initialized""#])
+    }
+
+    fn started(editor: &mut Editor) {
+        fake::wait_for(editor, |editor| !editor.lsp().has_pending_requests());
+    }
+
+    #[test]
+    fn opens_documents_and_asks_about_them() {
+        let (mut editor, _runtime) = visiting("hover", |uri| {
+            initialize(json!({"positionEncoding": "utf-32"}))
+                .expect(&[
+                    r#""method":"textDocument/didOpen""#,
+                    &format!(r#""uri":"{uri}""#),
+                    r#""languageId":"text""#,
+                    r#""text":"let x = 1;\n""#,
+                ])
+                .expect(&[
+                    r#""method":"textDocument/hover""#,
+                    r#""position":{"character":4,"line":0}"#,
+                ])
+                //Servers can ask things of their own before answering
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "id": 99,
+                    "method": "workspace/configuration",
+                    "params": {"items": [{}, {}]},
+                }))
+                .expect(&[r#""id":99"#, r#""result":[null,null]"#])
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "result": {"contents": {"kind": "plaintext", "value": "x: i32"}},
+                }))
+                .expect(&[r#""method":"shutdown""#])
+        });
+        started(&mut editor);
+        editor.current_buffer_mut().set_point(4);
+        editor.run_command("lsp-hover").unwrap();
+        fake::wait_for(&mut editor, |editor| {
+            editor.current_message() == Some("x: i32")
+        });
+    }
+
+    #[test]
+    fn shows_diagnostics() {
+        let (mut editor, _runtime) = visiting("diagnostics", |uri| {
+            initialize(json!({}))
+                .expect(&[r#""method":"textDocument/didOpen""#])
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": [{
+                        "range": {
+                            "start": {"line": 0, "character": 4},
+                            "end": {"line": 0, "character": 5},
+                        },
+                        "severity": 2,
+                        "source": "fake",
+                        "message": "unused",
+                    }]},
+                }))
+                .expect(&[r#""method":"shutdown""#])
+        });
+        fake::wait_for(&mut editor, |editor| {
+            editor.run_command("lsp-next-diagnostic").is_ok()
+        });
+        assert_eq!(editor.current_buffer().point(), 4);
+        assert_eq!(editor.current_message(), Some("fake: unused"));
+        assert!(editor.run_command("lsp-next-diagnostic").is_err());
+    }
+
+    #[test]
+    fn reports_errors_and_shuts_down() {
+        let (mut editor, _runtime) = visiting("errors", |_| {
+            initialize(json!({}))
+                .expect(&[r#""method":"textDocument/hover""#])
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "id": ID,
+                    "error": {"code": -32603, "message": "no hover here"},
+                }))
+                .expect(&[r#""method":"shutdown""#])
+                .send(json!({"jsonrpc": "2.0", "id": ID, "result": null}))
+                .expect(&[r#""method":"exit""#])
+        });
+        started(&mut editor);
+        editor.run_command("lsp-hover").unwrap();
+        fake::wait_for(&mut editor, |editor| {
+            editor.current_message() == Some("no hover here")
+        });
+        editor.run_command("lsp-shutdown").unwrap();
+        fake::wait_for(&mut editor, |editor| {
+            editor.current_message() == Some("sh shut down")
+        });
+        assert!(!editor.lsp().is_attached(editor.current_buffer_id()));
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    fmt::{self, Debug},
+    ops::Range,
+};
+
+use crate::{
+    completion::{self, CompletionStyle, Match},
+    editor::Editor,
+    keymap::{Key, KeyCode},
+};
+
+//Called with the chosen character, or None if the user quit with C-g
+pub type ChoiceCallback = Box<dyn FnOnce(&mut Editor, Option<char>)>;
+//Called with what was entered, or None if the user quit with C-g
+pub type InputCallback = Box<dyn FnOnce(&mut Editor, Option<String>)>;
+//A callback with its answer filled in, ready to run
+pub(crate) type Answer = Box<dyn FnOnce(&mut Editor)>;
+
+enum Mode {
+    //A single key answers, like y-or-n-p
+    Choice {
+        choices: Vec<char>,
+        callback: ChoiceCallback,
+    },
+    //A line of text finished with RET
+    Input {
+        callback: InputCallback,
+    },
+}
+
+#[derive(Debug)]
+pub struct Completions {
+    candidates: Vec<String>,
+    styles: Vec<CompletionStyle>,
+    require_match: bool,
+    //Recomputed on every edit, best first
+    matches: Vec<Match>,
+    //Index into `matches`
+    selected: Option<usize>,
+}
+
+pub struct Minibuffer {
+    prompt: String,
+    input: String,
+    //Char index into `input`
+    cursor: usize,
+    completions: Option<Completions>,
+    mode: Mode,
+}
+
+impl Debug for Minibuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Minibuffer")
+            .field("prompt", &self.prompt)
+            .field("input", &self.input)
+            .field("cursor", &self.cursor)
+            .field("choices", &self.choices())
+            .field("completions", &self.completions)
+            .finish()
+    }
+}
+
+pub(crate) enum KeyResult {
+    Answered(Answer),
+    //Still waiting, maybe with something to tell the user
+    Pending(Minibuffer, Option<String>),
+}
+
+impl Completions {
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    pub fn candidate(&self, m: &Match) -> &str {
+        &self.candidates[m.index]
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    fn selected_candidate(&self) -> Option<&str> {
+        self.selected
+            .and_then(|i| self.matches.get(i))
+            .map(|m| self.candidate(m))
+    }
+
+    //Which matches to show in `rows` lines, scrolled so the selected one
+    //is on screen
+    pub fn visible(&self, rows: usize) -> Range<usize> {
+        let rows = rows.min(self.matches.len());
+        let start = match self.selected {
+            Some(selected) if selected >= rows => selected + 1 - rows,
+            _ => 0,
+        };
+        start..start + rows
+    }
+
+    fn update(&mut self, input: &str) {
+        self.matches = completion::complete(input, &self.candidates, &self.styles);
+        self.selected = (!self.matches.is_empty()).then_some(0);
+    }
+
+    fn select(&mut self, forward: bool) {
+        let len = self.matches.len();
+        if len == 0 {
+            return;
+        }
+        self.selected = Some(match (self.selected, forward) {
+            (Some(i), true) => (i + 1) % len,
+            (Some(i), false) => (i + len - 1) % len,
+            (None, true) => 0,
+            (None, false) => len - 1,
+        });
+    }
+}
+
+impl Minibuffer {
+    pub(crate) fn choice(prompt: String, choices: Vec<char>, callback: ChoiceCallback) -> Self {
+        Minibuffer {
+            prompt,
+            input: String::new(),
+            cursor: 0,
+            completions: None,
+            mode: Mode::Choice { choices, callback },
+        }
+    }
+
+    pub(crate) fn input(prompt: String, initial: &str, callback: InputCallback) -> Self {
+        Minibuffer {
+            prompt,
+            input: initial.to_owned(),
+            cursor: initial.chars().count(),
+            completions: None,
+            mode: Mode::Input { callback },
+        }
+    }
+
+    pub(crate) fn completing(
+        prompt: String,
+        initial: &str,
+        candidates: Vec<String>,
+        styles: Vec<CompletionStyle>,
+        require_match: bool,
+        callback: InputCallback,
+    ) -> Self {
+        let mut minibuffer = Minibuffer::input(prompt, initial, callback);
+        let mut completions = Completions {
+            candidates,
+            styles,
+            require_match,
+            matches: vec![],
+            selected: None,
+        };
+        completions.update(initial);
+        minibuffer.completions = Some(completions);
+        minibuffer
+    }
+
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.input
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn choices(&self) -> &[char] {
+        match &self.mode {
+            Mode::Choice { choices, .. } => choices,
+            Mode::Input { .. } => &[],
+        }
+    }
+
+    pub fn completions(&self) -> Option<&Completions> {
+        self.completions.as_ref()
+    }
+
+    //Answers with nothing, as if the user hit C-g
+    pub(crate) fn quit(self) -> Answer {
+        match self.mode {
+            Mode::Choice { callback, .. } => Box::new(move |editor| callback(editor, None)),
+            Mode::Input { callback } => Box::new(move |editor| callback(editor, None)),
+        }
+    }
+
+    pub(crate) fn handle_key(self, key: Key) -> KeyResult {
+        if key == Key::ctrl('g') || key == Key::plain(KeyCode::Escape) {
+            return KeyResult::Answered(self.quit());
+        }
+        if let Mode::Input { .. } = self.mode {
+            return self.handle_input_key(key);
+        }
+        match key.self_insert_char() {
+            Some(c) if self.choices().contains(&c) => {
+                let Mode::Choice { callback, .. } = self.mode else {
+                    unreachable!()
+                };
+                KeyResult::Answered(Box::new(move |editor| callback(editor, Some(c))))
+            }
+            _ => {
+                let choices = self
+                    .choices()
+                    .iter()
+                    .map(char::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                KeyResult::Pending(self, Some(format!("Please answer one of {choices}")))
+            }
+        }
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.input
+            .char_indices()
+            .nth(cursor)
+            .map(|(i, _)| i)
+            .unwrap_or(self.input.len())
+    }
+
+    fn set_input(&mut self, input: String) {
+        self.cursor = input.chars().count();
+        self.input = input;
+        if let Some(completions) = &mut self.completions {
+            completions.update(&self.input);
+        }
+    }
+
+    fn delete(&mut self, start: usize, end: usize) {
+        let range = self.byte_index(start)..self.byte_index(end);
+        self.input.replace_range(range, "");
+        self.cursor = start;
+        if let Some(completions) = &mut self.completions {
+            completions.update(&self.input);
+        }
+    }
+
+    fn handle_input_key(mut self, key: Key) -> KeyResult {
+        let len = self.input.chars().count();
+        let is = |keys: &[Key]| keys.contains(&key);
+        if is(&[Key::plain(KeyCode::Return)]) {
+            return self.accept();
+        } else if is(&[Key::plain(KeyCode::Tab)]) {
+            return self.complete();
+        } else if is(&[Key::ctrl('n'), Key::plain(KeyCode::Down)]) {
+            if let Some(completions) = &mut self.completions {
+                completions.select(true);
+            }
+        } else if is(&[Key::ctrl('p'), Key::plain(KeyCode::Up)]) {
+            if let Some(completions) = &mut self.completions {
+                completions.select(false);
+            }
+        } else if is(&[Key::plain(KeyCode::Backspace)]) {
+            if self.cursor > 0 {
+                self.delete(self.cursor - 1, self.cursor);
+            }
+        } else if is(&[Key::ctrl('d'), Key::plain(KeyCode::Delete)]) {
+            if self.cursor < len {
+                self.delete(self.cursor, self.cursor + 1);
+            }
+        } else if is(&[Key::ctrl('k')]) {
+            self.delete(self.cursor, len);
+        } else if is(&[Key::ctrl('f'), Key::plain(KeyCode::Right)]) {
+            self.cursor = (self.cursor + 1).min(len);
+        } else if is(&[Key::ctrl('b'), Key::plain(KeyCode::Left)]) {
+            self.cursor = self.cursor.saturating_sub(1);
+        } else if is(&[Key::ctrl('a'), Key::plain(KeyCode::Home)]) {
+            self.cursor = 0;
+        } else if is(&[Key::ctrl('e'), Key::plain(KeyCode::End)]) {
+            self.cursor = len;
+        } else if let Some(c) = key.self_insert_char() {
+            let at = self.byte_index(self.cursor);
+            self.input.insert(at, c);
+            self.cursor += 1;
+            if let Some(completions) = &mut self.completions {
+                completions.update(&self.input);
+            }
+        } else {
+            return KeyResult::Pending(self, Some(format!("{key} is undefined")));
+        }
+        KeyResult::Pending(self, None)
+    }
+
+    //RET: an exact match wins, then the selected candidate, then whatever
+    //was typed if that's allowed
+    fn accept(self) -> KeyResult {
+        let answer = match &self.completions {
+            Some(c) if c.candidates.contains(&self.input) => self.input.clone(),
+            Some(c) => match c.selected_candidate() {
+                Some(candidate) => candidate.to_owned(),
+                None if !c.require_match => self.input.clone(),
+                None => return KeyResult::Pending(self, Some("No match".to_owned())),
+            },
+            None => self.input.clone(),
+        };
+        let Mode::Input { callback } = self.mode else {
+            unreachable!("only input minibuffers take RET")
+        };
+        KeyResult::Answered(Box::new(move |editor| callback(editor, Some(answer))))
+    }
+
+    //TAB: fill in as much as every match has in common
+    fn complete(mut self) -> KeyResult {
+        let Some(c) = &self.completions else {
+            return KeyResult::Pending(self, None);
+        };
+        let matched: Vec<&str> = c.matches.iter().map(|m| c.candidate(m)).collect();
+        let completed = match matched[..] {
+            [] => return KeyResult::Pending(self, Some("No match".to_owned())),
+            [only] => Some(only.to_owned()),
+            _ => completion::try_completion(&self.input, &matched),
+        };
+        match completed {
+            Some(input) if input != self.input => {
+                self.set_input(input);
+                KeyResult::Pending(self, None)
+            }
+            Some(_) => KeyResult::Pending(self, Some("Sole completion".to_owned())),
+            None => KeyResult::Pending(self, None),
+        }
+    }
+}
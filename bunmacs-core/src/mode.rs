@@ -0,0 +1,321 @@
+//Major and minor modes. A buffer has exactly one major mode, picked from
+//its file name when it's visited, and any number of minor modes on top.
+//Modes are defined from bunlang, see modes.bl for the ones every editor
+//starts with.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::{buffer::Buffer, keymap::Keymap};
+
+pub const FUNDAMENTAL_MODE: &str = "fundamental-mode";
+
+//Mode definitions evaluated when the editor starts
+pub(crate) const BUILTIN_MODES: &str = include_str!("modes.bl");
+
+#[derive(Debug, Clone)]
+pub struct MajorMode {
+    pub name: String,
+    //What the mode line calls it, e.g. "Rust"
+    pub pretty_name: String,
+    //Modes inherit their parent's keymap, and the parent's body and hook run
+    //first
+    pub parent: Option<String>,
+    //Name of the SyntaxLanguage buffers in this mode are parsed as, the
+    //nearest ancestor's if None
+    pub syntax: Option<String>,
+    //bunlang source run in the buffer every time the mode is turned on
+    pub body: Vec<String>,
+    pub keymap: Keymap,
+}
+
+#[derive(Debug, Clone)]
+pub struct MinorMode {
+    pub name: String,
+    //Shown after the major mode in the mode line, usually with a leading
+    //space like " Fill"
+    pub lighter: String,
+    //Global modes are on or off everywhere instead of per buffer
+    pub global: bool,
+    //bunlang source run every time the mode is turned on or off
+    pub body: Vec<String>,
+    pub keymap: Keymap,
+}
+
+#[derive(Debug)]
+pub struct Modes {
+    major: HashMap<String, MajorMode>,
+    minor: HashMap<String, MinorMode>,
+    //File name patterns and the major mode they get. Later entries win,
+    //like add-to-list pushing onto the front of Emacs' auto-mode-alist.
+    auto_mode_alist: Vec<(Regex, String)>,
+    //Global minor modes that are on
+    global_minor_modes: Vec<String>,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Modes {
+    pub fn new() -> Self {
+        let mut modes = Modes {
+            major: HashMap::new(),
+            minor: HashMap::new(),
+            auto_mode_alist: vec![],
+            global_minor_modes: vec![],
+        };
+        modes.define_major(MajorMode {
+            name: FUNDAMENTAL_MODE.to_owned(),
+            pretty_name: "Fundamental".to_owned(),
+            parent: None,
+            syntax: None,
+            body: vec![],
+            keymap: Keymap::new(),
+        });
+        modes
+    }
+
+    //Redefining a mode keeps the bindings already in its keymap
+    pub fn define_major(&mut self, mut mode: MajorMode) {
+        if let Some(old) = self.major.remove(&mode.name) {
+            mode.keymap = old.keymap;
+        }
+        self.major.insert(mode.name.clone(), mode);
+    }
+
+    pub fn define_minor(&mut self, mut mode: MinorMode) {
+        if let Some(old) = self.minor.remove(&mode.name) {
+            mode.keymap = old.keymap;
+        }
+        self.minor.insert(mode.name.clone(), mode);
+    }
+
+    pub fn major(&self, name: &str) -> Option<&MajorMode> {
+        self.major.get(name)
+    }
+
+    pub fn minor(&self, name: &str) -> Option<&MinorMode> {
+        self.minor.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.major
+            .keys()
+            .chain(self.minor.keys())
+            .map(String::as_str)
+    }
+
+    //The keymap of the mode called `map` minus its -map suffix
+    pub fn keymap_mut(&mut self, map: &str) -> Option<&mut Keymap> {
+        let name = map.strip_suffix("-map")?;
+        match self.major.get_mut(name) {
+            Some(mode) => Some(&mut mode.keymap),
+            None => self.minor.get_mut(name).map(|mode| &mut mode.keymap),
+        }
+    }
+
+    //`name` and its ancestors, the root first
+    pub fn lineage(&self, name: &str) -> Result<Vec<&MajorMode>, String> {
+        let mut lineage = vec![];
+        let mut next = Some(name);
+        while let Some(name) = next {
+            let mode = self
+                .major
+                .get(name)
+                .ok_or_else(|| format!("No major mode named {name}"))?;
+            if lineage.iter().any(|m: &&MajorMode| m.name == name) {
+                return Err(format!("{name} is its own ancestor"));
+            }
+            lineage.push(mode);
+            next = mode.parent.as_deref();
+        }
+        lineage.reverse();
+        Ok(lineage)
+    }
+
+    pub fn add_auto_mode(&mut self, pattern: &str, mode: &str) -> Result<(), String> {
+        let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+        self.auto_mode_alist.push((pattern, mode.to_owned()));
+        Ok(())
+    }
+
+    //The major mode a file called `file_name` starts out in
+    pub fn mode_for_file(&self, file_name: &str) -> &str {
+        self.auto_mode_alist
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.is_match(file_name))
+            .map_or(FUNDAMENTAL_MODE, |(_, mode)| mode)
+    }
+
+    pub fn is_global_minor_mode_on(&self, name: &str) -> bool {
+        self.global_minor_modes.iter().any(|m| m == name)
+    }
+
+    pub(crate) fn global_minor_modes_mut(&mut self) -> &mut Vec<String> {
+        &mut self.global_minor_modes
+    }
+
+    //Minor modes on in `buffer`, its own first and then the global ones
+    fn minor_modes_in<'a>(&'a self, buffer: &'a Buffer) -> impl Iterator<Item = &'a MinorMode> {
+        buffer
+            .minor_modes()
+            .iter()
+            .chain(&self.global_minor_modes)
+            .filter_map(|name| self.minor.get(name))
+    }
+
+    //The keymaps keys in `buffer` go through before the global one, most
+    //specific first: minor modes, then the major mode and its ancestors
+    pub fn active_keymaps<'a>(&'a self, buffer: &'a Buffer) -> Vec<&'a Keymap> {
+        let mut keymaps: Vec<&Keymap> = self
+            .minor_modes_in(buffer)
+            .map(|mode| &mode.keymap)
+            .collect();
+        if let Ok(lineage) = self.lineage(buffer.major_mode()) {
+            keymaps.extend(lineage.iter().rev().map(|mode| &mode.keymap));
+        }
+        keymaps
+    }
+
+    pub fn mode_name<'a>(&'a self, buffer: &'a Buffer) -> &'a str {
+        self.major
+            .get(buffer.major_mode())
+            .map_or(buffer.major_mode(), |mode| &mode.pretty_name)
+    }
+
+    //What the mode line shows for `buffer`'s modes, e.g. "(Rust Fill)"
+    pub fn mode_line_modes(&self, buffer: &Buffer) -> String {
+        let lighters: String = self
+            .minor_modes_in(buffer)
+            .map(|mode| mode.lighter.as_str())
+            .collect();
+        format!("({}{lighters})", self.mode_name(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bunlang::Value;
+
+    use crate::{editor::Editor, keymap::Key, testing::editing};
+
+    const MODES: &str = r#"
+(define-major-mode base-mode "Base"
+  "(setq-default trail (list (symbol-value trail) base))")
+(define-major-mode child-mode "Child" :parent base-mode
+  "(setq-default trail (list (symbol-value trail) child))")
+(add-hook base-mode-hook "(setq-default trail (list (symbol-value trail) base-hook))")
+(add-hook child-mode-hook "(setq-default trail (list (symbol-value trail) child-hook))")
+(define-minor-mode keys-mode " Keys")
+"#;
+
+    fn with_modes(text: &str) -> Editor {
+        let (mut editor, _) = editing(text);
+        editor.set_default("trail", Value::List(vec![])).unwrap();
+        editor.eval_immediately(MODES).unwrap();
+        editor
+    }
+
+    //What ran since the last call, oldest first
+    fn trail(editor: &mut Editor) -> Vec<String> {
+        let mut trail = vec![];
+        let mut value = editor.variable("trail").cloned();
+        while let Some(Value::List(pair)) = value {
+            let [rest, Value::Symbol(step)] = &pair[..] else {
+                break;
+            };
+            trail.push(step.clone());
+            value = Some(rest.clone());
+        }
+        trail.reverse();
+        editor.set_default("trail", Value::List(vec![])).unwrap();
+        trail
+    }
+
+    #[test]
+    fn auto_mode_alist_picks_modes_by_file_name() {
+        let (mut editor, _) = editing("");
+        let modes = editor.modes();
+        assert_eq!(modes.mode_for_file("/src/main.rs"), "rust-mode");
+        assert_eq!(modes.mode_for_file("init.bl"), "bunlang-mode");
+        assert_eq!(modes.mode_for_file("README.markdown"), "markdown-mode");
+        assert_eq!(modes.mode_for_file("main.rs.orig"), super::FUNDAMENTAL_MODE);
+        assert_eq!(modes.mode_for_file("Makefile"), super::FUNDAMENTAL_MODE);
+
+        //Later patterns win over earlier ones
+        let modes = editor.modes_mut();
+        modes.add_auto_mode(r"/tests/.*\.rs$", "text-mode").unwrap();
+        assert_eq!(modes.mode_for_file("/tests/it.rs"), "text-mode");
+        assert_eq!(modes.mode_for_file("/src/it.rs"), "rust-mode");
+        assert!(modes.add_auto_mode("(", "text-mode").is_err());
+
+        //A buffer visiting no file stays fundamental
+        editor.set_auto_mode().unwrap();
+        assert_eq!(
+            editor.current_buffer().major_mode(),
+            super::FUNDAMENTAL_MODE
+        );
+    }
+
+    #[test]
+    fn derived_modes_run_every_body_before_any_hook() {
+        let mut editor = with_modes("");
+        editor.set_major_mode("child-mode").unwrap();
+        assert_eq!(
+            trail(&mut editor),
+            ["base", "child", "base-hook", "child-hook"]
+        );
+        editor.set_major_mode("base-mode").unwrap();
+        assert_eq!(trail(&mut editor), ["base", "base-hook"]);
+        assert_eq!(
+            editor.modes().mode_line_modes(editor.current_buffer()),
+            "(Base)"
+        );
+
+        assert!(editor.set_major_mode("no-such-mode").is_err());
+        assert_eq!(editor.current_buffer().major_mode(), "base-mode");
+        assert!(trail(&mut editor).is_empty());
+    }
+
+    #[test]
+    fn keymaps_go_minor_then_major_then_global() {
+        let mut editor = with_modes("one two");
+        editor
+            .eval_immediately(
+                r#"
+(define-key global-map "C-c k" beginning-of-line)
+(define-key base-mode-map "C-c k" forward-char)
+(define-key base-mode-map "C-c j" end-of-line)
+(define-key child-mode-map "C-c k" backward-char)
+(define-key keys-mode-map "C-c k" end-of-line)
+"#,
+            )
+            .unwrap();
+        let press = |editor: &mut Editor, keys: &str| {
+            editor.current_buffer_mut().set_point(3);
+            for key in Key::parse_sequence(keys).unwrap() {
+                editor.handle_key(key);
+            }
+            editor.current_buffer().point()
+        };
+        assert_eq!(press(&mut editor, "C-c k"), 0);
+        editor.set_major_mode("base-mode").unwrap();
+        assert_eq!(press(&mut editor, "C-c k"), 4);
+        //A derived mode's own bindings come before its parent's, which it
+        //still has
+        editor.set_major_mode("child-mode").unwrap();
+        assert_eq!(press(&mut editor, "C-c k"), 2);
+        assert_eq!(press(&mut editor, "C-c j"), 7);
+        editor.set_minor_mode("keys-mode", true).unwrap();
+        assert_eq!(press(&mut editor, "C-c k"), 7);
+        //Keys nobody else binds still reach the global map
+        assert_eq!(press(&mut editor, "C-f"), 4);
+        editor.set_minor_mode("keys-mode", false).unwrap();
+        assert_eq!(press(&mut editor, "C-c k"), 2);
+    }
+}
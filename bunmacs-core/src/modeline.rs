@@ -0,0 +1,300 @@
+use bunlang::{Host, Interpreter, Program, Value};
+
+use crate::{
+    buffer::Buffer,
+    face::{FaceSpans, Faces},
+    fileio::FileFormat,
+    mode::Modes,
+};
+
+//What `mode-line-format` starts out as. Every segment is a bunlang
+//expression evaluated for the window being drawn, so literal text has to be
+//a string literal.
+pub const DEFAULT_MODE_LINE: &[&str] = &[
+    "\" \"",
+    "(mode-line-modified)",
+    "\"  \"",
+    "(propertize (buffer-name) mode-line-buffer-id)",
+    "\"   \"",
+    "(mode-line-position)",
+    "\"  \"",
+    "(mode-line-modes)",
+    "\"  \"",
+    "(buffer-encoding)",
+];
+
+//One segment of the mode line, parsed when it's set rather than on every
+//redisplay
+#[derive(Debug)]
+struct Segment {
+    source: String,
+    program: Result<Program, String>,
+    //So a broken segment is only complained about once
+    warned: bool,
+}
+
+//The segments of `mode-line-format` and what evaluates them. Has its own
+//interpreter, separate from the editor's script engine so a script waiting
+//on the minibuffer doesn't block redisplay.
+#[derive(Debug)]
+pub struct ModeLineFormat {
+    interpreter: Interpreter,
+    segments: Vec<Segment>,
+}
+
+impl Default for ModeLineFormat {
+    fn default() -> Self {
+        let mut format = ModeLineFormat {
+            interpreter: Interpreter::new(),
+            segments: vec![],
+        };
+        format.set(DEFAULT_MODE_LINE.iter().map(|s| s.to_string()).collect());
+        format
+    }
+}
+
+impl ModeLineFormat {
+    //Replaces every segment with `sources`, each one bunlang source
+    pub fn set(&mut self, sources: Vec<String>) {
+        self.segments = sources
+            .into_iter()
+            .map(|source| {
+                let program = self.interpreter.parse(&source).map_err(|errs| {
+                    errs.iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                });
+                Segment {
+                    source,
+                    program,
+                    warned: false,
+                }
+            })
+            .collect();
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().map(|s| s.source.as_str())
+    }
+}
+
+//A stretch of mode line text drawn in one face
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub text: String,
+    pub face: Option<String>,
+}
+
+//Head of what `propertize` returns, same as Emacs' mode line construct
+const PROPERTIZE: &str = ":propertize";
+
+//What segments can ask about. Only looks at the window being drawn, so
+//segments can't change anything.
+struct ModeLineHost<'a> {
+    modes: &'a Modes,
+    buffer: &'a Buffer,
+    point: usize,
+    active: bool,
+}
+
+impl ModeLineHost<'_> {
+    fn line_and_column(&self) -> (usize, usize) {
+        let text = self.buffer.text();
+        let point = self.point.min(text.len_chars());
+        let line = text.char_to_line(point);
+        (line + 1, point - text.line_to_char(line))
+    }
+
+    fn format(&self) -> FileFormat {
+        self.buffer.file().map(|f| f.format).unwrap_or_default()
+    }
+}
+
+impl Host for ModeLineHost<'_> {
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Option<Result<Value, String>> {
+        let (line, column) = self.line_and_column();
+        Some(Ok(match name {
+            "buffer-name" => Value::Str(self.buffer.name().to_owned()),
+            "buffer-file-name" => match self.buffer.file_path() {
+                Some(path) => Value::Str(path.display().to_string()),
+                None => Value::Bool(false),
+            },
+            "buffer-modified-p" => Value::Bool(self.buffer.is_modified()),
+            "line-number-at-pos" => Value::Number(line as i64),
+            "current-column" => Value::Number(column as i64),
+            "mode-line-window-selected-p" => Value::Bool(self.active),
+            "mode-name" => Value::Str(self.modes.mode_name(self.buffer).to_owned()),
+            "major-mode" => Value::Symbol(self.buffer.major_mode().to_owned()),
+            "mode-line-modified" => Value::Str(
+                if self.buffer.is_modified() {
+                    "**"
+                } else {
+                    "--"
+                }
+                .to_owned(),
+            ),
+            "mode-line-position" => Value::Str(format!("L{line} C{column}")),
+            "mode-line-modes" => Value::Str(self.modes.mode_line_modes(self.buffer)),
+            "buffer-encoding" => {
+                let format = self.format();
+                Value::Str(format!(
+                    "{}-{}",
+                    format.encoding.name(),
+                    format.line_ending.name()
+                ))
+            }
+            "concat" => Value::Str(args.iter().map(plain_text).collect()),
+            "propertize" => match &args[..] {
+                [text, Value::Symbol(face) | Value::Str(face)] => Value::List(vec![
+                    Value::Symbol(PROPERTIZE.to_owned()),
+                    text.clone(),
+                    Value::Symbol(face.clone()),
+                ]),
+                _ => return Some(Err("propertize: expected TEXT and FACE".to_owned())),
+            },
+            _ => return None,
+        }))
+    }
+}
+
+fn plain_text(value: &Value) -> String {
+    match value {
+        Value::Str(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+//Flattens what a segment returned. Lists are concatenated and
+//`(:propertize TEXT FACE)` puts TEXT in FACE, an inner one wins over an
+//outer one. The empty list and #f show nothing.
+fn push_runs(value: &Value, face: Option<&str>, runs: &mut Vec<Run>) {
+    let mut push = |text: String| {
+        if !text.is_empty() {
+            runs.push(Run {
+                text,
+                face: face.map(str::to_owned),
+            })
+        }
+    };
+    match value {
+        Value::Str(s) => push(s.clone()),
+        Value::Number(n) => push(n.to_string()),
+        Value::Symbol(s) => push(s.clone()),
+        Value::Bool(true) => push("#t".to_owned()),
+        Value::Bool(false) => (),
+        Value::List(items) => match &items[..] {
+            [Value::Symbol(head), text, Value::Symbol(inner)] if head == PROPERTIZE => {
+                push_runs(text, Some(inner), runs)
+            }
+            _ => {
+                for item in items {
+                    push_runs(item, face, runs);
+                }
+            }
+        },
+    }
+}
+
+//Evaluates every segment of `format` for a window showing `buffer` with
+//point at `point`. A broken segment shows up as its error rather than
+//taking the whole mode line down.
+pub(crate) fn format_mode_line(
+    format: &mut ModeLineFormat,
+    modes: &Modes,
+    buffer: &Buffer,
+    point: usize,
+    active: bool,
+) -> Vec<Run> {
+    let mut host = ModeLineHost {
+        modes,
+        buffer,
+        point,
+        active,
+    };
+    let mut runs = vec![];
+    for segment in &mut format.segments {
+        let value = match &segment.program {
+            Ok(program) => format
+                .interpreter
+                .eval_program(program, &mut host)
+                .into_iter()
+                .try_fold(Value::List(vec![]), |_, result| result),
+            Err(e) => Err(e.clone()),
+        };
+        match value {
+            Ok(value) => push_runs(&value, None, &mut runs),
+            Err(e) => {
+                if !segment.warned {
+                    log::warn!("Mode line segment {} failed: {e}", segment.source);
+                    segment.warned = true;
+                }
+                push_runs(&Value::Str(format!("[{e}]")), Some("error"), &mut runs)
+            }
+        }
+    }
+    runs
+}
+
+//The text of `runs` and its faces, each run's face merged over `base`
+pub(crate) fn styled_text(runs: &[Run], faces: &Faces, base: &str) -> (String, FaceSpans) {
+    let mut text = String::new();
+    let mut spans = FaceSpans::new(faces.resolve(&[base]));
+    let mut start = 0;
+    for run in runs {
+        let len = run.text.chars().count();
+        text.push_str(&run.text);
+        if let Some(face) = &run.face {
+            spans
+                .spans
+                .push((start..start + len, faces.resolve(&[face, base])));
+        }
+        start += len;
+    }
+    (text, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferId;
+
+    fn text(runs: &[Run]) -> String {
+        runs.iter().map(|run| run.text.as_str()).collect()
+    }
+
+    #[test]
+    fn default_format_keeps_its_spacing() {
+        let mut buffer = Buffer::new(BufferId(0), "notes".to_owned());
+        buffer.insert(0, "one\ntwo");
+        let runs = format_mode_line(
+            &mut ModeLineFormat::default(),
+            &Modes::new(),
+            &buffer,
+            5,
+            true,
+        );
+        assert_eq!(text(&runs), " **  notes   L2 C1  (Fundamental)  utf-8-unix");
+        let name = runs.iter().find(|run| run.text == "notes").unwrap();
+        assert_eq!(name.face.as_deref(), Some("mode-line-buffer-id"));
+    }
+
+    #[test]
+    fn broken_segments_show_their_error() {
+        let buffer = Buffer::new(BufferId(0), "notes".to_owned());
+        let mut format = ModeLineFormat::default();
+        format.set(vec![
+            "\"[\"".to_owned(),
+            "(no-such-function)".to_owned(),
+            "(buffer-name".to_owned(),
+        ]);
+        for _ in 0..2 {
+            let runs = format_mode_line(&mut format, &Modes::new(), &buffer, 0, true);
+            assert_eq!(runs[0].text, "[");
+            assert_eq!(runs[1].text, "[Unknown op no-such-function]");
+            assert_eq!(runs[1].face.as_deref(), Some("error"));
+            assert_eq!(runs[2].text, "[1 unmatched opening delimiter]");
+        }
+        assert!(format.segments.iter().skip(1).all(|s| s.warned));
+    }
+}
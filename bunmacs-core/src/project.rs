@@ -0,0 +1,456 @@
+//Searching every file in a project. On the async runtime's blocking pool,
+//one task lists the files git wouldn't ignore and a worker per core
+//searches them in parallel, sending what they find back as they go so
+//the results buffer fills in while the search runs.
+
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    background::Background,
+    buffer::{Buffer, BufferId},
+    face, fileio,
+    gitignore::Gitignore,
+};
+use regex::Regex;
+
+pub const RESULTS_BUFFER: &str = "*project-search*";
+pub const RESULTS_MODE: &str = "project-search-mode";
+
+//The nearest directory from `dir` up that's the top of a git checkout, or
+//`dir` itself if it isn't in one
+pub fn project_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .unwrap_or(dir)
+        .to_owned()
+}
+
+#[derive(Debug)]
+struct LineMatch {
+    //Zero based, in chars
+    line: usize,
+    column: usize,
+    //How much of the line the match covers
+    len: usize,
+    text: String,
+}
+
+#[derive(Debug)]
+struct FileMatches {
+    //Relative to the project root
+    path: PathBuf,
+    matches: Vec<LineMatch>,
+}
+
+#[derive(Debug)]
+pub struct ProjectSearch {
+    buffer: BufferId,
+    root: PathBuf,
+    query: String,
+    case_fold: bool,
+    //None once every file has been searched
+    results: Option<Receiver<FileMatches>>,
+    //Tells the walker and workers to stop early
+    cancelled: Arc<AtomicBool>,
+    matches: usize,
+    files: usize,
+}
+
+impl ProjectSearch {
+    //Starts searching the files under `root` for regex `query`, listing
+    //matches in `buffer`
+    pub(crate) fn start(
+        background: &Background,
+        buffer: &mut Buffer,
+        root: PathBuf,
+        query: &str,
+        case_fold: bool,
+    ) -> Result<Self, String> {
+        let regex = crate::search::regex(query, case_fold)?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (paths, path_rx) = mpsc::sync_channel(256);
+        let walk_root = root.clone();
+        let walk_cancelled = cancelled.clone();
+        let runtime = background.runtime();
+        runtime.spawn_blocking(move || {
+            walk(&walk_root, &walk_cancelled, &mut |path| {
+                paths.send(path).is_ok()
+            })
+        });
+        let path_rx = Arc::new(Mutex::new(path_rx));
+        let (results, result_rx) = mpsc::channel();
+        let workers = thread::available_parallelism().map_or(4, |n| n.get());
+        for _ in 0..workers {
+            let worker = Worker {
+                root: root.clone(),
+                regex: regex.clone(),
+                paths: path_rx.clone(),
+                results: results.clone(),
+                cancelled: cancelled.clone(),
+                background: background.clone(),
+            };
+            runtime.spawn_blocking(move || worker.run());
+        }
+        buffer.delete(0, buffer.len_chars());
+        buffer.insert(0, &format!("Searching {} for {query}\n\n", root.display()));
+        buffer.set_point(0);
+        Ok(ProjectSearch {
+            buffer: buffer.id(),
+            root,
+            query: query.to_owned(),
+            case_fold,
+            results: Some(result_rx),
+            cancelled,
+            matches: 0,
+            files: 0,
+        })
+    }
+
+    pub fn buffer(&self) -> BufferId {
+        self.buffer
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn case_fold(&self) -> bool {
+        self.case_fold
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.results.is_some()
+    }
+
+    //Adds whatever the workers found since last time to the end of
+    //`buffer`. True if anything changed.
+    pub(crate) fn process(&mut self, buffer: &mut Buffer) -> bool {
+        let Some(results) = self.results.take() else {
+            return false;
+        };
+        let mut changed = false;
+        loop {
+            match results.try_recv() {
+                Ok(found) => {
+                    self.insert(buffer, &found);
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => {
+                    self.results = Some(results);
+                    return changed;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    let summary = self.summary();
+                    buffer.insert(buffer.len_chars(), &format!("\n{summary}\n"));
+                    return true;
+                }
+            }
+        }
+    }
+
+    //"3 matches in 2 files"
+    pub fn summary(&self) -> String {
+        let matches = if self.matches == 1 {
+            "match"
+        } else {
+            "matches"
+        };
+        let files = if self.files == 1 { "file" } else { "files" };
+        format!("{} {matches} in {} {files}", self.matches, self.files)
+    }
+
+    //One grep style line per match, path:line:column:text with the match
+    //in the `match` face
+    fn insert(&mut self, buffer: &mut Buffer, found: &FileMatches) {
+        let path = found.path.to_string_lossy().replace('\\', "/");
+        for m in &found.matches {
+            let prefix = format!("{path}:{}:{}:", m.line + 1, m.column + 1);
+            let start = buffer.len_chars();
+            buffer.insert(start, &format!("{prefix}{}\n", m.text));
+            let match_start = start + prefix.chars().count() + m.column;
+            buffer.put_face(match_start, match_start + m.len, face::MATCH);
+        }
+        self.matches += found.matches.len();
+        self.files += 1;
+    }
+}
+
+impl Drop for ProjectSearch {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+//Where a line of the results buffer points: the file relative to the
+//project root, and the zero based line and char column. Reads it back
+//out of the text so lines can be deleted to leave them out of a
+//replacement.
+pub fn parse_result_line(line: &str) -> Option<(PathBuf, usize, usize)> {
+    //The first :LINE:COLUMN: so paths can have colons in them, as long
+    //as they aren't followed by numbers
+    for (i, _) in line.match_indices(':') {
+        let mut fields = line[i + 1..].splitn(3, ':');
+        let (Some(row), Some(column), Some(_)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return None;
+        };
+        if let (Ok(row), Ok(column)) = (row.parse::<usize>(), column.parse::<usize>()) {
+            if i > 0 && row > 0 && column > 0 {
+                return Some((PathBuf::from(&line[..i]), row - 1, column - 1));
+            }
+        }
+    }
+    None
+}
+
+//Calls `found` with every file under `root` that isn't ignored, until it
+//returns false or the search is cancelled
+fn walk(root: &Path, cancelled: &AtomicBool, found: &mut dyn FnMut(PathBuf) -> bool) {
+    let mut ignores = vec![];
+    let exclude = Gitignore::from_file(&root.join(".git/info/exclude"));
+    if !exclude.is_empty() {
+        ignores.push((root.to_owned(), exclude));
+    }
+    walk_dir(root, &mut ignores, cancelled, found);
+}
+
+//False once walking should stop
+fn walk_dir(
+    dir: &Path,
+    ignores: &mut Vec<(PathBuf, Gitignore)>,
+    cancelled: &AtomicBool,
+    found: &mut dyn FnMut(PathBuf) -> bool,
+) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return true;
+    };
+    let gitignore = Gitignore::from_file(&dir.join(".gitignore"));
+    let pushed = !gitignore.is_empty();
+    if pushed {
+        ignores.push((dir.to_owned(), gitignore));
+    }
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    let mut going = true;
+    for entry in entries {
+        if cancelled.load(Ordering::Relaxed) {
+            going = false;
+            break;
+        }
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        //Symlinks aren't followed, they could go round in circles
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if is_ignored(ignores, &path, file_type.is_dir()) {
+            continue;
+        }
+        going = if file_type.is_dir() {
+            walk_dir(&path, ignores, cancelled, found)
+        } else if file_type.is_file() {
+            found(path)
+        } else {
+            true
+        };
+        if !going {
+            break;
+        }
+    }
+    if pushed {
+        ignores.pop();
+    }
+    going
+}
+
+//What the closest .gitignore with anything to say about `path` says
+fn is_ignored(ignores: &[(PathBuf, Gitignore)], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|(base, gitignore)| {
+            let relative = path.strip_prefix(base).ok()?;
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            gitignore.matched(&relative, is_dir)
+        })
+        .unwrap_or(false)
+}
+
+struct Worker {
+    root: PathBuf,
+    regex: Regex,
+    paths: Arc<Mutex<Receiver<PathBuf>>>,
+    results: Sender<FileMatches>,
+    cancelled: Arc<AtomicBool>,
+    background: Background,
+}
+
+impl Worker {
+    fn run(self) {
+        self.search();
+        //The editor notices the search is done once every worker has
+        //dropped its sender, so wake it after
+        let background = self.background.clone();
+        drop(self);
+        background.wake();
+    }
+
+    fn search(&self) {
+        loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let next = self.paths.lock().map(|paths| paths.recv());
+            let Ok(Ok(path)) = next else {
+                return;
+            };
+            let matches = search_file(&self.regex, &path);
+            if matches.is_empty() {
+                continue;
+            }
+            let path = path.strip_prefix(&self.root).unwrap_or(&path).to_owned();
+            if self.results.send(FileMatches { path, matches }).is_err() {
+                return;
+            }
+            self.background.wake();
+        }
+    }
+}
+
+//Files bigger than this aren't searched, they're hardly ever source
+const MAX_SEARCH_SIZE: u64 = 16 * 1024 * 1024;
+
+//Every match in the file at `path`. Plain UTF-8 files are searched a line
+//at a time as they're read, anything else is decoded whole the way
+//visiting it would. Files that can't be read, look binary or are too big
+//have none.
+fn search_file(regex: &Regex, path: &Path) -> Vec<LineMatch> {
+    let Ok(file) = File::open(path) else {
+        return vec![];
+    };
+    if file.metadata().map_or(true, |m| m.len() > MAX_SEARCH_SIZE) {
+        return vec![];
+    }
+    let mut reader = BufReader::new(file);
+    let mut matches = vec![];
+    let mut bytes = vec![];
+    for line in 0.. {
+        bytes.clear();
+        match reader.read_until(b'\n', &mut bytes) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) if !text.contains('\0') => text,
+            //Some other encoding, or binary
+            _ => return search_decoded(regex, path),
+        };
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let text = if line == 0 {
+            text.strip_prefix('\u{feff}').unwrap_or(text)
+        } else {
+            text
+        };
+        search_line(regex, line, text, &mut matches);
+    }
+    matches
+}
+
+fn search_decoded(regex: &Regex, path: &Path) -> Vec<LineMatch> {
+    let Ok(bytes) = fs::read(path) else {
+        return vec![];
+    };
+    let (text, _) = fileio::decode(&bytes);
+    if text.contains('\0') {
+        return vec![];
+    }
+    let mut matches = vec![];
+    for (line, text) in text.lines().enumerate() {
+        search_line(regex, line, text, &mut matches);
+    }
+    matches
+}
+
+fn search_line(regex: &Regex, line: usize, text: &str, matches: &mut Vec<LineMatch>) {
+    //Patterns like `^` or `x*` match nothing all over the place, which
+    //isn't worth listing
+    for m in regex.find_iter(text).filter(|m| !m.is_empty()) {
+        matches.push(LineMatch {
+            line,
+            column: text[..m.start()].chars().count(),
+            len: m.as_str().chars().count(),
+            text: text.to_owned(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(name: &str, contents: &[u8], pattern: &str) -> Vec<(usize, usize, usize, String)> {
+        let dir = std::env::temp_dir().join(format!("bunmacs-search-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let matches = search_file(&Regex::new(pattern).unwrap(), &path);
+        fs::remove_file(&path).unwrap();
+        matches
+            .into_iter()
+            .map(|m| (m.line, m.column, m.len, m.text))
+            .collect()
+    }
+
+    #[test]
+    fn finds_every_match_on_each_line() {
+        let matches = search("lines.txt", "ab ab\r\nnone\r\néab\r\n".as_bytes(), "ab");
+        assert_eq!(
+            matches,
+            [
+                (0, 0, 2, "ab ab".to_owned()),
+                (0, 3, 2, "ab ab".to_owned()),
+                (2, 1, 2, "éab".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_empty_matches() {
+        assert_eq!(search("empty.txt", b"one\ntwo\n", "x*"), []);
+        assert_eq!(search("start.txt", b"one\ntwo\n", "^t?").len(), 1);
+    }
+
+    #[test]
+    fn skips_binary_files() {
+        assert_eq!(search("binary.bin", b"match\0\xff\xfe", "match"), []);
+    }
+
+    #[test]
+    fn decodes_other_encodings() {
+        let utf16: Vec<u8> = "\u{feff}no\nmatch"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(
+            search("utf16.txt", &utf16, "at"),
+            [(1, 1, 2, "match".to_owned())]
+        );
+    }
+}
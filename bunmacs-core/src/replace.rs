@@ -0,0 +1,244 @@
+//query-replace, going through the matches after point and asking about
+//each one. Editor::query_replace drives it through the minibuffer.
+
+use std::ops::Range;
+
+use regex::Regex;
+use ropey::Rope;
+
+use crate::{
+    buffer::{Buffer, BufferId, MarkerId},
+    face,
+    search::{self, SearchPattern},
+};
+
+#[derive(Debug)]
+pub struct QueryReplace {
+    buffer: BufferId,
+    from: String,
+    to: String,
+    pattern: SearchPattern,
+    //The same pattern for filling in $1 and the like, None when replacing
+    //literally
+    regex: Option<Regex>,
+    //Where the next search starts
+    position: usize,
+    //The end of the region being replaced in, None for the whole rest of
+    //the buffer
+    end: Option<MarkerId>,
+    //The match being asked about
+    current: Option<Range<usize>>,
+    //Set by !, the rest get replaced without asking
+    pub(crate) replace_all: bool,
+    replaced: usize,
+}
+
+impl QueryReplace {
+    //Replaces in the active region if there is one, otherwise from point on
+    pub(crate) fn new(
+        buffer: &mut Buffer,
+        from: &str,
+        to: &str,
+        regex: bool,
+        case_fold_search: bool,
+    ) -> Result<Self, String> {
+        let case_fold = search::case_fold(from, regex, case_fold_search);
+        let pattern = SearchPattern::new(from, regex, case_fold)?;
+        let regex = if regex {
+            Some(search::regex(from, case_fold)?)
+        } else {
+            None
+        };
+        let (position, end) = match buffer.region() {
+            Some((start, end)) => (start, Some(buffer.make_marker(end))),
+            None => (buffer.point(), None),
+        };
+        buffer.deactivate_mark();
+        Ok(QueryReplace {
+            buffer: buffer.id(),
+            from: from.to_owned(),
+            to: to.to_owned(),
+            pattern,
+            regex,
+            position,
+            end,
+            current: None,
+            replace_all: false,
+            replaced: 0,
+        })
+    }
+
+    pub fn buffer(&self) -> BufferId {
+        self.buffer
+    }
+
+    pub fn current(&self) -> Option<Range<usize>> {
+        self.current.clone()
+    }
+
+    pub fn replaced(&self) -> usize {
+        self.replaced
+    }
+
+    //"Query replacing foo with bar: "
+    pub(crate) fn prompt(&self) -> String {
+        let kind = if self.regex.is_some() { " regexp" } else { "" };
+        format!(
+            "Query replacing{kind} {} with {}: (y, n, !, . or q) ",
+            self.from, self.to
+        )
+    }
+
+    fn limit(&self, buffer: &Buffer) -> usize {
+        self.end
+            .and_then(|end| buffer.marker(end))
+            .unwrap_or(buffer.len_chars())
+    }
+
+    //Finds the next match to ask about
+    pub(crate) fn next_match(&mut self, buffer: &Buffer) -> Result<Option<Range<usize>>, String> {
+        let limit = self.limit(buffer);
+        self.current = self
+            .pattern
+            .find_forward(buffer.text(), self.position, limit)?;
+        Ok(self.current.clone())
+    }
+
+    //What the current match gets replaced with. Regex replacements fill in
+    //$1, ${name} and $0 from the match.
+    pub(crate) fn replacement(&self, buffer: &Buffer) -> String {
+        match (&self.regex, &self.current) {
+            (Some(regex), Some(current)) => search::expand(regex, buffer.text(), current, &self.to),
+            _ => self.to.clone(),
+        }
+    }
+
+    //Moves past the current match without replacing it
+    pub(crate) fn skip(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.position = current.end + current.is_empty() as usize;
+        }
+    }
+
+    //The current match was replaced by text ending at `end`
+    pub(crate) fn did_replace(&mut self, end: usize) {
+        if let Some(current) = self.current.take() {
+            self.position = end + current.is_empty() as usize;
+            self.replaced += 1;
+        }
+    }
+
+    pub(crate) fn finish(self, buffer: &mut Buffer) -> usize {
+        if let Some(end) = self.end {
+            buffer.delete_marker(end);
+        }
+        self.replaced
+    }
+
+    //Faces for the matches in `range` of `text` still to come: the one
+    //being asked about in `query-replace` and the others in
+    //`lazy-highlight` if `lazy`
+    pub(crate) fn highlights(
+        &mut self,
+        text: &Rope,
+        range: Range<usize>,
+        lazy: bool,
+    ) -> Vec<(Range<usize>, &'static str)> {
+        let mut highlights = vec![];
+        if lazy {
+            let range = range.start.max(self.position)..range.end;
+            let matches = self.pattern.find_all(text, range).unwrap_or_default();
+            highlights.extend(
+                matches
+                    .into_iter()
+                    .filter(|m| !m.is_empty() && Some(m) != self.current.as_ref())
+                    .map(|m| (m, face::LAZY_HIGHLIGHT)),
+            );
+        }
+        highlights.extend(
+            self.current
+                .clone()
+                .map(|current| (current, face::QUERY_REPLACE)),
+        );
+        highlights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{editor::Editor, keymap::Key, testing::editing};
+
+    //Replaces `from` in `text` from its start, answering `answers`
+    fn replacing(from: &str, to: &str, regex: bool, text: &str, answers: &str) -> Editor {
+        let (mut editor, _) = editing(text);
+        editor.current_buffer_mut().set_point(0);
+        editor.query_replace(from, to, regex).unwrap();
+        for answer in answers.chars() {
+            editor.handle_key(Key::parse(&answer.to_string()).unwrap());
+        }
+        editor
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.current_buffer().text().to_string()
+    }
+
+    const TEXT: &str = "foo foo foo foo";
+
+    #[test]
+    fn y_replaces_and_n_skips() {
+        let editor = replacing("foo", "bar", false, TEXT, "ynyn");
+        assert_eq!(text(&editor), "bar foo bar foo");
+        assert_eq!(editor.current_message(), Some("Replaced 2 occurrences"));
+        assert!(editor.minibuffer().is_none());
+    }
+
+    #[test]
+    fn asks_about_each_match_in_turn() {
+        let editor = replacing("foo", "bar", false, TEXT, "n");
+        assert_eq!(text(&editor), TEXT);
+        //Point's at the end of the match being asked about
+        assert_eq!(editor.current_buffer().point(), 7);
+        assert!(editor.minibuffer().is_some());
+    }
+
+    #[test]
+    fn bang_replaces_the_rest() {
+        let editor = replacing("foo", "bar", false, TEXT, "n!");
+        assert_eq!(text(&editor), "foo bar bar bar");
+        assert_eq!(editor.current_message(), Some("Replaced 3 occurrences"));
+    }
+
+    #[test]
+    fn dot_replaces_one_and_stops() {
+        let editor = replacing("foo", "bar", false, TEXT, "n.");
+        assert_eq!(text(&editor), "foo bar foo foo");
+        assert_eq!(editor.current_message(), Some("Replaced 1 occurrence"));
+        assert!(editor.minibuffer().is_none());
+    }
+
+    #[test]
+    fn q_stops_without_replacing() {
+        let editor = replacing("foo", "bar", false, TEXT, "yq");
+        assert_eq!(text(&editor), "bar foo foo foo");
+        assert_eq!(editor.current_message(), Some("Replaced 1 occurrence"));
+    }
+
+    #[test]
+    fn regexes_fill_in_groups() {
+        let editor = replacing(r"(\w+)=(\d+)", "$2=$1", true, "a=1 b=x c=3", "!");
+        assert_eq!(text(&editor), "1=a b=x 3=c");
+        //Literal replacements leave $ alone
+        let editor = replacing("a", "$1", false, "a", "y");
+        assert_eq!(text(&editor), "$1");
+    }
+
+    #[test]
+    fn replacements_matching_again_are_not_replaced() {
+        let editor = replacing("o", "oo", false, "foo", "!");
+        assert_eq!(text(&editor), "foooo");
+        //Empty matches move on by a char each time
+        let editor = replacing("^", "> ", true, "a\nb", "!");
+        assert_eq!(text(&editor), "> a\n> b");
+    }
+}
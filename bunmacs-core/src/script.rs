@@ -0,0 +1,909 @@
+use std::{
+    fmt::{self, Debug},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use bunlang::{Host, Interpreter, Value};
+
+use crate::{
+    dap::{self, AdapterConfig, LaunchConfig, Request},
+    editor::Editor,
+    face::{self, Face, Slant, Weight},
+    hook::TimerId,
+    keymap::{Key, Keymap},
+    lsp::{self, ServerConfig},
+    mode::{MajorMode, MinorMode},
+    syntax::SyntaxLanguage,
+    variable::{VariableType, Watcher},
+};
+
+//Called with the value of the last expression, or the first error
+pub type ScriptCallback = Box<dyn FnOnce(&mut Editor, Result<Value, String>)>;
+
+//What a host function call comes back with, None if there's no such function
+pub(crate) type Reply = Option<Result<Value, String>>;
+
+pub(crate) enum Event {
+    Call { name: String, args: Vec<Value> },
+    Done(Result<Value, String>),
+}
+
+//bunlang runs on its own thread so a script can stop halfway to wait on the
+//minibuffer. Every host call is a round trip to the editor thread, which
+//waits while the script runs, so only one side is ever busy.
+pub(crate) struct ScriptEngine {
+    jobs: Sender<String>,
+    replies: Sender<Reply>,
+    events: Receiver<Event>,
+    //Set while a script is running or waiting on the minibuffer
+    done: Option<ScriptCallback>,
+}
+
+impl Debug for ScriptEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptEngine")
+            .field("busy", &self.is_busy())
+            .finish()
+    }
+}
+
+struct ChannelHost {
+    events: Sender<Event>,
+    replies: Receiver<Reply>,
+}
+
+impl Host for ChannelHost {
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Option<Result<Value, String>> {
+        let call = Event::Call {
+            name: name.to_owned(),
+            args,
+        };
+        if self.events.send(call).is_err() {
+            return Some(Err("The editor went away".to_owned()));
+        }
+        self.replies
+            .recv()
+            .unwrap_or_else(|_| Some(Err("The editor went away".to_owned())))
+    }
+}
+
+//The value of the last expression in `source`, or the first error
+pub(crate) fn eval(
+    interpreter: &mut Interpreter,
+    source: &str,
+    host: &mut dyn Host,
+) -> Result<Value, String> {
+    match interpreter.eval_str(source, host) {
+        Ok(results) => results
+            .into_iter()
+            .try_fold(Value::List(vec![]), |_, result| result),
+        Err(errs) => Err(errs
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")),
+    }
+}
+
+//Answers calls on the spot, for code run in the middle of something else
+//that can't wait on the minibuffer
+struct ImmediateHost<'a> {
+    editor: &'a mut Editor,
+}
+
+impl Host for ImmediateHost<'_> {
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Option<Result<Value, String>> {
+        if parse_prompt(name, &args).is_some() {
+            return Some(Err(format!("{name}: can't ask for input here")));
+        }
+        call_builtin(self.editor, name, args)
+    }
+}
+
+pub(crate) fn eval_immediately(editor: &mut Editor, source: &str) -> Result<Value, String> {
+    eval(
+        &mut Interpreter::new(),
+        source,
+        &mut ImmediateHost { editor },
+    )
+}
+
+fn run(jobs: Receiver<String>, events: Sender<Event>, replies: Receiver<Reply>) {
+    let mut interpreter = Interpreter::new();
+    let mut host = ChannelHost {
+        events: events.clone(),
+        replies,
+    };
+    for source in jobs {
+        let result = eval(&mut interpreter, &source, &mut host);
+        if events.send(Event::Done(result)).is_err() {
+            break;
+        }
+    }
+}
+
+impl ScriptEngine {
+    pub(crate) fn new() -> Self {
+        let (jobs, job_rx) = mpsc::channel();
+        let (replies, reply_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        thread::Builder::new()
+            .name("bunlang".to_owned())
+            .spawn(move || run(job_rx, event_tx, reply_rx))
+            .expect("couldn't start the bunlang thread");
+        ScriptEngine {
+            jobs,
+            replies,
+            events,
+            done: None,
+        }
+    }
+
+    pub(crate) fn is_busy(&self) -> bool {
+        self.done.is_some()
+    }
+
+    pub(crate) fn start(&mut self, source: String, done: ScriptCallback) {
+        self.done = Some(done);
+        if self.jobs.send(source).is_err() {
+            log::error!("The bunlang thread is gone");
+        }
+    }
+
+    //Blocks until the script calls into the editor or finishes
+    pub(crate) fn next_event(&self) -> Event {
+        self.events
+            .recv()
+            .unwrap_or_else(|_| Event::Done(Err("The bunlang thread died".to_owned())))
+    }
+
+    pub(crate) fn reply(&self, reply: Reply) {
+        //If the thread died next_event says so
+        let _ = self.replies.send(reply);
+    }
+
+    pub(crate) fn finish(&mut self) -> Option<ScriptCallback> {
+        self.done.take()
+    }
+}
+
+//A script asking for input, answered through the minibuffer
+pub(crate) enum Prompt {
+    ReadString {
+        prompt: String,
+        initial: String,
+    },
+    CompletingRead {
+        prompt: String,
+        candidates: Vec<String>,
+        require_match: bool,
+        initial: String,
+    },
+}
+
+fn string_arg(args: &[Value], i: usize, function: &str) -> Result<Option<String>, String> {
+    match args.get(i) {
+        None => Ok(None),
+        Some(Value::Str(s)) => Ok(Some(s.clone())),
+        Some(other) => Err(format!("{function}: expected a string, got {other}")),
+    }
+}
+
+fn prompt_arg(args: &[Value], function: &str) -> Result<String, String> {
+    string_arg(args, 0, function)?.ok_or_else(|| format!("{function}: missing prompt"))
+}
+
+//(read-string PROMPT [INITIAL])
+fn read_string_args(args: &[Value]) -> Result<Prompt, String> {
+    Ok(Prompt::ReadString {
+        prompt: prompt_arg(args, "read-string")?,
+        initial: string_arg(args, 1, "read-string")?.unwrap_or_default(),
+    })
+}
+
+//(completing-read PROMPT COLLECTION [REQUIRE-MATCH] [INITIAL])
+fn completing_read_args(args: &[Value]) -> Result<Prompt, String> {
+    let name = "completing-read";
+    let candidates = match args.get(1) {
+        Some(Value::List(items)) => items
+            .iter()
+            .map(|item| match item {
+                Value::Str(s) | Value::Symbol(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .collect(),
+        _ => return Err(format!("{name}: expected a list of candidates")),
+    };
+    let require_match = match args.get(2) {
+        None => false,
+        Some(Value::Bool(b)) => *b,
+        Some(other) => return Err(format!("{name}: expected #t or #f, got {other}")),
+    };
+    Ok(Prompt::CompletingRead {
+        prompt: prompt_arg(args, name)?,
+        candidates,
+        require_match,
+        initial: string_arg(args, 3, name)?.unwrap_or_default(),
+    })
+}
+
+//None if `name` isn't a prompting function
+pub(crate) fn parse_prompt(name: &str, args: &[Value]) -> Option<Result<Prompt, String>> {
+    match name {
+        "read-string" => Some(read_string_args(args)),
+        "completing-read" => Some(completing_read_args(args)),
+        _ => None,
+    }
+}
+
+pub(crate) fn face_name(value: &Value, function: &str) -> Result<String, String> {
+    match value {
+        Value::Symbol(s) | Value::Str(s) => Ok(s.clone()),
+        other => Err(format!("{function}: expected a face name, got {other}")),
+    }
+}
+
+fn name_arg(args: &[Value], i: usize, function: &str) -> Result<String, String> {
+    match args.get(i) {
+        Some(Value::Symbol(s) | Value::Str(s)) => Ok(s.clone()),
+        Some(other) => Err(format!("{function}: expected a name, got {other}")),
+        None => Err(format!("{function}: missing name")),
+    }
+}
+
+fn position_arg(args: &[Value], i: usize, function: &str) -> Result<usize, String> {
+    match args.get(i) {
+        Some(Value::Number(n)) if *n >= 0 => Ok(*n as usize),
+        Some(other) => Err(format!("{function}: expected a position, got {other}")),
+        None => Err(format!("{function}: missing position")),
+    }
+}
+
+//Applies `:attribute value` pairs to `face`. `unspecified` clears an
+//attribute so it's inherited again.
+pub(crate) fn set_face_attributes(
+    face: &mut Face,
+    args: &[Value],
+    function: &str,
+) -> Result<(), String> {
+    if !args.len().is_multiple_of(2) {
+        return Err(format!("{function}: expected :attribute value pairs"));
+    }
+    for pair in args.chunks(2) {
+        let (attribute, value) = (&pair[0], &pair[1]);
+        let bad_value = || format!("{function}: bad value {value} for {attribute}");
+        let unspecified = matches!(value, Value::Symbol(s) if s == "unspecified");
+        let color = || match value {
+            _ if unspecified => Ok(None),
+            Value::Str(s) => face::parse_color(s).map(Some).ok_or_else(bad_value),
+            _ => Err(bad_value()),
+        };
+        match attribute {
+            Value::Symbol(a) if a == ":foreground" => face.foreground = color()?,
+            Value::Symbol(a) if a == ":background" => face.background = color()?,
+            Value::Symbol(a) if a == ":weight" => {
+                face.weight = match value {
+                    _ if unspecified => None,
+                    Value::Symbol(s) if s == "normal" => Some(Weight::Normal),
+                    Value::Symbol(s) if s == "bold" => Some(Weight::Bold),
+                    _ => return Err(bad_value()),
+                }
+            }
+            Value::Symbol(a) if a == ":slant" => {
+                face.slant = match value {
+                    _ if unspecified => None,
+                    Value::Symbol(s) if s == "normal" => Some(Slant::Normal),
+                    Value::Symbol(s) if s == "italic" => Some(Slant::Italic),
+                    _ => return Err(bad_value()),
+                }
+            }
+            Value::Symbol(a) if a == ":underline" => {
+                face.underline = match value {
+                    _ if unspecified => None,
+                    Value::Bool(b) => Some(*b),
+                    _ => return Err(bad_value()),
+                }
+            }
+            Value::Symbol(a) if a == ":inherit" => {
+                face.inherit = match value {
+                    _ if unspecified => vec![],
+                    Value::List(parents) => parents
+                        .iter()
+                        .map(|p| face_name(p, function))
+                        .collect::<Result<_, _>>()?,
+                    parent => vec![face_name(parent, function)?],
+                }
+            }
+            other => return Err(format!("{function}: unknown face attribute {other}")),
+        }
+    }
+    Ok(())
+}
+
+//(defface NAME :attribute value...), replacing any face called NAME
+fn defface(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let name = face_name(args.first().ok_or("defface: missing name")?, "defface")?;
+    let mut face = Face::default();
+    set_face_attributes(&mut face, &args[1..], "defface")?;
+    editor.faces.define(&name, face);
+    Ok(Value::Symbol(name))
+}
+
+//(set-face-attribute FACE :attribute value...)
+fn set_face_attribute(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "set-face-attribute";
+    let name = face_name(
+        args.first().ok_or("set-face-attribute: missing face")?,
+        function,
+    )?;
+    let face = editor
+        .faces
+        .get_mut(&name)
+        .ok_or_else(|| format!("{function}: no face called {name}"))?;
+    set_face_attributes(face, &args[1..], function)?;
+    Ok(Value::Symbol(name))
+}
+
+//(put-text-property START END face FACE), only the face property exists
+fn put_text_property(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "put-text-property";
+    let (start, end) = (
+        position_arg(args, 0, function)?,
+        position_arg(args, 1, function)?,
+    );
+    match args.get(2) {
+        Some(Value::Symbol(p)) if p == "face" => (),
+        _ => return Err(format!("{function}: only the face property is supported")),
+    }
+    let buffer = editor.current_buffer_mut();
+    match args.get(3) {
+        Some(Value::Bool(false)) => buffer.remove_faces(start, end),
+        Some(value) => buffer.put_face(start, end, &face_name(value, function)?),
+        None => return Err(format!("{function}: missing face")),
+    }
+    Ok(Value::List(vec![]))
+}
+
+//(make-overlay START END FACE [PRIORITY]), returns the overlay's number
+fn make_overlay(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "make-overlay";
+    let (start, end) = (
+        position_arg(args, 0, function)?,
+        position_arg(args, 1, function)?,
+    );
+    let face = face_name(args.get(2).ok_or("make-overlay: missing face")?, function)?;
+    let priority = match args.get(3) {
+        None => 0,
+        Some(Value::Number(n)) => *n,
+        Some(other) => return Err(format!("{function}: expected a priority, got {other}")),
+    };
+    let id = editor
+        .current_buffer_mut()
+        .make_overlay(start, end, &face, priority);
+    Ok(Value::Number(id.0 as i64))
+}
+
+//(delete-overlay OVERLAY)
+fn delete_overlay(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let id = position_arg(args, 0, "delete-overlay")?;
+    editor
+        .current_buffer_mut()
+        .delete_overlay(crate::buffer::OverlayId(id));
+    Ok(Value::List(vec![]))
+}
+
+//The `:keyword value` pairs and bunlang source strings of a define form
+type KeywordArgs = (Vec<(String, Value)>, Vec<String>);
+
+//Splits `args` into the `:keyword value` pairs at the front and the bunlang
+//source strings after them
+fn keyword_args(args: &[Value], function: &str) -> Result<KeywordArgs, String> {
+    let mut options = vec![];
+    let mut rest = args;
+    while let [Value::Symbol(keyword), value, tail @ ..] = rest {
+        if !keyword.starts_with(':') {
+            break;
+        }
+        options.push((keyword.clone(), value.clone()));
+        rest = tail;
+    }
+    let body = rest
+        .iter()
+        .map(|form| match form {
+            Value::Str(source) => Ok(source.clone()),
+            other => Err(format!("{function}: expected bunlang source, got {other}")),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((options, body))
+}
+
+//(define-major-mode NAME PRETTY-NAME [:parent MODE] [:syntax LANGUAGE]
+//BODY...), every BODY a string of bunlang source
+fn define_major_mode(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "define-major-mode";
+    let name = name_arg(args, 0, function)?;
+    let pretty_name =
+        string_arg(args, 1, function)?.ok_or_else(|| format!("{function}: missing pretty name"))?;
+    let (options, body) = keyword_args(args.get(2..).unwrap_or_default(), function)?;
+    let mut mode = MajorMode {
+        name: name.clone(),
+        pretty_name,
+        parent: None,
+        syntax: None,
+        body,
+        keymap: Keymap::new(),
+    };
+    for (keyword, value) in options {
+        let value = name_arg(&[value], 0, function)?;
+        match keyword.as_str() {
+            ":parent" if editor.modes().major(&value).is_some() => mode.parent = Some(value),
+            ":parent" => return Err(format!("{function}: no major mode named {value}")),
+            ":syntax" if SyntaxLanguage::named(&value).is_some() => mode.syntax = Some(value),
+            ":syntax" => return Err(format!("{function}: no grammar for {value}")),
+            other => return Err(format!("{function}: unknown keyword {other}")),
+        }
+    }
+    editor.modes_mut().define_major(mode);
+    Ok(Value::Symbol(name))
+}
+
+//(define-minor-mode NAME LIGHTER [:global BOOL] BODY...)
+fn define_minor_mode(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "define-minor-mode";
+    let name = name_arg(args, 0, function)?;
+    let lighter = string_arg(args, 1, function)?.unwrap_or_default();
+    let (options, body) = keyword_args(args.get(2..).unwrap_or_default(), function)?;
+    let mut mode = MinorMode {
+        name: name.clone(),
+        lighter,
+        global: false,
+        body,
+        keymap: Keymap::new(),
+    };
+    for (keyword, value) in options {
+        match (keyword.as_str(), value) {
+            (":global", Value::Bool(global)) => mode.global = global,
+            (":global", other) => {
+                return Err(format!("{function}: expected #t or #f, got {other}"))
+            }
+            (other, _) => return Err(format!("{function}: unknown keyword {other}")),
+        }
+    }
+    editor.modes_mut().define_minor(mode);
+    Ok(Value::Symbol(name))
+}
+
+//(define-key KEYMAP KEYS COMMAND), KEYMAP global-map or a mode's MODE-map
+fn define_key(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "define-key";
+    let map = name_arg(args, 0, function)?;
+    let kbd = string_arg(args, 1, function)?.ok_or_else(|| format!("{function}: missing keys"))?;
+    let keys = Key::parse_sequence(&kbd).ok_or_else(|| format!("{function}: bad keys {kbd}"))?;
+    let command = name_arg(args, 2, function)?;
+    let keymap = match map.as_str() {
+        "global-map" => editor.global_keymap_mut(),
+        _ => editor
+            .modes_mut()
+            .keymap_mut(&map)
+            .ok_or_else(|| format!("{function}: no keymap named {map}"))?,
+    };
+    keymap.bind(keys, command.clone());
+    Ok(Value::Symbol(command))
+}
+
+//(defvar NAME DEFAULT [DOC] [:type TYPE] [:local BOOL]). TYPE is any,
+//boolean, natural, positive, string or (list choice SYMBOL...), and :local
+//makes setq give buffers their own value.
+fn defvar(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "defvar";
+    let name = name_arg(args, 0, function)?;
+    let default = args
+        .get(1)
+        .cloned()
+        .ok_or_else(|| format!("{function}: missing value"))?;
+    let (doc, rest) = match args.get(2) {
+        Some(Value::Str(doc)) => (doc.clone(), args.get(3..).unwrap_or_default()),
+        _ => (String::new(), args.get(2..).unwrap_or_default()),
+    };
+    let (options, body) = keyword_args(rest, function)?;
+    if !body.is_empty() {
+        return Err(format!("{function}: expected :keyword value pairs"));
+    }
+    let mut value_type = VariableType::Any;
+    let mut local_if_set = false;
+    for (keyword, value) in options {
+        match (keyword.as_str(), value) {
+            (":type", value) => {
+                value_type = VariableType::parse(&value).map_err(|e| format!("{function}: {e}"))?
+            }
+            (":local", Value::Bool(local)) => local_if_set = local,
+            (":local", other) => return Err(format!("{function}: expected #t or #f, got {other}")),
+            (other, _) => return Err(format!("{function}: unknown keyword {other}")),
+        }
+    }
+    editor
+        .variables
+        .define(&name, default, &doc, value_type, local_if_set)
+        .map_err(|e| format!("{function}: {e}"))?;
+    Ok(Value::Symbol(name))
+}
+
+//(setq NAME VALUE) and friends, `set` deciding where the value goes
+fn set_variable(
+    editor: &mut Editor,
+    args: &[Value],
+    function: &str,
+    set: fn(&mut Editor, &str, Value) -> Result<(), String>,
+) -> Result<Value, String> {
+    let name = name_arg(args, 0, function)?;
+    let value = args
+        .get(1)
+        .cloned()
+        .ok_or_else(|| format!("{function}: missing value"))?;
+    set(editor, &name, value.clone()).map_err(|e| format!("{function}: {e}"))?;
+    Ok(value)
+}
+
+//(add-hook HOOK SOURCE [DEPTH] [LOCAL]), e.g.
+//(add-hook rust-mode-hook "(message \"hi\")"). Lower DEPTHs run first,
+//0 if not given, and LOCAL #t only runs it in the current buffer.
+fn add_hook(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "add-hook";
+    let hook = name_arg(args, 0, function)?;
+    let source =
+        string_arg(args, 1, function)?.ok_or_else(|| format!("{function}: missing source"))?;
+    let depth = match args.get(2) {
+        None => 0,
+        Some(Value::Number(depth)) => *depth,
+        Some(other) => return Err(format!("{function}: expected a depth, got {other}")),
+    };
+    let local = match args.get(3) {
+        None => false,
+        Some(Value::Bool(local)) => *local,
+        Some(other) => return Err(format!("{function}: expected #t or #f, got {other}")),
+    };
+    editor.add_hook(&hook, &source, depth, local);
+    Ok(Value::Symbol(hook))
+}
+
+//(run-with-idle-timer SECONDS REPEAT SOURCE), returns the timer's number
+//for cancel-timer
+fn run_with_idle_timer(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "run-with-idle-timer";
+    let seconds = position_arg(args, 0, function)?;
+    let repeat = match args.get(1) {
+        Some(Value::Bool(repeat)) => *repeat,
+        _ => return Err(format!("{function}: expected #t or #f for REPEAT")),
+    };
+    let source =
+        string_arg(args, 2, function)?.ok_or_else(|| format!("{function}: missing source"))?;
+    let id = editor.run_with_idle_timer(Duration::from_secs(seconds as u64), repeat, &source);
+    Ok(Value::Number(id.0 as i64))
+}
+
+//(MODE [ARG]) for a minor mode. Like Emacs, no ARG or #t turns it on, #f
+//turns it off and `toggle` toggles.
+fn call_minor_mode(editor: &mut Editor, name: &str, args: &[Value]) -> Result<Value, String> {
+    let on = match args.first() {
+        None | Some(Value::Bool(true)) => true,
+        Some(Value::Bool(false)) => false,
+        Some(Value::Symbol(s)) if s == "toggle" => !editor.is_minor_mode_on(name),
+        Some(other) => return Err(format!("{name}: expected #t, #f or toggle, got {other}")),
+    };
+    editor.set_minor_mode(name, on)?;
+    Ok(Value::Bool(on))
+}
+
+//Host functions that answer right away. Positions are char offsets from
+//0, unlike Emacs.
+pub(crate) fn call_builtin(editor: &mut Editor, name: &str, args: Vec<Value>) -> Reply {
+    let text = |args: &[Value]| {
+        args.iter()
+            .map(|arg| match arg {
+                Value::Str(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .collect::<String>()
+    };
+    Some(match name {
+        "message" => {
+            let message = text(&args);
+            editor.message(message.clone());
+            Ok(Value::Str(message))
+        }
+        "insert" => {
+            editor.insert(&text(&args));
+            Ok(Value::List(vec![]))
+        }
+        "buffer-name" => Ok(Value::Str(editor.current_buffer().name().to_owned())),
+        "point" => Ok(Value::Number(editor.current_buffer().point() as i64)),
+        "point-min" => Ok(Value::Number(0)),
+        "point-max" => Ok(Value::Number(editor.current_buffer().len_chars() as i64)),
+        "search-forward" => search(editor, &args, name, false, true),
+        "search-backward" => search(editor, &args, name, false, false),
+        "re-search-forward" => search(editor, &args, name, true, true),
+        "re-search-backward" => search(editor, &args, name, true, false),
+        "defface" => defface(editor, &args),
+        "load-theme" => string_arg(&args, 0, name)
+            .and_then(|theme| theme.ok_or_else(|| format!("{name}: missing theme")))
+            .and_then(|theme| editor.load_theme(&theme))
+            .map(|()| Value::List(vec![])),
+        "disable-theme" => string_arg(&args, 0, name)
+            .and_then(|theme| theme.ok_or_else(|| format!("{name}: missing theme")))
+            .and_then(|theme| editor.disable_theme(&theme))
+            .map(|()| Value::List(vec![])),
+        "set-face-attribute" => set_face_attribute(editor, &args),
+        "put-text-property" => put_text_property(editor, &args),
+        "make-overlay" => make_overlay(editor, &args),
+        "delete-overlay" => delete_overlay(editor, &args),
+        //(set-mode-line-format SEGMENT...), each one bunlang source
+        "set-mode-line-format" => {
+            editor.mode_line_format.set(
+                args.iter()
+                    .map(|arg| match arg {
+                        Value::Str(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect(),
+            );
+            Ok(Value::List(vec![]))
+        }
+        "set-frame-font" => set_frame_font(editor, &args),
+        "define-major-mode" => define_major_mode(editor, &args),
+        "define-minor-mode" => define_minor_mode(editor, &args),
+        "define-key" => define_key(editor, &args),
+        "add-hook" => add_hook(editor, &args),
+        //(remove-hook HOOK SOURCE [LOCAL])
+        "remove-hook" => name_arg(&args, 0, name).and_then(|hook| {
+            let source =
+                string_arg(&args, 1, name)?.ok_or_else(|| format!("{name}: missing source"))?;
+            let local = matches!(args.get(2), Some(Value::Bool(true)));
+            editor.remove_hook(&hook, &source, local);
+            Ok(Value::Symbol(hook))
+        }),
+        "run-hooks" => args
+            .iter()
+            .map(|hook| name_arg(std::slice::from_ref(hook), 0, name))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|hooks| {
+                for hook in hooks {
+                    editor.run_hooks(&hook);
+                }
+                Value::List(vec![])
+            }),
+        //What the running hook was called with, like the START END of
+        //after-change-functions
+        "hook-args" => Ok(Value::List(editor.hook_args())),
+        "run-with-idle-timer" => run_with_idle_timer(editor, &args),
+        "cancel-timer" => position_arg(&args, 0, name).map(|id| {
+            editor.cancel_timer(TimerId(id as u64));
+            Value::List(vec![])
+        }),
+        //(add-auto-mode PATTERN MODE), PATTERN a regex matched against the
+        //whole path of a file being visited
+        "add-auto-mode" => string_arg(&args, 0, name)
+            .and_then(|pattern| pattern.ok_or_else(|| format!("{name}: missing pattern")))
+            .and_then(|pattern| {
+                let mode = name_arg(&args, 1, name)?;
+                editor.modes_mut().add_auto_mode(&pattern, &mode)?;
+                Ok(Value::Symbol(mode))
+            }),
+        "major-mode" => Ok(Value::Symbol(
+            editor.current_buffer().major_mode().to_owned(),
+        )),
+        "minor-mode-enabled-p" => {
+            name_arg(&args, 0, name).map(|mode| Value::Bool(editor.is_minor_mode_on(&mode)))
+        }
+        "defvar" => defvar(editor, &args),
+        //(setq NAME VALUE), buffer-local if NAME is in the current buffer
+        "setq" => set_variable(editor, &args, name, Editor::set_variable),
+        "setq-default" => set_variable(editor, &args, name, Editor::set_default),
+        //(setq-local NAME VALUE), gone once the buffer changes major mode
+        "setq-local" => set_variable(editor, &args, name, Editor::set_local),
+        "symbol-value" => name_arg(&args, 0, name).and_then(|variable| {
+            editor
+                .variable(&variable)
+                .cloned()
+                .ok_or_else(|| format!("{name}: {variable} isn't a variable"))
+        }),
+        "default-value" => name_arg(&args, 0, name).and_then(|variable| {
+            editor
+                .variables
+                .get(&variable)
+                .map(|v| v.default_value().clone())
+                .ok_or_else(|| format!("{name}: {variable} isn't a variable"))
+        }),
+        "local-variable-p" => name_arg(&args, 0, name).map(|variable| {
+            Value::Bool(editor.current_buffer().local_variable(&variable).is_some())
+        }),
+        //(let-variable NAME VALUE SOURCE), SOURCE's value with NAME set to
+        //VALUE while it runs
+        "let-variable" => name_arg(&args, 0, name).and_then(|variable| {
+            let value = args
+                .get(1)
+                .cloned()
+                .ok_or_else(|| format!("{name}: missing value"))?;
+            let source =
+                string_arg(&args, 2, name)?.ok_or_else(|| format!("{name}: missing source"))?;
+            editor
+                .let_variable(&variable, value, |editor| editor.eval_immediately(&source))
+                .map_err(|e| format!("{name}: {e}"))?
+        }),
+        "kill-local-variable" => name_arg(&args, 0, name).map(|variable| {
+            editor.kill_local_variable(&variable);
+            Value::Symbol(variable)
+        }),
+        //(add-variable-watcher NAME SOURCE), SOURCE runs after every change
+        "add-variable-watcher" => name_arg(&args, 0, name).and_then(|variable| {
+            let source =
+                string_arg(&args, 1, name)?.ok_or_else(|| format!("{name}: missing source"))?;
+            if editor.variables.get(&variable).is_none() {
+                return Err(format!("{name}: {variable} isn't a variable"));
+            }
+            editor
+                .variables
+                .add_watcher(&variable, Watcher::Script(source));
+            Ok(Value::Symbol(variable))
+        }),
+        //(set-font-fallbacks FAMILY...), tried in order for missing glyphs
+        "set-font-fallbacks" => args
+            .iter()
+            .map(|arg| match arg {
+                Value::Str(family) => Ok(family.clone()),
+                other => Err(format!("{name}: expected a family name, got {other}")),
+            })
+            .collect::<Result<_, _>>()
+            .map(|fallbacks| {
+                let mut font = editor.font().clone();
+                font.fallbacks = fallbacks;
+                editor.set_font(font);
+                Value::List(vec![])
+            }),
+        //(set-language-server MODE COMMAND ARG...), the server the lsp
+        //command starts for MODE and the modes derived from it
+        "set-language-server" => name_arg(&args, 0, name).and_then(|mode| {
+            let command =
+                string_arg(&args, 1, name)?.ok_or_else(|| format!("{name}: missing command"))?;
+            let args = (2..args.len())
+                .map(|i| string_arg(&args, i, name).map(Option::unwrap_or_default))
+                .collect::<Result<_, _>>()?;
+            editor
+                .lsp_mut()
+                .set_server_config(&mode, ServerConfig { command, args });
+            Ok(Value::Symbol(mode))
+        }),
+        //(set-debug-adapter NAME COMMAND ARG...), how to start the adapter
+        //debug configurations refer to as NAME
+        "set-debug-adapter" => name_arg(&args, 0, name).and_then(|adapter| {
+            let command =
+                string_arg(&args, 1, name)?.ok_or_else(|| format!("{name}: missing command"))?;
+            let args = (2..args.len())
+                .map(|i| string_arg(&args, i, name).map(Option::unwrap_or_default))
+                .collect::<Result<_, _>>()?;
+            editor
+                .dap_mut()
+                .set_adapter(&adapter, AdapterConfig { command, args });
+            Ok(Value::Symbol(adapter))
+        }),
+        //(define-debug-configuration NAME ADAPTER :KEY VALUE...), something
+        //for dap-debug to start. The pairs are the launch or attach
+        //arguments, :request picks which.
+        "define-debug-configuration" => debug_configuration(&args).map(|config| {
+            let config_name = Value::Str(config.name.clone());
+            editor.dap_mut().add_configuration(config);
+            config_name
+        }),
+        //(dap-debug NAME), starts debugging configuration NAME
+        "dap-debug" => string_arg(&args, 0, name).and_then(|config| {
+            let config = config.ok_or_else(|| format!("{name}: missing configuration"))?;
+            dap::start(editor, &config).map(|()| Value::Bool(true))
+        }),
+        //(lsp), connects the current buffer to its language server unless
+        //it already is, for mode hooks. #t if it wasn't.
+        "lsp" if editor.lsp().is_attached(editor.current_buffer_id()) => Ok(Value::Bool(false)),
+        "lsp" => lsp::start(editor).map(|()| Value::Bool(true)),
+        "text-scale-adjust" => match args.first() {
+            Some(Value::Number(steps)) => {
+                editor.text_scale_adjust(*steps as i32);
+                Ok(Value::Number(editor.font().text_scale as i64))
+            }
+            _ => Err(format!("{name}: expected a number of steps")),
+        },
+        _ if editor.modes().major(name).is_some() => editor
+            .set_major_mode(name)
+            .map(|()| Value::Symbol(name.to_owned())),
+        _ if editor.modes().minor(name).is_some() => call_minor_mode(editor, name, &args),
+        _ => return None,
+    })
+}
+
+fn debug_configuration(args: &[Value]) -> Result<LaunchConfig, String> {
+    let function = "define-debug-configuration";
+    let name = string_arg(args, 0, function)?.ok_or_else(|| format!("{function}: missing name"))?;
+    let adapter = name_arg(args, 1, function)?;
+    let pairs = &args[2..];
+    if !pairs.len().is_multiple_of(2) {
+        return Err(format!(
+            "{function}: {} has no value",
+            pairs[pairs.len() - 1]
+        ));
+    }
+    let mut request = Request::Launch;
+    let mut arguments = serde_json::Map::new();
+    for pair in pairs.chunks(2) {
+        let key = match &pair[0] {
+            Value::Symbol(key) if key.starts_with(':') => &key[1..],
+            other => return Err(format!("{function}: expected a :keyword, got {other}")),
+        };
+        if key == "request" {
+            request = match &pair[1] {
+                Value::Symbol(s) | Value::Str(s) if s == "launch" => Request::Launch,
+                Value::Symbol(s) | Value::Str(s) if s == "attach" => Request::Attach,
+                other => {
+                    return Err(format!(
+                        "{function}: :request is launch or attach, not {other}"
+                    ))
+                }
+            };
+        }
+        arguments.insert(key.to_owned(), json_value(&pair[1]));
+    }
+    Ok(LaunchConfig {
+        name,
+        adapter,
+        request,
+        arguments: arguments.into(),
+    })
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Number(n) => (*n).into(),
+        Value::Bool(b) => (*b).into(),
+        Value::Str(s) | Value::Symbol(s) => s.as_str().into(),
+        Value::List(items) => items.iter().map(json_value).collect(),
+    }
+}
+
+//(search-forward STRING [BOUND]) and the like. Point moves to the far end
+//of the match and the result is where that is, or #f if nothing matched.
+fn search(
+    editor: &mut Editor,
+    args: &[Value],
+    function: &str,
+    regex: bool,
+    forward: bool,
+) -> Result<Value, String> {
+    let query = string_arg(args, 0, function)?
+        .ok_or_else(|| format!("{function}: missing string to search for"))?;
+    let bound = match args.get(1) {
+        None | Some(Value::Bool(false)) => None,
+        Some(_) => Some(position_arg(args, 1, function)?),
+    };
+    let found = editor.search(&query, regex, forward, bound)?;
+    Ok(match found {
+        Some(_) => Value::Number(editor.current_buffer().point() as i64),
+        None => Value::Bool(false),
+    })
+}
+
+//(set-frame-font FAMILY [SIZE]), FAMILY #f for the system monospace font
+//and SIZE in points
+fn set_frame_font(editor: &mut Editor, args: &[Value]) -> Result<Value, String> {
+    let function = "set-frame-font";
+    let mut font = editor.font().clone();
+    font.family = match args.first() {
+        Some(Value::Bool(false)) => None,
+        Some(Value::Str(family)) => Some(family.clone()),
+        _ => return Err(format!("{function}: expected a family name or #f")),
+    };
+    match args.get(1) {
+        None => (),
+        Some(Value::Number(size)) if *size > 0 => font.size = *size as f32,
+        Some(other) => return Err(format!("{function}: expected a size, got {other}")),
+    }
+    editor.set_font(font);
+    Ok(Value::List(vec![]))
+}
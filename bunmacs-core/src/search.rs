@@ -0,0 +1,442 @@
+//Searching buffer text. A pattern compiles to lazy DFAs that get fed the
+//rope a byte at a time, chunk after chunk, so searching never copies the
+//text it looks through no matter how big the buffer is.
+
+use std::{borrow::Cow, error::Error, ops::Range};
+
+use regex::{Regex, RegexBuilder};
+use regex_automata::{
+    hybrid::{
+        dfa::{Cache, DFA},
+        LazyStateID,
+    },
+    nfa::thompson,
+    util::{start, syntax},
+    Anchored, MatchKind,
+};
+use ropey::Rope;
+
+//Something to search for, either literal text or a regex in Rust's syntax.
+//^ and $ match at the start and end of every line, like Emacs.
+#[derive(Debug)]
+pub struct SearchPattern {
+    //Finds where the leftmost match ends
+    forward: DFA,
+    forward_cache: Cache,
+    //Runs backwards from the end of a match to find its start, or from
+    //where a backward search starts to find the last match before it
+    reverse: DFA,
+    reverse_cache: Cache,
+}
+
+//Whether searching for `query` should ignore case. Like Emacs, it does
+//when case-fold-search is on unless `query` has an upper case letter in it.
+//Escapes like \W or \p{Greek} in a regex don't count.
+pub fn case_fold(query: &str, regex: bool, case_fold_search: bool) -> bool {
+    if !case_fold_search {
+        return false;
+    }
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            match chars.next() {
+                Some('p' | 'P') if chars.clone().next() == Some('{') => {
+                    chars.by_ref().find(|c| *c == '}');
+                }
+                Some('p' | 'P') => {
+                    chars.next();
+                }
+                _ => (),
+            }
+        } else if c.is_uppercase() {
+            return false;
+        }
+    }
+    true
+}
+
+//`query` as a regex::Regex with the same flags SearchPattern uses, for
+//searching flat strings and filling in replacements
+pub fn regex(query: &str, case_fold: bool) -> Result<Regex, String> {
+    RegexBuilder::new(query)
+        .case_insensitive(case_fold)
+        .multi_line(true)
+        .build()
+        .map_err(|e| match e {
+            //The last line of a syntax error says what's wrong, the rest
+            //points at where
+            regex::Error::Syntax(error) => error
+                .lines()
+                .last()
+                .map(|line| line.trim_start_matches("error: ").to_owned())
+                .unwrap_or(error),
+            e => e.to_string(),
+        })
+}
+
+//`template` with $1, ${name} and $0 filled in from where `regex` matches
+//at the start of char range `found` in `text`. Only the lines the match is
+//on get copied out of the rope, with enough around it for ^, $ and \b to
+//see what they'd see in place.
+pub fn expand(regex: &Regex, text: &Rope, found: &Range<usize>, template: &str) -> String {
+    let start = text.line_to_char(text.char_to_line(found.start));
+    let end_line = text.char_to_line(found.end);
+    let end = if end_line + 1 < text.len_lines() {
+        text.line_to_char(end_line + 1)
+    } else {
+        text.len_chars()
+    };
+    let haystack = text.slice(start..end).to_string();
+    let offset = text.slice(start..found.start).len_bytes();
+    match regex.captures_at(&haystack, offset) {
+        Some(captures) if captures.get(0).is_some_and(|m| m.start() == offset) => {
+            let mut expanded = String::new();
+            captures.expand(template, &mut expanded);
+            expanded
+        }
+        _ => template.to_owned(),
+    }
+}
+
+//The DFAs can't tell Unicode word boundaries apart, so searches for \b
+//give up at the first non-ASCII byte they see
+fn quit_error(byte: u8) -> String {
+    format!("Can't search for \\b past non-ASCII text (byte {byte:#x})")
+}
+
+impl SearchPattern {
+    pub fn new(query: &str, regex: bool, case_fold: bool) -> Result<Self, String> {
+        let pattern = if regex {
+            Cow::Borrowed(query)
+        } else {
+            Cow::Owned(regex::escape(query))
+        };
+        let build = |reverse: bool, match_kind: MatchKind| {
+            DFA::builder()
+                .configure(
+                    DFA::config()
+                        .match_kind(match_kind)
+                        .unicode_word_boundary(true),
+                )
+                .syntax(
+                    syntax::Config::new()
+                        .case_insensitive(case_fold)
+                        .multi_line(true),
+                )
+                .thompson(thompson::Config::new().reverse(reverse))
+                .build(&pattern)
+                .map_err(|e| {
+                    //The parser's error says what's wrong with the regex
+                    let mut error: &dyn Error = &e;
+                    while let Some(source) = error.source() {
+                        error = source;
+                    }
+                    error.to_string()
+                })
+        };
+        let forward = build(false, MatchKind::LeftmostFirst)?;
+        //Every match so the start found is the leftmost one
+        let reverse = build(true, MatchKind::All)?;
+        Ok(SearchPattern {
+            forward_cache: forward.create_cache(),
+            reverse_cache: reverse.create_cache(),
+            forward,
+            reverse,
+        })
+    }
+
+    //The first match starting at or after char `from` that ends by char
+    //`limit`, as a char range
+    pub fn find_forward(
+        &mut self,
+        text: &Rope,
+        from: usize,
+        limit: usize,
+    ) -> Result<Option<Range<usize>>, String> {
+        if from > limit {
+            return Ok(None);
+        }
+        let (from, limit) = (text.char_to_byte(from), text.char_to_byte(limit));
+        let Some(end) = self.match_end(text, from, limit, Anchored::No)? else {
+            return Ok(None);
+        };
+        let start = self.match_start(text, end, from, Anchored::Yes)?;
+        Ok(start.map(|start| text.byte_to_char(start)..text.byte_to_char(end)))
+    }
+
+    //The match starting last at or before char `from` that still ends by
+    //it and doesn't start before char `limit`, like Emacs'
+    //re-search-backward
+    pub fn find_backward(
+        &mut self,
+        text: &Rope,
+        from: usize,
+        limit: usize,
+    ) -> Result<Option<Range<usize>>, String> {
+        if limit > from {
+            return Ok(None);
+        }
+        let (from, limit) = (text.char_to_byte(from), text.char_to_byte(limit));
+        let Some(start) = self.match_start(text, from, limit, Anchored::No)? else {
+            return Ok(None);
+        };
+        let start = text.char_to_byte(text.byte_to_char(start));
+        let end = self.match_end(text, start, from, Anchored::Yes)?;
+        Ok(end.map(|end| text.byte_to_char(start)..text.byte_to_char(end)))
+    }
+
+    //Every match in the char range `range`, in order and not overlapping
+    pub fn find_all(
+        &mut self,
+        text: &Rope,
+        range: Range<usize>,
+    ) -> Result<Vec<Range<usize>>, String> {
+        let mut matches = vec![];
+        let mut from = range.start;
+        while let Some(found) = self.find_forward(text, from, range.end)? {
+            //Step over empty matches or they'd be found forever
+            from = if found.is_empty() {
+                found.end + 1
+            } else {
+                found.end
+            };
+            matches.push(found);
+        }
+        Ok(matches)
+    }
+
+    //Byte offset where the leftmost match starting at or after byte `start`
+    //ends, or starting right at `start` if `anchored`. Text after `limit`
+    //only counts as context for things like $.
+    fn match_end(
+        &mut self,
+        text: &Rope,
+        start: usize,
+        limit: usize,
+        anchored: Anchored,
+    ) -> Result<Option<usize>, String> {
+        let (dfa, cache) = (&self.forward, &mut self.forward_cache);
+        let config = start::Config::new()
+            .anchored(anchored)
+            .look_behind(start.checked_sub(1).map(|i| text.byte(i)));
+        let mut state = dfa.start_state(cache, &config).map_err(|e| e.to_string())?;
+        let mut end = None;
+        //Matches only show up one byte late, after the byte following them
+        for (at, byte) in (start..limit).zip(text.bytes_at(start)) {
+            state = dfa
+                .next_state(cache, state, byte)
+                .map_err(|e| e.to_string())?;
+            match classify(state, byte)? {
+                Step::Match => end = Some(at),
+                Step::Dead => return Ok(end),
+                Step::Continue => (),
+            }
+        }
+        state = if limit < text.len_bytes() {
+            dfa.next_state(cache, state, text.byte(limit))
+        } else {
+            dfa.next_eoi_state(cache, state)
+        }
+        .map_err(|e| e.to_string())?;
+        if state.is_match() {
+            end = Some(limit);
+        }
+        Ok(end)
+    }
+
+    //Searches backwards from byte `end` to byte `limit` for where a match
+    //starts. Anchored, that's the start of the match ending at `end`.
+    //Unanchored, it's the start of the last match that ends by `end`.
+    fn match_start(
+        &mut self,
+        text: &Rope,
+        end: usize,
+        limit: usize,
+        anchored: Anchored,
+    ) -> Result<Option<usize>, String> {
+        let (dfa, cache) = (&self.reverse, &mut self.reverse_cache);
+        let config = start::Config::new()
+            .anchored(anchored)
+            .look_behind((end < text.len_bytes()).then(|| text.byte(end)));
+        let mut state = dfa.start_state(cache, &config).map_err(|e| e.to_string())?;
+        let mut start = None;
+        let mut bytes = text.bytes_at(end);
+        let bytes = std::iter::from_fn(|| bytes.prev());
+        for (at, byte) in (limit..end).rev().zip(bytes) {
+            state = dfa
+                .next_state(cache, state, byte)
+                .map_err(|e| e.to_string())?;
+            match classify(state, byte)? {
+                Step::Match if anchored == Anchored::No => return Ok(Some(at + 1)),
+                Step::Match => start = Some(at + 1),
+                Step::Dead => return Ok(start),
+                Step::Continue => (),
+            }
+        }
+        state = if limit > 0 {
+            dfa.next_state(cache, state, text.byte(limit - 1))
+        } else {
+            dfa.next_eoi_state(cache, state)
+        }
+        .map_err(|e| e.to_string())?;
+        if state.is_match() {
+            start = Some(limit);
+        }
+        Ok(start)
+    }
+}
+
+enum Step {
+    Match,
+    Dead,
+    Continue,
+}
+
+fn classify(state: LazyStateID, byte: u8) -> Result<Step, String> {
+    if !state.is_tagged() {
+        Ok(Step::Continue)
+    } else if state.is_match() {
+        Ok(Step::Match)
+    } else if state.is_dead() {
+        Ok(Step::Dead)
+    } else if state.is_quit() {
+        Err(quit_error(byte))
+    } else {
+        Ok(Step::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(query: &str, regex: bool) -> SearchPattern {
+        SearchPattern::new(query, regex, false).unwrap()
+    }
+
+    #[test]
+    fn finds_matches_across_chunks() {
+        //Big enough for the rope to split it, with "jabc" across every
+        //seam between repeats and the chunks ending wherever they like
+        let text = Rope::from("abcdéfghij".repeat(2000));
+        assert!(text.chunks().count() > 1);
+        let mut pattern = pattern("jabc", false);
+        let matches = pattern.find_all(&text, 0..text.len_chars()).unwrap();
+        assert_eq!(matches.len(), 1999);
+        assert!(matches.iter().all(|m| text.slice(m.clone()) == "jabc"));
+        let chunk = |char: usize| text.chunk_at_char(char).2;
+        assert!(matches.iter().any(|m| chunk(m.start) != chunk(m.end - 1)));
+
+        let len = text.len_chars();
+        assert_eq!(
+            pattern.find_backward(&text, len, 0).unwrap(),
+            matches.last().cloned()
+        );
+        let regex = &mut SearchPattern::new("é[f-h]+ij", true, false).unwrap();
+        assert_eq!(regex.find_all(&text, 0..len).unwrap().len(), 2000);
+    }
+
+    #[test]
+    fn backward_finds_the_last_match_before_point() {
+        let text = Rope::from("foo bar foo baz foo");
+        let mut foo = pattern("foo", false);
+        assert_eq!(foo.find_backward(&text, 14, 0).unwrap(), Some(8..11));
+        //It has to end by where the search starts
+        assert_eq!(foo.find_backward(&text, 10, 0).unwrap(), Some(0..3));
+        assert_eq!(foo.find_backward(&text, 10, 1).unwrap(), None);
+        //Like re-search-backward, the match starting closest to point
+        let text = Rope::from("aaa");
+        let mut a = pattern("a+", true);
+        assert_eq!(a.find_backward(&text, 3, 0).unwrap(), Some(2..3));
+        assert_eq!(a.find_forward(&text, 0, 3).unwrap(), Some(0..3));
+    }
+
+    #[test]
+    fn find_all_steps_over_empty_matches() {
+        let text = Rope::from("axxb");
+        let matches = pattern("x*", true).find_all(&text, 0..4).unwrap();
+        assert_eq!(matches, [0..0, 1..3, 3..3, 4..4]);
+        assert_eq!(
+            pattern("", false).find_all(&text, 1..3).unwrap(),
+            [1..1, 2..2, 3..3]
+        );
+    }
+
+    #[test]
+    fn anchors_match_at_every_line() {
+        let text = Rope::from("one\ntwo\nthree");
+        let all = |query: &str| pattern(query, true).find_all(&text, 0..13).unwrap();
+        assert_eq!(all("^"), [0..0, 4..4, 8..8]);
+        assert_eq!(all("$"), [3..3, 7..7, 13..13]);
+        assert_eq!(all(r"^t\w+$"), [4..7, 8..13]);
+        //What's before the start still counts
+        assert_eq!(
+            pattern("^w", true).find_forward(&text, 5, 13).unwrap(),
+            None
+        );
+        //And what's after the limit
+        assert_eq!(pattern("e$", true).find_forward(&text, 0, 2).unwrap(), None);
+        assert_eq!(pattern("n$", true).find_forward(&text, 0, 2).unwrap(), None);
+        assert_eq!(
+            pattern("on", true).find_forward(&text, 0, 2).unwrap(),
+            Some(0..2)
+        );
+    }
+
+    #[test]
+    fn capitals_turn_case_folding_off() {
+        assert!(case_fold("foo", false, true));
+        assert!(!case_fold("Foo", false, true));
+        assert!(!case_fold("foo", false, false));
+        //Escapes aren't capitals
+        assert!(case_fold(r"foo\W", true, true));
+        assert!(case_fold(r"\p{Greek}\PL", true, true));
+        assert!(!case_fold(r"\p{Greek}X", true, true));
+        //Unless it's not a regex
+        assert!(!case_fold(r"\W", false, true));
+
+        let text = Rope::from("FOO! foo_ ΑΒΓ");
+        let mut folded = SearchPattern::new(r"foo\W", true, true).unwrap();
+        assert_eq!(folded.find_all(&text, 0..13).unwrap().len(), 1);
+        assert_eq!(folded.find_forward(&text, 0, 13).unwrap(), Some(0..4));
+        let mut greek = SearchPattern::new(r"\p{Greek}+", true, true).unwrap();
+        assert_eq!(greek.find_forward(&text, 0, 13).unwrap(), Some(10..13));
+        let mut exact = SearchPattern::new("foo", false, false).unwrap();
+        assert_eq!(exact.find_forward(&text, 0, 13).unwrap(), Some(5..8));
+        assert_eq!(exact.find_forward(&text, 6, 13).unwrap(), None);
+    }
+
+    #[test]
+    fn word_boundaries_give_up_on_non_ascii() {
+        let mut bar = pattern(r"\bbar\b", true);
+        assert_eq!(
+            bar.find_forward(&Rope::from("foo bar"), 0, 7).unwrap(),
+            Some(4..7)
+        );
+        let error = bar.find_forward(&Rope::from("café bar"), 0, 8).unwrap_err();
+        assert_eq!(error, quit_error(0xc3));
+        //Only what's searched through counts
+        assert_eq!(
+            bar.find_forward(&Rope::from("café bar"), 5, 8).unwrap(),
+            Some(5..8)
+        );
+    }
+
+    #[test]
+    fn literal_queries_are_not_regexes() {
+        let text = Rope::from("axb a.b");
+        assert_eq!(
+            pattern("a.b", false).find_forward(&text, 0, 7).unwrap(),
+            Some(4..7)
+        );
+        assert!(SearchPattern::new("a(", true, false).is_err());
+    }
+
+    #[test]
+    fn expands_groups_where_the_match_is() {
+        let regex = regex(r"^(\w+)@(\w+)$", false).unwrap();
+        let text = Rope::from("x\nuser@host\ny");
+        assert_eq!(expand(&regex, &text, &(2..11), "$2 at $1"), "host at user");
+        assert_eq!(expand(&regex, &text, &(2..11), "<$0>"), "<user@host>");
+    }
+}
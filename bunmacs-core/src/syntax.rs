@@ -0,0 +1,350 @@
+//Syntax trees from tree-sitter. A buffer with a language keeps its tree up
+//to date as it's edited, letting tree-sitter reuse everything outside the
+//edit, and highlights only get queried for the text being drawn.
+
+use std::{fmt, ops::Range, sync::OnceLock};
+
+use ropey::Rope;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+pub struct SyntaxLanguage {
+    pub name: &'static str,
+    language: Language,
+    highlights: Query,
+    //The face each of the query's captures colors with
+    capture_faces: Vec<Option<&'static str>>,
+}
+
+//Highlight query captures and the faces they color with. Captures that
+//aren't listed color like the part before their last dot, so
+//`function.method` is a `function`.
+const CAPTURE_FACES: &[(&str, &str)] = &[
+    ("comment", "font-lock-comment-face"),
+    ("string", "font-lock-string-face"),
+    ("escape", "font-lock-constant-face"),
+    ("keyword", "font-lock-keyword-face"),
+    ("function.builtin", "font-lock-builtin-face"),
+    ("function.macro", "font-lock-builtin-face"),
+    ("function", "font-lock-function-name-face"),
+    ("variable.builtin", "font-lock-builtin-face"),
+    ("variable.parameter", "font-lock-variable-name-face"),
+    ("type", "font-lock-type-face"),
+    ("constructor", "font-lock-type-face"),
+    ("attribute", "font-lock-builtin-face"),
+    ("label", "font-lock-constant-face"),
+    ("constant", "font-lock-constant-face"),
+    ("number", "font-lock-number-face"),
+    ("text.title", "font-lock-function-name-face"),
+    ("text.literal", "font-lock-string-face"),
+    ("text.uri", "font-lock-constant-face"),
+    ("text.reference", "font-lock-constant-face"),
+    ("punctuation.special", "font-lock-keyword-face"),
+];
+
+fn capture_face(mut capture: &str) -> Option<&'static str> {
+    loop {
+        if let Some((_, face)) = CAPTURE_FACES.iter().find(|(name, _)| *name == capture) {
+            return Some(face);
+        }
+        capture = &capture[..capture.rfind('.')?];
+    }
+}
+
+fn languages() -> &'static [SyntaxLanguage] {
+    static LANGUAGES: OnceLock<Vec<SyntaxLanguage>> = OnceLock::new();
+    LANGUAGES.get_or_init(|| {
+        vec![
+            SyntaxLanguage::new(
+                "bunlang",
+                tree_sitter_bunlang::LANGUAGE.into(),
+                tree_sitter_bunlang::HIGHLIGHTS_QUERY,
+            ),
+            SyntaxLanguage::new(
+                "rust",
+                tree_sitter_rust::LANGUAGE.into(),
+                tree_sitter_rust::HIGHLIGHTS_QUERY,
+            ),
+            //Only the block grammar, so no emphasis or inline code
+            SyntaxLanguage::new(
+                "markdown",
+                tree_sitter_md::LANGUAGE.into(),
+                tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+            ),
+        ]
+    })
+}
+
+impl SyntaxLanguage {
+    fn new(name: &'static str, language: Language, highlights: &str) -> Self {
+        //The queries ship with their grammars, so a bad one is a bug
+        let highlights = Query::new(&language, highlights).unwrap();
+        let capture_faces = highlights
+            .capture_names()
+            .iter()
+            .map(|name| capture_face(name))
+            .collect();
+        SyntaxLanguage {
+            name,
+            language,
+            highlights,
+            capture_faces,
+        }
+    }
+
+    //Which language a buffer gets is up to its major mode
+    pub fn named(name: &str) -> Option<&'static SyntaxLanguage> {
+        languages().iter().find(|language| language.name == name)
+    }
+}
+
+impl fmt::Debug for SyntaxLanguage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyntaxLanguage")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+//Where byte `byte` of `text` is as a row and a byte column
+pub(crate) fn point(text: &Rope, byte: usize) -> Point {
+    let row = text.byte_to_line(byte);
+    Point::new(row, byte - text.line_to_byte(row))
+}
+
+//`node` or the first sibling after it that isn't a comment or anything
+//else that can go anywhere
+fn skip_comments(node: Option<Node<'_>>) -> Option<Node<'_>> {
+    let mut node = node?;
+    while node.is_extra() {
+        node = node.next_named_sibling()?;
+    }
+    Some(node)
+}
+
+pub struct Syntax {
+    language: &'static SyntaxLanguage,
+    parser: Parser,
+    //None if parsing gave up
+    tree: Option<Tree>,
+}
+
+impl fmt::Debug for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Syntax")
+            .field("language", &self.language.name)
+            .field("tree", &self.tree)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Syntax {
+    pub(crate) fn new(language: &'static SyntaxLanguage, text: &Rope) -> Self {
+        let mut parser = Parser::new();
+        parser.set_language(&language.language).unwrap();
+        let mut syntax = Syntax {
+            language,
+            parser,
+            tree: None,
+        };
+        syntax.reparse(text, None);
+        syntax
+    }
+
+    pub fn language(&self) -> &'static SyntaxLanguage {
+        self.language
+    }
+
+    pub fn tree(&self) -> Option<&Tree> {
+        self.tree.as_ref()
+    }
+
+    //Catches the tree up with `text` after `edit`, only reparsing what the
+    //edit touched. Without an edit everything is parsed from scratch.
+    pub(crate) fn reparse(&mut self, text: &Rope, edit: Option<&InputEdit>) {
+        let old_tree = match (edit, &mut self.tree) {
+            (Some(edit), Some(tree)) => {
+                tree.edit(edit);
+                Some(&*tree)
+            }
+            _ => None,
+        };
+        let mut chunk = |byte: usize, _: Point| -> &[u8] {
+            if byte >= text.len_bytes() {
+                return &[];
+            }
+            let (chunk, start, _, _) = text.chunk_at_byte(byte);
+            &chunk.as_bytes()[byte - start..]
+        };
+        self.tree = self.parser.parse_with_options(&mut chunk, old_tree, None);
+    }
+
+    //The faces highlighting gives the chars in `range`, sorted and not
+    //overlapping. Inner nodes win over the nodes around them, and for the
+    //same node the earlier pattern in the query wins.
+    pub fn highlights(
+        &self,
+        text: &Rope,
+        range: Range<usize>,
+    ) -> Vec<(Range<usize>, &'static str)> {
+        let Some(tree) = &self.tree else {
+            return vec![];
+        };
+        let range = range.start.min(text.len_chars())..range.end.min(text.len_chars());
+        let bytes = text.char_to_byte(range.start)..text.char_to_byte(range.end);
+        let mut painted: Vec<Option<&'static str>> = vec![None; range.len()];
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(bytes.clone());
+        let node_text = |node: Node| {
+            text.byte_slice(node.byte_range())
+                .chunks()
+                .map(str::as_bytes)
+        };
+        let mut captures = cursor.captures(&self.language.highlights, tree.root_node(), node_text);
+        let mut last_node = None;
+        while let Some((found, i)) = captures.next() {
+            let capture = found.captures[*i];
+            let Some(face) = self.language.capture_faces[capture.index as usize] else {
+                continue;
+            };
+            if last_node == Some(capture.node.id()) {
+                continue;
+            }
+            last_node = Some(capture.node.id());
+            let node = capture.node.byte_range();
+            let start = text.byte_to_char(node.start.clamp(bytes.start, bytes.end));
+            let end = text.byte_to_char(node.end.clamp(bytes.start, bytes.end));
+            for slot in &mut painted[start - range.start..end - range.start] {
+                *slot = Some(face);
+            }
+        }
+        let mut highlights: Vec<(Range<usize>, &'static str)> = vec![];
+        for (i, face) in painted.into_iter().enumerate() {
+            let pos = range.start + i;
+            match (highlights.last_mut(), face) {
+                (Some((last, last_face)), Some(face)) if last.end == pos && *last_face == face => {
+                    last.end += 1;
+                }
+                (_, Some(face)) => highlights.push((pos..pos + 1, face)),
+                (_, None) => (),
+            }
+        }
+        highlights
+    }
+
+    //Where the named node after the one at `pos` starts, comments don't
+    //count. Between two nodes that's the one after `pos`.
+    pub fn next_sibling(&self, text: &Rope, pos: usize) -> Option<usize> {
+        let byte = text.char_to_byte(pos);
+        let mut node = self.tree.as_ref()?.root_node();
+        while let Some(child) = node.first_named_child_for_byte(byte) {
+            if child.start_byte() > byte {
+                //At the start of `node` it's the node after that instead
+                if node.start_byte() == byte {
+                    break;
+                }
+                let next = skip_comments(Some(child))?;
+                return Some(text.byte_to_char(next.start_byte()));
+            }
+            node = child;
+        }
+        let next = skip_comments(node.next_named_sibling())?;
+        Some(text.byte_to_char(next.start_byte()))
+    }
+
+    //Where the named node before `pos` starts. From inside a node that's
+    //its own start, like backward-sexp.
+    pub fn previous_sibling(&self, text: &Rope, pos: usize) -> Option<usize> {
+        let byte = text.char_to_byte(pos);
+        let mut node = self.tree.as_ref()?.root_node();
+        while let Some(child) = node.first_named_child_for_byte(byte) {
+            if child.start_byte() >= byte {
+                break;
+            }
+            node = child;
+        }
+        if node.start_byte() >= byte {
+            return None;
+        }
+        let mut cursor = node.walk();
+        let before = node
+            .named_children(&mut cursor)
+            .take_while(|child| child.end_byte() <= byte)
+            .filter(|child| !child.is_extra())
+            .last();
+        Some(text.byte_to_char(before.unwrap_or(node).start_byte()))
+    }
+
+    //The smallest named node that's bigger than [start, end), as chars
+    pub fn parent_range(&self, text: &Rope, start: usize, end: usize) -> Option<Range<usize>> {
+        let bytes = text.char_to_byte(start)..text.char_to_byte(end);
+        let mut node = self
+            .tree
+            .as_ref()?
+            .root_node()
+            .named_descendant_for_byte_range(bytes.start, bytes.end)?;
+        while node.byte_range() == bytes {
+            node = node.parent()?;
+        }
+        Some(text.byte_to_char(node.start_byte())..text.byte_to_char(node.end_byte()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{Buffer, BufferId};
+
+    //What a parse of the whole text from scratch gives
+    fn from_scratch(buffer: &Buffer) -> (String, Vec<(Range<usize>, &'static str)>) {
+        let text = buffer.text();
+        let syntax = Syntax::new(buffer.syntax().unwrap().language(), text);
+        let tree = syntax.tree().unwrap().root_node().to_sexp();
+        (tree, syntax.highlights(text, 0..text.len_chars()))
+    }
+
+    fn incremental(buffer: &Buffer) -> (String, Vec<(Range<usize>, &'static str)>) {
+        let text = buffer.text();
+        let syntax = buffer.syntax().unwrap();
+        let tree = syntax.tree().unwrap().root_node().to_sexp();
+        (tree, syntax.highlights(text, 0..text.len_chars()))
+    }
+
+    enum Edit {
+        Insert(usize, &'static str),
+        Delete(usize, usize),
+    }
+
+    #[test]
+    fn incremental_reparses_match_parsing_from_scratch() {
+        let mut buffer = Buffer::new(BufferId(0), "main.rs".to_owned());
+        buffer.set_major_mode("rust-mode", SyntaxLanguage::named("rust"));
+        buffer.insert(0, "fn main() {\n    let x = 1;\n}\n");
+        let edits = [
+            //Multi-byte text moves byte offsets away from char offsets
+            Edit::Insert(16, "let naïve_日本 = \"é🦀\"; "),
+            Edit::Insert(0, "// ünïcödé\n"),
+            //Opening a string swallows the rest of the line
+            Edit::Insert(27, "\""),
+            Edit::Delete(27, 28),
+            //Across lines and through the multi-byte text
+            Edit::Delete(8, 30),
+            Edit::Insert(8, "\n/* 🦀\n*/ struct S;\n"),
+            Edit::Delete(0, 11),
+            Edit::Insert(3, "é"),
+        ];
+        for edit in edits {
+            match edit {
+                Edit::Insert(at, text) => buffer.insert(at, text),
+                Edit::Delete(start, end) => buffer.delete(start, end),
+            }
+            assert_eq!(
+                incremental(&buffer),
+                from_scratch(&buffer),
+                "after editing to {:?}",
+                buffer.text().to_string()
+            );
+        }
+        assert!(!incremental(&buffer).1.is_empty());
+    }
+}
@@ -0,0 +1,13 @@
+//Fixtures shared by the tests of several modules
+
+use crate::{editor::Editor, frame::FrameId};
+
+//An editor with a frame showing the scratch buffer, `text` inserted into
+//it and point after that
+pub(crate) fn editing(text: &str) -> (Editor, FrameId) {
+    let mut editor = Editor::new();
+    let frame = editor.make_frame();
+    editor.insert(text);
+    editor.take_frontend_requests();
+    (editor, frame)
+}
@@ -0,0 +1,638 @@
+//Themes are sets of face definitions that can be switched on and off. They
+//come from bunlang files, or get imported from VS Code and TextMate themes.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bunlang::{Host, Interpreter, Value};
+use serde_json::Value as Json;
+
+use crate::{
+    face::{self, Face, Slant, Weight},
+    script,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    pub faces: HashMap<String, Face>,
+}
+
+//Extensions load-theme looks for, in the order it tries them
+pub const THEME_EXTENSIONS: &[&str] = &["bl", "json", "tmTheme"];
+
+//Faces a syntax theme's scopes color, each with the scopes that stand for
+//it, most specific first
+const SCOPE_FACES: &[(&str, &[&str])] = &[
+    ("font-lock-comment-face", &["comment.line", "comment"]),
+    ("font-lock-string-face", &["string.quoted", "string"]),
+    (
+        "font-lock-keyword-face",
+        &["keyword.control", "keyword", "storage.modifier"],
+    ),
+    ("font-lock-builtin-face", &["support.function", "support"]),
+    (
+        "font-lock-function-name-face",
+        &["entity.name.function", "entity.name"],
+    ),
+    (
+        "font-lock-variable-name-face",
+        &["variable.other", "variable"],
+    ),
+    (
+        "font-lock-type-face",
+        &["entity.name.type", "support.type", "storage.type"],
+    ),
+    (
+        "font-lock-constant-face",
+        &["constant.language", "constant"],
+    ),
+    ("font-lock-number-face", &["constant.numeric"]),
+];
+
+//VS Code workbench colors and which face's foreground or background each
+//one sets
+const VSCODE_COLORS: &[(&str, &str, Attribute)] = &[
+    ("editor.foreground", face::DEFAULT, Attribute::Foreground),
+    ("editor.background", face::DEFAULT, Attribute::Background),
+    (
+        "editorCursor.foreground",
+        face::CURSOR,
+        Attribute::Background,
+    ),
+    (
+        "editor.selectionBackground",
+        face::REGION,
+        Attribute::Background,
+    ),
+    (
+        "editor.lineHighlightBackground",
+        face::HL_LINE,
+        Attribute::Background,
+    ),
+    (
+        "statusBar.foreground",
+        face::MODE_LINE,
+        Attribute::Foreground,
+    ),
+    (
+        "statusBar.background",
+        face::MODE_LINE,
+        Attribute::Background,
+    ),
+    (
+        "tab.inactiveForeground",
+        face::MODE_LINE_INACTIVE,
+        Attribute::Foreground,
+    ),
+    (
+        "tab.inactiveBackground",
+        face::MODE_LINE_INACTIVE,
+        Attribute::Background,
+    ),
+    (
+        "editorGutter.background",
+        face::FRINGE,
+        Attribute::Background,
+    ),
+    (
+        "editorGroup.border",
+        face::VERTICAL_BORDER,
+        Attribute::Foreground,
+    ),
+    (
+        "list.activeSelectionForeground",
+        face::COMPLETIONS_CURRENT,
+        Attribute::Foreground,
+    ),
+    (
+        "list.activeSelectionBackground",
+        face::COMPLETIONS_CURRENT,
+        Attribute::Background,
+    ),
+    (
+        "textLink.foreground",
+        face::MINIBUFFER_PROMPT,
+        Attribute::Foreground,
+    ),
+    (
+        "editor.findMatchBackground",
+        face::ISEARCH,
+        Attribute::Background,
+    ),
+    (
+        "editor.findMatchHighlightBackground",
+        face::LAZY_HIGHLIGHT,
+        Attribute::Background,
+    ),
+    ("editorError.foreground", "error", Attribute::Foreground),
+    ("editorWarning.foreground", "warning", Attribute::Foreground),
+];
+
+//The same for the global settings of a TextMate theme
+const TEXTMATE_SETTINGS: &[(&str, &str, Attribute)] = &[
+    ("foreground", face::DEFAULT, Attribute::Foreground),
+    ("background", face::DEFAULT, Attribute::Background),
+    ("caret", face::CURSOR, Attribute::Background),
+    ("selection", face::REGION, Attribute::Background),
+    ("lineHighlight", face::HL_LINE, Attribute::Background),
+    ("findHighlight", face::ISEARCH, Attribute::Background),
+    (
+        "findHighlightForeground",
+        face::ISEARCH,
+        Attribute::Foreground,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Attribute {
+    Foreground,
+    Background,
+}
+
+impl Theme {
+    fn new(name: &str) -> Self {
+        Theme {
+            name: name.to_owned(),
+            faces: HashMap::new(),
+        }
+    }
+
+    fn set_color(&mut self, face: &str, attribute: Attribute, color: &str) {
+        let Some(color) = face::parse_color(color) else {
+            return;
+        };
+        let face = self.faces.entry(face.to_owned()).or_default();
+        match attribute {
+            Attribute::Foreground => face.foreground = Some(color),
+            Attribute::Background => face.background = Some(color),
+        }
+    }
+}
+
+//Like Emacs' custom-theme-load-path, ~/.config/bunmacs/themes
+pub fn default_load_path() -> Vec<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    config
+        .map(|config| config.join("bunmacs").join("themes"))
+        .into_iter()
+        .collect()
+}
+
+//Where theme `name` lives in `load_path`, trying each extension in turn.
+//Something that's already a path to a file is taken as is.
+pub fn find(load_path: &[PathBuf], name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_file() {
+        return Some(path.to_owned());
+    }
+    load_path.iter().find_map(|dir| {
+        THEME_EXTENSIONS
+            .iter()
+            .map(|ext| dir.join(format!("{name}.{ext}")))
+            .find(|path| path.is_file())
+    })
+}
+
+//Every theme in `load_path`, sorted
+pub fn available(load_path: &[PathBuf]) -> Vec<String> {
+    let mut names: Vec<String> = load_path
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let ext = path.extension()?.to_str()?;
+            THEME_EXTENSIONS.contains(&ext).then_some(())?;
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+//Reads a theme file, picking the format by extension. Themes are named
+//after their file, so disable-theme takes the name load-theme did.
+pub fn load(path: &Path) -> Result<Theme, String> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let read_error = |e| format!("Couldn't read {}: {e}", path.display());
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => parse_vscode(&fs::read_to_string(path).map_err(read_error)?, &name),
+        Some("tmTheme") => parse_textmate(&fs::read(path).map_err(read_error)?, &name),
+        _ => parse_bunlang(&fs::read_to_string(path).map_err(read_error)?, &name),
+    }
+}
+
+//Collects what a bunlang theme file defines. Theme files can't do anything
+//else to the editor.
+struct ThemeHost {
+    theme: Theme,
+}
+
+impl Host for ThemeHost {
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Option<Result<Value, String>> {
+        Some(match name {
+            //(deftheme NAME), which has to match the file name
+            "deftheme" => match args.first() {
+                Some(value) => script::face_name(value, name).and_then(|theme| {
+                    if theme != self.theme.name {
+                        return Err(format!(
+                            "deftheme: {theme} defined in the file for {}",
+                            self.theme.name
+                        ));
+                    }
+                    Ok(Value::List(vec![]))
+                }),
+                None => Err("deftheme: missing name".to_owned()),
+            },
+            //(theme-face FACE :attribute value...)
+            "theme-face" => match args.split_first() {
+                Some((face, attributes)) => script::face_name(face, name).and_then(|face| {
+                    let mut spec = Face::default();
+                    script::set_face_attributes(&mut spec, attributes, name)?;
+                    self.theme.faces.insert(face.clone(), spec);
+                    Ok(Value::Symbol(face))
+                }),
+                None => Err("theme-face: missing face".to_owned()),
+            },
+            _ => return None,
+        })
+    }
+}
+
+pub fn parse_bunlang(source: &str, name: &str) -> Result<Theme, String> {
+    let mut host = ThemeHost {
+        theme: Theme::new(name),
+    };
+    script::eval(&mut Interpreter::new(), source, &mut host)?;
+    Ok(host.theme)
+}
+
+//VS Code themes are JSON with comments and trailing commas
+fn strip_jsonc(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            //A trailing comma, once comments are gone only whitespace can
+            //stand between it and the bracket
+            (']' | '}', _) => {
+                let trimmed = out.trim_end().len();
+                if out[..trimmed].ends_with(',') {
+                    out.truncate(trimmed - 1);
+                }
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn font_style(face: &mut Face, style: &str) {
+    face.weight = Some(Weight::Normal);
+    face.slant = Some(Slant::Normal);
+    face.underline = Some(false);
+    for word in style.split_whitespace() {
+        match word {
+            "bold" => face.weight = Some(Weight::Bold),
+            "italic" => face.slant = Some(Slant::Italic),
+            "underline" => face.underline = Some(true),
+            _ => (),
+        }
+    }
+}
+
+//One entry of `tokenColors` or a TextMate `settings` array
+struct ScopeRule {
+    selectors: Vec<String>,
+    face: Face,
+}
+
+fn scope_rule(rule: &Json) -> Option<ScopeRule> {
+    let selectors: Vec<&str> = match rule.get("scope")? {
+        Json::String(scopes) => scopes.split(',').collect(),
+        Json::Array(scopes) => scopes.iter().filter_map(Json::as_str).collect(),
+        _ => return None,
+    };
+    let settings = rule.get("settings")?;
+    let color = |key| settings.get(key)?.as_str().and_then(face::parse_color);
+    let mut face = Face {
+        foreground: color("foreground"),
+        background: color("background"),
+        ..Face::default()
+    };
+    if let Some(style) = settings.get("fontStyle").and_then(Json::as_str) {
+        font_style(&mut face, style);
+    }
+    //Only the last scope of a descendant selector like "source.rust
+    //keyword" counts, the language doesn't matter here
+    let selectors = selectors
+        .iter()
+        .filter_map(|s| s.split_whitespace().last())
+        .map(str::to_owned)
+        .collect();
+    Some(ScopeRule { selectors, face })
+}
+
+//How specific `selector` is about `scope`, None if it doesn't match.
+//"keyword" matches "keyword.control" but not the other way around.
+fn selector_match(selector: &str, scope: &str) -> Option<usize> {
+    let matches = scope == selector
+        || (scope.starts_with(selector) && scope[selector.len()..].starts_with('.'));
+    matches.then(|| selector.split('.').count())
+}
+
+//Picks a rule for each font lock face: the first of its scopes that any
+//rule matches, and of those the most specific rule, later ones winning ties
+fn apply_scope_rules(theme: &mut Theme, rules: &[ScopeRule]) {
+    for (face, scopes) in SCOPE_FACES {
+        let best = scopes.iter().find_map(|scope| {
+            rules
+                .iter()
+                .filter_map(|rule| {
+                    let specificity = rule
+                        .selectors
+                        .iter()
+                        .filter_map(|selector| selector_match(selector, scope))
+                        .max()?;
+                    Some((specificity, rule))
+                })
+                //max_by_key keeps the last of equals
+                .max_by_key(|(specificity, _)| *specificity)
+        });
+        if let Some((_, rule)) = best {
+            theme.faces.insert(face.to_string(), rule.face.clone());
+        }
+    }
+}
+
+//Rules with no scope hold the global colors, like a tmTheme's first entry
+fn apply_global_settings(theme: &mut Theme, rules: &[Json]) {
+    for settings in rules
+        .iter()
+        .filter(|rule| rule.get("scope").is_none())
+        .filter_map(|rule| rule.get("settings"))
+    {
+        for (key, face, attribute) in TEXTMATE_SETTINGS {
+            if let Some(color) = settings.get(key).and_then(Json::as_str) {
+                theme.set_color(face, *attribute, color);
+            }
+        }
+    }
+}
+
+pub fn parse_vscode(source: &str, name: &str) -> Result<Theme, String> {
+    let json: Json =
+        serde_json::from_str(&strip_jsonc(source)).map_err(|e| format!("Bad theme JSON: {e}"))?;
+    let mut theme = Theme::new(name);
+    let token_colors = json
+        .get("tokenColors")
+        .and_then(Json::as_array)
+        .cloned()
+        .unwrap_or_default();
+    apply_global_settings(&mut theme, &token_colors);
+    if let Some(colors) = json.get("colors") {
+        for (key, face, attribute) in VSCODE_COLORS {
+            if let Some(color) = colors.get(key).and_then(Json::as_str) {
+                theme.set_color(face, *attribute, color);
+            }
+        }
+    }
+    let rules: Vec<ScopeRule> = token_colors.iter().filter_map(scope_rule).collect();
+    apply_scope_rules(&mut theme, &rules);
+    Ok(theme)
+}
+
+fn plist_to_json(value: plist::Value) -> Json {
+    match value {
+        plist::Value::Dictionary(dict) => Json::Object(
+            dict.into_iter()
+                .map(|(key, value)| (key, plist_to_json(value)))
+                .collect(),
+        ),
+        plist::Value::Array(items) => Json::Array(items.into_iter().map(plist_to_json).collect()),
+        plist::Value::String(s) => Json::String(s),
+        plist::Value::Boolean(b) => Json::Bool(b),
+        plist::Value::Integer(n) => n.as_signed().map(Json::from).unwrap_or(Json::Null),
+        plist::Value::Real(n) => Json::from(n),
+        _ => Json::Null,
+    }
+}
+
+//TextMate's .tmTheme, a plist whose settings are laid out like VS Code's
+//tokenColors
+pub fn parse_textmate(bytes: &[u8], name: &str) -> Result<Theme, String> {
+    let plist = plist::Value::from_reader(std::io::Cursor::new(bytes))
+        .map_err(|e| format!("Bad tmTheme: {e}"))?;
+    let json = plist_to_json(plist);
+    let mut theme = Theme::new(name);
+    let settings = json
+        .get("settings")
+        .and_then(Json::as_array)
+        .cloned()
+        .unwrap_or_default();
+    apply_global_settings(&mut theme, &settings);
+    let rules: Vec<ScopeRule> = settings.iter().filter_map(scope_rule).collect();
+    apply_scope_rules(&mut theme, &rules);
+    Ok(theme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoration::Color;
+
+    const VSCODE: &str = r##"{
+  // Comments and trailing commas are fine in VS Code themes
+  "name": "Fixture",
+  "colors": {
+    "editor.foreground": "#d0d0d0",
+    "editor.background": "#101010",
+    "statusBar.background": "#202040", /* and block comments */
+  },
+  "tokenColors": [
+    {"settings": {"foreground": "#eeeeee", "caret": "#ff0000"}},
+    {"scope": "comment", "settings": {"foreground": "#808080", "fontStyle": "italic"}},
+    {"scope": ["keyword", "storage.modifier"], "settings": {"foreground": "#c080ff"}},
+    {"scope": "source.rust keyword.control", "settings": {"foreground": "#ff80c0", "fontStyle": "bold italic"}},
+    {"scope": "string", "settings": {"foreground": "#80ff80", "background": "#002000"}},
+    {"scope": "constant", "settings": {"foreground": "#ffc080", "fontStyle": ""}},
+  ],
+}"##;
+
+    const TEXTMATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>name</key>
+  <string>Fixture</string>
+  <key>settings</key>
+  <array>
+    <dict>
+      <key>settings</key>
+      <dict>
+        <key>foreground</key>
+        <string>#F8F8F2</string>
+        <key>background</key>
+        <string>#272822</string>
+        <key>selection</key>
+        <string>#49483E</string>
+      </dict>
+    </dict>
+    <dict>
+      <key>scope</key>
+      <string>comment</string>
+      <key>settings</key>
+      <dict>
+        <key>foreground</key>
+        <string>#75715E</string>
+        <key>fontStyle</key>
+        <string>italic</string>
+      </dict>
+    </dict>
+    <dict>
+      <key>scope</key>
+      <string>keyword, storage.type</string>
+      <key>settings</key>
+      <dict>
+        <key>foreground</key>
+        <string>#F92672</string>
+        <key>fontStyle</key>
+        <string>bold underline</string>
+      </dict>
+    </dict>
+    <dict>
+      <key>scope</key>
+      <string>string</string>
+      <key>settings</key>
+      <dict>
+        <key>foreground</key>
+        <string>#E6DB74</string>
+      </dict>
+    </dict>
+  </array>
+</dict>
+</plist>"#;
+
+    fn color(hex: &str) -> Option<Color> {
+        face::parse_color(hex)
+    }
+
+    fn face<'a>(theme: &'a Theme, name: &str) -> &'a Face {
+        theme
+            .faces
+            .get(name)
+            .unwrap_or_else(|| panic!("no {name} in {:?}", theme.faces.keys()))
+    }
+
+    #[test]
+    fn imports_vscode_themes() {
+        let theme = parse_vscode(VSCODE, "fixture").unwrap();
+        assert_eq!(theme.name, "fixture");
+
+        //The workbench colors win over the global token settings
+        let default = face(&theme, face::DEFAULT);
+        assert_eq!(default.foreground, color("#d0d0d0"));
+        assert_eq!(default.background, color("#101010"));
+        assert_eq!(face(&theme, face::CURSOR).background, color("#ff0000"));
+        assert_eq!(face(&theme, face::MODE_LINE).background, color("#202040"));
+
+        let comment = face(&theme, "font-lock-comment-face");
+        assert_eq!(comment.foreground, color("#808080"));
+        assert_eq!(comment.slant, Some(Slant::Italic));
+        assert_eq!(comment.weight, Some(Weight::Normal));
+
+        //keyword.control's own rule is more specific than plain keyword's
+        let keyword = face(&theme, "font-lock-keyword-face");
+        assert_eq!(keyword.foreground, color("#ff80c0"));
+        assert_eq!(keyword.weight, Some(Weight::Bold));
+        assert_eq!(keyword.slant, Some(Slant::Italic));
+
+        //No fontStyle leaves the style to the face underneath, an empty
+        //one resets it
+        let string = face(&theme, "font-lock-string-face");
+        assert_eq!(string.foreground, color("#80ff80"));
+        assert_eq!(string.background, color("#002000"));
+        assert_eq!((string.weight, string.slant), (None, None));
+        let constant = face(&theme, "font-lock-constant-face");
+        assert_eq!(constant.foreground, color("#ffc080"));
+        assert_eq!(constant.weight, Some(Weight::Normal));
+        assert_eq!(constant.slant, Some(Slant::Normal));
+        assert_eq!(face(&theme, "font-lock-number-face"), constant);
+
+        assert!(!theme.faces.contains_key("font-lock-type-face"));
+        assert!(parse_vscode("{", "broken").is_err());
+    }
+
+    #[test]
+    fn imports_textmate_themes() {
+        let theme = parse_textmate(TEXTMATE.as_bytes(), "fixture").unwrap();
+        let default = face(&theme, face::DEFAULT);
+        assert_eq!(default.foreground, color("#F8F8F2"));
+        assert_eq!(default.background, color("#272822"));
+        assert_eq!(face(&theme, face::REGION).background, color("#49483E"));
+
+        let comment = face(&theme, "font-lock-comment-face");
+        assert_eq!(comment.foreground, color("#75715E"));
+        assert_eq!(comment.slant, Some(Slant::Italic));
+        assert_eq!(comment.weight, Some(Weight::Normal));
+
+        //One rule can cover several scopes
+        for name in ["font-lock-keyword-face", "font-lock-type-face"] {
+            let keyword = face(&theme, name);
+            assert_eq!(keyword.foreground, color("#F92672"));
+            assert_eq!(keyword.weight, Some(Weight::Bold));
+            assert_eq!(keyword.slant, Some(Slant::Normal));
+            assert_eq!(keyword.underline, Some(true));
+        }
+
+        let string = face(&theme, "font-lock-string-face");
+        assert_eq!(string.foreground, color("#E6DB74"));
+        assert_eq!(string.background, None);
+        assert_eq!(string.weight, None);
+
+        assert!(parse_textmate(b"<plist>", "broken").is_err());
+    }
+}
@@ -0,0 +1,542 @@
+//Editor variables. Each one has a global default that buffers can override
+//with a value of their own, like Emacs' buffer-local variables, plus
+//documentation, a type every value gets checked against, and watchers
+//that run whenever it changes.
+
+use std::{collections::HashMap, fmt};
+
+use bunlang::Value;
+
+use crate::{buffer::Buffer, editor::Editor};
+
+//What values a variable accepts. A fixed set rather than predicates
+//scripts supply, since bunlang has no comparisons to write them with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableType {
+    Any,
+    Boolean,
+    //0 and up
+    Natural,
+    //1 and up
+    Positive,
+    String,
+    //One of these symbols
+    Choice(Vec<String>),
+}
+
+impl VariableType {
+    pub fn accepts(&self, value: &Value) -> bool {
+        match (self, value) {
+            (VariableType::Any, _) => true,
+            (VariableType::Boolean, Value::Bool(_)) => true,
+            (VariableType::Natural, Value::Number(n)) => *n >= 0,
+            (VariableType::Positive, Value::Number(n)) => *n > 0,
+            (VariableType::String, Value::Str(_)) => true,
+            (VariableType::Choice(choices), Value::Symbol(s)) => choices.contains(s),
+            _ => false,
+        }
+    }
+
+    //From a defvar's :type, e.g. `natural` or `(list choice box bar)`
+    pub(crate) fn parse(value: &Value) -> Result<Self, String> {
+        let bad_type = || format!("unknown type {value}");
+        match value {
+            Value::Symbol(s) => match s.as_str() {
+                "any" => Ok(VariableType::Any),
+                "boolean" => Ok(VariableType::Boolean),
+                "natural" => Ok(VariableType::Natural),
+                "positive" => Ok(VariableType::Positive),
+                "string" => Ok(VariableType::String),
+                _ => Err(bad_type()),
+            },
+            Value::List(items) => match &items[..] {
+                [Value::Symbol(head), choices @ ..] if head == "choice" => choices
+                    .iter()
+                    .map(|choice| match choice {
+                        Value::Symbol(s) => Ok(s.clone()),
+                        _ => Err(bad_type()),
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(VariableType::Choice),
+                _ => Err(bad_type()),
+            },
+            _ => Err(bad_type()),
+        }
+    }
+}
+
+impl fmt::Display for VariableType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableType::Any => f.write_str("anything"),
+            VariableType::Boolean => f.write_str("#t or #f"),
+            VariableType::Natural => f.write_str("a number 0 or more"),
+            VariableType::Positive => f.write_str("a number 1 or more"),
+            VariableType::String => f.write_str("a string"),
+            VariableType::Choice(choices) => write!(f, "one of {}", choices.join(", ")),
+        }
+    }
+}
+
+//Runs after a variable changes, with the buffer it changed in current.
+//Gets the variable's name.
+#[derive(Debug, Clone)]
+pub enum Watcher {
+    Native(fn(&mut Editor, &str)),
+    //bunlang source
+    Script(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub doc: String,
+    pub value_type: VariableType,
+    //Plain setq makes a buffer-local value, like make-variable-buffer-local
+    pub local_if_set: bool,
+    default: Value,
+    watchers: Vec<Watcher>,
+}
+
+impl Variable {
+    pub fn default_value(&self) -> &Value {
+        &self.default
+    }
+}
+
+#[derive(Debug)]
+pub struct Variables {
+    variables: HashMap<String, Variable>,
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn redraw(editor: &mut Editor, _: &str) {
+    editor.request_redraw();
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        let mut variables = Variables {
+            variables: HashMap::new(),
+        };
+        let choice = |choices: &[&str]| {
+            VariableType::Choice(choices.iter().map(|s| s.to_string()).collect())
+        };
+        let builtins = [
+            (
+                "tab-width",
+                Value::Number(8),
+                VariableType::Positive,
+                true,
+                "Distance between tab stops, in columns.",
+            ),
+            (
+                "fill-column",
+                Value::Number(70),
+                VariableType::Natural,
+                true,
+                "Column past which filling breaks lines.",
+            ),
+            (
+                "truncate-lines",
+                Value::Bool(false),
+                VariableType::Boolean,
+                true,
+                "Whether long lines get cut off at the window edge instead of wrapping.",
+            ),
+            (
+                "left-fringe-width",
+                Value::Number(0),
+                VariableType::Natural,
+                true,
+                "Columns left of the text for marks like breakpoints. 0 for no fringe.",
+            ),
+            (
+                "cursor-type",
+                Value::Symbol("box".to_owned()),
+                choice(&["box", "bar", "underline", "hollow"]),
+                false,
+                "How the cursor of the selected window is drawn.",
+            ),
+            (
+                "highlight-current-line",
+                Value::Bool(true),
+                VariableType::Boolean,
+                false,
+                "Whether the line with point is highlighted in the selected window.",
+            ),
+            (
+                "make-backup-files",
+                Value::Bool(true),
+                VariableType::Boolean,
+                false,
+                "Whether the first save of a file copies what was there to FILE~.",
+            ),
+            (
+                "case-fold-search",
+                Value::Bool(true),
+                VariableType::Boolean,
+                true,
+                "Whether searches ignore case. Ones with upper case letters never do.",
+            ),
+            (
+                "lazy-highlight",
+                Value::Bool(true),
+                VariableType::Boolean,
+                false,
+                "Whether isearch and query-replace highlight every match on screen.",
+            ),
+            (
+                "completion-rows",
+                Value::Number(10),
+                VariableType::Positive,
+                false,
+                "How many candidates the minibuffer shows at once.",
+            ),
+            (
+                "default-directory",
+                Value::Str(String::new()),
+                VariableType::String,
+                true,
+                "Where relative file names start from in buffers not visiting a file. Empty for the working directory.",
+            ),
+        ];
+        for (name, default, value_type, local_if_set, doc) in builtins {
+            variables
+                .define(name, default, doc, value_type, local_if_set)
+                .unwrap();
+        }
+        for name in [
+            "tab-width",
+            "truncate-lines",
+            "left-fringe-width",
+            "cursor-type",
+            "highlight-current-line",
+            "lazy-highlight",
+            "completion-rows",
+        ] {
+            variables.add_watcher(name, Watcher::Native(redraw));
+        }
+        variables
+    }
+
+    //Defines variable `name`, like defvar. Redefining one updates its
+    //documentation and type but keeps its value, unless the value doesn't
+    //fit the new type.
+    pub fn define(
+        &mut self,
+        name: &str,
+        default: Value,
+        doc: &str,
+        value_type: VariableType,
+        local_if_set: bool,
+    ) -> Result<(), String> {
+        if !value_type.accepts(&default) {
+            return Err(format!("{name} should be {value_type}, not {default}"));
+        }
+        let (default, watchers) = match self.variables.remove(name) {
+            Some(old) if value_type.accepts(&old.default) => (old.default, old.watchers),
+            Some(old) => (default, old.watchers),
+            None => (default, vec![]),
+        };
+        self.variables.insert(
+            name.to_owned(),
+            Variable {
+                doc: doc.to_owned(),
+                value_type,
+                local_if_set,
+                default,
+                watchers,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Variable> {
+        self.variables.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.variables.keys().map(String::as_str)
+    }
+
+    //Whether `value` can go in `name`. Setting a variable that was never
+    //defined defines it, taking anything.
+    pub fn check(&self, name: &str, value: &Value) -> Result<(), String> {
+        match self.variables.get(name) {
+            Some(variable) if !variable.value_type.accepts(value) => Err(format!(
+                "{name} should be {}, not {value}",
+                variable.value_type
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn set_default(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.check(name, &value)?;
+        match self.variables.get_mut(name) {
+            Some(variable) => variable.default = value,
+            None => self.define(name, value, "", VariableType::Any, false)?,
+        }
+        Ok(())
+    }
+
+    pub fn add_watcher(&mut self, name: &str, watcher: Watcher) {
+        let Some(variable) = self.variables.get_mut(name) else {
+            return;
+        };
+        //Adding the same source twice shouldn't run it twice
+        let duplicate = variable.watchers.iter().any(|old| match (old, &watcher) {
+            (Watcher::Script(old), Watcher::Script(new)) => old == new,
+            _ => false,
+        });
+        if !duplicate {
+            variable.watchers.push(watcher);
+        }
+    }
+
+    pub(crate) fn watchers(&self, name: &str) -> Vec<Watcher> {
+        self.variables
+            .get(name)
+            .map(|variable| variable.watchers.clone())
+            .unwrap_or_default()
+    }
+
+    //The value of `name` in `buffer`, its own if it has one
+    pub fn value<'a>(&'a self, name: &str, buffer: &'a Buffer) -> Option<&'a Value> {
+        buffer
+            .local_variable(name)
+            .or_else(|| self.variables.get(name).map(|v| &v.default))
+    }
+
+    //Shorthands for the builtin variables, whose types are known
+    pub fn flag(&self, name: &str, buffer: &Buffer) -> bool {
+        matches!(self.value(name, buffer), Some(Value::Bool(true)))
+    }
+
+    pub fn number(&self, name: &str, buffer: &Buffer) -> usize {
+        match self.value(name, buffer) {
+            Some(Value::Number(n)) => (*n).max(0) as usize,
+            _ => 0,
+        }
+    }
+
+    pub fn symbol<'a>(&'a self, name: &str, buffer: &'a Buffer) -> &'a str {
+        match self.value(name, buffer) {
+            Some(Value::Symbol(s)) => s,
+            _ => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::editing;
+
+    //Counts its runs in `watched`, which has no watchers of its own
+    fn count(editor: &mut Editor, _: &str) {
+        let Some(Value::Number(n)) = editor.variable("watched").cloned() else {
+            unreachable!()
+        };
+        editor.set_default("watched", Value::Number(n + 1)).unwrap();
+    }
+
+    fn watching(name: &str) -> Editor {
+        let (mut editor, _) = editing("");
+        editor.set_default("watched", Value::Number(0)).unwrap();
+        editor.variables.add_watcher(name, Watcher::Native(count));
+        editor
+    }
+
+    fn runs(editor: &Editor) -> Value {
+        editor.variable("watched").cloned().unwrap()
+    }
+
+    #[test]
+    fn values_must_fit_the_type() {
+        let (mut editor, _) = editing("");
+        assert!(editor.set_variable("tab-width", Value::Number(0)).is_err());
+        assert!(editor
+            .set_default("fill-column", Value::Number(-1))
+            .is_err());
+        assert!(editor
+            .set_local("truncate-lines", Value::Number(1))
+            .is_err());
+        let cursor = |s: &str| Value::Symbol(s.to_owned());
+        assert!(editor.set_variable("cursor-type", cursor("blink")).is_err());
+        editor.set_variable("cursor-type", cursor("bar")).unwrap();
+        assert_eq!(editor.variable("cursor-type"), Some(&cursor("bar")));
+        assert_eq!(editor.variable("tab-width"), Some(&Value::Number(8)));
+
+        //Redefining keeps a value that fits the new type and drops one
+        //that doesn't
+        let vars = &mut editor.variables;
+        vars.define("depth", Value::Number(3), "", VariableType::Natural, false)
+            .unwrap();
+        vars.define("depth", Value::Number(1), "", VariableType::Any, false)
+            .unwrap();
+        assert_eq!(
+            vars.get("depth").unwrap().default_value(),
+            &Value::Number(3)
+        );
+        let string = Value::Str("deep".to_owned());
+        vars.define("depth", string.clone(), "", VariableType::String, false)
+            .unwrap();
+        assert_eq!(vars.get("depth").unwrap().default_value(), &string);
+        assert!(vars
+            .define("depth", Value::Number(1), "", VariableType::String, false)
+            .is_err());
+    }
+
+    #[test]
+    fn local_values_override_the_default_in_their_buffer_only() {
+        let (mut editor, _) = editing("");
+        let scratch = editor.current_buffer_id();
+        editor.set_local("fill-column", Value::Number(40)).unwrap();
+        editor
+            .set_default("fill-column", Value::Number(60))
+            .unwrap();
+        assert_eq!(editor.variable("fill-column"), Some(&Value::Number(40)));
+
+        let other = editor.create_buffer("other");
+        editor.switch_to_buffer(other);
+        assert_eq!(editor.variable("fill-column"), Some(&Value::Number(60)));
+
+        editor.switch_to_buffer(scratch);
+        editor.kill_local_variable("fill-column");
+        assert_eq!(editor.variable("fill-column"), Some(&Value::Number(60)));
+    }
+
+    #[test]
+    fn set_variable_changes_the_local_value_once_there_is_one() {
+        let (mut editor, _) = editing("");
+        //make-backup-files isn't automatically local, so setq sets the
+        //default until the buffer has its own value
+        editor
+            .set_variable("make-backup-files", Value::Bool(false))
+            .unwrap();
+        assert!(editor
+            .current_buffer()
+            .local_variable("make-backup-files")
+            .is_none());
+        editor
+            .set_local("make-backup-files", Value::Bool(true))
+            .unwrap();
+        editor
+            .set_variable("make-backup-files", Value::Bool(false))
+            .unwrap();
+        let variable = editor.variables.get("make-backup-files").unwrap();
+        assert_eq!(variable.default_value(), &Value::Bool(false));
+        assert_eq!(
+            editor.current_buffer().local_variable("make-backup-files"),
+            Some(&Value::Bool(false))
+        );
+
+        //fill-column is, so setq never touches its default
+        editor
+            .set_variable("fill-column", Value::Number(50))
+            .unwrap();
+        let variable = editor.variables.get("fill-column").unwrap();
+        assert_eq!(variable.default_value(), &Value::Number(70));
+        assert_eq!(editor.variable("fill-column"), Some(&Value::Number(50)));
+    }
+
+    #[test]
+    fn watchers_run_on_every_change() {
+        let mut editor = watching("fill-column");
+        editor
+            .set_default("fill-column", Value::Number(60))
+            .unwrap();
+        assert_eq!(runs(&editor), Value::Number(1));
+        editor.set_local("fill-column", Value::Number(50)).unwrap();
+        assert_eq!(runs(&editor), Value::Number(2));
+        editor
+            .set_variable("fill-column", Value::Number(40))
+            .unwrap();
+        assert_eq!(runs(&editor), Value::Number(3));
+        editor.kill_local_variable("fill-column");
+        assert_eq!(runs(&editor), Value::Number(4));
+        //Nothing was local, so nothing changed
+        editor.kill_local_variable("fill-column");
+        assert_eq!(runs(&editor), Value::Number(4));
+        //Nor does a value that doesn't fit
+        assert!(editor
+            .set_default("fill-column", Value::Number(-1))
+            .is_err());
+        assert_eq!(runs(&editor), Value::Number(4));
+    }
+
+    #[test]
+    fn changing_major_mode_runs_the_watchers_of_dropped_locals() {
+        let mut editor = watching("fill-column");
+        editor.set_local("fill-column", Value::Number(50)).unwrap();
+        editor.set_local("tab-width", Value::Number(4)).unwrap();
+        assert_eq!(runs(&editor), Value::Number(1));
+        editor.set_major_mode("text-mode").unwrap();
+        assert_eq!(runs(&editor), Value::Number(2));
+        assert_eq!(editor.variable("fill-column"), Some(&Value::Number(70)));
+        assert_eq!(editor.variable("tab-width"), Some(&Value::Number(8)));
+    }
+
+    #[test]
+    fn let_variable_puts_back_the_value_it_shadowed() {
+        let mut editor = watching("make-backup-files");
+        let backups = |editor: &mut Editor| editor.variable("make-backup-files").cloned();
+        let inside = editor
+            .let_variable("make-backup-files", Value::Bool(false), backups)
+            .unwrap();
+        assert_eq!(inside, Some(Value::Bool(false)));
+        assert_eq!(backups(&mut editor), Some(Value::Bool(true)));
+        assert_eq!(runs(&editor), Value::Number(2));
+
+        //An automatically local variable gets a local value just while
+        //bound, even if the body switches buffers
+        let scratch = editor.current_buffer_id();
+        let other = editor.create_buffer("other");
+        editor
+            .let_variable("fill-column", Value::Number(20), |editor| {
+                editor.switch_to_buffer(other);
+                assert_eq!(editor.variable("fill-column"), Some(&Value::Number(70)));
+            })
+            .unwrap();
+        assert_eq!(editor.current_buffer_id(), other);
+        let local = |editor: &Editor| {
+            editor
+                .buffer(scratch)
+                .unwrap()
+                .local_variable("fill-column")
+                .cloned()
+        };
+        assert_eq!(local(&editor), None);
+
+        editor.switch_to_buffer(scratch);
+        editor.set_local("fill-column", Value::Number(30)).unwrap();
+        editor
+            .let_variable("fill-column", Value::Number(20), |_| ())
+            .unwrap();
+        assert_eq!(local(&editor), Some(Value::Number(30)));
+
+        assert!(editor
+            .let_variable("no-such-variable", Value::Bool(true), |_| ())
+            .is_err());
+        assert!(editor
+            .let_variable("tab-width", Value::Number(0), |_| ())
+            .is_err());
+        assert_eq!(editor.variable("tab-width"), Some(&Value::Number(8)));
+    }
+
+    #[test]
+    fn let_variable_from_scripts() {
+        let (mut editor, _) = editing("");
+        let inside = editor
+            .eval_immediately(r#"(let-variable fill-column 12 "(symbol-value fill-column)")"#)
+            .unwrap();
+        assert_eq!(inside, Value::Number(12));
+        assert_eq!(editor.variable("fill-column"), Some(&Value::Number(70)));
+    }
+}
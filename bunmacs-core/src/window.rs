@@ -0,0 +1,541 @@
+use ropey::Rope;
+
+use crate::{
+    buffer::{BufferId, MarkerId},
+    layout::{CellMetrics, Layout, LayoutParams, LineCache, Rect},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WindowId(pub(crate) u64);
+
+//A view of a buffer inside a frame. Each window remembers its own point so
+//two windows on one buffer can look at different places.
+#[derive(Debug)]
+pub struct Window {
+    id: WindowId,
+    buffer: BufferId,
+    //Only authoritative while the window isn't selected, the selected
+    //window's point is the buffer's point
+    pub(crate) point: MarkerId,
+    pub(crate) start_line: usize,
+    //Text grid size as of the last redisplay
+    pub(crate) cols: usize,
+    pub(crate) rows: usize,
+    //The lines last redisplay laid out, so an edit only lays out the lines
+    //it touched again
+    lines: LineCache,
+}
+
+impl Window {
+    pub(crate) fn new(id: WindowId, buffer: BufferId, point: MarkerId) -> Self {
+        Window {
+            id,
+            buffer,
+            point,
+            start_line: 0,
+            cols: 0,
+            rows: 0,
+            lines: LineCache::default(),
+        }
+    }
+
+    //Lays out `text` from the window's start line, reusing whatever lines
+    //haven't changed since last time
+    pub(crate) fn text_layout(&mut self, text: &Rope, area: Rect, params: &LayoutParams) -> Layout {
+        Layout::with_cache(text, self.start_line, area, params, &mut self.lines)
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
+
+    pub fn buffer(&self) -> BufferId {
+        self.buffer
+    }
+
+    pub(crate) fn set_buffer(&mut self, buffer: BufferId, point: MarkerId) {
+        self.buffer = buffer;
+        self.point = point;
+        self.start_line = 0;
+    }
+
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    //Stacked on top of each other, C-x 2
+    Below,
+    //Side by side, C-x 3
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowTree {
+    Leaf(WindowId),
+    //Each child's weight is its share of the split, weights sum to 1
+    Split {
+        direction: SplitDirection,
+        children: Vec<(WindowTree, f32)>,
+    },
+}
+
+//A rectangle on the character grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CellRect {
+    pub col: usize,
+    pub row: usize,
+    pub cols: usize,
+    pub rows: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TreeLayout {
+    pub windows: Vec<(WindowId, CellRect)>,
+    //Vertical borders between side by side windows
+    pub dividers: Vec<CellRect>,
+}
+
+pub const WINDOW_MIN_COLS: usize = 10;
+pub const WINDOW_MIN_ROWS: usize = 4;
+
+impl SplitDirection {
+    fn extent(&self, rect: CellRect) -> usize {
+        match self {
+            SplitDirection::Below => rect.rows,
+            SplitDirection::Right => rect.cols,
+        }
+    }
+
+    //Side by side windows get a one column divider between them
+    pub(crate) fn gap(&self) -> usize {
+        match self {
+            SplitDirection::Below => 0,
+            SplitDirection::Right => 1,
+        }
+    }
+
+    pub(crate) fn min_size(&self) -> usize {
+        match self {
+            SplitDirection::Below => WINDOW_MIN_ROWS,
+            SplitDirection::Right => WINDOW_MIN_COLS,
+        }
+    }
+}
+
+//Pixel rectangle of `cells`, a part of `grid` which covers `area`. Windows
+//on the right or bottom edge soak up the leftover pixels.
+pub fn cell_area(cells: CellRect, grid: CellRect, area: Rect, metrics: CellMetrics) -> Rect {
+    let x = area.x + (cells.col - grid.col) as f32 * metrics.width;
+    let y = area.y + (cells.row - grid.row) as f32 * metrics.height;
+    let right = if cells.col + cells.cols >= grid.col + grid.cols {
+        area.x + area.width
+    } else {
+        x + cells.cols as f32 * metrics.width
+    };
+    let bottom = if cells.row + cells.rows >= grid.row + grid.rows {
+        area.y + area.height
+    } else {
+        y + cells.rows as f32 * metrics.height
+    };
+    Rect {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    }
+}
+
+//Splits `extent` cells between children by weight. Boundaries are rounded
+//from the running total so the sizes always add up exactly.
+fn distribute(extent: usize, weights: &[f32]) -> Vec<usize> {
+    let total: f32 = weights.iter().sum();
+    let mut sizes = Vec::with_capacity(weights.len());
+    let mut acc = 0.0;
+    let mut prev = 0;
+    for weight in weights {
+        acc += weight;
+        let boundary = if total > 0.0 {
+            ((acc / total) * extent as f32).round() as usize
+        } else {
+            extent
+        };
+        let boundary = boundary.clamp(prev, extent);
+        sizes.push(boundary - prev);
+        prev = boundary;
+    }
+    if let Some(last) = sizes.last_mut() {
+        *last += extent - prev;
+    }
+    sizes
+}
+
+impl WindowTree {
+    pub fn leaves(&self) -> Vec<WindowId> {
+        match self {
+            WindowTree::Leaf(id) => vec![*id],
+            WindowTree::Split { children, .. } => children
+                .iter()
+                .flat_map(|(child, _)| child.leaves())
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, id: WindowId) -> bool {
+        match self {
+            WindowTree::Leaf(leaf) => *leaf == id,
+            WindowTree::Split { children, .. } => children.iter().any(|(c, _)| c.contains(id)),
+        }
+    }
+
+    //Computes where every window goes inside `area`
+    pub fn layout(&self, area: CellRect) -> TreeLayout {
+        let mut layout = TreeLayout::default();
+        self.layout_into(area, &mut layout);
+        layout
+    }
+
+    fn layout_into(&self, area: CellRect, out: &mut TreeLayout) {
+        match self {
+            WindowTree::Leaf(id) => out.windows.push((*id, area)),
+            WindowTree::Split {
+                direction,
+                children,
+            } => {
+                let gaps = direction.gap() * children.len().saturating_sub(1);
+                let extent = direction.extent(area).saturating_sub(gaps);
+                let weights: Vec<f32> = children.iter().map(|(_, w)| *w).collect();
+                let sizes = distribute(extent, &weights);
+                let mut pos = 0;
+                for (i, ((child, _), size)) in children.iter().zip(sizes).enumerate() {
+                    if i > 0 && direction.gap() > 0 {
+                        out.dividers.push(CellRect {
+                            col: area.col + pos,
+                            cols: direction.gap(),
+                            ..area
+                        });
+                        pos += direction.gap();
+                    }
+                    let rect = match direction {
+                        SplitDirection::Below => CellRect {
+                            row: area.row + pos,
+                            rows: size,
+                            ..area
+                        },
+                        SplitDirection::Right => CellRect {
+                            col: area.col + pos,
+                            cols: size,
+                            ..area
+                        },
+                    };
+                    child.layout_into(rect, out);
+                    pos += size;
+                }
+            }
+        }
+    }
+
+    //Puts `new` next to `target`, splitting target's space in half
+    pub fn split(&mut self, target: WindowId, direction: SplitDirection, new: WindowId) -> bool {
+        match self {
+            WindowTree::Leaf(id) if *id == target => {
+                *self = WindowTree::Split {
+                    direction,
+                    children: vec![
+                        (WindowTree::Leaf(target), 0.5),
+                        (WindowTree::Leaf(new), 0.5),
+                    ],
+                };
+                true
+            }
+            WindowTree::Leaf(_) => false,
+            WindowTree::Split {
+                direction: dir,
+                children,
+            } => {
+                let Some(i) = children.iter().position(|(c, _)| c.contains(target)) else {
+                    return false;
+                };
+                if *dir == direction && children[i].0 == WindowTree::Leaf(target) {
+                    //Same direction as the parent, just add a sibling
+                    let half = children[i].1 / 2.0;
+                    children[i].1 = half;
+                    children.insert(i + 1, (WindowTree::Leaf(new), half));
+                    true
+                } else {
+                    children[i].0.split(target, direction, new)
+                }
+            }
+        }
+    }
+
+    //Removes `target`, giving its space to its neighbours. Returns false if
+    //it's the last window.
+    pub fn remove(&mut self, target: WindowId) -> bool {
+        let WindowTree::Split { children, .. } = self else {
+            return false;
+        };
+        let Some(i) = children.iter().position(|(c, _)| c.contains(target)) else {
+            return false;
+        };
+        if children[i].0 == WindowTree::Leaf(target) {
+            let (_, weight) = children.remove(i);
+            //Emacs gives the space to the window above/left if there is one
+            let heir = i.saturating_sub(1).min(children.len() - 1);
+            children[heir].1 += weight;
+        } else if !children[i].0.remove(target) {
+            return false;
+        }
+        self.normalize();
+        true
+    }
+
+    //Collapses splits left with one child and flattens nested splits going
+    //the same way
+    fn normalize(&mut self) {
+        let WindowTree::Split {
+            direction,
+            children,
+        } = self
+        else {
+            return;
+        };
+        if children.len() == 1 {
+            *self = children.remove(0).0;
+            return;
+        }
+        let direction = *direction;
+        let mut flattened = Vec::with_capacity(children.len());
+        for (child, weight) in children.drain(..) {
+            match child {
+                WindowTree::Split {
+                    direction: inner,
+                    children: grandchildren,
+                } if inner == direction => {
+                    flattened.extend(grandchildren.into_iter().map(|(g, w)| (g, w * weight)));
+                }
+                child => flattened.push((child, weight)),
+            }
+        }
+        *children = flattened;
+    }
+
+    //Grows (or shrinks, for negative `delta`) `target` by `delta` cells along
+    //`direction`, taking the space from the next window over, or the
+    //previous one if it's last. `area` is the space the whole tree is laid out in.
+    pub fn resize(
+        &mut self,
+        target: WindowId,
+        direction: SplitDirection,
+        delta: isize,
+        area: CellRect,
+    ) -> Result<(), String> {
+        let WindowTree::Split {
+            direction: dir,
+            children,
+        } = self
+        else {
+            return Err("No window to take space from".to_owned());
+        };
+        let i = children
+            .iter()
+            .position(|(c, _)| c.contains(target))
+            .ok_or_else(|| "Window not in this tree".to_owned())?;
+        let gaps = dir.gap() * children.len().saturating_sub(1);
+        let extent = dir.extent(area).saturating_sub(gaps);
+        let weights: Vec<f32> = children.iter().map(|(_, w)| *w).collect();
+        let sizes = distribute(extent, &weights);
+
+        //The innermost split going the right way does the resizing
+        let pos: usize = sizes[..i].iter().map(|size| size + dir.gap()).sum();
+        let child_area = match dir {
+            SplitDirection::Below => CellRect {
+                row: area.row + pos,
+                rows: sizes[i],
+                ..area
+            },
+            SplitDirection::Right => CellRect {
+                col: area.col + pos,
+                cols: sizes[i],
+                ..area
+            },
+        };
+        let inner = children[i].0.resize(target, direction, delta, child_area);
+        if inner.is_ok() || *dir != direction {
+            return inner;
+        }
+
+        let other = if i + 1 < children.len() { i + 1 } else { i - 1 };
+        let min = direction.min_size() as isize;
+        let (size, other_size) = (sizes[i] as isize, sizes[other] as isize);
+        let delta = delta.min(other_size - min).max(min - size);
+        if delta == 0 {
+            return Err("Cannot resize window any further".to_owned());
+        }
+        let total: f32 = weights.iter().sum();
+        let per_cell = if extent > 0 {
+            total / extent as f32
+        } else {
+            0.0
+        };
+        children[i].1 = (size + delta) as f32 * per_cell;
+        children[other].1 = (other_size - delta) as f32 * per_cell;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: CellRect = CellRect {
+        col: 0,
+        row: 0,
+        cols: 81,
+        rows: 20,
+    };
+
+    fn leaf(id: u64) -> WindowTree {
+        WindowTree::Leaf(WindowId(id))
+    }
+
+    fn split(direction: SplitDirection, children: Vec<(WindowTree, f32)>) -> WindowTree {
+        WindowTree::Split {
+            direction,
+            children,
+        }
+    }
+
+    fn sizes(tree: &WindowTree) -> Vec<(u64, usize, usize)> {
+        tree.layout(AREA)
+            .windows
+            .iter()
+            .map(|(id, rect)| (id.0, rect.cols, rect.rows))
+            .collect()
+    }
+
+    #[test]
+    fn splitting_the_same_way_adds_siblings() {
+        let mut tree = leaf(1);
+        assert!(tree.split(WindowId(1), SplitDirection::Right, WindowId(2)));
+        assert!(tree.split(WindowId(2), SplitDirection::Right, WindowId(3)));
+        let expected = split(
+            SplitDirection::Right,
+            vec![(leaf(1), 0.5), (leaf(2), 0.25), (leaf(3), 0.25)],
+        );
+        assert_eq!(tree, expected);
+        assert!(!tree.split(WindowId(9), SplitDirection::Right, WindowId(4)));
+    }
+
+    #[test]
+    fn splitting_the_other_way_nests() {
+        let mut tree = leaf(1);
+        tree.split(WindowId(1), SplitDirection::Right, WindowId(2));
+        tree.split(WindowId(2), SplitDirection::Below, WindowId(3));
+        assert_eq!(tree.leaves(), [WindowId(1), WindowId(2), WindowId(3)]);
+        assert_eq!(sizes(&tree), [(1, 40, 20), (2, 40, 10), (3, 40, 10)]);
+        let layout = tree.layout(AREA);
+        assert_eq!(
+            layout.dividers,
+            [CellRect {
+                col: 40,
+                cols: 1,
+                ..AREA
+            }]
+        );
+        assert_eq!(layout.windows[2].1.row, 10);
+    }
+
+    #[test]
+    fn removing_gives_space_to_the_neighbour_before() {
+        let mut tree = split(
+            SplitDirection::Right,
+            vec![(leaf(1), 0.5), (leaf(2), 0.25), (leaf(3), 0.25)],
+        );
+        assert!(tree.remove(WindowId(2)));
+        assert_eq!(
+            tree,
+            split(
+                SplitDirection::Right,
+                vec![(leaf(1), 0.75), (leaf(3), 0.25)]
+            )
+        );
+        assert!(tree.remove(WindowId(1)));
+        assert_eq!(tree, leaf(3));
+        assert!(!tree.remove(WindowId(3)));
+    }
+
+    #[test]
+    fn removing_flattens_splits_going_the_same_way() {
+        let mut tree = split(
+            SplitDirection::Right,
+            vec![
+                (leaf(1), 0.5),
+                (
+                    split(
+                        SplitDirection::Below,
+                        vec![
+                            (leaf(2), 0.5),
+                            (
+                                split(SplitDirection::Right, vec![(leaf(3), 0.5), (leaf(4), 0.5)]),
+                                0.5,
+                            ),
+                        ],
+                    ),
+                    0.5,
+                ),
+            ],
+        );
+        assert!(tree.remove(WindowId(2)));
+        assert_eq!(
+            tree,
+            split(
+                SplitDirection::Right,
+                vec![(leaf(1), 0.5), (leaf(3), 0.25), (leaf(4), 0.25)]
+            )
+        );
+    }
+
+    #[test]
+    fn resizing_takes_space_from_the_next_window() {
+        let mut tree = split(SplitDirection::Below, vec![(leaf(1), 0.5), (leaf(2), 0.5)]);
+        tree.resize(WindowId(1), SplitDirection::Below, 3, AREA)
+            .unwrap();
+        assert_eq!(sizes(&tree), [(1, 81, 13), (2, 81, 7)]);
+        //The last window takes from the one before it
+        tree.resize(WindowId(2), SplitDirection::Below, 1, AREA)
+            .unwrap();
+        assert_eq!(sizes(&tree), [(1, 81, 12), (2, 81, 8)]);
+    }
+
+    #[test]
+    fn resizing_stops_at_the_minimum_size() {
+        let mut tree = split(SplitDirection::Below, vec![(leaf(1), 0.5), (leaf(2), 0.5)]);
+        tree.resize(WindowId(1), SplitDirection::Below, 100, AREA)
+            .unwrap();
+        assert_eq!(sizes(&tree), [(1, 81, 16), (2, 81, WINDOW_MIN_ROWS)]);
+        assert!(tree
+            .resize(WindowId(1), SplitDirection::Below, 1, AREA)
+            .is_err());
+        //Nothing to take columns from in a vertical split
+        assert!(tree
+            .resize(WindowId(1), SplitDirection::Right, 1, AREA)
+            .is_err());
+        assert!(leaf(1)
+            .resize(WindowId(1), SplitDirection::Below, 1, AREA)
+            .is_err());
+    }
+
+    #[test]
+    fn distributing_always_adds_up() {
+        assert_eq!(distribute(10, &[1.0, 1.0, 1.0]), [3, 4, 3]);
+        assert_eq!(distribute(80, &[0.75, 0.25]), [60, 20]);
+        assert_eq!(distribute(7, &[0.0, 0.0]), [7, 0]);
+        for extent in 0..50 {
+            let sizes = distribute(extent, &[0.3, 0.1, 0.6, 0.2]);
+            assert_eq!(sizes.iter().sum::<usize>(), extent);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//Copies a window's canvas onto its surface, one texel per pixel
+
+@group(0) @binding(0)
+var canvas: texture_2d<f32>;
+
+//One triangle big enough to cover the whole surface
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32((index << 1u) & 2u);
+    let y = f32(index & 2u);
+    return vec4(x * 2.0 - 1.0, y * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(canvas, vec2<i32>(position.xy), 0);
+}
@@ -0,0 +1,168 @@
+use std::{iter, sync::Arc};
+
+use bunmacs_core::{font::FontConfig, layout::CellMetrics};
+use font_kit::{
+    family_name::FamilyName,
+    font::Font,
+    properties::{Properties, Style, Weight},
+    source::SystemSource,
+};
+
+//Inconsolata, so there's always something to draw with even on a system
+//without fonts. See fonts/OFL.txt.
+pub(crate) const BUNDLED_FONT: &[u8] = include_bytes!("../fonts/Inconsolata-Regular.ttf");
+
+//Tried after the user's fallbacks for glyphs the main font doesn't have
+const DEFAULT_FALLBACKS: &[&str] = &[
+    "Noto Sans Mono CJK SC",
+    "Noto Sans CJK SC",
+    "Noto Emoji",
+    "Noto Color Emoji",
+    "Symbola",
+    "DejaVu Sans",
+];
+
+//Index of the first fallback font in what `load` returns
+pub(crate) const FALLBACK_START: usize = 4;
+
+//Fallback advance for fonts that don't have an 'M', as a fraction of an em
+const DEFAULT_ADVANCE_EM: f32 = 0.6;
+
+//What `load` found, with the grid cell of the regular font measured once
+//rather than every time the text size is worked out
+pub(crate) struct Fonts {
+    pub(crate) faces: Vec<Font>,
+    cell: Cell,
+}
+
+//The grid cell of a monospace font in font units
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    units_per_em: f32,
+    advance: f32,
+    ascent: f32,
+    descent: f32,
+    line_gap: f32,
+}
+
+impl Cell {
+    fn measure(font: &Font) -> Self {
+        let metrics = font.metrics();
+        let units_per_em = metrics.units_per_em as f32;
+        let advance = font
+            .glyph_for_char('M')
+            .and_then(|glyph| font.advance(glyph).ok())
+            .map(|advance| advance.x())
+            .filter(|advance| *advance > 0.0)
+            .unwrap_or(units_per_em * DEFAULT_ADVANCE_EM);
+        Cell {
+            units_per_em,
+            advance,
+            ascent: metrics.ascent,
+            descent: -metrics.descent,
+            line_gap: metrics.line_gap,
+        }
+    }
+}
+
+impl Fonts {
+    fn new(faces: Vec<Font>) -> Self {
+        let cell = Cell::measure(&faces[0]);
+        Fonts { faces, cell }
+    }
+
+    //The grid cell rendered at `px_per_em`
+    pub(crate) fn cell_metrics(&self, px_per_em: f32) -> CellMetrics {
+        let cell = self.cell;
+        let scale = px_per_em / cell.units_per_em;
+        //Rounding the vertical metrics keeps every baseline on a whole pixel
+        let ascent = (cell.ascent * scale).ceil();
+        let descent = (cell.descent * scale).ceil();
+        let line_gap = (cell.line_gap * scale).round().max(0.0);
+        CellMetrics {
+            width: cell.advance * scale,
+            height: ascent + descent + line_gap,
+            ascent: ascent + (line_gap / 2.0).floor(),
+        }
+    }
+}
+
+fn bundled() -> Font {
+    Font::from_bytes(Arc::new(BUNDLED_FONT.to_vec()), 0).unwrap()
+}
+
+//The bundled font in every slot `load` fills, for pictures that have to
+//come out the same whatever's installed
+pub(crate) fn load_bundled() -> Fonts {
+    Fonts::new(vec![bundled(); FALLBACK_START + 1])
+}
+
+//Regular, bold, italic and bold italic of `config`'s family, in the order
+//graphics::font_id picks them, then the fallbacks that are installed and the
+//bundled font last. Styles the system doesn't have fall back to the regular
+//font, and the regular font to the bundled one.
+pub(crate) fn load(config: &FontConfig) -> Fonts {
+    let source = SystemSource::new();
+    let load = |families: &[FamilyName], properties: &Properties| {
+        source
+            .select_best_match(families, properties)
+            .ok()?
+            .load()
+            .ok()
+    };
+    //A family that isn't installed falls through to the system monospace
+    let families: Vec<FamilyName> = config
+        .family
+        .iter()
+        .map(|family| FamilyName::Title(family.clone()))
+        .chain(iter::once(FamilyName::Monospace))
+        .collect();
+    let regular = load(&families, &Properties::new()).unwrap_or_else(|| {
+        log::warn!("No monospace font found, using the bundled one");
+        bundled()
+    });
+    let styles = [
+        (Weight::BOLD, Style::Normal),
+        (Weight::NORMAL, Style::Italic),
+        (Weight::BOLD, Style::Italic),
+    ];
+    let others = styles.map(|(weight, style)| {
+        load(&families, Properties::new().weight(weight).style(style))
+            .unwrap_or_else(|| regular.clone())
+    });
+    let fallbacks = config
+        .fallbacks
+        .iter()
+        .map(String::as_str)
+        .chain(DEFAULT_FALLBACKS.iter().copied())
+        .filter_map(|family| load(&[FamilyName::Title(family.to_owned())], &Properties::new()));
+    Fonts::new(
+        iter::once(regular)
+            .chain(others)
+            .chain(fallbacks)
+            .chain(iter::once(bundled()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bunmacs_core::font::points_to_pixels;
+
+    #[test]
+    fn cells_scale_with_the_window_on_whole_pixels() {
+        let fonts = load_bundled();
+        let base = fonts.cell_metrics(points_to_pixels(12.0, 1.0));
+        for scale_factor in [1.0, 1.5, 2.0] {
+            let cell = fonts.cell_metrics(points_to_pixels(12.0, scale_factor));
+            let width = base.width * scale_factor as f32;
+            assert!((cell.width - width).abs() < 1e-3, "{scale_factor}");
+            assert_eq!(cell.height, cell.height.round());
+            assert_eq!(cell.ascent, cell.ascent.round());
+            //Rounding never takes more than a pixel off each metric
+            assert!((cell.height - base.height * scale_factor as f32).abs() <= 3.0);
+            assert!(cell.ascent < cell.height);
+        }
+    }
+}
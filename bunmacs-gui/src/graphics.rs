@@ -1,16 +1,37 @@
-use std::{cell::RefCell, fmt::Debug, iter, mem::size_of_val, num::NonZeroU64, sync::Arc};
+use crate::fonts;
+use bunmacs_core::{
+    decoration::{self, Quad},
+    face::{Slant, Weight},
+    frontend::{self, Cursor, Damage, Frontend, GlyphRun},
+    layout::{CellMetrics, Rect},
+};
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    iter,
+    mem::{size_of, size_of_val},
+    num::NonZeroU64,
+    rc::Rc,
+};
 
 use tokio::runtime::Runtime;
 use wgpu::{
-    util::StagingBelt, Backends, BlendState, Buffer, BufferDescriptor, BufferUsages, Color,
-    ColorTargetState, ColorWrites, CommandEncoderDescriptor, Device, DeviceDescriptor, Features,
-    FragmentState, Instance, InstanceDescriptor, LoadOp, MultisampleState, Operations,
-    PipelineLayoutDescriptor, Queue, RenderPassColorAttachment, RenderPassDescriptor,
-    RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, ShaderModuleDescriptor,
-    Surface, SurfaceConfiguration, SurfaceError, TextureUsages, VertexAttribute,
-    VertexBufferLayout, VertexFormat, VertexState,
+    util::StagingBelt, Adapter, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry,
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BlendState, Buffer, BufferDescriptor, BufferUsages, Color, ColorTargetState, ColorWrites,
+    CommandEncoder, CommandEncoderDescriptor, Device, DeviceDescriptor, Extent3d, Features,
+    FragmentState, ImageCopyBuffer, ImageDataLayout, Instance, InstanceDescriptor, LoadOp,
+    Maintain, MapMode, MultisampleState, Operations, PipelineLayoutDescriptor, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+    RequestAdapterOptions, ShaderModuleDescriptor, ShaderStages, Surface, SurfaceCapabilities,
+    SurfaceConfiguration, SurfaceError, Texture, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDimension,
+    VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+use wgpu_glyph::{
+    ab_glyph::{self, Font, FontArc, PxScale},
+    orthographic_projection, Extra, FontId, GlyphBrush, GlyphBrushBuilder, Region, SectionGlyph,
 };
-use wgpu_glyph::{ab_glyph::FontArc, GlyphBrush, GlyphBrushBuilder, Section, Text};
 
 use winit::{
     dpi::PhysicalSize,
@@ -20,30 +41,46 @@ use winit::{
 //TODO: Genericize over backend?
 #[derive(Debug)]
 pub(crate) struct WgpuInfo {
-    _shared_context: Arc<SharedWgpuContext>,
+    shared_context: Rc<SharedWgpuContext>,
 }
 
+//Everything frames have in common. Every window gets its own surface but
+//they all draw with the same device, pipeline and fonts.
 struct SharedWgpuContext {
-    _instance: Instance,
-    //currently unknown if we need this?
-    //_adapter: Adapter,
+    instance: Instance,
+    //Needed to ask new surfaces what they support
+    adapter: Adapter,
+    //The pipeline and glyph brushes are built for this format, so every
+    //surface or texture drawn to has to use it
+    surface_format: TextureFormat,
     device: Device,
     queue: Queue,
     render_pipeline: RenderPipeline,
-    vertex_buffer: Buffer,
-    glyph_brush: RefCell<GlyphBrush<()>>,
+    //Copies a window's canvas onto its surface
+    blit_pipeline: RenderPipeline,
+    blit_layout: BindGroupLayout,
+    fonts: RefCell<GlyphFonts>,
     staging_belt: RefCell<StagingBelt>,
 }
 
+//Fonts every renderer's glyph brush is built from. Renderers rebuild their
+//brush when the generation moves on.
+struct GlyphFonts {
+    generation: u64,
+    fonts: Vec<FontArc>,
+}
+
 impl Debug for SharedWgpuContext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SharedWgpuContext")
-            .field("_instance", &self._instance)
+            .field("instance", &self.instance)
+            .field("adapter", &self.adapter)
+            .field("surface_format", &self.surface_format)
             .field("device", &self.device)
             .field("queue", &self.queue)
             .field("render_pipeline", &self.render_pipeline)
-            .field("vertex_buffer", &self.vertex_buffer)
-            .field("glyph_brush", &self.glyph_brush.borrow())
+            .field("blit_pipeline", &self.blit_pipeline)
+            .field("font_generation", &self.fonts.borrow().generation)
             .finish()
     }
 }
@@ -51,73 +88,204 @@ impl Debug for SharedWgpuContext {
 #[derive(Debug)]
 pub(crate) struct WindowContext {
     surface: Surface,
-    wgpu_info: Arc<SharedWgpuContext>,
+    wgpu_info: Rc<SharedWgpuContext>,
     surface_config: SurfaceConfiguration,
     win: Window,
     inner_size: PhysicalSize<u32>,
+    //Physical pixels per logical pixel of the monitor the window is on
+    scale_factor: f64,
+    renderer: Renderer,
+    text_size: TextSize,
+    //What the editor is drawing, and the pictures it finished since the
+    //window was last redrawn
+    scene: Scene,
+    pending: Vec<Scene>,
+    //What the window shows. Pictures only paint what changed onto it, and
+    //then it's copied to the surface, whose contents don't last.
+    canvas: Option<Canvas>,
+}
+
+//A texture as big as the window and what the blit pipeline reads it with
+#[derive(Debug)]
+struct Canvas {
+    texture: Texture,
+    bind_group: BindGroup,
+    size: PhysicalSize<u32>,
+}
+
+//Draws frames into textures. Each window keeps its own so a window that
+//didn't change can draw again without re-uploading anything.
+#[derive(Debug)]
+struct Renderer {
+    vertex_buffer: VertexBuffer,
+    //What's in `vertex_buffer`
+    vertices: Vec<Vertex>,
+    glyph_brush: GlyphBrush<()>,
+    font_generation: u64,
+}
+
+//Draws frames into a texture rather than a window and reads the pixels
+//back, for screenshots and for machines without a display or GPU. The
+//texture keeps its contents, so pictures only paint what changed.
+#[derive(Debug)]
+pub(crate) struct Headless {
+    shared: Rc<SharedWgpuContext>,
+    renderer: Renderer,
+    texture: Texture,
+    size: PhysicalSize<u32>,
+    text_size: TextSize,
+    scene: Scene,
+}
+
+//What headless rendering draws to, and what screenshots are saved as
+const HEADLESS_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+//How big text is drawn, which depends on the fonts and the scale factor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TextSize {
+    pub px_per_em: f32,
+    pub cell_metrics: CellMetrics,
+}
+
+//Until the window's set its own
+impl Default for TextSize {
+    fn default() -> Self {
+        TextSize {
+            px_per_em: 1.0,
+            cell_metrics: CellMetrics {
+                width: 1.0,
+                height: 1.0,
+                ascent: 1.0,
+            },
+        }
+    }
+}
+
+//One picture, as the editor drew it through the Frontend trait
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Scene {
+    background: [f32; 4],
+    //What it paints over, nothing is drawn outside of it
+    damage: Damage,
+    quads: Vec<Quad>,
+    runs: Vec<GlyphRun>,
+    text_size: TextSize,
+}
+
+impl Scene {
+    fn new(background: [f32; 4], damage: &Damage, text_size: TextSize) -> Self {
+        Scene {
+            background,
+            damage: damage.clone(),
+            text_size,
+            ..Scene::default()
+        }
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: [f32; 4]) {
+        self.quads.push(Quad { rect, color });
+    }
+
+    fn draw_cursor(&mut self, cursor: Cursor) {
+        for rect in decoration::cursor_rects(cursor.cell, cursor.style) {
+            self.fill_rect(rect, cursor.color);
+        }
+    }
+}
+
+//The whole pixels `rect` touches, None if that's none of `size`
+fn pixel_region(rect: Rect, size: PhysicalSize<u32>) -> Option<Region> {
+    let left = rect.x.floor().clamp(0.0, size.width as f32) as u32;
+    let top = rect.y.floor().clamp(0.0, size.height as f32) as u32;
+    let right = (rect.x + rect.width).ceil().clamp(0.0, size.width as f32) as u32;
+    let bottom = (rect.y + rect.height).ceil().clamp(0.0, size.height as f32) as u32;
+    (right > left && bottom > top).then_some(Region {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+fn area(size: PhysicalSize<u32>) -> Rect {
+    Rect {
+        x: 0.0,
+        y: 0.0,
+        width: size.width as f32,
+        height: size.height as f32,
+    }
 }
 
-// lib.rs
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Default, PartialEq)]
 struct Vertex {
     position: [f32; 3],
-    color: [f32; 3],
-}
-
-const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [0.0, 0.5, 0.0],
-        color: [1.0, 0.0, 0.0],
-    },
-    Vertex {
-        position: [-0.5, -0.5, 0.0],
-        color: [0.0, 1.0, 0.0],
-    },
-    Vertex {
-        position: [0.5, -0.5, 0.0],
-        color: [0.0, 0.0, 1.0],
-    },
-];
-
-impl WgpuInfo {
-    pub(crate) fn new(
-        win: Window,
-        rt: &Runtime,
-        font: &font_kit::font::Font,
-    ) -> (Self, WindowContext) {
-        let instance = Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
+    color: [f32; 4],
+}
 
-        let inner_size = win.inner_size();
+//Vertex buffer that grows to fit however many quads a frame has
+#[derive(Debug)]
+struct VertexBuffer {
+    buffer: Buffer,
+    capacity: usize,
+}
 
-        //#SAFETY
+const INITIAL_QUAD_CAPACITY: usize = 256;
+const VERTICES_PER_QUAD: usize = 6;
 
-        //This unsafe is necessary because initial_window must live as long as
-        //the surface or longer. Basically there's a lifetime here that's not
-        //enforced by the type system
-        let surface = unsafe { instance.create_surface(&win).unwrap() };
-
-        let adapter = rt
-            .block_on(instance.request_adapter(&RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::LowPower,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            }))
-            .unwrap();
+impl VertexBuffer {
+    fn new(device: &Device, capacity: usize) -> Self {
+        VertexBuffer {
+            buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("vertex buffer"),
+                size: (capacity * size_of::<Vertex>()) as u64,
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            capacity,
+        }
+    }
 
-        let surface_caps = surface.get_capabilities(&adapter);
+    fn reserve(&mut self, device: &Device, vertices: usize) {
+        if vertices > self.capacity {
+            *self = VertexBuffer::new(device, vertices.next_power_of_two());
+        }
+    }
+}
 
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .filter(|f| f.is_srgb())
-            .next()
-            .unwrap_or(surface_caps.formats[0]);
+//Two triangles per quad, converted from pixels to clip space
+fn quad_vertices(quads: &[Quad], width: f32, height: f32) -> Vec<Vertex> {
+    let to_clip = |x: f32, y: f32| [x / width * 2.0 - 1.0, 1.0 - y / height * 2.0, 0.0];
+    quads
+        .iter()
+        .flat_map(|quad| {
+            let r = quad.rect;
+            let (left, top, right, bottom) = (r.x, r.y, r.x + r.width, r.y + r.height);
+            [
+                (left, top),
+                (left, bottom),
+                (right, bottom),
+                (left, top),
+                (right, bottom),
+                (right, top),
+            ]
+            .map(|(x, y)| Vertex {
+                position: to_clip(x, y),
+                color: quad.color,
+            })
+        })
+        .collect()
+}
 
+impl SharedWgpuContext {
+    //`fonts` are the faces fonts::load finds
+    fn new(
+        instance: Instance,
+        adapter: Adapter,
+        format: TextureFormat,
+        rt: &Runtime,
+        fonts: &[font_kit::font::Font],
+    ) -> Result<Self, String> {
         let (device, queue) = rt
             .block_on(adapter.request_device(
                 &DeviceDescriptor {
@@ -131,7 +299,7 @@ impl WgpuInfo {
                 },
                 None,
             ))
-            .unwrap();
+            .map_err(|e| format!("Couldn't get a device: {e}"))?;
 
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("shader"),
@@ -156,8 +324,8 @@ impl WgpuInfo {
                 module: &shader,
                 entry_point: "fs_main",
                 targets: &[Some(ColorTargetState {
-                    format: surface_format,
-                    blend: Some(BlendState::REPLACE),
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
@@ -165,7 +333,8 @@ impl WgpuInfo {
                 topology: wgpu::PrimitiveTopology::TriangleList, // 1.
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
+                //Quads are flat on screen, nothing to cull
+                cull_mode: None,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
@@ -179,71 +348,242 @@ impl WgpuInfo {
             multiview: None,
         });
 
-        let surface_config = SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: inner_size.width,
-            height: inner_size.height,
-            //Present_Mode::Fifo, guaranteed to exist and good enough for our
-            //purposes as an editor
-            present_mode: surface_caps.present_modes[0],
-            //seems we can just paste whatever here so long as it's supported?
-            //TODO: look into later
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-        };
-
-        let vertices_size: u64 = size_of_val(VERTICES) as u64;
-
-        if inner_size.width != 0 && inner_size.height != 0 {
-            surface.configure(&device, &surface_config);
-        }
-
-        let vertex_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("vertex buffer"),
-            size: vertices_size,
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+        let blit_shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("blit shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("blit.wgsl").into()),
+        });
+        let blit_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("blit bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let blit_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("blit pipeline layout"),
+            bind_group_layouts: &[&blit_layout],
+            push_constant_ranges: &[],
+        });
+        let blit_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("blit pipeline"),
+            layout: Some(&blit_pipeline_layout),
+            vertex: VertexState {
+                module: &blit_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &blit_shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
         });
-        let font_bytes = font.copy_font_data().unwrap();
-
-        let glyph_font = FontArc::try_from_vec((*font_bytes).clone()).unwrap();
-        let glyph_brush =
-            GlyphBrushBuilder::using_font(glyph_font).build(&device, surface_config.format);
-
-        let staging_belt = StagingBelt::new(64);
 
-        let wgpu_info = Arc::new(SharedWgpuContext {
-            _instance: instance,
-            //_adapter: adapter,
+        Ok(SharedWgpuContext {
+            instance,
+            adapter,
+            surface_format: format,
             device,
             queue,
             render_pipeline,
-            vertex_buffer,
-            staging_belt: RefCell::new(staging_belt),
-            glyph_brush: RefCell::new(glyph_brush),
+            blit_pipeline,
+            blit_layout,
+            fonts: RefCell::new(GlyphFonts {
+                generation: 0,
+                fonts: glyph_fonts(fonts),
+            }),
+            staging_belt: RefCell::new(StagingBelt::new(64)),
+        })
+    }
+}
+
+impl WgpuInfo {
+    //`fonts` are the faces fonts::load finds
+    pub(crate) fn new(
+        win: Window,
+        rt: &Runtime,
+        fonts: &[font_kit::font::Font],
+    ) -> (Self, WindowContext) {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::all(),
+            dx12_shader_compiler: Default::default(),
         });
 
+        let inner_size = win.inner_size();
+
+        //#SAFETY
+
+        //This unsafe is necessary because initial_window must live as long as
+        //the surface or longer. Basically there's a lifetime here that's not
+        //enforced by the type system
+        let surface = unsafe { instance.create_surface(&win).unwrap() };
+
+        let adapter = rt
+            .block_on(instance.request_adapter(&RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                force_fallback_adapter: false,
+                compatible_surface: Some(&surface),
+            }))
+            .unwrap();
+
+        let surface_caps = surface.get_capabilities(&adapter);
+
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .unwrap_or(surface_caps.formats[0]);
+
+        let wgpu_info =
+            Rc::new(SharedWgpuContext::new(instance, adapter, surface_format, rt, fonts).unwrap());
+
+        let surface_config = surface_config(&surface_caps, surface_format, inner_size);
+
+        if inner_size.width != 0 && inner_size.height != 0 {
+            surface.configure(&wgpu_info.device, &surface_config);
+        }
+
         (
             WgpuInfo {
-                _shared_context: wgpu_info.clone(),
-            },
-            WindowContext {
-                surface,
-                win,
-                wgpu_info,
-                inner_size,
-                surface_config,
+                shared_context: wgpu_info.clone(),
             },
+            WindowContext::new(wgpu_info, win, surface, surface_config),
         )
     }
+
+    //Every window draws with `fonts` from its next redraw on
+    pub(crate) fn set_fonts(&self, fonts: &[font_kit::font::Font]) {
+        let mut glyph_fonts = self.shared_context.fonts.borrow_mut();
+        glyph_fonts.generation += 1;
+        glyph_fonts.fonts = self::glyph_fonts(fonts);
+    }
+
+    //Sets up another OS window to draw with the shared context
+    pub(crate) fn make_window_context(&self, win: Window) -> WindowContext {
+        let shared = &self.shared_context;
+        let inner_size = win.inner_size();
+
+        //#SAFETY
+
+        //Same as in new, the surface must not outlive the window. Both end up
+        //in the WindowContext and the surface is dropped first.
+        let surface = unsafe { shared.instance.create_surface(&win).unwrap() };
+
+        let surface_caps = surface.get_capabilities(&shared.adapter);
+        if !surface_caps.formats.contains(&shared.surface_format) {
+            log::warn!(
+                "New window doesn't support {:?}, drawing may fail",
+                shared.surface_format
+            );
+        }
+        let surface_config = surface_config(&surface_caps, shared.surface_format, inner_size);
+
+        if inner_size.width != 0 && inner_size.height != 0 {
+            surface.configure(&shared.device, &surface_config);
+        }
+
+        WindowContext::new(shared.clone(), win, surface, surface_config)
+    }
+}
+
+//Fonts glyph_brush can't read are swapped for the bundled one, so every
+//font keeps its FontId
+fn glyph_fonts(fonts: &[font_kit::font::Font]) -> Vec<FontArc> {
+    fonts
+        .iter()
+        .map(|font| {
+            font.copy_font_data()
+                .and_then(|bytes| FontArc::try_from_vec((*bytes).clone()).ok())
+                .unwrap_or_else(|| {
+                    log::warn!("Couldn't read {}, using the bundled font", font.full_name());
+                    FontArc::try_from_slice(fonts::BUNDLED_FONT).unwrap()
+                })
+        })
+        .collect()
+}
+
+fn surface_config(
+    caps: &SurfaceCapabilities,
+    format: TextureFormat,
+    size: PhysicalSize<u32>,
+) -> SurfaceConfiguration {
+    SurfaceConfiguration {
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width: size.width,
+        height: size.height,
+        //Present_Mode::Fifo, guaranteed to exist and good enough for our
+        //purposes as an editor
+        present_mode: caps.present_modes[0],
+        //seems we can just paste whatever here so long as it's supported?
+        //TODO: look into later
+        alpha_mode: caps.alpha_modes[0],
+        view_formats: vec![],
+    }
 }
 
 impl WindowContext {
+    fn new(
+        wgpu_info: Rc<SharedWgpuContext>,
+        win: Window,
+        surface: Surface,
+        surface_config: SurfaceConfiguration,
+    ) -> Self {
+        WindowContext {
+            renderer: Renderer::new(&wgpu_info),
+            text_size: TextSize::default(),
+            scene: Scene::default(),
+            pending: vec![],
+            canvas: None,
+            surface,
+            scale_factor: win.scale_factor(),
+            inner_size: win.inner_size(),
+            win,
+            wgpu_info,
+            surface_config,
+        }
+    }
+
     pub fn id(&self) -> WindowId {
         self.win.id()
     }
 
+    pub fn request_redraw(&self) {
+        self.win.request_redraw()
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    //The window moved to a monitor with a different scale factor, or the
+    //monitor's changed
+    pub fn rescale(&mut self, scale_factor: f64, new_size: PhysicalSize<u32>) {
+        self.scale_factor = scale_factor;
+        self.resize(new_size);
+    }
+
+    //Sets the surface up again at the window's current size, after it was
+    //lost or stopped matching the window
+    pub fn reconfigure(&mut self) {
+        self.resize(self.win.inner_size());
+    }
+
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.surface_config.width = new_size.width;
         self.surface_config.height = new_size.height;
@@ -255,96 +595,498 @@ impl WindowContext {
         }
     }
 
+    //Has to be set before the editor draws on the window
+    pub fn set_text_size(&mut self, text_size: TextSize) {
+        self.text_size = text_size;
+    }
+
+    //Paints the pictures the editor presented since last time onto the
+    //canvas and shows it
     pub fn redraw(&mut self) -> Result<(), SurfaceError> {
-        if self.inner_size.width != 0 && self.inner_size.height != 0 {
-            let output = self.surface.get_current_texture()?;
-            let view = output.texture.create_view(&Default::default());
-            let mut encoder =
-                self.wgpu_info
-                    .device
-                    .create_command_encoder(&CommandEncoderDescriptor {
-                        label: Some("Render Encoder"),
-                    });
-
-            let mut staging_belt = self.wgpu_info.staging_belt.borrow_mut();
-
-            staging_belt
-                .write_buffer(
-                    &mut encoder,
-                    &self.wgpu_info.vertex_buffer,
-                    0,
-                    NonZeroU64::new(size_of_val(VERTICES) as u64).unwrap(),
-                    &self.wgpu_info.device,
-                )
-                .copy_from_slice(bytemuck::cast_slice(&VERTICES[..]));
-
-            //Do our render pass here
-            {
-                let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                    label: Some("Render Pass"),
-                    color_attachments: &[Some(RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: Operations {
-                            load: LoadOp::Clear(Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1f64,
-                            }),
-                            store: true,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                });
-                render_pass.set_pipeline(&self.wgpu_info.render_pipeline);
-                render_pass.set_vertex_buffer(0, self.wgpu_info.vertex_buffer.slice(..));
-                render_pass.draw(0..3, 0..1);
-            }
+        let size = self.inner_size;
+        if size.width == 0 || size.height == 0 {
+            return Ok(());
+        }
+        if self.canvas.is_none() && self.pending.is_empty() {
+            return Ok(());
+        }
+        let output = self.surface.get_current_texture()?;
+        let shared = &self.wgpu_info;
+        if self.canvas.as_ref().map(|c| c.size) != Some(size) {
+            //Only a whole picture can start a new canvas, and one always
+            //comes after a resize
+            self.canvas = Some(Canvas::new(shared, size));
+        }
+        let canvas = self.canvas.as_ref().unwrap();
+        let canvas_view = canvas.texture.create_view(&Default::default());
+        for scene in self.pending.drain(..) {
+            self.renderer.draw(shared, &canvas_view, size, &scene);
+        }
+
+        let view = output.texture.create_view(&Default::default());
+        let mut encoder = shared
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Blit Encoder"),
+            });
+        canvas.blit(shared, &mut encoder, &view);
+        shared.queue.submit(iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+}
+
+impl Canvas {
+    fn new(shared: &SharedWgpuContext, size: PhysicalSize<u32>) -> Self {
+        let texture = shared.device.create_texture(&TextureDescriptor {
+            label: Some("canvas"),
+            size: Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: shared.surface_format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        let bind_group = shared.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("canvas bind group"),
+            layout: &shared.blit_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+        Canvas {
+            texture,
+            bind_group,
+            size,
+        }
+    }
+
+    fn blit(&self, shared: &SharedWgpuContext, encoder: &mut CommandEncoder, view: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&shared.blit_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl Frontend for WindowContext {
+    fn area(&self) -> Rect {
+        area(self.inner_size)
+    }
+
+    fn cell_metrics(&self) -> CellMetrics {
+        self.text_size.cell_metrics
+    }
+
+    fn clear(&mut self, background: [f32; 4], damage: &Damage) {
+        self.scene = Scene::new(background, damage, self.text_size);
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: [f32; 4]) {
+        self.scene.fill_rect(rect, color);
+    }
+
+    fn draw_glyphs(&mut self, run: GlyphRun) {
+        self.scene.runs.push(run);
+    }
+
+    fn draw_cursor(&mut self, cursor: Cursor) {
+        self.scene.draw_cursor(cursor);
+    }
+
+    //Queues the picture up for the next redraw. A whole picture makes any
+    //that came before it pointless.
+    fn present(&mut self) {
+        let scene = std::mem::take(&mut self.scene);
+        if scene.damage == Damage::Full {
+            self.pending.clear();
+        }
+        self.pending.push(scene);
+        self.request_redraw();
+    }
+}
+
+impl Renderer {
+    fn new(shared: &SharedWgpuContext) -> Self {
+        let fonts = shared.fonts.borrow();
+        Renderer {
+            vertex_buffer: VertexBuffer::new(
+                &shared.device,
+                INITIAL_QUAD_CAPACITY * VERTICES_PER_QUAD,
+            ),
+            vertices: vec![],
+            glyph_brush: GlyphBrushBuilder::using_fonts(fonts.fonts.clone())
+                .build(&shared.device, shared.surface_format),
+            font_generation: fonts.generation,
+        }
+    }
+
+    //Draws `scene` on `view`, `size` big, only painting over its damage
+    fn draw(
+        &mut self,
+        shared: &SharedWgpuContext,
+        view: &TextureView,
+        size: PhysicalSize<u32>,
+        scene: &Scene,
+    ) {
+        let fonts = shared.fonts.borrow();
+        if fonts.generation != self.font_generation {
+            self.glyph_brush = GlyphBrushBuilder::using_fonts(fonts.fonts.clone())
+                .build(&shared.device, shared.surface_format);
+            self.font_generation = fonts.generation;
+        }
+        drop(fonts);
+        let mut encoder = shared
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
 
-            {
-                let glyph_brush = &mut self.wgpu_info.glyph_brush.borrow_mut();
-                glyph_brush.queue(Section {
-                    screen_position: (50.0, 90.0),
-                    bounds: (
-                        self.surface_config.width as f32,
-                        self.surface_config.height as f32,
-                    ),
-                    text: vec![Text::new("Hello world")
-                        .with_color([1.0, 1.0, 1.0, 1.0])
-                        .with_scale(40.0)],
-                    ..Default::default()
-                });
-
-                glyph_brush.queue(Section {
-                    screen_position: (180.0, 360.0),
-                    bounds: (
-                        self.surface_config.width as f32,
-                        self.surface_config.height as f32,
-                    ),
-                    text: vec![Text::new("Hello world")
-                        .with_color([1.0, 0.0, 0.0, 1.0])
-                        .with_scale(13.0)],
-                    ..Default::default()
-                });
-
-                glyph_brush
-                    .draw_queued(
-                        &self.wgpu_info.device,
-                        &mut staging_belt,
+        let mut staging_belt = shared.staging_belt.borrow_mut();
+
+        let regions: Vec<Region> = match &scene.damage {
+            Damage::Full => vec![],
+            Damage::Rects(rects) => rects
+                .iter()
+                .filter_map(|rect| pixel_region(*rect, size))
+                .collect(),
+        };
+        //Damage is painted over with the background before anything else
+        let background = (scene.damage != Damage::Full).then_some(Quad {
+            rect: area(size),
+            color: scene.background,
+        });
+        let quads: Vec<Quad> = background
+            .into_iter()
+            .chain(scene.quads.iter().copied())
+            .collect();
+        let vertices = quad_vertices(&quads, size.width as f32, size.height as f32);
+        let vertex_buffer = &mut self.vertex_buffer;
+        //A window that's only being redrawn, say because it was uncovered,
+        //still has its quads on the GPU
+        if vertices != self.vertices {
+            vertex_buffer.reserve(&shared.device, vertices.len());
+            if let Some(buffer_size) = NonZeroU64::new(size_of_val(&vertices[..]) as u64) {
+                staging_belt
+                    .write_buffer(
                         &mut encoder,
-                        &view,
-                        self.surface_config.width,
-                        self.surface_config.height,
+                        &vertex_buffer.buffer,
+                        0,
+                        buffer_size,
+                        &shared.device,
                     )
-                    .unwrap();
+                    .copy_from_slice(bytemuck::cast_slice(&vertices));
             }
-            staging_belt.finish();
-            self.wgpu_info.queue.submit(iter::once(encoder.finish()));
-            output.present();
+            self.vertices = vertices;
         }
-        Ok(())
+        let vertices = &self.vertices;
+
+        //Do our render pass here
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: match scene.damage {
+                            Damage::Full => LoadOp::Clear(clear_color(scene.background)),
+                            Damage::Rects(_) => LoadOp::Load,
+                        },
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            if !vertices.is_empty() {
+                render_pass.set_pipeline(&shared.render_pipeline);
+                render_pass.set_vertex_buffer(0, vertex_buffer.buffer.slice(..));
+                if scene.damage == Damage::Full {
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                for region in &regions {
+                    render_pass.set_scissor_rect(region.x, region.y, region.width, region.height);
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+            }
+        }
+
+        //glyph_brush skips regenerating vertices when the glyphs queued are
+        //the same as last time
+        let glyph_brush = &mut self.glyph_brush;
+        if scene.damage == Damage::Full {
+            for run in &scene.runs {
+                queue_run(glyph_brush, run, scene.text_size.px_per_em);
+            }
+            glyph_brush
+                .draw_queued(
+                    &shared.device,
+                    &mut staging_belt,
+                    &mut encoder,
+                    view,
+                    size.width,
+                    size.height,
+                )
+                .unwrap();
+        }
+        for region in regions {
+            let rect = Rect {
+                x: region.x as f32,
+                y: region.y as f32,
+                width: region.width as f32,
+                height: region.height as f32,
+            };
+            let metrics = scene.text_size.cell_metrics;
+            for run in &scene.runs {
+                if run
+                    .glyphs
+                    .iter()
+                    .any(|glyph| frontend::glyph_rect(glyph, metrics).intersects(&rect))
+                {
+                    queue_run(glyph_brush, run, scene.text_size.px_per_em);
+                }
+            }
+            glyph_brush
+                .draw_queued_with_transform_and_scissoring(
+                    &shared.device,
+                    &mut staging_belt,
+                    &mut encoder,
+                    view,
+                    orthographic_projection(size.width, size.height),
+                    region,
+                )
+                .unwrap();
+        }
+        staging_belt.finish();
+        shared.queue.submit(iter::once(encoder.finish()));
+        //Chunks go back to being reused once the GPU is done with them
+        staging_belt.recall();
+    }
+}
+
+impl Headless {
+    //Prefers wgpu's software adapter so the output doesn't depend on the
+    //GPU, but takes whatever there is
+    pub(crate) fn new(
+        rt: &Runtime,
+        fonts: &[font_kit::font::Font],
+        size: PhysicalSize<u32>,
+        text_size: TextSize,
+    ) -> Result<Self, String> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+        let request = |force_fallback_adapter| {
+            rt.block_on(instance.request_adapter(&RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                force_fallback_adapter,
+                compatible_surface: None,
+            }))
+        };
+        let adapter = request(true)
+            .or_else(|| request(false))
+            .ok_or_else(|| "No graphics adapter, not even a software one".to_owned())?;
+        log::info!("Rendering headless with {:?}", adapter.get_info());
+        let shared = SharedWgpuContext::new(instance, adapter, HEADLESS_FORMAT, rt, fonts)?;
+        let texture = shared.device.create_texture(&TextureDescriptor {
+            label: Some("headless target"),
+            size: Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HEADLESS_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        Ok(Headless {
+            renderer: Renderer::new(&shared),
+            shared: Rc::new(shared),
+            texture,
+            size,
+            text_size,
+            scene: Scene::default(),
+        })
+    }
+
+    pub(crate) fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    //What was last presented as RGBA rows, top to bottom
+    pub(crate) fn read_pixels(&self) -> Vec<u8> {
+        //Rows of a texture copy have to be padded out to a multiple of 256
+        let row_bytes = self.size.width * 4;
+        let padded_row_bytes =
+            row_bytes.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let device = &self.shared.device;
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("headless readback"),
+            size: (padded_row_bytes * self.size.height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(self.size.height),
+                },
+            },
+            Extent3d {
+                width: self.size.width,
+                height: self.size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.shared.queue.submit(iter::once(encoder.finish()));
+        let slice = readback.slice(..);
+        slice.map_async(MapMode::Read, |result| {
+            if let Err(e) = result {
+                log::error!("Couldn't read the headless target back: {e}");
+            }
+        });
+        device.poll(Maintain::Wait);
+        let padded = slice.get_mapped_range();
+        padded
+            .chunks(padded_row_bytes as usize)
+            .flat_map(|row| &row[..row_bytes as usize])
+            .copied()
+            .collect()
+    }
+}
+
+fn clear_color(color: [f32; 4]) -> Color {
+    Color {
+        r: color[0] as f64,
+        g: color[1] as f64,
+        b: color[2] as f64,
+        a: color[3] as f64,
+    }
+}
+
+impl Frontend for Headless {
+    fn area(&self) -> Rect {
+        area(self.size)
+    }
+
+    fn cell_metrics(&self) -> CellMetrics {
+        self.text_size.cell_metrics
+    }
+
+    fn clear(&mut self, background: [f32; 4], damage: &Damage) {
+        self.scene = Scene::new(background, damage, self.text_size);
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: [f32; 4]) {
+        self.scene.fill_rect(rect, color);
+    }
+
+    fn draw_glyphs(&mut self, run: GlyphRun) {
+        self.scene.runs.push(run);
+    }
+
+    fn draw_cursor(&mut self, cursor: Cursor) {
+        self.scene.draw_cursor(cursor);
+    }
+
+    fn present(&mut self) {
+        let view = self.texture.create_view(&Default::default());
+        self.renderer
+            .draw(&self.shared, &view, self.size, &self.scene);
+    }
+}
+
+//Which of the fonts WgpuInfo was made with draws `run`, see fonts::load
+fn font_id(run: &GlyphRun) -> FontId {
+    FontId(match (run.weight, run.slant) {
+        (Weight::Normal, Slant::Normal) => 0,
+        (Weight::Bold, Slant::Normal) => 1,
+        (Weight::Normal, Slant::Italic) => 2,
+        (Weight::Bold, Slant::Italic) => 3,
+    })
+}
+
+//Queues every glyph of `run` exactly where the layout put it rather than
+//letting glyph_brush do its own line layout
+fn queue_run(glyph_brush: &mut GlyphBrush<()>, run: &GlyphRun, px_per_em: f32) {
+    let fonts = glyph_brush.fonts();
+    let font_id = font_id(run);
+    let font_id = if font_id.0 < fonts.len() {
+        font_id
+    } else {
+        FontId(0)
+    };
+    let glyphs = run
+        .glyphs
+        .iter()
+        .map(|g| {
+            //Glyphs the face's font doesn't have come from the first
+            //fallback that does
+            let font_id = iter::once(font_id)
+                .chain((fonts::FALLBACK_START..fonts.len()).map(FontId))
+                .find(|id| fonts[id.0].glyph_id(g.ch).0 != 0)
+                .unwrap_or(font_id);
+            let font = &fonts[font_id.0];
+            let scale = match font.units_per_em() {
+                Some(units_per_em) => {
+                    PxScale::from(px_per_em * font.height_unscaled() / units_per_em)
+                }
+                None => PxScale::from(px_per_em),
+            };
+            SectionGlyph {
+                section_index: 0,
+                byte_index: 0,
+                glyph: font
+                    .glyph_id(g.ch)
+                    .with_scale_and_position(scale, ab_glyph::point(g.x, g.baseline)),
+                font_id,
+            }
+        })
+        .collect::<Vec<_>>();
+    if glyphs.is_empty() {
+        return;
     }
+    let clip = run.clip;
+    glyph_brush.queue_pre_positioned(
+        glyphs,
+        vec![Extra {
+            color: run.color,
+            z: 0.0,
+        }],
+        ab_glyph::Rect {
+            min: ab_glyph::point(clip.x, clip.y),
+            max: ab_glyph::point(clip.x + clip.width, clip.y + clip.height),
+        },
+    );
 }
 
 impl Vertex {
@@ -0,0 +1,102 @@
+use bunmacs_core::keymap::{Key, KeyCode, Modifiers};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode};
+
+//winit reports printable text through ReceivedCharacter and everything else
+//through KeyboardInput, so we need both to build Emacs style keys. Chords
+//with C- or M- come from the keycode since the character winit reports for
+//them is a control character (or nothing at all).
+#[derive(Debug, Default)]
+pub(crate) struct KeyTranslator {
+    mods: ModifiersState,
+}
+
+impl KeyTranslator {
+    pub(crate) fn set_modifiers(&mut self, mods: ModifiersState) {
+        self.mods = mods;
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            ctrl: self.mods.ctrl(),
+            meta: self.mods.alt(),
+            shift: self.mods.shift(),
+        }
+    }
+
+    fn chording(&self) -> bool {
+        self.mods.ctrl() || self.mods.alt()
+    }
+
+    pub(crate) fn keyboard_input(&self, input: &KeyboardInput) -> Option<Key> {
+        if input.state != ElementState::Pressed {
+            return None;
+        }
+        let vk = input.virtual_keycode?;
+        let named = match vk {
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(KeyCode::Return),
+            VirtualKeyCode::Back => Some(KeyCode::Backspace),
+            VirtualKeyCode::Delete => Some(KeyCode::Delete),
+            VirtualKeyCode::Tab => Some(KeyCode::Tab),
+            VirtualKeyCode::Escape => Some(KeyCode::Escape),
+            VirtualKeyCode::Left => Some(KeyCode::Left),
+            VirtualKeyCode::Right => Some(KeyCode::Right),
+            VirtualKeyCode::Up => Some(KeyCode::Up),
+            VirtualKeyCode::Down => Some(KeyCode::Down),
+            VirtualKeyCode::Home => Some(KeyCode::Home),
+            VirtualKeyCode::End => Some(KeyCode::End),
+            VirtualKeyCode::PageUp => Some(KeyCode::PageUp),
+            VirtualKeyCode::PageDown => Some(KeyCode::PageDown),
+            _ => None,
+        };
+        if let Some(code) = named {
+            return Some(Key {
+                code,
+                mods: self.modifiers(),
+            });
+        }
+        if !self.chording() {
+            return None;
+        }
+        let c = chord_char(vk)?;
+        Some(Key {
+            code: KeyCode::Char(c),
+            mods: Modifiers {
+                shift: false,
+                ..self.modifiers()
+            },
+        })
+    }
+
+    pub(crate) fn received_character(&self, c: char) -> Option<Key> {
+        if self.chording() || c.is_control() {
+            return None;
+        }
+        Some(Key::plain(KeyCode::Char(c)))
+    }
+}
+
+fn chord_char(vk: VirtualKeyCode) -> Option<char> {
+    let code = vk as u32;
+    if (VirtualKeyCode::A as u32..=VirtualKeyCode::Z as u32).contains(&code) {
+        return char::from_u32('a' as u32 + code - VirtualKeyCode::A as u32);
+    }
+    if (VirtualKeyCode::Key1 as u32..=VirtualKeyCode::Key9 as u32).contains(&code) {
+        return char::from_u32('1' as u32 + code - VirtualKeyCode::Key1 as u32);
+    }
+    Some(match vk {
+        VirtualKeyCode::Key0 => '0',
+        VirtualKeyCode::Space => ' ',
+        VirtualKeyCode::Minus => '-',
+        VirtualKeyCode::Equals => '=',
+        VirtualKeyCode::Comma => ',',
+        VirtualKeyCode::Period => '.',
+        VirtualKeyCode::Slash => '/',
+        VirtualKeyCode::Semicolon => ';',
+        VirtualKeyCode::Apostrophe => '\'',
+        VirtualKeyCode::Grave => '`',
+        VirtualKeyCode::LBracket => '[',
+        VirtualKeyCode::RBracket => ']',
+        VirtualKeyCode::Backslash => '\\',
+        _ => return None,
+    })
+}
@@ -1,51 +1,115 @@
+mod fonts;
 mod graphics;
+mod input;
+mod screenshot;
 
-use font_kit::{family_name::FamilyName, properties::Properties, source::SystemSource};
-use graphics::{WgpuInfo, WindowContext};
+use bunmacs_core::{
+    decoration::Blink,
+    editor::{Editor, FrontendRequest},
+};
+use graphics::{TextSize, WgpuInfo, WindowContext};
+use input::KeyTranslator;
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use wgpu::SurfaceError;
 use winit::{
     dpi::LogicalSize,
     event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoopBuilder},
-    window::WindowBuilder,
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopWindowTarget},
+    window::{Window, WindowBuilder},
 };
 
 enum Win {
-    WindowContext(WindowContext),
+    WindowContext(Box<WindowContext>),
     Tombstone,
 }
-fn main() {
-    env_logger::init();
 
-    let async_runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
-
-    let event_loop = EventLoopBuilder::new().build();
-
-    let window = WindowBuilder::new()
+fn build_window<T>(target: &EventLoopWindowTarget<T>) -> Window {
+    WindowBuilder::new()
         .with_inner_size(LogicalSize {
             width: 1280,
             height: 720,
         })
         .with_title("Bunmacs!")
-        .build(&event_loop)
-        .unwrap();
-    let font = SystemSource::new()
-        .select_best_match(&[FamilyName::Monospace], &Properties::new())
+        .build(target)
         .unwrap()
-        .load()
+}
+
+fn main() {
+    env_logger::init();
+
+    let async_runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
         .unwrap();
 
-    let (_, window_context) = WgpuInfo::new(window, &async_runtime, &font);
+    let args = std::env::args_os().skip(1).collect();
+    let files = match screenshot::Options::parse(args) {
+        Ok(Some(options)) => {
+            if let Err(e) = screenshot::run(options, &async_runtime) {
+                log::error!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(None) => std::env::args_os().skip(1),
+        Err(e) => {
+            log::error!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    //Background work wakes the event loop with an empty user event when
+    //it has something for the editor
+    let event_loop = EventLoopBuilder::with_user_event().build();
+    let proxy = Mutex::new(event_loop.create_proxy());
+
+    let mut editor = Editor::new();
+    editor.set_async_runtime(
+        async_runtime.handle().clone(),
+        Some(Arc::new(move || {
+            if let Ok(proxy) = proxy.lock() {
+                let _ = proxy.send_event(());
+            }
+        })),
+    );
+    for path in files {
+        if let Err(e) = editor.find_file(&path) {
+            log::error!("Could not visit {}: {e}", path.to_string_lossy());
+        }
+    }
+
+    let window = build_window(&event_loop);
+    let mut font_config = editor.font().clone();
+    let mut fonts = fonts::load(&font_config);
+
+    let (wgpu_info, window_context) = WgpuInfo::new(window, &async_runtime, &fonts.faces);
 
     let mut window_set = HashSet::new();
     window_set.insert(window_context.id());
 
     let mut window_contexts = HashMap::new();
 
-    window_contexts.insert(window_context.id(), Win::WindowContext(window_context));
+    let mut frame_ids = HashMap::new();
+    frame_ids.insert(window_context.id(), editor.make_frame());
 
-    event_loop.run(move |event, _target, control_flow| match event {
+    window_contexts.insert(
+        window_context.id(),
+        Win::WindowContext(Box::new(window_context)),
+    );
+
+    let mut key_translator = KeyTranslator::default();
+    let mut blink = Blink::new(Instant::now());
+    //Set when something happened that might change what a frame shows.
+    //Frames can show the same buffers, so one edit can change any of them,
+    //but each only repaints the lines that actually changed.
+    let mut dirty = true;
+
+    event_loop.run(move |event, target, control_flow| match event {
         Event::WindowEvent {
             window_id,
             ref event,
@@ -53,19 +117,59 @@ fn main() {
             if let Some(win) = window_contexts.get_mut(&window_id) {
                 match event {
                     WindowEvent::CloseRequested => {
-                        //TODO: confirm if user wants to close? Unsaved files?
-
-                        *win = Win::Tombstone;
+                        //The editor asks about unsaved buffers first and
+                        //tells us to tombstone the window once it's sure
+                        if let Some(frame) = frame_ids.get(&window_id) {
+                            editor.delete_frame_interactively(*frame);
+                        }
+                        dirty = true;
+                    }
+                    WindowEvent::Focused(true) => {
+                        if let Some(frame) = frame_ids.get(&window_id) {
+                            editor.select_frame(*frame);
+                        }
+                        editor.focus_changed(true);
+                        dirty = true;
+                    }
+                    WindowEvent::Focused(false) => {
+                        editor.focus_changed(false);
+                        dirty = true;
                     }
 
                     WindowEvent::Resized(new_size) => {
                         if let Win::WindowContext(context) = win {
                             context.resize(*new_size)
                         }
+                        dirty = true;
+                    }
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    } => {
+                        if let Win::WindowContext(context) = win {
+                            context.rescale(*scale_factor, **new_inner_size);
+                        }
+                        dirty = true;
+                    }
+                    WindowEvent::ModifiersChanged(mods) => key_translator.set_modifiers(*mods),
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        if let Some(key) = key_translator.keyboard_input(input) {
+                            editor.handle_key(key);
+                            blink.reset(Instant::now());
+                            dirty = true;
+                        }
+                    }
+                    WindowEvent::ReceivedCharacter(c) => {
+                        if let Some(key) = key_translator.received_character(*c) {
+                            editor.handle_key(key);
+                            blink.reset(Instant::now());
+                            dirty = true;
+                        }
                     }
                     WindowEvent::Destroyed => {
                         window_contexts.remove(&window_id);
-                        if window_contexts.len() == 0 {
+                        frame_ids.remove(&window_id);
+                        if window_contexts.is_empty() {
                             *control_flow = ControlFlow::Exit;
                         }
                     }
@@ -76,14 +180,119 @@ fn main() {
             }
         }
 
-        Event::RedrawRequested(window_id) => {
-            if let Some(Win::WindowContext(context)) = window_contexts.get_mut(&window_id) {
-                context.redraw().expect("WGPU Surface Error");
-            } else {
-                log::error!("Invalid window ID passed to redraw.");
+        Event::MainEventsCleared => {
+            //Woken up for an idle timer, or to blink the cursor which is
+            //taken care of below
+            dirty |= editor.run_timers(Instant::now());
+            dirty |= editor.process_background_events();
+            let requests = editor.take_frontend_requests();
+            dirty |= !requests.is_empty();
+            for request in requests {
+                match request {
+                    FrontendRequest::MakeFrame(frame) => {
+                        let context = wgpu_info.make_window_context(build_window(target));
+                        frame_ids.insert(context.id(), frame);
+                        window_contexts.insert(context.id(), Win::WindowContext(Box::new(context)));
+                    }
+                    FrontendRequest::DeleteFrame(frame) => {
+                        let window_id = frame_ids
+                            .iter()
+                            .find(|(_, f)| **f == frame)
+                            .map(|(w, _)| *w);
+                        if let Some(win) = window_id.and_then(|w| window_contexts.get_mut(&w)) {
+                            *win = Win::Tombstone;
+                        }
+                    }
+                    //Every window gets redisplayed below anyway
+                    FrontendRequest::RedrawAll => {}
+                    FrontendRequest::FontChanged => {
+                        let new_config = editor.font().clone();
+                        //Zooming only changes the size, no need to go
+                        //looking for fonts again
+                        if (&new_config.family, &new_config.fallbacks)
+                            != (&font_config.family, &font_config.fallbacks)
+                        {
+                            fonts = fonts::load(&new_config);
+                            wgpu_info.set_fonts(&fonts.faces);
+                            //Same display, different glyphs
+                            for frame in frame_ids.values() {
+                                editor.invalidate_frame(*frame);
+                            }
+                        }
+                        font_config = new_config;
+                    }
+                }
+            }
+            if dirty {
+                for (window_id, win) in &mut window_contexts {
+                    if let (Win::WindowContext(context), Some(frame)) =
+                        (win, frame_ids.get(window_id))
+                    {
+                        //Text is sized in points, so it's as big on every
+                        //monitor
+                        let px_per_em = font_config.pixel_size(context.scale_factor());
+                        context.set_text_size(TextSize {
+                            px_per_em,
+                            cell_metrics: fonts.cell_metrics(px_per_em),
+                        });
+                        //Only the selected frame's cursor blinks
+                        let cursor_on =
+                            editor.selected_frame() != Some(*frame) || blink.is_on(Instant::now());
+                        editor.draw_frame(*frame, context.as_mut(), cursor_on);
+                    }
+                }
+                dirty = false;
+            } else if let Some(frame) = editor.selected_frame() {
+                //Nothing changed but maybe the cursor's blinked, which
+                //only repaints the cursor's cells
+                let window_id = frame_ids
+                    .iter()
+                    .find(|(_, f)| **f == frame)
+                    .map(|(w, _)| *w);
+                if let Some(Win::WindowContext(context)) =
+                    window_id.and_then(|w| window_contexts.get_mut(&w))
+                {
+                    editor.redraw_cursor(frame, context.as_mut(), blink.is_on(Instant::now()));
+                }
+            }
+            if *control_flow != ControlFlow::Exit {
+                let wake = blink
+                    .next_change(Instant::now())
+                    .into_iter()
+                    .chain(editor.next_timer())
+                    .min();
+                *control_flow = match wake {
+                    Some(when) => ControlFlow::WaitUntil(when),
+                    None => ControlFlow::Wait,
+                };
             }
         }
 
+        Event::RedrawRequested(window_id) => match window_contexts.get_mut(&window_id) {
+            Some(Win::WindowContext(context)) => {
+                let mut result = context.redraw();
+                //Common after a resize, suspend or monitor change
+                if let Err(SurfaceError::Lost | SurfaceError::Outdated) = result {
+                    context.reconfigure();
+                    result = context.redraw();
+                }
+                match result {
+                    Ok(()) => (),
+                    Err(SurfaceError::OutOfMemory) => {
+                        log::error!("Out of GPU memory, exiting");
+                        for path in editor.do_auto_save() {
+                            log::error!("Auto-saved to {}", path.display());
+                        }
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    //The next change draws the window again
+                    Err(e) => log::warn!("Skipped a frame: {e}"),
+                }
+            }
+            Some(Win::Tombstone) => (),
+            None => log::error!("Invalid window ID passed to redraw."),
+        },
+
         _ => {}
     });
 }
@@ -0,0 +1,260 @@
+//`bunmacs --screenshot out.png [--eval EXPR]... [FILE]...` draws one frame
+//offscreen and saves it, so rendering can be checked without a display.
+//Screenshots always use the bundled font so they look the same everywhere.
+
+use crate::{
+    fonts,
+    graphics::{Headless, TextSize},
+};
+use bunmacs_core::editor::Editor;
+
+use std::{cell::RefCell, ffi::OsString, fs::File, io::BufWriter, path::PathBuf, rc::Rc};
+use winit::dpi::PhysicalSize;
+
+//The same size a new window opens at, with a scale factor of 1
+const SIZE: PhysicalSize<u32> = PhysicalSize {
+    width: 1280,
+    height: 720,
+};
+
+pub struct Options {
+    pub output: PathBuf,
+    //Run in order after the files are visited
+    pub evals: Vec<String>,
+    pub files: Vec<OsString>,
+}
+
+impl Options {
+    //None if there's no --screenshot, in which case the arguments are all
+    //files to visit
+    pub fn parse(args: Vec<OsString>) -> Result<Option<Self>, String> {
+        let mut output = None;
+        let mut evals = vec![];
+        let mut files = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--screenshot" {
+                let path = args.next().ok_or("--screenshot needs a file to save to")?;
+                output = Some(PathBuf::from(path));
+            } else if arg == "--eval" {
+                let expr = args.next().ok_or("--eval needs an expression")?;
+                let expr = expr
+                    .into_string()
+                    .map_err(|e| format!("--eval {} isn't UTF-8", e.to_string_lossy()))?;
+                evals.push(expr);
+            } else {
+                files.push(arg);
+            }
+        }
+        match output {
+            Some(output) => Ok(Some(Options {
+                output,
+                evals,
+                files,
+            })),
+            None if evals.is_empty() => Ok(None),
+            None => Err("--eval only works with --screenshot".to_owned()),
+        }
+    }
+}
+
+pub fn run(options: Options, rt: &tokio::runtime::Runtime) -> Result<(), String> {
+    let mut editor = Editor::new();
+    for path in &options.files {
+        editor
+            .find_file(path)
+            .map_err(|e| format!("Could not visit {}: {e}", path.to_string_lossy()))?;
+    }
+    let frame = editor.make_frame();
+    for expr in &options.evals {
+        let result = Rc::new(RefCell::new(None));
+        let slot = result.clone();
+        editor.eval_script(
+            expr,
+            Box::new(move |_, r| {
+                slot.borrow_mut().replace(r);
+            }),
+        );
+        //Nobody is around to answer the minibuffer
+        match result.take() {
+            Some(Ok(_)) => (),
+            Some(Err(e)) => return Err(format!("{expr}: {e}")),
+            None => return Err(format!("{expr}: waiting for input")),
+        }
+    }
+
+    let mut headless = headless(&editor, rt, SIZE)?;
+    if !editor.draw_frame(frame, &mut headless, true) {
+        return Err("The frame went away".to_owned());
+    }
+    save_png(&options.output, headless.size(), &headless.read_pixels())
+        .map_err(|e| format!("Could not save {}: {e}", options.output.display()))
+}
+
+//An offscreen target of `size` drawing in the bundled font at the size
+//`editor` asks for
+fn headless(
+    editor: &Editor,
+    rt: &tokio::runtime::Runtime,
+    size: PhysicalSize<u32>,
+) -> Result<Headless, String> {
+    let fonts = fonts::load_bundled();
+    let px_per_em = editor.font().pixel_size(1.0);
+    let text_size = TextSize {
+        px_per_em,
+        cell_metrics: fonts.cell_metrics(px_per_em),
+    };
+    Headless::new(rt, &fonts.faces, size, text_size)
+}
+
+fn save_png(
+    path: &PathBuf,
+    size: PhysicalSize<u32>,
+    rgba: &[u8],
+) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    //The pixels were rendered to an sRGB texture
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::Path, sync::Mutex};
+
+    const GOLDEN_SIZE: PhysicalSize<u32> = PhysicalSize {
+        width: 640,
+        height: 360,
+    };
+    //Adapters don't all round or antialias the same way, so channels can be
+    //this far off the golden image...
+    const TOLERANCE: u8 = 24;
+    //...and this many pixels further off than that, fewer than a changed
+    //letter makes
+    const STRAY_PIXELS: usize = 8;
+
+    const TEXT: &str = "(define (greet name)\n\t(concat \"Hello, \" name \"!\"))\n\nÀccénts, and a long line that wraps around the edge of the window and keeps going\n";
+
+    fn golden_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(format!("{name}.png"))
+    }
+
+    //Setting up EGL from two threads at once falls over, so tests take
+    //turns with the GPU
+    static GPU: Mutex<()> = Mutex::new(());
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    //None when there's no adapter to render with at all
+    fn render(
+        editor: &mut Editor,
+        rt: &tokio::runtime::Runtime,
+        cursor_on: bool,
+    ) -> Option<Vec<u8>> {
+        let frame = editor
+            .selected_frame()
+            .unwrap_or_else(|| editor.make_frame());
+        let mut headless = match headless(editor, rt, GOLDEN_SIZE) {
+            Ok(headless) => headless,
+            Err(e) => {
+                eprintln!("Not rendering: {e}");
+                return None;
+            }
+        };
+        assert!(editor.draw_frame(frame, &mut headless, cursor_on));
+        Some(headless.read_pixels())
+    }
+
+    //How many pixels of `a` and `b` are further apart than TOLERANCE
+    fn stray_pixels(a: &[u8], b: &[u8]) -> usize {
+        a.chunks(4)
+            .zip(b.chunks(4))
+            .filter(|(a, b)| a.iter().zip(*b).any(|(a, b)| a.abs_diff(*b) > TOLERANCE))
+            .count()
+    }
+
+    fn read_png(path: &Path) -> (PhysicalSize<u32>, Vec<u8>) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        rgba.truncate(info.buffer_size());
+        (PhysicalSize::new(info.width, info.height), rgba)
+    }
+
+    //Run with BUNMACS_BLESS=1 to write the golden image instead after a
+    //change that's meant to look different
+    fn assert_golden(name: &str, rgba: &[u8]) {
+        let path = golden_path(name);
+        if std::env::var_os("BUNMACS_BLESS").is_some() {
+            save_png(&path, GOLDEN_SIZE, rgba).unwrap();
+            return;
+        }
+        let (size, golden) = read_png(&path);
+        assert_eq!(size, GOLDEN_SIZE);
+        let stray = stray_pixels(rgba, &golden);
+        if stray > STRAY_PIXELS {
+            let actual = std::env::temp_dir().join(format!("{name}-actual.png"));
+            save_png(&actual, GOLDEN_SIZE, rgba).unwrap();
+            panic!(
+                "{stray} pixels differ from {}, see {}",
+                path.display(),
+                actual.display()
+            );
+        }
+    }
+
+    fn editor() -> Editor {
+        let mut editor = Editor::new();
+        editor.insert(TEXT);
+        editor.make_frame();
+        editor
+    }
+
+    #[test]
+    fn scratch_buffer_matches_golden() {
+        let _gpu = GPU.lock().unwrap_or_else(|e| e.into_inner());
+        let rt = runtime();
+        let mut editor = editor();
+        if let Some(rgba) = render(&mut editor, &rt, true) {
+            assert_golden("scratch", &rgba);
+        }
+    }
+
+    //Painting only what changed has to end up with the same picture as
+    //painting everything
+    #[test]
+    fn partial_redraws_match_full_ones() {
+        let _gpu = GPU.lock().unwrap_or_else(|e| e.into_inner());
+        let rt = runtime();
+        let mut editor = editor();
+        let frame = editor.selected_frame().unwrap();
+        //wgpu's GL backend can't cope with two of these at once
+        let partial = {
+            let Ok(mut target) = headless(&editor, &rt, GOLDEN_SIZE) else {
+                return;
+            };
+            editor.draw_frame(frame, &mut target, true);
+            editor.insert("more\nlines");
+            editor.draw_frame(frame, &mut target, true);
+            editor.redraw_cursor(frame, &mut target, false);
+            target.read_pixels()
+        };
+        editor.invalidate_frame(frame);
+        let full = render(&mut editor, &rt, false).unwrap();
+        assert_eq!(stray_pixels(&partial, &full), 0);
+    }
+}
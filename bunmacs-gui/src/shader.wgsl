@@ -1,11 +1,11 @@
 struct VertexInput {
     @location(0) pos: vec3<f32>,
-    @location(1) color: vec3<f32>
+    @location(1) color: vec4<f32>
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>
+    @location(0) color: vec4<f32>
 };
 
 @vertex
@@ -18,6 +18,6 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    return in.color;
 }
 
@@ -0,0 +1,53 @@
+use bunmacs_core::keymap::{Key, KeyCode, Modifiers};
+use crossterm::event::{self, KeyEvent, KeyEventKind, KeyModifiers};
+
+//Terminals send C- chords as control characters and M- chords as an ESC
+//prefix, crossterm turns both back into modifiers for us. Shift is already
+//in the case of the character, so it only matters for named keys.
+pub(crate) fn translate(event: KeyEvent) -> Option<Key> {
+    if event.kind == KeyEventKind::Release {
+        return None;
+    }
+    let mods = Modifiers {
+        ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+        meta: event.modifiers.contains(KeyModifiers::ALT),
+        shift: event.modifiers.contains(KeyModifiers::SHIFT),
+    };
+    let code = match event.code {
+        event::KeyCode::Char(c) => {
+            //C-x shows up as 'x' but C-X as 'X' on some terminals
+            let c = if mods.ctrl { c.to_ascii_lowercase() } else { c };
+            return Some(Key {
+                code: KeyCode::Char(c),
+                mods: Modifiers {
+                    shift: false,
+                    ..mods
+                },
+            });
+        }
+        event::KeyCode::Enter => KeyCode::Return,
+        event::KeyCode::Backspace => KeyCode::Backspace,
+        event::KeyCode::Delete => KeyCode::Delete,
+        event::KeyCode::Tab => KeyCode::Tab,
+        event::KeyCode::BackTab => {
+            return Some(Key {
+                code: KeyCode::Tab,
+                mods: Modifiers {
+                    shift: true,
+                    ..mods
+                },
+            })
+        }
+        event::KeyCode::Esc => KeyCode::Escape,
+        event::KeyCode::Left => KeyCode::Left,
+        event::KeyCode::Right => KeyCode::Right,
+        event::KeyCode::Up => KeyCode::Up,
+        event::KeyCode::Down => KeyCode::Down,
+        event::KeyCode::Home => KeyCode::Home,
+        event::KeyCode::End => KeyCode::End,
+        event::KeyCode::PageUp => KeyCode::PageUp,
+        event::KeyCode::PageDown => KeyCode::PageDown,
+        _ => return None,
+    };
+    Some(Key { code, mods })
+}
@@ -0,0 +1,206 @@
+mod input;
+mod screen;
+
+use bunmacs_core::{
+    editor::{self, Editor, FrontendRequest},
+    grid::{self, Grid},
+    layout::Rect,
+};
+use screen::{ColorMode, Screen};
+
+use crossterm::{
+    cursor::{SetCursorStyle, Show},
+    event::{self, DisableFocusChange, EnableFocusChange, Event},
+    execute,
+    style::{Attribute, SetAttribute},
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+//What the main loop wakes up for
+enum Wake {
+    Input(io::Result<Event>),
+    //Background work has something for the editor
+    Background,
+}
+
+//Raw mode and the alternate screen, undone when dropped so the shell gets
+//its terminal back however we exit
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, EnableFocusChange)?;
+        //A panic would otherwise leave the message on the alternate screen
+        //and the terminal in raw mode. Ones the editor catches, like in a
+        //hook, get shown in the echo area and we keep going on this screen.
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if editor::isolating_panics() {
+                log::error!("{info}");
+                return;
+            }
+            restore();
+            hook(info);
+        }));
+        Ok(Terminal)
+    }
+}
+
+fn restore() {
+    let _ = execute!(
+        io::stdout(),
+        SetAttribute(Attribute::Reset),
+        SetCursorStyle::DefaultUserShape,
+        Show,
+        DisableFocusChange,
+        LeaveAlternateScreen
+    );
+    let _ = terminal::disable_raw_mode();
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+//Where logs go, stderr being the terminal we draw on. $BUNMACS_LOG_FILE
+//or bunmacs-tty.log in the temp directory.
+fn log_path() -> PathBuf {
+    std::env::var_os("BUNMACS_LOG_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("bunmacs-tty.log"))
+}
+
+fn init_logging() {
+    let target: Box<dyn Write + Send> = match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+    {
+        Ok(file) => Box::new(file),
+        Err(_) => Box::new(io::sink()),
+    };
+    env_logger::Builder::from_default_env()
+        .target(env_logger::Target::Pipe(target))
+        .init();
+}
+
+fn main() -> io::Result<()> {
+    init_logging();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    //Input and background work both wake the main loop up through here
+    let (wake, wakes) = mpsc::channel();
+    let background = wake.clone();
+    let mut editor = Editor::new();
+    editor.set_async_runtime(
+        runtime.handle().clone(),
+        Some(Arc::new(move || {
+            let _ = background.send(Wake::Background);
+        })),
+    );
+    for path in std::env::args_os().skip(1) {
+        if let Err(e) = editor.find_file(&path) {
+            log::error!("Could not visit {}: {e}", path.to_string_lossy());
+        }
+    }
+    editor.make_frame();
+
+    let _terminal = Terminal::enter()?;
+    let mut out = io::stdout().lock();
+    let mut screen = Screen::new(ColorMode::detect());
+    let (mut cols, mut rows) = terminal::size()?;
+
+    thread::spawn(move || loop {
+        let event = event::read();
+        let failed = event.is_err();
+        if wake.send(Wake::Input(event)).is_err() || failed {
+            break;
+        }
+    });
+
+    //Set when something happened that might change the screen
+    let mut dirty = true;
+    //A terminal shows one frame at a time, the selected one. The editor
+    //picks another when it's deleted and we exit once there are none left.
+    while let Some(frame) = editor.selected_frame() {
+        if dirty {
+            let area = Rect {
+                x: 0.0,
+                y: 0.0,
+                width: cols as f32,
+                height: rows as f32,
+            };
+            if let Some(display) = editor.redisplay(frame, area, grid::CELL_METRICS) {
+                let grid = Grid::from_display(&display, cols as usize, rows as usize);
+                screen.draw(&mut out, &grid)?;
+            }
+            dirty = false;
+        }
+
+        //Sleep until there's input, background work has something or the
+        //next idle timer is due
+        let woken = match editor.next_timer() {
+            Some(when) => wakes.recv_timeout(when.saturating_duration_since(Instant::now())),
+            None => wakes.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match woken {
+            Ok(Wake::Input(event)) => match event? {
+                Event::Key(key) => {
+                    if let Some(key) = input::translate(key) {
+                        editor.handle_key(key);
+                        dirty = true;
+                    }
+                }
+                Event::Resize(new_cols, new_rows) => {
+                    (cols, rows) = (new_cols, new_rows);
+                    screen.invalidate();
+                    dirty = true;
+                }
+                Event::FocusGained => {
+                    editor.focus_changed(true);
+                    dirty = true;
+                }
+                Event::FocusLost => {
+                    editor.focus_changed(false);
+                    dirty = true;
+                }
+                _ => (),
+            },
+            //Picked up below
+            Ok(Wake::Background) | Err(RecvTimeoutError::Timeout) => (),
+            //Can't happen, the editor holds on to a sender
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        dirty |= editor.run_timers(Instant::now());
+        dirty |= editor.process_background_events();
+
+        for request in editor.take_frontend_requests() {
+            dirty = true;
+            match request {
+                //A different frame or different colors, easiest to start over
+                FrontendRequest::MakeFrame(_)
+                | FrontendRequest::DeleteFrame(_)
+                | FrontendRequest::RedrawAll => screen.invalidate(),
+                //The terminal picks the font
+                FrontendRequest::FontChanged => (),
+            }
+        }
+    }
+    out.flush()
+}
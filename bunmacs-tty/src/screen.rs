@@ -0,0 +1,190 @@
+use bunmacs_core::{
+    decoration::{Color, CursorStyle},
+    grid::{Cell, Grid, WIDE_CONTINUATION},
+};
+use crossterm::{
+    cursor::{Hide, MoveTo, SetCursorStyle, Show},
+    queue,
+    style::{self, Attribute, Colors, Print, SetAttribute, SetColors},
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColorMode {
+    TrueColor,
+    //The xterm palette, for terminals that don't do 24 bit color
+    Ansi256,
+}
+
+impl ColorMode {
+    //There's no way to ask a terminal, COLORTERM is the convention
+    pub(crate) fn detect() -> Self {
+        match std::env::var("COLORTERM").as_deref() {
+            Ok("truecolor" | "24bit") => ColorMode::TrueColor,
+            _ => ColorMode::Ansi256,
+        }
+    }
+
+    fn color(self, color: Color) -> style::Color {
+        let [r, g, b] = rgb(color);
+        match self {
+            ColorMode::TrueColor => style::Color::Rgb { r, g, b },
+            ColorMode::Ansi256 => style::Color::AnsiValue(ansi256([r, g, b])),
+        }
+    }
+}
+
+fn rgb(color: Color) -> [u8; 3] {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(color[0]), channel(color[1]), channel(color[2])]
+}
+
+//Levels of each channel in the 6x6x6 color cube at 16..232
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+//Nearest of the color cube and the gray ramp at 232..256. The 16 basic
+//colors are left out since every terminal has its own idea of them.
+fn ansi256(color: [u8; 3]) -> u8 {
+    let distance = |other: [u8; 3]| {
+        color
+            .iter()
+            .zip(other)
+            .map(|(a, b)| (*a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    let level = |c: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - c as i32).abs())
+            .unwrap()
+    };
+    let [r, g, b] = color.map(level);
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let average = color.iter().map(|c| *c as u32).sum::<u32>() / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray = 8 + 10 * gray_index;
+    if distance([gray; 3]) < distance(cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+//What the terminal already shows, so only cells that changed get written
+#[derive(Debug)]
+pub(crate) struct Screen {
+    colors: ColorMode,
+    shown: Option<Grid>,
+}
+
+//Attributes of the cell last written, so runs of the same style don't
+//repeat escape sequences
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pen {
+    foreground: Color,
+    background: Color,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Pen {
+    fn of(cell: &Cell) -> Self {
+        Pen {
+            foreground: cell.foreground,
+            background: cell.background,
+            bold: cell.bold,
+            italic: cell.italic,
+            underline: cell.underline,
+        }
+    }
+}
+
+impl Screen {
+    pub(crate) fn new(colors: ColorMode) -> Self {
+        Screen {
+            colors,
+            shown: None,
+        }
+    }
+
+    //Forget what's on the terminal so the next draw repaints everything
+    pub(crate) fn invalidate(&mut self) {
+        self.shown = None;
+    }
+
+    pub(crate) fn draw(&mut self, out: &mut impl Write, grid: &Grid) -> io::Result<()> {
+        let previous = self
+            .shown
+            .take()
+            .filter(|shown| (shown.cols, shown.rows) == (grid.cols, grid.rows));
+        if previous.is_none() {
+            queue!(out, SetAttribute(Attribute::Reset), Clear(ClearType::All))?;
+        }
+        queue!(out, Hide)?;
+        let mut pen = None;
+        for row in 0..grid.rows {
+            let cells = grid.row(row);
+            //Where the terminal's cursor ended up after the last write
+            let mut at = None;
+            let mut col = 0;
+            while col < cells.len() {
+                //A wide char and the cells it covers get written together
+                let width = 1 + cells[col + 1..]
+                    .iter()
+                    .take_while(|cell| cell.ch == WIDE_CONTINUATION)
+                    .count();
+                let span = col..col + width;
+                let unchanged = previous
+                    .as_ref()
+                    .is_some_and(|previous| previous.row(row)[span.clone()] == cells[span]);
+                let cell = &cells[col];
+                if unchanged || cell.ch == WIDE_CONTINUATION {
+                    col += width;
+                    continue;
+                }
+                if at != Some(col) {
+                    queue!(out, MoveTo(col as u16, row as u16))?;
+                }
+                if pen != Some(Pen::of(cell)) {
+                    self.set_pen(out, cell)?;
+                    pen = Some(Pen::of(cell));
+                }
+                queue!(out, Print(cell.ch))?;
+                col += width;
+                at = Some(col);
+            }
+        }
+        if let Some((col, row)) = grid.cursor {
+            let style = match grid.cursor_style {
+                CursorStyle::Bar => SetCursorStyle::SteadyBar,
+                CursorStyle::Underline => SetCursorStyle::SteadyUnderScore,
+                CursorStyle::Box | CursorStyle::Hollow => SetCursorStyle::SteadyBlock,
+            };
+            queue!(out, MoveTo(col as u16, row as u16), style, Show)?;
+        }
+        out.flush()?;
+        self.shown = Some(grid.clone());
+        Ok(())
+    }
+
+    fn set_pen(&self, out: &mut impl Write, cell: &Cell) -> io::Result<()> {
+        queue!(out, SetAttribute(Attribute::Reset))?;
+        if cell.bold {
+            queue!(out, SetAttribute(Attribute::Bold))?;
+        }
+        if cell.italic {
+            queue!(out, SetAttribute(Attribute::Italic))?;
+        }
+        if cell.underline {
+            queue!(out, SetAttribute(Attribute::Underlined))?;
+        }
+        queue!(
+            out,
+            SetColors(Colors::new(
+                self.colors.color(cell.foreground),
+                self.colors.color(cell.background)
+            ))
+        )
+    }
+}